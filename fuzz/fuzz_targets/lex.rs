@@ -0,0 +1,10 @@
+//! Lexes arbitrary bytes, lossily decoded as UTF-8.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+    let (tokens, _) = rlox::lex(&source);
+    assert!(!tokens.is_empty(), "lexing always ends with an Eof token");
+});
@@ -0,0 +1,9 @@
+//! Parses arbitrary strings, whether they lex or not.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let (tokens, _) = rlox::lex(source);
+    let _ = rlox::parse(&tokens);
+});
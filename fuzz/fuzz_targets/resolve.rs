@@ -0,0 +1,13 @@
+//! Whatever lexes and parses without errors also has to make it through the resolver, with or
+//! without an error but without panicking.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let (tokens, lexer_errors) = rlox::lex(source);
+    let (program, parser_errors) = rlox::parse(&tokens);
+    if lexer_errors.is_empty() && parser_errors.is_empty() {
+        let _ = rlox::resolve(&program);
+    }
+});
@@ -0,0 +1,49 @@
+#ifndef RLOX_H
+#define RLOX_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// An interpreter owned by a C host. Code runs on a thread of its own, so deep recursion in Lox
+// does not depend on the stack size of the host's thread.
+typedef struct RloxInterpreter RloxInterpreter;
+
+// Creates an interpreter. It has to be freed with `rlox_free`.
+RloxInterpreter *rlox_new(void);
+
+// Runs `source`, with the globals of earlier calls still defined. Returns 0 on success and 1 if
+// the code could not be compiled or failed at runtime, see `rlox_get_error`.
+//
+// # Safety
+//
+// `rlox` has to come from `rlox_new` and `source` has to be a nul terminated UTF-8 string. The
+// string stays owned by the caller.
+int rlox_eval(RloxInterpreter *rlox, const char *source);
+
+// The value of the last statement run by a successful `rlox_eval`, printed as `print` would,
+// or NULL if the last call failed.
+//
+// # Safety
+//
+// `rlox` has to come from `rlox_new`. The string is owned by the interpreter and valid until
+// the next call to `rlox_eval` or `rlox_free`.
+const char *rlox_get_result(const RloxInterpreter *rlox);
+
+// The error message of the last `rlox_eval`, or NULL if it succeeded.
+//
+// # Safety
+//
+// `rlox` has to come from `rlox_new`. The string is owned by the interpreter and valid until
+// the next call to `rlox_eval` or `rlox_free`.
+const char *rlox_get_error(const RloxInterpreter *rlox);
+
+// Frees an interpreter and all strings it handed out. Passing NULL does nothing.
+//
+// # Safety
+//
+// `rlox` has to come from `rlox_new` and must not be used afterwards.
+void rlox_free(RloxInterpreter *rlox);
+
+#endif /* RLOX_H */
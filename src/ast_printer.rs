@@ -0,0 +1,573 @@
+//! Renders syntax trees as text, for `--dump-ast`, tests and tools that want to look at what
+//! the parser built.
+//!
+//! [`Style::Parenthesized`] is the Lisp-like form of the book's `AstPrinter`, one line per
+//! top-level statement; [`Style::Tree`] puts every node on a line of its own, indented below
+//! its parent:
+//!
+//! ```
+//! use rlox::{print_ast, Style};
+//!
+//! let (tokens, _) = rlox::lex("(3 + 4) * 6;");
+//! let (program, _) = rlox::parse(&tokens);
+//! assert_eq!(print_ast(&program, Style::Parenthesized), "(; (* (group (+ 3 4)) 6))\n");
+//! assert_eq!(
+//!     print_ast(&program, Style::Tree),
+//!     "Expression\n  Binary *\n    Grouping\n      Binary +\n        Number 3\n        Number 4\n    Number 6\n"
+//! );
+//! ```
+
+use crate::object::Object;
+use crate::recursion;
+use crate::statement::{Ast, Expr, ExprRef, Pattern, Program, Stmt, StmtRef, TypeAnnotation};
+use crate::token::TokenType;
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Parenthesized,
+    Tree,
+}
+
+pub fn print(program: &Program, style: Style) -> String {
+    let mut out = String::new();
+    for &stmt in &program.statements {
+        match style {
+            Style::Parenthesized => {
+                out.push_str(&stmt_to_string(&program.ast, stmt));
+                out.push('\n');
+            }
+            Style::Tree => TreePrinter {
+                ast: &program.ast,
+                out: &mut out,
+            }
+            .stmt(stmt, 0),
+        }
+    }
+    out
+}
+
+/// The parenthesized form of an expression, e.g. `(* (group (+ 3 4)) 6)`.
+pub fn expr_to_string(ast: &Ast, expr: ExprRef) -> String {
+    recursion::grow_stack(|| {
+        let parens = |name: &str, children: &[ExprRef]| {
+            let mut out = format!("({}", name);
+            for &child in children {
+                out.push(' ');
+                out.push_str(&expr_to_string(ast, child));
+            }
+            out.push(')');
+            out
+        };
+        match &ast[expr] {
+            Expr::Number(number) => Object::Number(*number).to_string(),
+            Expr::String(string) => format!("{:?}", string),
+            Expr::Boolean(boolean) => boolean.to_string(),
+            Expr::Nil => "nil".to_string(),
+            Expr::Binary {
+                left,
+                token_type,
+                right,
+            } => parens(operator(token_type), &[*left, *right]),
+            Expr::Logical {
+                left,
+                operator: token_type,
+                right,
+            } => parens(operator(token_type), &[*left, *right]),
+            Expr::Unary { token_type, right } => parens(operator(token_type), &[*right]),
+            Expr::Grouping { expression } => parens("group", &[*expression]),
+            Expr::Call { callee, arguments } => {
+                let mut children = vec![*callee];
+                children.extend(arguments);
+                parens("call", &children)
+            }
+            Expr::Get { object, name, .. } => {
+                format!("(. {} {})", expr_to_string(ast, *object), name)
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => format!(
+                "(= {} {} {})",
+                expr_to_string(ast, *object),
+                name,
+                expr_to_string(ast, *value)
+            ),
+            Expr::Super { method, .. } => format!("(super {})", method),
+            Expr::This { .. } => "this".to_string(),
+            Expr::Variable { name, .. } => name.to_string(),
+            Expr::Assign { name, value, .. } => {
+                format!("(= {} {})", name, expr_to_string(ast, *value))
+            }
+            Expr::List { elements } => parens("list", elements),
+            Expr::Map { entries } => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .flat_map(|&(key, value)| [key, value])
+                    .collect();
+                parens("map", &entries)
+            }
+            Expr::Match { subject, arms } => {
+                let mut out = format!("(match {}", expr_to_string(ast, *subject));
+                for arm in arms {
+                    let pattern = match &arm.pattern {
+                        Pattern::Value(value) => expr_to_string(ast, *value),
+                        Pattern::Class { class, binding, .. } => {
+                            format!("({} {})", expr_to_string(ast, *class), binding)
+                        }
+                        Pattern::Wildcard => "_".to_string(),
+                    };
+                    write!(out, " (=> {} {})", pattern, expr_to_string(ast, arm.body)).unwrap();
+                }
+                out + ")"
+            }
+        }
+    })
+}
+
+/// The parenthesized form of a statement, e.g. `(var a (+ 1 2))`.
+pub fn stmt_to_string(ast: &Ast, stmt: StmtRef) -> String {
+    recursion::grow_stack(|| {
+        let expr = |expr: ExprRef| expr_to_string(ast, expr);
+        let block = |statements: &[StmtRef]| {
+            statements
+                .iter()
+                .map(|&stmt| format!(" {}", stmt_to_string(ast, stmt)))
+                .collect::<String>()
+        };
+        match &ast[stmt] {
+            Stmt::Expression { expression } => format!("(; {})", expr(*expression)),
+            Stmt::Print { expression } => format!("(print {})", expr(*expression)),
+            Stmt::Var {
+                name,
+                type_annotation,
+                initializer,
+                constant,
+                ..
+            } => {
+                let mut out = format!(
+                    "({} {}{}",
+                    if *constant { "const" } else { "var" },
+                    name,
+                    annotation(": ", type_annotation.as_ref())
+                );
+                if let Some(initializer) = initializer {
+                    write!(out, " {}", expr(*initializer)).unwrap();
+                }
+                out + ")"
+            }
+            Stmt::Block { statements } => format!("(block{})", block(statements)),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let mut out = format!(
+                    "(if {} {}",
+                    expr(*condition),
+                    stmt_to_string(ast, *then_branch)
+                );
+                if let Some(else_branch) = else_branch {
+                    write!(out, " {}", stmt_to_string(ast, *else_branch)).unwrap();
+                }
+                out + ")"
+            }
+            Stmt::While { condition, body } => format!(
+                "(while {} {})",
+                expr(*condition),
+                stmt_to_string(ast, *body)
+            ),
+            Stmt::DoWhile { body, condition } => {
+                format!("(do {} {})", stmt_to_string(ast, *body), expr(*condition))
+            }
+            Stmt::Labeled { label, body } => {
+                format!("(label {} {})", label, stmt_to_string(ast, *body))
+            }
+            Stmt::Break { label: None } => "(break)".to_string(),
+            Stmt::Break { label: Some(label) } => format!("(break {})", label),
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                let mut out = format!("(switch {}", expr(*subject));
+                for (value, body) in cases {
+                    write!(
+                        out,
+                        " (case {} {})",
+                        expr(*value),
+                        stmt_to_string(ast, *body)
+                    )
+                    .unwrap();
+                }
+                if let Some(default) = default {
+                    write!(out, " (default {})", stmt_to_string(ast, *default)).unwrap();
+                }
+                out + ")"
+            }
+            Stmt::Function { body, .. } => format!("(fun {}{})", signature(ast, stmt), block(body)),
+            Stmt::Return { value } => match value {
+                Some(value) => format!("(return {})", expr(*value)),
+                None => "(return)".to_string(),
+            },
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let mut out = format!("(class {}", name);
+                if let Some(superclass) = superclass {
+                    write!(out, " < {}", expr(*superclass)).unwrap();
+                }
+                out + &block(methods) + ")"
+            }
+            Stmt::Enum { name, members, .. } => format!("(enum {} {})", name, members.join(" ")),
+        }
+    })
+}
+
+/// `name(a: Type, b) -> Type` of a function statement.
+pub(crate) fn signature(ast: &Ast, function: StmtRef) -> String {
+    match &ast[function] {
+        Stmt::Function {
+            name,
+            parameters,
+            parameter_types,
+            return_type,
+            ..
+        } => {
+            let parameters: Vec<_> = parameters
+                .iter()
+                .enumerate()
+                .map(|(index, parameter)| {
+                    let parameter_type = parameter_types.get(index).and_then(Option::as_ref);
+                    format!("{}{}", parameter, annotation(": ", parameter_type))
+                })
+                .collect();
+            format!(
+                "{}({}){}",
+                name,
+                parameters.join(", "),
+                annotation(" -> ", return_type.as_ref())
+            )
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn annotation(prefix: &str, annotation: Option<&TypeAnnotation>) -> String {
+    annotation.map_or(String::new(), |annotation| {
+        format!("{}{}", prefix, annotation.name)
+    })
+}
+
+fn operator(operator: &TokenType) -> &'static str {
+    match operator {
+        TokenType::Minus => "-",
+        TokenType::Plus => "+",
+        TokenType::Slash => "/",
+        TokenType::Star => "*",
+        TokenType::Bang => "!",
+        TokenType::BangEqual => "!=",
+        TokenType::EqualEqual => "==",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        TokenType::And => "and",
+        TokenType::Or => "or",
+        TokenType::Typeof => "typeof",
+        _ => "?",
+    }
+}
+
+struct TreePrinter<'a> {
+    ast: &'a Ast,
+    out: &'a mut String,
+}
+
+impl<'a> TreePrinter<'a> {
+    fn line(&mut self, depth: usize, text: &str) {
+        writeln!(self.out, "{}{}", "  ".repeat(depth), text).unwrap();
+    }
+
+    fn stmt(&mut self, stmt: StmtRef, depth: usize) {
+        recursion::grow_stack(|| {
+            let ast = self.ast;
+            match &ast[stmt] {
+                Stmt::Expression { expression } => {
+                    self.line(depth, "Expression");
+                    self.expr(*expression, depth + 1);
+                }
+                Stmt::Print { expression } => {
+                    self.line(depth, "Print");
+                    self.expr(*expression, depth + 1);
+                }
+                Stmt::Var {
+                    name,
+                    type_annotation,
+                    initializer,
+                    constant,
+                    ..
+                } => {
+                    let keyword = if *constant { "Const" } else { "Var" };
+                    self.line(
+                        depth,
+                        &format!(
+                            "{} {}{}",
+                            keyword,
+                            name,
+                            annotation(": ", type_annotation.as_ref())
+                        ),
+                    );
+                    if let Some(initializer) = initializer {
+                        self.expr(*initializer, depth + 1);
+                    }
+                }
+                Stmt::Block { statements } => {
+                    self.line(depth, "Block");
+                    self.stmts(statements, depth + 1);
+                }
+                Stmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                } => {
+                    self.line(depth, "If");
+                    self.expr(*condition, depth + 1);
+                    self.stmt(*then_branch, depth + 1);
+                    if let Some(else_branch) = else_branch {
+                        self.line(depth, "Else");
+                        self.stmt(*else_branch, depth + 1);
+                    }
+                }
+                Stmt::While { condition, body } => {
+                    self.line(depth, "While");
+                    self.expr(*condition, depth + 1);
+                    self.stmt(*body, depth + 1);
+                }
+                Stmt::DoWhile { body, condition } => {
+                    self.line(depth, "DoWhile");
+                    self.stmt(*body, depth + 1);
+                    self.expr(*condition, depth + 1);
+                }
+                Stmt::Labeled { label, body } => {
+                    self.line(depth, &format!("Labeled {}", label));
+                    self.stmt(*body, depth + 1);
+                }
+                Stmt::Break { label: None } => self.line(depth, "Break"),
+                Stmt::Break { label: Some(label) } => self.line(depth, &format!("Break {}", label)),
+                Stmt::Switch {
+                    subject,
+                    cases,
+                    default,
+                } => {
+                    self.line(depth, "Switch");
+                    self.expr(*subject, depth + 1);
+                    for (value, body) in cases {
+                        self.line(depth, "Case");
+                        self.expr(*value, depth + 1);
+                        self.stmt(*body, depth + 1);
+                    }
+                    if let Some(default) = default {
+                        self.line(depth, "Default");
+                        self.stmt(*default, depth + 1);
+                    }
+                }
+                Stmt::Function { body, .. } => {
+                    self.line(depth, &format!("Function {}", signature(ast, stmt)));
+                    self.stmts(body, depth + 1);
+                }
+                Stmt::Return { value } => {
+                    self.line(depth, "Return");
+                    if let Some(value) = value {
+                        self.expr(*value, depth + 1);
+                    }
+                }
+                Stmt::Class {
+                    name,
+                    superclass,
+                    methods,
+                    ..
+                } => {
+                    let superclass = superclass.map_or(String::new(), |superclass| {
+                        format!(" < {}", expr_to_string(ast, superclass))
+                    });
+                    self.line(depth, &format!("Class {}{}", name, superclass));
+                    self.stmts(methods, depth + 1);
+                }
+                Stmt::Enum { name, members, .. } => {
+                    self.line(depth, &format!("Enum {}", name));
+                    for member in members {
+                        self.line(depth + 1, member);
+                    }
+                }
+            }
+        })
+    }
+
+    fn stmts(&mut self, stmts: &[StmtRef], depth: usize) {
+        for &stmt in stmts {
+            self.stmt(stmt, depth);
+        }
+    }
+
+    fn expr(&mut self, expr: ExprRef, depth: usize) {
+        recursion::grow_stack(|| {
+            let ast = self.ast;
+            let (label, children) = match &ast[expr] {
+                Expr::Number(number) => (format!("Number {}", Object::Number(*number)), vec![]),
+                Expr::String(string) => (format!("String {:?}", string), vec![]),
+                Expr::Boolean(boolean) => (format!("Boolean {}", boolean), vec![]),
+                Expr::Nil => ("Nil".to_string(), vec![]),
+                Expr::Binary {
+                    left,
+                    token_type,
+                    right,
+                } => (
+                    format!("Binary {}", operator(token_type)),
+                    vec![*left, *right],
+                ),
+                Expr::Logical {
+                    left,
+                    operator: token_type,
+                    right,
+                } => (
+                    format!("Logical {}", operator(token_type)),
+                    vec![*left, *right],
+                ),
+                Expr::Unary { token_type, right } => {
+                    (format!("Unary {}", operator(token_type)), vec![*right])
+                }
+                Expr::Grouping { expression } => ("Grouping".to_string(), vec![*expression]),
+                Expr::Call { callee, arguments } => {
+                    let mut children = vec![*callee];
+                    children.extend(arguments);
+                    ("Call".to_string(), children)
+                }
+                Expr::Get { object, name, .. } => (format!("Get {}", name), vec![*object]),
+                Expr::Set {
+                    object,
+                    name,
+                    value,
+                } => (format!("Set {}", name), vec![*object, *value]),
+                Expr::Super { method, .. } => (format!("Super {}", method), vec![]),
+                Expr::This { .. } => ("This".to_string(), vec![]),
+                Expr::Variable { name, .. } => (format!("Variable {}", name), vec![]),
+                Expr::Assign { name, value, .. } => (format!("Assign {}", name), vec![*value]),
+                Expr::List { elements } => ("List".to_string(), elements.clone()),
+                Expr::Map { entries } => (
+                    "Map".to_string(),
+                    entries
+                        .iter()
+                        .flat_map(|&(key, value)| [key, value])
+                        .collect(),
+                ),
+                Expr::Match { subject, arms } => {
+                    self.line(depth, "Match");
+                    self.expr(*subject, depth + 1);
+                    for arm in arms {
+                        match &arm.pattern {
+                            Pattern::Value(value) => {
+                                self.line(depth + 1, "Arm");
+                                self.expr(*value, depth + 2);
+                            }
+                            Pattern::Class { class, binding, .. } => {
+                                self.line(depth + 1, &format!("Arm binding {}", binding));
+                                self.expr(*class, depth + 2);
+                            }
+                            Pattern::Wildcard => self.line(depth + 1, "Arm _"),
+                        }
+                        self.expr(arm.body, depth + 2);
+                    }
+                    return;
+                }
+            };
+            self.line(depth, &label);
+            for child in children {
+                self.expr(child, depth + 1);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{print, Style};
+    use crate::lexer;
+    use crate::parser;
+
+    const SOURCE: &str = r#"
+        class B < A {
+            m(x: Number) -> Nil { return super.m(x, "s"); }
+        }
+        var b = B();
+        if (!b.f or nil) b.f = -1; else { while (false) print this; }
+    "#;
+
+    fn print_source(source: &str, style: Style) -> String {
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parser::parse(&tokens);
+        assert!(errors.is_empty());
+        print(&program, style)
+    }
+
+    #[test]
+    fn parenthesized() {
+        let expected = r#"(class B < A (fun m(x: Number) -> Nil (return (call (super m) x "s"))))
+(var b (call B))
+(if (or (! (. b f)) nil) (; (= b f (- 1))) (block (while false (print this))))
+"#;
+        assert_eq!(print_source(SOURCE, Style::Parenthesized), expected);
+        assert_eq!(
+            print_source("a = 3 / 2 >= 3;", Style::Parenthesized),
+            "(; (= a (>= (/ 3 2) 3)))\n"
+        );
+    }
+
+    #[test]
+    fn tree() {
+        let expected = r#"Class B < A
+  Function m(x: Number) -> Nil
+    Return
+      Call
+        Super m
+        Variable x
+        String "s"
+Var b
+  Call
+    Variable B
+If
+  Logical or
+    Unary !
+      Get f
+        Variable b
+    Nil
+  Expression
+    Set f
+      Variable b
+      Unary -
+        Number 1
+Else
+  Block
+    While
+      Boolean false
+      Print
+        This
+"#;
+        assert_eq!(print_source(SOURCE, Style::Tree), expected);
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let source = format!("print {}1{};", "(".repeat(5_000), ")".repeat(5_000));
+        let printed = print_source(&source, Style::Parenthesized);
+        assert_eq!(printed.matches("(group").count(), 5_000);
+        let printed = print_source(&source, Style::Tree);
+        assert_eq!(printed.lines().count(), 5_002);
+    }
+}
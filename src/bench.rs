@@ -0,0 +1,183 @@
+//! The micro-benchmarks behind `rlox bench`: a script runs a few times to warm up, then a fixed
+//! number of measured times, each in a fresh interpreter with its output discarded.
+
+use crate::error::{LoxError, Result};
+use crate::lox::Lox;
+
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// What runs the benchmarked script.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// The tree-walking interpreter.
+    TreeWalker,
+    /// The bytecode VM, which is not written yet.
+    Vm,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::TreeWalker => "tree-walker",
+            Backend::Vm => "vm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tree-walker" => Some(Backend::TreeWalker),
+            "vm" => Some(Backend::Vm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BenchOptions {
+    /// Measured runs.
+    pub runs: usize,
+    /// Runs before the measured ones, which are not counted.
+    pub warmup: usize,
+    pub backend: Backend,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            runs: 10,
+            warmup: 1,
+            backend: Backend::TreeWalker,
+        }
+    }
+}
+
+/// Wall time statistics of the measured runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub runs: usize,
+    pub mean: Duration,
+    pub median: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// The standard deviation.
+    pub deviation: Duration,
+}
+
+impl Stats {
+    /// The statistics of `times`, which must not be empty.
+    pub fn new(mut times: Vec<Duration>) -> Self {
+        times.sort();
+        let runs = times.len();
+        let seconds: Vec<_> = times.iter().map(Duration::as_secs_f64).collect();
+        let mean = seconds.iter().sum::<f64>() / runs as f64;
+        let variance = seconds
+            .iter()
+            .map(|seconds| (seconds - mean) * (seconds - mean))
+            .sum::<f64>()
+            / runs as f64;
+        let median = if runs.is_multiple_of(2) {
+            (times[runs / 2 - 1] + times[runs / 2]) / 2
+        } else {
+            times[runs / 2]
+        };
+        Self {
+            runs,
+            mean: Duration::from_secs_f64(mean),
+            median,
+            min: times[0],
+            max: times[runs - 1],
+            deviation: Duration::from_secs_f64(variance.sqrt()),
+        }
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "runs:    {}", self.runs)?;
+        writeln!(
+            f,
+            "mean:    {} ± {}",
+            milliseconds(self.mean),
+            milliseconds(self.deviation)
+        )?;
+        writeln!(f, "median:  {}", milliseconds(self.median))?;
+        write!(
+            f,
+            "range:   {} … {}",
+            milliseconds(self.min),
+            milliseconds(self.max)
+        )
+    }
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Runs `source` as configured and returns the statistics of the measured runs, or the first
+/// error of any run.
+pub fn bench(source: &str, options: BenchOptions) -> Result<Stats> {
+    if options.backend == Backend::Vm {
+        return Err(LoxError::InterpreterError(
+            "The VM backend is not available yet".into(),
+        ));
+    }
+    if options.runs == 0 {
+        return Err(LoxError::InterpreterError(
+            "At least one measured run is needed".into(),
+        ));
+    }
+    for _ in 0..options.warmup {
+        run(source)?;
+    }
+    let times = (0..options.runs)
+        .map(|_| run(source))
+        .collect::<Result<_>>()?;
+    Ok(Stats::new(times))
+}
+
+fn run(source: &str) -> Result<Duration> {
+    let mut lox = Lox::new();
+    lox.interpreter().set_output(std::io::sink());
+    let start = Instant::now();
+    lox.eval(source)?;
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{bench, Backend, BenchOptions, Stats};
+    use std::time::Duration;
+
+    #[test]
+    fn stats() {
+        let times = [4, 1, 3, 2].iter().map(|&ms| Duration::from_millis(ms));
+        let stats = Stats::new(times.collect());
+        assert_eq!(stats.runs, 4);
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.max, Duration::from_millis(4));
+        assert_eq!(stats.median, Duration::from_micros(2500));
+        assert!((stats.mean.as_secs_f64() - 0.0025).abs() < 1e-9);
+        assert!((stats.deviation.as_secs_f64() - 1.25f64.sqrt() / 1000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn runs() {
+        let options = BenchOptions {
+            runs: 3,
+            ..BenchOptions::default()
+        };
+        let stats = bench("var a = 0; while (a < 10) a = a + 1; print a;", options).unwrap();
+        assert_eq!(stats.runs, 3);
+        assert!(stats.min <= stats.median && stats.median <= stats.max);
+
+        assert!(bench("print -nil;", options).is_err());
+        let options = BenchOptions {
+            backend: Backend::Vm,
+            ..options
+        };
+        assert!(bench("print 1;", options).is_err());
+    }
+}
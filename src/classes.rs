@@ -1,31 +1,87 @@
-use crate::error::{LoxError, Result};
 use crate::functions::LoxFunction;
+use crate::gc::{Marker, INSTANCE_SIZE};
 use crate::object::Object;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::mem::size_of;
 use std::rc::Rc;
 
+/// The field layout shared by all instances of a class that got the same fields in the same
+/// order. Instances store their values in a plain vector, the shape says which index holds
+/// which field. Adding a field moves an instance on to the next shape, those transitions are
+/// remembered so the instances end up sharing shapes.
+#[derive(Debug, Default)]
+pub struct Shape {
+    indices: HashMap<Rc<str>, usize>,
+    transitions: RefCell<HashMap<Rc<str>, Rc<Shape>>>,
+}
+
+impl Shape {
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+
+    fn with_field(&self, name: &Rc<str>) -> Rc<Shape> {
+        let mut transitions = self.transitions.borrow_mut();
+        let shape = transitions.entry(Rc::clone(name)).or_insert_with(|| {
+            let mut indices = self.indices.clone();
+            indices.insert(Rc::clone(name), indices.len());
+            Rc::new(Shape {
+                indices,
+                transitions: RefCell::new(HashMap::new()),
+            })
+        });
+        Rc::clone(shape)
+    }
+}
+
 #[derive(Debug)]
 pub struct LoxClass {
-    name: String,
+    name: Rc<str>,
     superclass: Option<Rc<LoxClass>>,
-    methods: HashMap<String, Rc<LoxFunction>>,
+    methods: HashMap<Rc<str>, Rc<LoxFunction>>,
+    /// The shape of instances without fields.
+    shape: Rc<Shape>,
 }
 
 impl LoxClass {
     pub fn new(
-        name: String,
+        name: Rc<str>,
         superclass: Option<Rc<LoxClass>>,
-        methods: HashMap<String, Rc<LoxFunction>>,
+        methods: HashMap<Rc<str>, Rc<LoxFunction>>,
     ) -> Self {
         Self {
             name,
             superclass,
             methods,
+            shape: Rc::new(Shape::default()),
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn trace(&self, marker: &mut Marker) {
+        if let Some(superclass) = &self.superclass {
+            marker.mark_class(superclass);
+        }
+        self.methods
+            .values()
+            .for_each(|method| marker.mark_method(method));
+    }
+
+    /// Whether this is `class` or inherits from it.
+    pub fn is_subclass_of(&self, class: &LoxClass) -> bool {
+        std::ptr::eq(self, class)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.is_subclass_of(class))
+    }
+
     pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
         if let Some(method) = self.methods.get(name) {
             Some(Rc::clone(method))
@@ -43,42 +99,69 @@ impl Display for LoxClass {
     }
 }
 
-#[derive(Debug)]
+/// Cloning an instance copies its fields, the values in them are shared.
+#[derive(Debug, Clone)]
 pub struct LoxInstance {
     class: Rc<LoxClass>,
-    fields: HashMap<String, Rc<Object>>,
+    shape: Rc<Shape>,
+    fields: Vec<Object>,
 }
 
 impl LoxInstance {
     pub fn new(class: Rc<LoxClass>) -> Self {
         Self {
+            shape: Rc::clone(&class.shape),
             class,
-            fields: HashMap::new(),
+            fields: Vec::new(),
         }
     }
 
-    pub fn get(wrapping_object: Rc<Object>, name: &str) -> Result<Rc<Object>> {
-        if let Object::Instance(instance) = wrapping_object.clone().as_ref() {
-            if let Some(value) = instance.borrow().fields.get(name) {
-                Ok(Rc::clone(value))
-            } else if let Some(method) = instance.borrow().class.as_ref().find_method(name) {
-                Ok(Rc::new(Object::Function(Rc::new(
-                    method.bind(wrapping_object),
-                ))))
-            } else {
-                Err(LoxError::InterpreterError(
-                    format!("Undefined property {}.", name).into(),
-                ))
-            }
-        } else {
-            Err(LoxError::InterpreterError(
-                "Only instances have fields.".into(),
-            ))
-        }
+    pub fn class(&self) -> &Rc<LoxClass> {
+        &self.class
+    }
+
+    pub fn shape(&self) -> &Rc<Shape> {
+        &self.shape
+    }
+
+    /// `index` has to come from this instance's shape.
+    pub fn field_at(&self, index: usize) -> Object {
+        self.fields[index].clone()
     }
 
-    pub fn set(&mut self, name: &str, value: Rc<Object>) {
-        self.fields.insert(name.to_string(), value);
+    pub fn fields(&self) -> &[Object] {
+        &self.fields
+    }
+
+    pub fn fields_mut(&mut self) -> &mut [Object] {
+        &mut self.fields
+    }
+
+    pub fn trace(&self, marker: &mut Marker) {
+        marker.mark_class(&self.class);
+        self.fields
+            .iter()
+            .for_each(|value| marker.mark_object(value));
+    }
+
+    /// An estimate of the bytes the instance takes up, see `Heap`.
+    pub fn size(&self) -> usize {
+        INSTANCE_SIZE + self.fields.capacity() * size_of::<Object>()
+    }
+
+    /// Drops all fields, see `Heap`.
+    pub fn clear(&mut self) {
+        self.shape = Rc::clone(&self.class.shape);
+        self.fields.clear();
+    }
+
+    pub fn set(&mut self, name: &Rc<str>, value: Object) {
+        if let Some(index) = self.shape.index(name) {
+            self.fields[index] = value;
+        } else {
+            self.shape = self.shape.with_field(name);
+            self.fields.push(value);
+        }
     }
 }
 
@@ -87,3 +170,37 @@ impl Display for LoxInstance {
         write!(f, "{} instance", self.class.name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::{LoxClass, LoxInstance};
+
+    use crate::object::Object;
+
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn shapes() {
+        let class = Rc::new(LoxClass::new("Point".into(), None, HashMap::new()));
+        let (x, y) = ("x".into(), "y".into());
+
+        let mut first = LoxInstance::new(Rc::clone(&class));
+        first.set(&x, Object::Number(1.0));
+        first.set(&y, Object::Number(2.0));
+        let mut second = LoxInstance::new(Rc::clone(&class));
+        second.set(&x, Object::Number(3.0));
+        second.set(&y, Object::Number(4.0));
+        second.set(&x, Object::Number(5.0));
+        let mut reversed = LoxInstance::new(class);
+        reversed.set(&y, Object::Number(6.0));
+        reversed.set(&x, Object::Number(7.0));
+
+        assert!(Rc::ptr_eq(first.shape(), second.shape()));
+        assert!(!Rc::ptr_eq(first.shape(), reversed.shape()));
+        assert_eq!(second.field_at(0), Object::Number(5.0));
+        assert_eq!(reversed.shape().index("x"), Some(1));
+        assert_eq!(reversed.shape().index("z"), None);
+    }
+}
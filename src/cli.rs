@@ -0,0 +1,782 @@
+//! The `rlox` command line. The binary only calls `main`, everything it does is here so it can
+//! use the crate's internals.
+
+use crate::ast_printer::{self, Style};
+use crate::bench::{self, Backend, BenchOptions};
+use crate::coverage::{Coverage, Recorder};
+use crate::error::LoxError;
+use crate::interpreter::{Interpreter, InterpreterConfig, DEFAULT_MAX_CALL_DEPTH};
+use crate::minifier::{self, MinifyOptions};
+use crate::natives::Natives;
+use crate::optimizer::{OptLevel, Pipeline};
+use crate::parser::{self, ParserOptions};
+use crate::trace::Tracer;
+use crate::{debugger, doc, formatter, lexer, lint, resolver, test_runner, typechecker};
+
+use std::cell::RefCell;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Trace {
+    Off,
+    Statements,
+    /// Statements and the values stored in variables.
+    Assignments,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TypeCheck {
+    Off,
+    Warn,
+    Error,
+}
+
+struct Options {
+    parser: ParserOptions,
+    typecheck: TypeCheck,
+    opt_level: OptLevel,
+    /// Runs exactly these optimization passes instead of the ones of `opt_level`.
+    passes: Option<Vec<String>>,
+    verbose: bool,
+    max_call_depth: usize,
+    /// Aborts programs after executing this many statements and expressions.
+    max_steps: Option<u64>,
+    /// Aborts programs whose strings, instances and variables take up more bytes than this.
+    max_memory: Option<usize>,
+    /// The groups of native functions scripts may call.
+    natives: Natives,
+    /// Lets `+` join strings and numbers.
+    coerce_strings: bool,
+    /// Rounds printed numbers to this many decimal places.
+    precision: Option<usize>,
+    /// Prints the syntax tree in this style instead of running the program.
+    dump_ast: Option<Style>,
+    /// Prints where the resolver put each variable instead of running the program.
+    dump_scopes: bool,
+    /// Writes a coverage report of the file that runs.
+    coverage: Option<CoverageReport>,
+    /// Prints how often each function was called and how long that took.
+    profile: bool,
+    /// Prints what the program allocated and called.
+    stats: bool,
+    /// Prints the statements to stderr as they run.
+    trace: Trace,
+}
+
+#[derive(Clone, Copy)]
+enum CoverageReport {
+    /// To `lcov.info`.
+    Lcov,
+    /// To `coverage.html`.
+    Html,
+}
+
+fn new_interpreter(options: &Options) -> Interpreter {
+    let config = InterpreterConfig::default()
+        .with_max_call_depth(options.max_call_depth)
+        .with_max_steps(options.max_steps)
+        .with_max_memory(options.max_memory)
+        .with_natives(options.natives)
+        .with_string_coercion(options.coerce_strings)
+        .with_print_precision(options.precision);
+    let mut interpreter = Interpreter::with_config(config);
+    if options.profile {
+        interpreter.enable_profiler();
+    }
+    interpreter
+}
+
+fn run_prompt(options: &Options) {
+    let mut interpreter = new_interpreter(options);
+    loop {
+        print!("> ");
+        io::stdout().flush().expect("Could not write to stdout");
+        let mut buffer = String::new();
+        match io::stdin().read_line(&mut buffer) {
+            Ok(_) => run(&mut interpreter, &buffer, None, options),
+            Err(error) => eprintln!("error reading line: {}", error),
+        }
+    }
+}
+
+fn run_file(filename: &str, options: &Options) {
+    let mut interpreter = new_interpreter(options);
+    let code = match interpreter.file_system().read_to_string(filename) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Could not read file '{}': {}", filename, error);
+            std::process::exit(66);
+        }
+    };
+    run(&mut interpreter, &code, Some(filename), options);
+}
+
+fn pipeline(options: &Options) -> Pipeline {
+    match &options.passes {
+        Some(names) => Pipeline::with_passes(names).unwrap_or_else(|name| {
+            eprintln!("Unknown optimization pass '{}'.", name);
+            std::process::exit(64);
+        }),
+        None => Pipeline::for_level(options.opt_level),
+    }
+}
+
+/// Runs `code`, read from `filename` unless it was typed at the prompt.
+fn run(interpreter: &mut Interpreter, code: &str, filename: Option<&str>, options: &Options) {
+    let (tokens, lexer_errors) = lexer::lex(code);
+    print_errors(&lexer_errors);
+
+    let parser_options = ParserOptions {
+        first_id: interpreter.next_expression_id(),
+        optional_semicolons: options.parser.optional_semicolons || filename.is_none(),
+        ..options.parser
+    };
+    let (program, parser_errors) = parser::parse_with_options(&tokens, parser_options);
+    print_errors(&parser_errors);
+
+    if !lexer_errors.is_empty() || !parser_errors.is_empty() {
+        std::process::exit(64);
+    }
+
+    let mut pipeline = pipeline(options);
+    let program = pipeline.run(program);
+    if options.verbose {
+        for line in pipeline.report() {
+            eprintln!("note: {}", line);
+        }
+    }
+
+    let scopes = match resolver::resolve(&program) {
+        Ok(scopes) => scopes,
+        Err(error) => {
+            print_errors(&[error]);
+            std::process::exit(64);
+        }
+    };
+    if options.dump_scopes {
+        print!("{}", resolver::dump(&program, &scopes));
+        return;
+    }
+
+    if options.typecheck != TypeCheck::Off {
+        let globals = interpreter.globals();
+        let globals: Vec<_> = globals.iter().map(|(name, _)| &**name).collect();
+        let type_errors = typechecker::check(&program, code, options.natives, &globals);
+        if options.typecheck == TypeCheck::Error {
+            print_errors(&type_errors);
+            if !type_errors.is_empty() {
+                std::process::exit(64);
+            }
+        } else {
+            for error in type_errors {
+                eprintln!("warning: {}", error);
+            }
+        }
+    }
+    interpreter.add_scopes(scopes);
+
+    if let Some(style) = options.dump_ast {
+        print!("{}", ast_printer::print(&program, style));
+        return;
+    }
+
+    if options.trace != Trace::Off {
+        let assignments = options.trace == Trace::Assignments;
+        interpreter.set_debugger(Tracer::new(code, assignments, io::stderr()));
+    }
+    let coverage = match (options.coverage, filename) {
+        (Some(report), Some(filename)) => {
+            let coverage = Rc::new(RefCell::new(Coverage::new(&program)));
+            interpreter.add_debugger(Recorder(Rc::clone(&coverage)));
+            Some((report, filename, coverage))
+        }
+        _ => None,
+    };
+    let result = interpreter.interpret(program);
+    if let Some((report, filename, coverage)) = coverage {
+        write_coverage(&coverage.borrow(), report, filename, code);
+    }
+    if let (Some(profiler), Some(_)) = (interpreter.profiler(), filename) {
+        eprintln!("{}", profiler);
+    }
+    if let (true, Some(_)) = (options.stats, filename) {
+        eprintln!("{}", interpreter.stats());
+    }
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        print_stack_trace(interpreter.stack_trace());
+        std::process::exit(70);
+    }
+}
+
+fn write_coverage(coverage: &Coverage, report: CoverageReport, filename: &str, code: &str) {
+    let (path, contents) = match report {
+        CoverageReport::Lcov => ("lcov.info", coverage.lcov(filename)),
+        CoverageReport::Html => ("coverage.html", coverage.html(filename, code)),
+    };
+    match std::fs::write(path, contents) {
+        Ok(()) => eprintln!("note: wrote coverage report to {}", path),
+        Err(error) => eprintln!("Could not write coverage report '{}': {}", path, error),
+    }
+}
+
+/// Prints the calls active at a runtime error, folding runs of the same call (as in deep
+/// recursion) into a single line.
+fn print_stack_trace(trace: &[String]) {
+    let mut frames = trace.iter().peekable();
+    while let Some(frame) = frames.next() {
+        let mut repeated = 1;
+        while frames.next_if_eq(&frame).is_some() {
+            repeated += 1;
+        }
+        if repeated == 1 {
+            eprintln!("  in {}", frame);
+        } else {
+            eprintln!("  in {} ({} times)", frame, repeated);
+        }
+    }
+}
+
+fn lint_files(filenames: &[String]) {
+    let mut found_problems = false;
+    for filename in filenames {
+        let code = match std::fs::read_to_string(filename) {
+            Ok(code) => code,
+            Err(error) => {
+                eprintln!("{}: could not read file: {}", filename, error);
+                found_problems = true;
+                continue;
+            }
+        };
+
+        let (tokens, lexer_errors) = lexer::lex(&code);
+        let (program, parser_errors) = parser::parse(&tokens);
+        let mut errors: Vec<_> = lexer_errors.into_iter().chain(parser_errors).collect();
+        if errors.is_empty() {
+            if let Err(error) = resolver::resolve(&program) {
+                errors.push(error);
+            }
+        }
+        for error in &errors {
+            println!("{}: {}", filename, error);
+        }
+
+        let lints = lint::lint(&program);
+        for lint in &lints {
+            match lint.span {
+                Some(span) => {
+                    let (line, column) = span.position(&code);
+                    println!("{}:{}:{}: {}", filename, line, column, lint);
+                }
+                None => println!("{}: {}", filename, lint),
+            }
+        }
+        found_problems |= !errors.is_empty() || !lints.is_empty();
+    }
+
+    if found_problems {
+        std::process::exit(1);
+    }
+}
+
+/// Formats the files in place, or with `check` only reports the ones that are not formatted.
+/// Without files, formats standard input to standard output.
+fn format_files(filenames: &[String], check: bool) {
+    if filenames.is_empty() {
+        let mut code = String::new();
+        if let Err(error) = io::stdin().read_to_string(&mut code) {
+            eprintln!("error reading standard input: {}", error);
+            std::process::exit(74);
+        }
+        match formatter::format(&code) {
+            Ok(formatted) => print!("{}", formatted),
+            Err(errors) => {
+                print_errors(&errors);
+                std::process::exit(65);
+            }
+        }
+        return;
+    }
+
+    let mut found_problems = false;
+    for filename in filenames {
+        let code = match std::fs::read_to_string(filename) {
+            Ok(code) => code,
+            Err(error) => {
+                eprintln!("{}: could not read file: {}", filename, error);
+                found_problems = true;
+                continue;
+            }
+        };
+        match formatter::format(&code) {
+            Ok(formatted) if formatted == code => {}
+            Ok(_) if check => {
+                println!("{}: not formatted", filename);
+                found_problems = true;
+            }
+            Ok(formatted) => {
+                if let Err(error) = std::fs::write(filename, formatted) {
+                    eprintln!("{}: could not write file: {}", filename, error);
+                    found_problems = true;
+                }
+            }
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("{}: {}", filename, error);
+                }
+                found_problems = true;
+            }
+        }
+    }
+
+    if found_problems {
+        std::process::exit(1);
+    }
+}
+
+/// The `.lox` files at `path`: the file itself, or those in the directory and below.
+fn lox_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<_> = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir()
+            || entry
+                .extension()
+                .is_some_and(|extension| extension == "lox")
+        {
+            lox_files(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the documentation of the files and directories, as Markdown or HTML.
+fn document(paths: &[String], as_html: bool) {
+    let mut files = vec![];
+    for path in paths {
+        if let Err(error) = lox_files(Path::new(path), &mut files) {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(66);
+        }
+    }
+
+    let mut modules = vec![];
+    for file in files {
+        let name = file.display().to_string();
+        let code = std::fs::read_to_string(&file).unwrap_or_else(|error| {
+            eprintln!("{}: could not read file: {}", name, error);
+            std::process::exit(66);
+        });
+        let (tokens, lexer_errors) = lexer::lex(&code);
+        let (mut program, parser_errors) = parser::parse(&tokens);
+        if !lexer_errors.is_empty() || !parser_errors.is_empty() {
+            for error in lexer_errors.iter().chain(&parser_errors) {
+                eprintln!("{}: {}", name, error);
+            }
+            std::process::exit(65);
+        }
+        doc::attach(&code, &mut program);
+        modules.push((name, doc::items(&program)));
+    }
+
+    if as_html {
+        print!("{}", doc::html(&modules));
+    } else {
+        print!("{}", doc::markdown(&modules));
+    }
+}
+
+/// Runs the test scripts among the files and directories and prints the failures and a
+/// summary. Exits with 1 if any failed.
+fn run_tests(paths: &[String]) {
+    let mut files = vec![];
+    for path in paths {
+        if let Err(error) = lox_files(Path::new(path), &mut files) {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(66);
+        }
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        let failures = match std::fs::read_to_string(file) {
+            Ok(code) => test_runner::check(&code),
+            Err(error) => vec![format!("Could not read file: {}", error)],
+        };
+        if !failures.is_empty() {
+            failed += 1;
+            println!("FAIL {}", file.display());
+            for failure in failures {
+                println!("     {}", failure);
+            }
+        }
+    }
+    println!("{} passed, {} failed", files.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn bench_usage() -> ! {
+    eprintln!("Usage: rlox bench [--runs=N] [--warmup=N] [--backend=tree-walker|vm]... <file>");
+    std::process::exit(64);
+}
+
+/// Benchmarks the file on each backend in turn and prints the statistics.
+fn bench_file(filename: &str, options: BenchOptions, backends: &[Backend]) {
+    let code = std::fs::read_to_string(filename).unwrap_or_else(|error| {
+        eprintln!("{}: could not read file: {}", filename, error);
+        std::process::exit(66);
+    });
+    let mut failed = false;
+    for (index, &backend) in backends.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("{} ({} warmup)", backend.name(), options.warmup);
+        match bench::bench(&code, BenchOptions { backend, ..options }) {
+            Ok(stats) => println!("{}", stats),
+            Err(error) => {
+                eprintln!("{}", error);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(70);
+    }
+}
+
+/// Runs the file under the interactive debugger, which talks on stdin and stdout.
+fn debug_file(filename: &str) {
+    let code = std::fs::read_to_string(filename).unwrap_or_else(|error| {
+        eprintln!("{}: could not read file: {}", filename, error);
+        std::process::exit(66);
+    });
+    let (tokens, lexer_errors) = lexer::lex(&code);
+    let (program, parser_errors) = parser::parse(&tokens);
+    if !lexer_errors.is_empty() || !parser_errors.is_empty() {
+        print_errors(&lexer_errors);
+        print_errors(&parser_errors);
+        std::process::exit(65);
+    }
+    let mut interpreter = Interpreter::new();
+    let input = io::BufReader::new(io::stdin());
+    match debugger::run(
+        &mut interpreter,
+        program,
+        &code,
+        filename,
+        input,
+        io::stdout(),
+    ) {
+        Ok(_) => println!("The program finished."),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(70);
+        }
+    }
+}
+
+/// Prints the minified file, or standard input if there is no file.
+fn minify_file(filename: Option<&String>, options: MinifyOptions) {
+    let code = match filename {
+        Some(filename) => std::fs::read_to_string(filename),
+        None => {
+            let mut code = String::new();
+            io::stdin().read_to_string(&mut code).map(|_| code)
+        }
+    };
+    let code = code.unwrap_or_else(|error| {
+        eprintln!("Could not read input: {}", error);
+        std::process::exit(66);
+    });
+    match minifier::minify(&code, options) {
+        Ok(minified) => println!("{}", minified),
+        Err(errors) => {
+            print_errors(&errors);
+            std::process::exit(65);
+        }
+    }
+}
+
+#[cfg(feature = "lsp")]
+fn serve_lsp() {
+    let stdin = io::stdin();
+    if let Err(error) = crate::lsp::serve(stdin.lock(), io::stdout()) {
+        eprintln!("Language server stopped: {}", error);
+        std::process::exit(74);
+    }
+}
+
+#[cfg(not(feature = "lsp"))]
+fn serve_lsp() {
+    eprintln!("This rlox was built without the 'lsp' feature.");
+    std::process::exit(64);
+}
+
+#[cfg(feature = "dap")]
+fn serve_dap() {
+    if let Err(error) = crate::dap::serve(io::BufReader::new(io::stdin()), io::stdout()) {
+        eprintln!("Debug adapter stopped: {}", error);
+        std::process::exit(74);
+    }
+}
+
+#[cfg(not(feature = "dap"))]
+fn serve_dap() {
+    eprintln!("This rlox was built without the 'dap' feature.");
+    std::process::exit(64);
+}
+
+#[cfg(feature = "kernel")]
+fn serve_kernel(connection_file: Option<String>) {
+    let connection_file = connection_file.unwrap_or_else(|| {
+        eprintln!("Usage: rlox kernel <connection file>");
+        std::process::exit(64);
+    });
+    if let Err(error) = crate::kernel::serve(&connection_file) {
+        eprintln!("Kernel stopped: {}", error);
+        std::process::exit(74);
+    }
+}
+
+#[cfg(not(feature = "kernel"))]
+fn serve_kernel(_connection_file: Option<String>) {
+    eprintln!("This rlox was built without the 'kernel' feature.");
+    std::process::exit(64);
+}
+
+fn print_errors(errors: &[LoxError]) {
+    for error in errors {
+        eprintln!("{}", error);
+    }
+}
+
+pub fn main() {
+    let mut options = Options {
+        parser: ParserOptions::default(),
+        typecheck: TypeCheck::Off,
+        opt_level: OptLevel::O0,
+        passes: None,
+        verbose: false,
+        max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        max_steps: None,
+        max_memory: None,
+        natives: Natives::ALL,
+        coerce_strings: false,
+        precision: None,
+        dump_ast: None,
+        dump_scopes: false,
+        coverage: None,
+        profile: false,
+        stats: false,
+        trace: Trace::Off,
+    };
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("lsp") {
+        serve_lsp();
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("dap") {
+        serve_dap();
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("kernel") {
+        serve_kernel(args.nth(1));
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("fmt") {
+        let args: Vec<_> = args.skip(1).collect();
+        let check = args.iter().any(|arg| arg == "--check");
+        let files: Vec<_> = args.into_iter().filter(|arg| arg != "--check").collect();
+        format_files(&files, check);
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("minify") {
+        let args: Vec<_> = args.skip(1).collect();
+        let options = MinifyOptions {
+            rename_locals: args.iter().any(|arg| arg == "--rename-locals"),
+        };
+        let files: Vec<_> = args
+            .iter()
+            .filter(|arg| *arg != "--rename-locals")
+            .collect();
+        if files.len() > 1 {
+            eprintln!("Usage: rlox minify [--rename-locals] [file]");
+            std::process::exit(64);
+        }
+        minify_file(files.first().copied(), options);
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("doc") {
+        let args: Vec<_> = args.skip(1).collect();
+        let as_html = args.iter().any(|arg| arg == "--html");
+        let paths: Vec<_> = args.into_iter().filter(|arg| arg != "--html").collect();
+        if paths.is_empty() {
+            eprintln!("Usage: rlox doc [--html] <file or directory>...");
+            std::process::exit(64);
+        }
+        document(&paths, as_html);
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("test") {
+        let paths: Vec<_> = args.skip(1).collect();
+        if paths.is_empty() {
+            eprintln!("Usage: rlox test <file or directory>...");
+            std::process::exit(64);
+        }
+        run_tests(&paths);
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("bench") {
+        let mut options = BenchOptions::default();
+        let mut backends = vec![];
+        let mut files = vec![];
+        for arg in args.skip(1) {
+            if let Some(runs) = arg.strip_prefix("--runs=") {
+                options.runs = runs.parse().unwrap_or_else(|_| bench_usage());
+            } else if let Some(warmup) = arg.strip_prefix("--warmup=") {
+                options.warmup = warmup.parse().unwrap_or_else(|_| bench_usage());
+            } else if let Some(name) = arg.strip_prefix("--backend=") {
+                backends.push(Backend::from_name(name).unwrap_or_else(|| bench_usage()));
+            } else {
+                files.push(arg);
+            }
+        }
+        if files.len() != 1 {
+            bench_usage();
+        }
+        if backends.is_empty() {
+            backends.push(options.backend);
+        }
+        bench_file(&files[0], options, &backends);
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("debug") {
+        match args.nth(1) {
+            Some(filename) => debug_file(&filename),
+            None => {
+                eprintln!("Usage: rlox debug <file>");
+                std::process::exit(64);
+            }
+        }
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("lint") {
+        let files: Vec<_> = args.skip(1).collect();
+        if files.is_empty() {
+            eprintln!("Usage: rlox lint <file>...");
+            std::process::exit(64);
+        }
+        lint_files(&files);
+        return;
+    }
+
+    let mut files = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--ignore-types" => options.parser.ignore_type_annotations = true,
+            "--relaxed" => options.parser.optional_semicolons = true,
+            "--typecheck" | "--typecheck=warn" => options.typecheck = TypeCheck::Warn,
+            "--typecheck=error" => options.typecheck = TypeCheck::Error,
+            "-v" | "--verbose" => options.verbose = true,
+            "--profile" => options.profile = true,
+            "--stats" => options.stats = true,
+            "--trace" => options.trace = Trace::Statements,
+            "--trace=assignments" => options.trace = Trace::Assignments,
+            "--dump-ast" | "--dump-ast=tree" => options.dump_ast = Some(Style::Tree),
+            "--dump-ast=parens" => options.dump_ast = Some(Style::Parenthesized),
+            "--dump-scopes" => options.dump_scopes = true,
+            "--coverage" | "--coverage=lcov" => options.coverage = Some(CoverageReport::Lcov),
+            "--coverage=html" => options.coverage = Some(CoverageReport::Html),
+            "-O0" => options.opt_level = OptLevel::O0,
+            "-O1" | "--optimize" => options.opt_level = OptLevel::O1,
+            flag if flag.starts_with("--passes=") => {
+                let names = flag["--passes=".len()..].split(',');
+                options.passes = Some(
+                    names
+                        .filter(|name| !name.is_empty())
+                        .map(String::from)
+                        .collect(),
+                );
+            }
+            flag if flag.starts_with("--max-call-depth=") => {
+                match flag["--max-call-depth=".len()..].parse() {
+                    Ok(depth) => options.max_call_depth = depth,
+                    Err(_) => {
+                        eprintln!("Invalid value for '--max-call-depth': expected a number.");
+                        std::process::exit(64);
+                    }
+                }
+            }
+            flag if flag.starts_with("--max-steps=") => {
+                match flag["--max-steps=".len()..].parse() {
+                    Ok(steps) => options.max_steps = Some(steps),
+                    Err(_) => {
+                        eprintln!("Invalid value for '--max-steps': expected a number.");
+                        std::process::exit(64);
+                    }
+                }
+            }
+            "--sandbox" => options.natives = Natives::SANDBOX,
+            "--coerce-strings" => options.coerce_strings = true,
+            flag if flag.starts_with("--precision=") => {
+                match flag["--precision=".len()..].parse() {
+                    Ok(precision) => options.precision = Some(precision),
+                    Err(_) => {
+                        eprintln!("Invalid value for '--precision': expected a number.");
+                        std::process::exit(64);
+                    }
+                }
+            }
+            flag if flag.starts_with("--natives=") => {
+                match Natives::from_names(&flag["--natives=".len()..]) {
+                    Ok(natives) => options.natives = natives,
+                    Err(name) => {
+                        eprintln!("Unknown group of natives '{}'.", name);
+                        std::process::exit(64);
+                    }
+                }
+            }
+            flag if flag.starts_with("--max-memory=") => {
+                match flag["--max-memory=".len()..].parse() {
+                    Ok(bytes) => options.max_memory = Some(bytes),
+                    Err(_) => {
+                        eprintln!("Invalid value for '--max-memory': expected a number of bytes.");
+                        std::process::exit(64);
+                    }
+                }
+            }
+            flag if flag.starts_with("-O") => {
+                eprintln!("Unknown optimization level '{}'.", flag);
+                std::process::exit(64);
+            }
+            flag if flag.starts_with("--") => {
+                eprintln!("Unknown option '{}'.", flag);
+                std::process::exit(64);
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    // report unknown passes before running anything
+    pipeline(&options);
+
+    if files.is_empty() {
+        run_prompt(&options);
+    } else if files.len() == 1 {
+        run_file(&files[0], &options);
+    } else {
+        eprintln!("Unexpected number of arguments. Expected none (interactive) or one(file).");
+    }
+}
@@ -0,0 +1,227 @@
+use crate::error::{LoxError, Result};
+use crate::object::Object;
+use crate::userdata::Userdata;
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+/// Rust values that can be handed to Lox code. Implemented for everything convertible into an
+/// [`Object`].
+pub trait ToLox {
+    fn to_lox(self) -> Object;
+}
+
+impl<T: Into<Object>> ToLox for T {
+    fn to_lox(self) -> Object {
+        self.into()
+    }
+}
+
+/// Rust values that can be taken from Lox values, failing if the value has the wrong type.
+pub trait FromLox: Sized {
+    fn from_lox(object: Object) -> Result<Self>;
+}
+
+fn expected(kind: &str, object: &Object) -> LoxError {
+    LoxError::InterpreterError(format!("Expected {}, but got '{}'", kind, object).into())
+}
+
+impl From<f64> for Object {
+    fn from(number: f64) -> Self {
+        Object::Number(number)
+    }
+}
+
+impl From<bool> for Object {
+    fn from(boolean: bool) -> Self {
+        Object::Boolean(boolean)
+    }
+}
+
+impl From<&str> for Object {
+    fn from(string: &str) -> Self {
+        Object::String(string.into())
+    }
+}
+
+impl From<String> for Object {
+    fn from(string: String) -> Self {
+        Object::String(string.into())
+    }
+}
+
+impl From<Rc<str>> for Object {
+    fn from(string: Rc<str>) -> Self {
+        Object::String(string)
+    }
+}
+
+impl From<()> for Object {
+    fn from(_: ()) -> Self {
+        Object::Nil
+    }
+}
+
+impl From<Userdata> for Object {
+    fn from(userdata: Userdata) -> Self {
+        Object::Userdata(userdata)
+    }
+}
+
+/// `None` becomes nil.
+impl<T: Into<Object>> From<Option<T>> for Object {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Object::Nil, Into::into)
+    }
+}
+
+/// The list is left to reference counting: unlike the lists Lox code creates, the garbage
+/// collector does not know about it, so it cannot free cycles running through it.
+impl<T: Into<Object>> From<Vec<T>> for Object {
+    fn from(elements: Vec<T>) -> Self {
+        let elements = elements.into_iter().map(Into::into).collect();
+        Object::List(Rc::new(RefCell::new(elements)))
+    }
+}
+
+impl FromLox for Object {
+    fn from_lox(object: Object) -> Result<Self> {
+        Ok(object)
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(object: Object) -> Result<Self> {
+        match object {
+            Object::Number(number) => Ok(number),
+            _ => Err(expected("a number", &object)),
+        }
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(object: Object) -> Result<Self> {
+        match object {
+            Object::Boolean(boolean) => Ok(boolean),
+            _ => Err(expected("a boolean", &object)),
+        }
+    }
+}
+
+impl FromLox for Rc<str> {
+    fn from_lox(object: Object) -> Result<Self> {
+        match object {
+            Object::String(string) => Ok(string),
+            _ => Err(expected("a string", &object)),
+        }
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(object: Object) -> Result<Self> {
+        Rc::<str>::from_lox(object).map(|string| string.to_string())
+    }
+}
+
+impl FromLox for Userdata {
+    fn from_lox(object: Object) -> Result<Self> {
+        match object {
+            Object::Userdata(userdata) => Ok(userdata),
+            _ => Err(expected("userdata", &object)),
+        }
+    }
+}
+
+/// Nil becomes `None`.
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(object: Object) -> Result<Self> {
+        match object {
+            Object::Nil => Ok(None),
+            _ => T::from_lox(object).map(Some),
+        }
+    }
+}
+
+/// Converts each element, failing on the first one that has the wrong type.
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(object: Object) -> Result<Self> {
+        match object {
+            Object::List(list) => list.borrow().iter().cloned().map(T::from_lox).collect(),
+            _ => Err(expected("a list", &object)),
+        }
+    }
+}
+
+macro_rules! try_from_object {
+    ($($target:ty),*) => {
+        $(
+            impl TryFrom<Object> for $target {
+                type Error = LoxError;
+
+                fn try_from(object: Object) -> Result<Self> {
+                    Self::from_lox(object)
+                }
+            }
+        )*
+    };
+}
+
+try_from_object!(f64, bool, String, Rc<str>, Userdata);
+
+#[cfg(test)]
+mod tests {
+
+    use super::{FromLox, ToLox};
+
+    use crate::error::LoxError;
+    use crate::object::Object;
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn to_lox() {
+        assert_eq!(1.5.to_lox(), Object::Number(1.5));
+        assert_eq!(true.to_lox(), Object::Boolean(true));
+        assert_eq!("lox".to_lox(), Object::String("lox".into()));
+        assert_eq!(String::from("lox").to_lox(), Object::String("lox".into()));
+        assert_eq!(Some(2.0).to_lox(), Object::Number(2.0));
+        assert_eq!(None::<f64>.to_lox(), Object::Nil);
+        assert_eq!(Object::from(()), Object::Nil);
+        assert_eq!(vec![1.0, 2.0].to_lox().to_string(), "[1, 2]",);
+    }
+
+    #[test]
+    fn from_lox() {
+        assert_eq!(f64::try_from(Object::Number(1.5)), Ok(1.5));
+        assert_eq!(bool::from_lox(Object::Boolean(false)), Ok(false));
+        assert_eq!(
+            String::try_from(Object::String("lox".into())),
+            Ok(String::from("lox"))
+        );
+        assert_eq!(Option::<f64>::from_lox(Object::Nil), Ok(None));
+        assert_eq!(Option::<f64>::from_lox(Object::Number(2.0)), Ok(Some(2.0)));
+        assert_eq!(
+            f64::from_lox(Object::String("1".into())),
+            Err(LoxError::InterpreterError(
+                "Expected a number, but got '1'".into()
+            ))
+        );
+        assert_eq!(
+            Vec::<f64>::from_lox(vec![1.0, 2.0].to_lox()),
+            Ok(vec![1.0, 2.0])
+        );
+        assert_eq!(
+            Vec::<f64>::from_lox(vec![Object::Number(1.0), Object::Nil].to_lox()),
+            Err(LoxError::InterpreterError(
+                "Expected a number, but got 'nil'".into()
+            ))
+        );
+        assert_eq!(
+            Vec::<f64>::from_lox(Object::Number(1.0)),
+            Err(LoxError::InterpreterError(
+                "Expected a list, but got '1'".into()
+            ))
+        );
+    }
+}
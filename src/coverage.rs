@@ -0,0 +1,211 @@
+//! Statement coverage, behind `--coverage`. A [`Recorder`] installed as the interpreter's
+//! debugger counts how often the statements of each line run, and [`Coverage`] writes the counts
+//! as an lcov tracefile or an HTML page.
+
+use crate::debug::Debugger;
+use crate::error::Result;
+use crate::interpreter::Interpreter;
+use crate::statement::{Ast, Program, Stmt, StmtRef};
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// How often the statements of each line ran.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Coverage {
+    hits: BTreeMap<u32, u64>,
+}
+
+impl Coverage {
+    /// No runs yet of the lines that have statements in `program`.
+    pub fn new(program: &Program) -> Self {
+        let mut coverage = Self::default();
+        coverage.add_lines(&program.ast, &program.statements);
+        coverage
+    }
+
+    /// Blocks only hold statements and methods are declared by their class, so neither runs on
+    /// its own.
+    fn add_lines(&mut self, ast: &Ast, stmts: &[StmtRef]) {
+        for &stmt in stmts {
+            if !matches!(ast[stmt], Stmt::Block { .. }) {
+                if let Some(line) = ast.line(stmt) {
+                    self.hits.entry(line).or_insert(0);
+                }
+            }
+            match &ast[stmt] {
+                Stmt::Block { statements } => self.add_lines(ast, statements),
+                Stmt::If {
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.add_lines(ast, &[*then_branch]);
+                    self.add_lines(ast, else_branch.as_slice());
+                }
+                Stmt::While { body, .. }
+                | Stmt::DoWhile { body, .. }
+                | Stmt::Labeled { body, .. } => self.add_lines(ast, &[*body]),
+                Stmt::Switch { cases, default, .. } => {
+                    let bodies: Vec<_> = cases
+                        .iter()
+                        .map(|(_, body)| *body)
+                        .chain(*default)
+                        .collect();
+                    self.add_lines(ast, &bodies);
+                }
+                Stmt::Function { body, .. } => self.add_lines(ast, body),
+                Stmt::Class { methods, .. } => {
+                    for method in methods {
+                        if let Stmt::Function { body, .. } = &ast[*method] {
+                            self.add_lines(ast, body);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn hit(&mut self, line: u32) {
+        *self.hits.entry(line).or_insert(0) += 1;
+    }
+
+    /// An lcov tracefile for the source file at `path`.
+    pub fn lcov(&self, path: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", path);
+        for (line, hits) in &self.hits {
+            writeln!(out, "DA:{},{}", line, hits).unwrap();
+        }
+        let hit = self.hits.values().filter(|hits| **hits > 0).count();
+        writeln!(out, "LF:{}\nLH:{}\nend_of_record", self.hits.len(), hit).unwrap();
+        out
+    }
+
+    /// An HTML page showing `source`, the file at `path`, with the lines that ran in green and
+    /// the lines that did not in red.
+    pub fn html(&self, path: &str, source: &str) -> String {
+        let hit = self.hits.values().filter(|hits| **hits > 0).count();
+        let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        writeln!(out, "<title>Coverage of {}</title>", escape(path)).unwrap();
+        out.push_str(
+            "<style>\n\
+             pre { margin: 0; }\n\
+             .hit { background: #dfd; }\n\
+             .missed { background: #fdd; }\n\
+             .count { display: inline-block; width: 5em; color: #888; text-align: right; }\n\
+             </style>\n</head>\n<body>\n",
+        );
+        writeln!(out, "<h1>{}</h1>", escape(path)).unwrap();
+        writeln!(
+            out,
+            "<p>{} of {} lines ran ({:.1}%)</p>",
+            hit,
+            self.hits.len(),
+            percentage(hit, self.hits.len())
+        )
+        .unwrap();
+        for (index, text) in source.lines().enumerate() {
+            let line = index as u32 + 1;
+            let (class, count) = match self.hits.get(&line) {
+                Some(0) => (" class=\"missed\"", "0".to_string()),
+                Some(hits) => (" class=\"hit\"", hits.to_string()),
+                None => ("", String::new()),
+            };
+            writeln!(
+                out,
+                "<pre{}><span class=\"count\">{}</span> {:>4}  {}</pre>",
+                class,
+                count,
+                line,
+                escape(text)
+            )
+            .unwrap();
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+fn percentage(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Counts the statements the interpreter runs into a shared [`Coverage`].
+pub struct Recorder(pub Rc<RefCell<Coverage>>);
+
+impl Debugger for Recorder {
+    fn statement(&mut self, _interpreter: &mut Interpreter, line: u32) -> Result<()> {
+        self.0.borrow_mut().hit(line);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Coverage, Recorder};
+    use crate::interpreter::Interpreter;
+    use crate::{lexer, parser, resolver};
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const SOURCE: &str = "fun twice(x) {\n  return x * 2;\n}\nclass A {\n  unused() {\n    print 0;\n  }\n}\nfor (var i = 0; i < 3; i = i + 1) {\n  twice(i);\n}\nif (false) {\n  print 1;\n}\n";
+
+    fn covered() -> Coverage {
+        let (tokens, _) = lexer::lex(SOURCE);
+        let (program, _) = parser::parse(&tokens);
+        let coverage = Rc::new(RefCell::new(Coverage::new(&program)));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Recorder(Rc::clone(&coverage)));
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        interpreter.interpret(program).unwrap();
+        let coverage = coverage.borrow().clone();
+        coverage
+    }
+
+    #[test]
+    fn counted() {
+        let coverage = covered();
+        let hits = &coverage.hits;
+        assert_eq!(hits[&1], 1);
+        assert_eq!(hits[&2], 3);
+        assert_eq!(hits[&4], 1);
+        assert_eq!(hits[&6], 0);
+        // the initializer, the loop and three increments
+        assert_eq!(hits[&9], 5);
+        assert_eq!(hits[&10], 3);
+        assert_eq!(hits[&12], 1);
+        assert_eq!(hits[&13], 0);
+        assert!(!hits.contains_key(&3));
+        assert!(!hits.contains_key(&5));
+    }
+
+    #[test]
+    fn reports() {
+        let coverage = covered();
+        let lcov = coverage.lcov("test.lox");
+        assert!(lcov.starts_with("TN:\nSF:test.lox\nDA:1,1\nDA:2,3\n"));
+        assert!(lcov.contains("DA:13,0\n"));
+        assert!(lcov.ends_with("LF:8\nLH:6\nend_of_record\n"));
+
+        let html = coverage.html("test.lox", SOURCE);
+        assert!(html.contains(
+            "<pre class=\"missed\"><span class=\"count\">0</span>   13    print 1;</pre>"
+        ));
+        assert!(html.contains("<pre><span class=\"count\"></span>    3  }</pre>"));
+    }
+}
@@ -0,0 +1,1224 @@
+//! A lossless concrete syntax tree. Unlike the [`Program`] built by the parser it keeps every
+//! character of the source: whitespace, comments and text the lexer rejected are tokens of their
+//! own, and every token knows its byte span. Concatenating the tokens of a tree gives back the
+//! exact source, which is what the formatter and other tools rewriting code need.
+//!
+//! Trivia (whitespace and comments) belongs to the node of the token following it; trivia at the
+//! end of the file belongs to the [`NodeKind::Program`]. The tree is built even for broken code,
+//! with tokens that fit nowhere wrapped in [`NodeKind::Error`] nodes.
+//!
+//! ```
+//! let cst = rlox::cst::parse("var answer = 42; // the answer\n");
+//! assert!(cst.errors.is_empty());
+//! assert_eq!(cst.root.text(), "var answer = 42; // the answer\n");
+//!
+//! let (program, errors) = cst.lower();
+//! assert!(errors.is_empty());
+//! assert_eq!(program.statements.len(), 1);
+//! ```
+
+use crate::error::LoxError;
+use crate::lexer::{self, LexerOptions};
+use crate::parser;
+use crate::recursion::{self, MAX_SYNTAX_DEPTH};
+use crate::statement::Program;
+use crate::token::{Span, Token, TokenType};
+
+use std::iter;
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// Spaces, tabs and line breaks.
+    Whitespace,
+    /// A `//` comment, without the line break ending it.
+    Comment,
+    /// Text the lexer rejected, e.g. an unterminated string.
+    Error,
+    Token(TokenType),
+}
+
+impl TokenKind {
+    /// Whether the token means nothing to the parser.
+    pub fn is_trivia(&self) -> bool {
+        !matches!(self, TokenKind::Token(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Byte offsets into the source.
+    pub span: Range<usize>,
+    /// The line the token starts on, counting from 1.
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Program,
+    Class,
+    Enum,
+    /// A function declaration or a method.
+    Function,
+    Parameter,
+    /// `: Type` or `-> Type`.
+    TypeAnnotation,
+    Var,
+    /// `{x, y}`, the fields a `var` declaration reads into variables of the same names.
+    Pattern,
+    Block,
+    Print,
+    If,
+    While,
+    DoWhile,
+    Loop,
+    For,
+    /// A loop with the label before it.
+    Labeled,
+    Break,
+    Switch,
+    /// A `case` or the `default` of a switch, with its statements.
+    Case,
+    Return,
+    ExpressionStatement,
+    Assign,
+    Logical,
+    Binary,
+    Unary,
+    Call,
+    Get,
+    Grouping,
+    Literal,
+    Variable,
+    This,
+    Super,
+    /// `[first, second]`.
+    List,
+    /// `{key: value}`.
+    Map,
+    /// `key: value`.
+    MapEntry,
+    Match,
+    /// `pattern => body`.
+    MatchArm,
+    /// `Circle(c)`, the class an arm matches and the name it binds. A value pattern is an
+    /// expression node, `_` a token of the arm.
+    ClassPattern,
+    /// Tokens the parser could not make sense of, or nothing where something was missing.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    fn new(kind: NodeKind, children: Vec<SyntaxElement>) -> Self {
+        Self { kind, children }
+    }
+
+    /// All tokens below the node, trivia included, in source order.
+    pub fn tokens(&self) -> impl Iterator<Item = &SyntaxToken> {
+        // a stack of the children left per level, as trees nest as deeply as the source does
+        let mut levels = vec![self.children.iter()];
+        iter::from_fn(move || loop {
+            match levels.last_mut()?.next() {
+                Some(SyntaxElement::Token(token)) => return Some(token),
+                Some(SyntaxElement::Node(node)) => levels.push(node.children.iter()),
+                None => {
+                    levels.pop();
+                }
+            }
+        })
+    }
+
+    /// The child nodes, without the tokens between them.
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// The source text the node was parsed from.
+    pub fn text(&self) -> String {
+        self.tokens().map(|token| token.text.as_str()).collect()
+    }
+
+    /// The bytes of the source the node covers, `None` if it is empty.
+    pub fn span(&self) -> Option<Range<usize>> {
+        let start = self.tokens().next()?.span.start;
+        let end = self.tokens().last()?.span.end;
+        Some(start..end)
+    }
+}
+
+/// A parsed source file.
+#[derive(Debug, PartialEq)]
+pub struct Cst {
+    pub root: SyntaxNode,
+    /// The errors of the lexer and the parser.
+    pub errors: Vec<LoxError>,
+}
+
+impl Cst {
+    /// Builds the [`Program`] the regular parser builds from the same source.
+    pub fn lower(&self) -> (Program, Vec<LoxError>) {
+        let mut tokens: Vec<_> = self
+            .root
+            .tokens()
+            .filter_map(|token| match &token.kind {
+                TokenKind::Token(token_type) => Some(Token {
+                    token_type: token_type.clone(),
+                    lexeme: &token.text,
+                    line: token.line,
+                    span: token.span.clone().into(),
+                }),
+                _ => None,
+            })
+            .collect();
+        let last = self.root.tokens().last();
+        let last_line = last.map_or(1, |token| {
+            token.line + token.text.matches('\n').count() as u32
+        });
+        let end = last.map_or(0, |token| token.span.end);
+        tokens.push(Token {
+            token_type: TokenType::Eof,
+            lexeme: "",
+            line: last_line,
+            span: Span::new(end, end),
+        });
+        parser::parse(&tokens)
+    }
+}
+
+/// Parses `source` into a tree that keeps all of it.
+pub fn parse(source: &str) -> Cst {
+    let (tokens, errors) = tokenize(source);
+    let mut parser = CstParser {
+        tokens,
+        position: 0,
+        errors,
+        depth: 0,
+        too_deep: false,
+    };
+    let root = parser.program();
+    Cst {
+        root,
+        errors: parser.errors,
+    }
+}
+
+/// The tokens of the lexer with the text between them as trivia.
+fn tokenize(source: &str) -> (Vec<SyntaxToken>, Vec<LoxError>) {
+    let options = LexerOptions {
+        comments: true,
+        whitespace: true,
+    };
+    let (lexed, errors) = lexer::lex_with_options(source, options);
+    let mut tokens = vec![];
+    let mut offset = 0;
+    let mut line = 1;
+    for token in lexed
+        .into_iter()
+        .filter(|token| token.token_type != TokenType::Eof)
+    {
+        // lexemes are slices of the source, so their position is where they point to
+        let start = token.lexeme.as_ptr() as usize - source.as_ptr() as usize;
+        // only text the lexer rejected is left between its tokens
+        trivia(source, offset..start, &mut line, &mut tokens);
+        let end = start + token.lexeme.len();
+        let kind = match token.token_type {
+            TokenType::Comment => TokenKind::Comment,
+            TokenType::Whitespace => TokenKind::Whitespace,
+            token_type => TokenKind::Token(token_type),
+        };
+        push_token(kind, source, start..end, &mut line, &mut tokens);
+        offset = end;
+    }
+    trivia(source, offset..source.len(), &mut line, &mut tokens);
+    (tokens, errors)
+}
+
+fn is_whitespace(ch: char) -> bool {
+    matches!(ch, ' ' | '\r' | '\t' | '\n')
+}
+
+/// Splits the text between two tokens into whitespace, comments and rejected text.
+fn trivia(source: &str, span: Range<usize>, line: &mut u32, tokens: &mut Vec<SyntaxToken>) {
+    let mut start = span.start;
+    while start < span.end {
+        let rest = &source[start..span.end];
+        let (kind, length) = if rest.starts_with("//") {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if rest.starts_with(is_whitespace) {
+            let length = rest.find(|ch| !is_whitespace(ch));
+            (TokenKind::Whitespace, length.unwrap_or(rest.len()))
+        } else {
+            let length = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(index, ch)| is_whitespace(ch) || rest[index..].starts_with("//"))
+                .map(|(index, _)| index);
+            (TokenKind::Error, length.unwrap_or(rest.len()))
+        };
+        push_token(kind, source, start..start + length, line, tokens);
+        start += length;
+    }
+}
+
+fn push_token(
+    kind: TokenKind,
+    source: &str,
+    span: Range<usize>,
+    line: &mut u32,
+    tokens: &mut Vec<SyntaxToken>,
+) {
+    let text = source[span.clone()].to_string();
+    let start_line = *line;
+    *line += text.matches('\n').count() as u32;
+    tokens.push(SyntaxToken {
+        kind,
+        text,
+        span,
+        line: start_line,
+    });
+}
+
+/// Follows the grammar of the regular parser, but reports errors instead of giving up on the
+/// statement, so no token is ever dropped.
+struct CstParser {
+    tokens: Vec<SyntaxToken>,
+    position: usize,
+    errors: Vec<LoxError>,
+    /// How many statements and expressions the one being parsed is nested in.
+    depth: usize,
+    /// Whether the source nested too deeply. The rest of it is in an error node then, and the
+    /// enclosing nodes missing their closing tokens are not reported on top.
+    too_deep: bool,
+}
+
+impl CstParser {
+    /// The next token that is not trivia.
+    fn peek(&self) -> Option<&SyntaxToken> {
+        self.tokens[self.position..]
+            .iter()
+            .find(|token| !token.kind.is_trivia())
+    }
+
+    fn peek_type(&self) -> Option<&TokenType> {
+        self.peek().map(|token| match &token.kind {
+            TokenKind::Token(token_type) => token_type,
+            _ => unreachable!(),
+        })
+    }
+
+    /// The type of the token after the next one, trivia skipped.
+    fn peek_second_type(&self) -> Option<&TokenType> {
+        self.tokens[self.position..]
+            .iter()
+            .filter_map(|token| match &token.kind {
+                TokenKind::Token(token_type) => Some(token_type),
+                _ => None,
+            })
+            .nth(1)
+    }
+
+    fn at(&self, token_type: &TokenType) -> bool {
+        self.peek_type() == Some(token_type)
+    }
+
+    fn at_any(&self, token_types: &[TokenType]) -> bool {
+        self.peek_type()
+            .is_some_and(|token_type| token_types.contains(token_type))
+    }
+
+    /// Moves the next token and the trivia before it into `children`.
+    fn bump(&mut self, children: &mut Vec<SyntaxElement>) {
+        while let Some(token) = self.tokens.get(self.position) {
+            self.position += 1;
+            let trivia = token.kind.is_trivia();
+            children.push(SyntaxElement::Token(token.clone()));
+            if !trivia {
+                break;
+            }
+        }
+    }
+
+    /// Moves all tokens left into `children`, only trivia once the statements are parsed.
+    fn bump_rest(&mut self, children: &mut Vec<SyntaxElement>) {
+        let rest = self.tokens[self.position..].iter().cloned();
+        children.extend(rest.map(SyntaxElement::Token));
+        self.position = self.tokens.len();
+    }
+
+    fn error(&mut self, message: &str) {
+        if self.too_deep {
+            return;
+        }
+        let line = self.peek().map(|token| token.line);
+        self.errors
+            .push(LoxError::ParserError(line, message.to_string().into()));
+    }
+
+    fn expect(&mut self, token_type: TokenType, message: &str, children: &mut Vec<SyntaxElement>) {
+        if self.at(&token_type) {
+            self.bump(children);
+        } else {
+            self.error(message);
+        }
+    }
+
+    fn expect_identifier(&mut self, message: &str, children: &mut Vec<SyntaxElement>) {
+        self.expect(TokenType::Identifier, message, children)
+    }
+
+    fn program(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        while self.peek().is_some() {
+            self.statement_into(&mut children);
+        }
+        self.bump_rest(&mut children);
+        SyntaxNode::new(NodeKind::Program, children)
+    }
+
+    /// Parses a statement into `children`. A statement that did not get anywhere has its first
+    /// token wrapped in an error node, so the callers' loops always advance.
+    fn statement_into(&mut self, children: &mut Vec<SyntaxElement>) {
+        let position = self.position;
+        let statement = self.statement();
+        children.push(SyntaxElement::Node(statement));
+        if self.position == position {
+            let mut error = vec![];
+            self.bump(&mut error);
+            children.push(SyntaxElement::Node(SyntaxNode::new(NodeKind::Error, error)));
+        }
+    }
+
+    /// Parses a statement or an expression inside the one being parsed. Nesting them too deeply
+    /// is an error that puts all that is left into an error node, as the regular parser gives up
+    /// there too.
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> SyntaxNode) -> SyntaxNode {
+        if self.depth >= MAX_SYNTAX_DEPTH {
+            self.error("Too much nesting.");
+            self.too_deep = true;
+            let mut rest = vec![];
+            self.bump_rest(&mut rest);
+            return SyntaxNode::new(NodeKind::Error, rest);
+        }
+        self.depth += 1;
+        let node = recursion::grow_stack(|| parse(self));
+        self.depth -= 1;
+        node
+    }
+
+    fn statement(&mut self) -> SyntaxNode {
+        self.nested(Self::parse_statement)
+    }
+
+    fn parse_statement(&mut self) -> SyntaxNode {
+        match self.peek_type() {
+            Some(TokenType::Print) => self.print_statement(),
+            Some(TokenType::Var) | Some(TokenType::Const) => self.var_declaration(),
+            Some(TokenType::LeftBrace) => self.block(),
+            Some(TokenType::If) => self.if_statement(),
+            Some(TokenType::While) => self.while_statement(),
+            Some(TokenType::Do) => self.do_while_statement(),
+            Some(TokenType::Loop) => self.loop_statement(),
+            Some(TokenType::For) => self.for_statement(),
+            Some(TokenType::Break) => self.break_statement(),
+            Some(TokenType::Identifier) if self.peek_second_type() == Some(&TokenType::Colon) => {
+                self.labeled_statement()
+            }
+            Some(TokenType::Switch) => self.switch_statement(),
+            Some(TokenType::Fun) => {
+                let mut children = vec![];
+                self.bump(&mut children);
+                self.function(children)
+            }
+            Some(TokenType::Class) => self.class(),
+            Some(TokenType::Enum) => self.enum_declaration(),
+            Some(TokenType::Return) => self.return_statement(),
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn enum_declaration(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.expect_identifier("Expect enum name.", &mut children);
+        self.expect(
+            TokenType::LeftBrace,
+            "Expect '{' before enum members.",
+            &mut children,
+        );
+        while self.at(&TokenType::Identifier) {
+            self.bump(&mut children);
+            if !self.at(&TokenType::Comma) {
+                break;
+            }
+            self.bump(&mut children);
+        }
+        self.expect(
+            TokenType::RightBrace,
+            "Expect '}' after enum members.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Enum, children)
+    }
+
+    fn class(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.expect_identifier("Expect class name.", &mut children);
+        if self.at(&TokenType::Less) {
+            self.bump(&mut children);
+            let mut superclass = vec![];
+            self.expect_identifier("Expect superclass name.", &mut superclass);
+            children.push(SyntaxElement::Node(SyntaxNode::new(
+                NodeKind::Variable,
+                superclass,
+            )));
+        }
+        self.expect(
+            TokenType::LeftBrace,
+            "Expect '{' before class body",
+            &mut children,
+        );
+        while self.peek().is_some() && !self.at(&TokenType::RightBrace) {
+            let position = self.position;
+            let method = self.function(vec![]);
+            children.push(SyntaxElement::Node(method));
+            if self.position == position {
+                let mut error = vec![];
+                self.bump(&mut error);
+                children.push(SyntaxElement::Node(SyntaxNode::new(NodeKind::Error, error)));
+            }
+        }
+        self.expect(
+            TokenType::RightBrace,
+            "Expect '}' after class body",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Class, children)
+    }
+
+    /// Parses the rest of a function; `children` holds the `fun` keyword, if there is one.
+    fn function(&mut self, mut children: Vec<SyntaxElement>) -> SyntaxNode {
+        self.expect_identifier("Expect function name", &mut children);
+        self.expect(
+            TokenType::LeftParen,
+            "Expect '(' after function name",
+            &mut children,
+        );
+        while self.at(&TokenType::Identifier) {
+            let mut parameter = vec![];
+            self.bump(&mut parameter);
+            self.type_annotation(TokenType::Colon, &mut parameter);
+            children.push(SyntaxElement::Node(SyntaxNode::new(
+                NodeKind::Parameter,
+                parameter,
+            )));
+            if !self.at(&TokenType::Comma) {
+                break;
+            }
+            self.bump(&mut children);
+        }
+        self.expect(
+            TokenType::RightParen,
+            "Expect ')' after parameters.",
+            &mut children,
+        );
+        self.type_annotation(TokenType::Arrow, &mut children);
+        if self.at(&TokenType::LeftBrace) {
+            children.push(SyntaxElement::Node(self.block()));
+        } else {
+            self.error("Expect function body");
+        }
+        SyntaxNode::new(NodeKind::Function, children)
+    }
+
+    fn type_annotation(&mut self, prefix: TokenType, children: &mut Vec<SyntaxElement>) {
+        if !self.at(&prefix) {
+            return;
+        }
+        let mut annotation = vec![];
+        self.bump(&mut annotation);
+        self.expect_identifier("Expect type name.", &mut annotation);
+        children.push(SyntaxElement::Node(SyntaxNode::new(
+            NodeKind::TypeAnnotation,
+            annotation,
+        )));
+    }
+
+    fn return_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        if !self.at(&TokenType::Semicolon) {
+            children.push(SyntaxElement::Node(self.expression()));
+        }
+        self.expect(
+            TokenType::Semicolon,
+            "Expect ';' after return value.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Return, children)
+    }
+
+    fn while_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.expect(
+            TokenType::LeftParen,
+            "Expect '(' after 'while'.",
+            &mut children,
+        );
+        children.push(SyntaxElement::Node(self.expression()));
+        self.expect(
+            TokenType::RightParen,
+            "Expect ')' after while condition.",
+            &mut children,
+        );
+        self.statement_into(&mut children);
+        SyntaxNode::new(NodeKind::While, children)
+    }
+
+    fn do_while_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.statement_into(&mut children);
+        self.expect(
+            TokenType::While,
+            "Expect 'while' after do loop body.",
+            &mut children,
+        );
+        self.expect(
+            TokenType::LeftParen,
+            "Expect '(' after 'while'.",
+            &mut children,
+        );
+        children.push(SyntaxElement::Node(self.expression()));
+        self.expect(
+            TokenType::RightParen,
+            "Expect ')' after while condition.",
+            &mut children,
+        );
+        self.expect(
+            TokenType::Semicolon,
+            "Expect ';' after do loop condition.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::DoWhile, children)
+    }
+
+    fn loop_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.statement_into(&mut children);
+        SyntaxNode::new(NodeKind::Loop, children)
+    }
+
+    fn labeled_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.bump(&mut children);
+        let loops = [
+            TokenType::While,
+            TokenType::For,
+            TokenType::Do,
+            TokenType::Loop,
+        ];
+        if self.at_any(&loops) {
+            self.statement_into(&mut children);
+        } else {
+            self.error("Only loops can have labels.");
+        }
+        SyntaxNode::new(NodeKind::Labeled, children)
+    }
+
+    fn break_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        if self.at(&TokenType::Identifier) {
+            self.bump(&mut children);
+        }
+        self.expect(
+            TokenType::Semicolon,
+            "Expect ';' after 'break'.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Break, children)
+    }
+
+    fn for_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.expect(
+            TokenType::LeftParen,
+            "Expect '(' after 'for'.",
+            &mut children,
+        );
+        match self.peek_type() {
+            Some(TokenType::Semicolon) => self.bump(&mut children),
+            Some(TokenType::Var) | Some(TokenType::Const) => {
+                children.push(SyntaxElement::Node(self.var_declaration()))
+            }
+            _ => children.push(SyntaxElement::Node(self.expression_statement())),
+        }
+        if !self.at(&TokenType::Semicolon) {
+            children.push(SyntaxElement::Node(self.expression()));
+        }
+        self.expect(
+            TokenType::Semicolon,
+            "Expect ';' after loop condition.",
+            &mut children,
+        );
+        if !self.at(&TokenType::RightParen) {
+            children.push(SyntaxElement::Node(self.expression()));
+        }
+        self.expect(
+            TokenType::RightParen,
+            "Expect ')' after for clauses.",
+            &mut children,
+        );
+        self.statement_into(&mut children);
+        SyntaxNode::new(NodeKind::For, children)
+    }
+
+    fn if_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.expect(
+            TokenType::LeftParen,
+            "Expect '(' after 'if'.",
+            &mut children,
+        );
+        children.push(SyntaxElement::Node(self.expression()));
+        self.expect(
+            TokenType::RightParen,
+            "Expect ')' after if condition.",
+            &mut children,
+        );
+        self.statement_into(&mut children);
+        if self.at(&TokenType::Else) {
+            self.bump(&mut children);
+            self.statement_into(&mut children);
+        }
+        SyntaxNode::new(NodeKind::If, children)
+    }
+
+    fn switch_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.expect(
+            TokenType::LeftParen,
+            "Expect '(' after 'switch'.",
+            &mut children,
+        );
+        children.push(SyntaxElement::Node(self.expression()));
+        self.expect(
+            TokenType::RightParen,
+            "Expect ')' after switch value.",
+            &mut children,
+        );
+        self.expect(
+            TokenType::LeftBrace,
+            "Expect '{' before switch cases.",
+            &mut children,
+        );
+        while self.at_any(&[TokenType::Case, TokenType::Default]) {
+            children.push(SyntaxElement::Node(self.case()));
+        }
+        self.expect(
+            TokenType::RightBrace,
+            "Expect '}' after switch cases.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Switch, children)
+    }
+
+    fn case(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        if self.at(&TokenType::Case) {
+            self.bump(&mut children);
+            children.push(SyntaxElement::Node(self.expression()));
+            self.expect(
+                TokenType::Colon,
+                "Expect ':' after case value.",
+                &mut children,
+            );
+        } else {
+            self.bump(&mut children);
+            self.expect(
+                TokenType::Colon,
+                "Expect ':' after 'default'.",
+                &mut children,
+            );
+        }
+        let end = [TokenType::Case, TokenType::Default, TokenType::RightBrace];
+        while self.peek().is_some() && !self.at_any(&end) {
+            self.statement_into(&mut children);
+        }
+        SyntaxNode::new(NodeKind::Case, children)
+    }
+
+    fn block(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        while self.peek().is_some() && !self.at(&TokenType::RightBrace) {
+            self.statement_into(&mut children);
+        }
+        self.expect(
+            TokenType::RightBrace,
+            "Expect '}' after block.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Block, children)
+    }
+
+    fn print_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        children.push(SyntaxElement::Node(self.expression()));
+        self.expect(
+            TokenType::Semicolon,
+            "Expect ';' after value.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Print, children)
+    }
+
+    /// A `var` or `const` declaration.
+    fn var_declaration(&mut self) -> SyntaxNode {
+        let constant = self.at(&TokenType::Const);
+        let mut children = vec![];
+        self.bump(&mut children);
+        loop {
+            let pattern = self.at(&TokenType::LeftBrace);
+            if pattern {
+                children.push(SyntaxElement::Node(self.pattern()));
+            } else {
+                self.expect_identifier("Expect variable name after 'var'.", &mut children);
+                self.type_annotation(TokenType::Colon, &mut children);
+            }
+            if self.at(&TokenType::Equal) {
+                self.bump(&mut children);
+                children.push(SyntaxElement::Node(self.expression()));
+            } else if pattern {
+                self.error("Expect '=' after destructuring pattern.");
+            } else if constant {
+                self.error("Expect '=' after constant name.");
+            }
+            if !self.at(&TokenType::Comma) {
+                break;
+            }
+            self.bump(&mut children);
+        }
+        self.expect(
+            TokenType::Semicolon,
+            "Expect ';' after expression.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Var, children)
+    }
+
+    fn pattern(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        loop {
+            self.expect_identifier("Expect field name.", &mut children);
+            if !self.at(&TokenType::Comma) {
+                break;
+            }
+            self.bump(&mut children);
+        }
+        self.expect(
+            TokenType::RightBrace,
+            "Expect '}' after field names.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Pattern, children)
+    }
+
+    fn expression_statement(&mut self) -> SyntaxNode {
+        let mut children = vec![SyntaxElement::Node(self.expression())];
+        self.expect(
+            TokenType::Semicolon,
+            "Expect ';' after expression.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::ExpressionStatement, children)
+    }
+
+    fn expression(&mut self) -> SyntaxNode {
+        self.nested(Self::assignment)
+    }
+
+    fn assignment(&mut self) -> SyntaxNode {
+        let target = self.or();
+        if !self.at(&TokenType::Equal) {
+            return target;
+        }
+        if !matches!(target.kind, NodeKind::Variable | NodeKind::Get) {
+            self.error("Invalid assignment target");
+        }
+        let mut children = vec![SyntaxElement::Node(target)];
+        self.bump(&mut children);
+        children.push(SyntaxElement::Node(self.nested(Self::assignment)));
+        SyntaxNode::new(NodeKind::Assign, children)
+    }
+
+    /// Parses operands separated by any of `operators`, grouping them to the left.
+    fn binary(
+        &mut self,
+        kind: NodeKind,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> SyntaxNode,
+    ) -> SyntaxNode {
+        let mut left = operand(self);
+        while self.at_any(operators) {
+            let mut children = vec![SyntaxElement::Node(left)];
+            self.bump(&mut children);
+            children.push(SyntaxElement::Node(operand(self)));
+            left = SyntaxNode::new(kind, children);
+        }
+        left
+    }
+
+    fn or(&mut self) -> SyntaxNode {
+        self.binary(NodeKind::Logical, &[TokenType::Or], Self::and)
+    }
+
+    fn and(&mut self) -> SyntaxNode {
+        self.binary(NodeKind::Logical, &[TokenType::And], Self::equality)
+    }
+
+    fn equality(&mut self) -> SyntaxNode {
+        let operators = [TokenType::BangEqual, TokenType::EqualEqual];
+        self.binary(NodeKind::Binary, &operators, Self::comparison)
+    }
+
+    fn comparison(&mut self) -> SyntaxNode {
+        let operators = [
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+            TokenType::Less,
+            TokenType::LessEqual,
+        ];
+        self.binary(NodeKind::Binary, &operators, Self::addition)
+    }
+
+    fn addition(&mut self) -> SyntaxNode {
+        let operators = [TokenType::Minus, TokenType::Plus];
+        self.binary(NodeKind::Binary, &operators, Self::multiplication)
+    }
+
+    fn multiplication(&mut self) -> SyntaxNode {
+        let operators = [TokenType::Slash, TokenType::Star];
+        self.binary(NodeKind::Binary, &operators, Self::unary)
+    }
+
+    fn match_expression(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.expect(
+            TokenType::LeftParen,
+            "Expect '(' after 'match'.",
+            &mut children,
+        );
+        children.push(SyntaxElement::Node(self.expression()));
+        self.expect(
+            TokenType::RightParen,
+            "Expect ')' after match value.",
+            &mut children,
+        );
+        self.expect(
+            TokenType::LeftBrace,
+            "Expect '{' before match arms.",
+            &mut children,
+        );
+        while self.peek().is_some() && !self.at(&TokenType::RightBrace) {
+            children.push(SyntaxElement::Node(self.match_arm()));
+            if !self.at(&TokenType::Comma) {
+                break;
+            }
+            self.bump(&mut children);
+        }
+        self.expect(
+            TokenType::RightBrace,
+            "Expect '}' after match arms.",
+            &mut children,
+        );
+        SyntaxNode::new(NodeKind::Match, children)
+    }
+
+    fn match_arm(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        let second = self.peek_second_type();
+        if self.peek().is_some_and(|token| token.text == "_")
+            && second == Some(&TokenType::FatArrow)
+        {
+            self.bump(&mut children);
+        } else if self.at(&TokenType::Identifier) && second == Some(&TokenType::LeftParen) {
+            let mut pattern = vec![SyntaxElement::Node(self.primary())];
+            self.bump(&mut pattern);
+            self.expect_identifier("Expect binding name.", &mut pattern);
+            self.expect(
+                TokenType::RightParen,
+                "Expect ')' after binding name.",
+                &mut pattern,
+            );
+            children.push(SyntaxElement::Node(SyntaxNode::new(
+                NodeKind::ClassPattern,
+                pattern,
+            )));
+        } else {
+            children.push(SyntaxElement::Node(self.expression()));
+        }
+        self.expect(
+            TokenType::FatArrow,
+            "Expect '=>' after pattern.",
+            &mut children,
+        );
+        children.push(SyntaxElement::Node(self.expression()));
+        SyntaxNode::new(NodeKind::MatchArm, children)
+    }
+
+    fn unary(&mut self) -> SyntaxNode {
+        if self.at_any(&[TokenType::Bang, TokenType::Minus, TokenType::Typeof]) {
+            let mut children = vec![];
+            self.bump(&mut children);
+            children.push(SyntaxElement::Node(self.nested(Self::unary)));
+            SyntaxNode::new(NodeKind::Unary, children)
+        } else {
+            self.call()
+        }
+    }
+
+    fn call(&mut self) -> SyntaxNode {
+        let mut expression = self.primary();
+        loop {
+            let mut children = vec![SyntaxElement::Node(expression)];
+            let kind = match self.peek_type() {
+                Some(TokenType::LeftParen) => {
+                    self.bump(&mut children);
+                    if !self.at(&TokenType::RightParen) {
+                        children.push(SyntaxElement::Node(self.expression()));
+                        while self.at(&TokenType::Comma) {
+                            self.bump(&mut children);
+                            if self.at(&TokenType::RightParen) {
+                                break;
+                            }
+                            children.push(SyntaxElement::Node(self.expression()));
+                        }
+                    }
+                    self.expect(
+                        TokenType::RightParen,
+                        "Expect ')' after arguments.",
+                        &mut children,
+                    );
+                    NodeKind::Call
+                }
+                Some(TokenType::Dot) => {
+                    self.bump(&mut children);
+                    self.expect_identifier("Expect property name after '.'.", &mut children);
+                    NodeKind::Get
+                }
+                _ => match children.pop() {
+                    Some(SyntaxElement::Node(expression)) => return expression,
+                    _ => unreachable!(),
+                },
+            };
+            expression = SyntaxNode::new(kind, children);
+        }
+    }
+
+    fn primary(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        let kind = match self.peek_type() {
+            Some(
+                TokenType::False
+                | TokenType::True
+                | TokenType::Nil
+                | TokenType::Number(_)
+                | TokenType::String(_),
+            ) => {
+                self.bump(&mut children);
+                NodeKind::Literal
+            }
+            Some(TokenType::LeftParen) => {
+                self.bump(&mut children);
+                children.push(SyntaxElement::Node(self.expression()));
+                self.expect(
+                    TokenType::RightParen,
+                    "Expect ')' after expression.",
+                    &mut children,
+                );
+                NodeKind::Grouping
+            }
+            Some(TokenType::Identifier) => {
+                self.bump(&mut children);
+                NodeKind::Variable
+            }
+            Some(TokenType::This) => {
+                self.bump(&mut children);
+                NodeKind::This
+            }
+            Some(TokenType::Super) => {
+                self.bump(&mut children);
+                self.expect(TokenType::Dot, "Expect '.' after super.", &mut children);
+                self.expect_identifier("Expect superclass method name.", &mut children);
+                NodeKind::Super
+            }
+            Some(TokenType::Match) => return self.match_expression(),
+            Some(TokenType::LeftBracket) => {
+                self.bump(&mut children);
+                while self.peek().is_some() && !self.at(&TokenType::RightBracket) {
+                    children.push(SyntaxElement::Node(self.expression()));
+                    if !self.at(&TokenType::Comma) {
+                        break;
+                    }
+                    self.bump(&mut children);
+                }
+                self.expect(
+                    TokenType::RightBracket,
+                    "Expect ']' after list elements.",
+                    &mut children,
+                );
+                NodeKind::List
+            }
+            Some(TokenType::LeftBrace) => {
+                self.bump(&mut children);
+                while self.peek().is_some() && !self.at(&TokenType::RightBrace) {
+                    let mut entry = vec![SyntaxElement::Node(self.expression())];
+                    self.expect(TokenType::Colon, "Expect ':' after map key.", &mut entry);
+                    entry.push(SyntaxElement::Node(self.expression()));
+                    children.push(SyntaxElement::Node(SyntaxNode::new(
+                        NodeKind::MapEntry,
+                        entry,
+                    )));
+                    if !self.at(&TokenType::Comma) {
+                        break;
+                    }
+                    self.bump(&mut children);
+                }
+                self.expect(
+                    TokenType::RightBrace,
+                    "Expect '}' after map entries.",
+                    &mut children,
+                );
+                NodeKind::Map
+            }
+            _ => {
+                self.error("Expect expression.");
+                NodeKind::Error
+            }
+        };
+        SyntaxNode::new(kind, children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{parse, NodeKind, TokenKind};
+    use crate::error::LoxError;
+    use crate::interpreter::Interpreter;
+    use crate::resolver;
+
+    #[test]
+    fn deep_nesting() {
+        let nested = |depth| format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        let source = nested(9_000);
+        let cst = parse(&source);
+        assert_eq!(cst.errors, []);
+        assert_eq!(cst.root.text(), source);
+
+        let source = format!("{} print 2;", nested(100_000));
+        let cst = parse(&source);
+        assert_eq!(
+            cst.errors,
+            [LoxError::ParserError(Some(1), "Too much nesting.".into())]
+        );
+        assert_eq!(cst.root.text(), source);
+    }
+
+    #[test]
+    fn lossless() {
+        let sources = [
+            "// a comment\nclass A < B {\n  m(a: Number, b) -> Nil { return a + b; }\n}\n",
+            "for (var i = 0; i < 10; i = i + 1) {\r\n\tprint i; // inline\n}",
+            "print (;\nvar = @ 2;\n\"unterminated",
+            "print 1; // no line break",
+            "",
+        ];
+        for &source in sources.iter() {
+            let cst = parse(source);
+            assert_eq!(cst.root.text(), source);
+            let mut offset = 0;
+            for token in cst.root.tokens() {
+                assert_eq!(token.span.start, offset);
+                assert_eq!(&source[token.span.clone()], token.text);
+                offset = token.span.end;
+            }
+            assert_eq!(offset, source.len());
+        }
+    }
+
+    #[test]
+    fn structure() {
+        let cst = parse("// first\nvar a = 1 + 2 * 3;\n");
+        assert!(cst.errors.is_empty());
+
+        let var = cst.root.nodes().next().unwrap();
+        assert_eq!(var.kind, NodeKind::Var);
+        assert_eq!(var.span(), Some(0..27));
+        let first = var.tokens().next().unwrap();
+        assert_eq!(first.kind, TokenKind::Comment);
+        assert_eq!(first.text, "// first");
+
+        let sum = var.nodes().next().unwrap();
+        assert_eq!(sum.kind, NodeKind::Binary);
+        assert_eq!(sum.text(), " 1 + 2 * 3");
+        let kinds: Vec<_> = sum.nodes().map(|node| node.kind).collect();
+        assert_eq!(kinds, [NodeKind::Literal, NodeKind::Binary]);
+        assert_eq!(cst.root.tokens().last().unwrap().line, 2);
+    }
+
+    #[test]
+    fn errors() {
+        let cst = parse("print (;\nvar = 2;\nprint 1;");
+        assert_eq!(cst.errors.len(), 3);
+        assert_eq!(cst.errors[0].line(), Some(1));
+        assert_eq!(cst.errors[2].line(), Some(2));
+        let last = cst.root.nodes().last().unwrap();
+        assert_eq!(last.kind, NodeKind::Print);
+        assert_eq!(last.text(), "\nprint 1;");
+    }
+
+    #[test]
+    fn lower() -> crate::Result<()> {
+        let cst = parse("var a = 1; // one\n{ a = a + 2; }\n/* */ a * 2;");
+        assert!(!cst.errors.is_empty());
+        let (_, errors) = cst.lower();
+        assert!(!errors.is_empty());
+
+        let cst = parse("var a = 1; // one\n{ a = a + 2; }\na * 2;");
+        let (program, errors) = cst.lower();
+        assert!(errors.is_empty());
+        let mut interpreter = Interpreter::new();
+        interpreter.add_scopes(resolver::resolve(&program)?);
+        assert_eq!(interpreter.interpret(program)?.to_string(), "6");
+        Ok(())
+    }
+}
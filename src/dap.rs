@@ -0,0 +1,444 @@
+//! A debug adapter speaking the Debug Adapter Protocol over stdin and stdout, started with
+//! `rlox dap`. It launches a single Lox file, stops at line breakpoints and steps through
+//! statements. While stopped, clients can look at the calls in progress, the globals and the
+//! locals of the running call. Of the calls it was made from, only the instance a method runs on
+//! is shown, their other locals are out of reach.
+//!
+//! The names of the locals come from `Resolution::line_locals`, the locals in scope at the line
+//! the program stopped at.
+//!
+//! Requests are only read while the program is stopped or not running, so `pause` is not
+//! supported.
+
+use crate::debug::{Debugger, StepMode};
+use crate::error::{LoxError, Result};
+use crate::framing::{read_message, write_message};
+use crate::interpreter::Interpreter;
+use crate::resolver::ScopeLocals;
+use crate::{lexer, parser, resolver};
+
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+/// The only thread, Lox code runs on one.
+const THREAD_ID: u64 = 1;
+/// Variable references: globals, then the locals of each frame (innermost first).
+const GLOBALS: u64 = 1;
+const FIRST_LOCALS: u64 = 2;
+
+/// Answers the requests read from `input` until the client disconnects or closes the stream.
+pub fn serve(input: impl BufRead + 'static, output: impl Write + 'static) -> io::Result<()> {
+    let session = Rc::new(RefCell::new(Session {
+        input: Box::new(input),
+        output: Box::new(output),
+        seq: 0,
+        program: None,
+        breakpoints: HashSet::new(),
+        mode: StepMode::Continue,
+        lines: vec![],
+        line_locals: HashMap::new(),
+        disconnected: false,
+    }));
+    loop {
+        let request = match session.borrow_mut().read()? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let mut session_ref = session.borrow_mut();
+        match command(&request) {
+            "initialize" => {
+                let capabilities = json!({"supportsConfigurationDoneRequest": true});
+                session_ref.respond(&request, capabilities)?;
+                session_ref.event("initialized", json!({}))?;
+            }
+            "launch" => {
+                let arguments = &request["arguments"];
+                session_ref.program = arguments["program"].as_str().map(String::from);
+                if arguments["stopOnEntry"].as_bool() == Some(true) {
+                    session_ref.mode = StepMode::Entry;
+                }
+                session_ref.respond(&request, json!({}))?;
+            }
+            "configurationDone" => {
+                session_ref.respond(&request, json!({}))?;
+                drop(session_ref);
+                run(&session)?;
+                if session.borrow().disconnected {
+                    return Ok(());
+                }
+            }
+            "disconnect" => return session_ref.respond(&request, json!({})),
+            _ => session_ref.answer(&request, None)?,
+        }
+    }
+}
+
+/// Runs the launched program, with `session` stopping it as requested.
+fn run(session: &Rc<RefCell<Session>>) -> io::Result<()> {
+    let program = session.borrow().program.clone();
+    let source = match program.map(std::fs::read_to_string) {
+        Some(Ok(source)) => source,
+        Some(Err(error)) => return session.borrow_mut().exit(&error.to_string(), 66),
+        None => return session.borrow_mut().exit("No program was launched.", 64),
+    };
+
+    let (tokens, mut errors) = lexer::lex(&source);
+    let (program, parser_errors) = parser::parse(&tokens);
+    errors.extend(parser_errors);
+    if errors.is_empty() {
+        match resolver::resolve_for_debugging(&program) {
+            Ok(mut resolution) => {
+                session.borrow_mut().line_locals = std::mem::take(&mut resolution.line_locals);
+                let mut interpreter = Interpreter::new();
+                interpreter.set_output(Output(Rc::clone(session), vec![]));
+                interpreter.set_debugger(SessionDebugger(Rc::clone(session)));
+                interpreter.add_scopes(resolution);
+                if let Err(error) = interpreter.interpret(program) {
+                    if session.borrow().disconnected {
+                        return Ok(());
+                    }
+                    return session.borrow_mut().exit(&error.to_string(), 70);
+                }
+                return session.borrow_mut().exit("", 0);
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+    let messages: Vec<_> = errors.iter().map(LoxError::to_string).collect();
+    session.borrow_mut().exit(&messages.join("\n"), 64)
+}
+
+fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+struct Session {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: u64,
+    program: Option<String>,
+    breakpoints: HashSet<u32>,
+    mode: StepMode,
+    /// The line running in each call, the outermost (the script) first.
+    lines: Vec<u32>,
+    line_locals: HashMap<u32, ScopeLocals>,
+    disconnected: bool,
+}
+
+impl Session {
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        read_message(&mut self.input)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command(request),
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    /// Answers the requests that are valid whether or not the program is stopped.
+    /// `interpreter` is the stopped one.
+    fn answer(&mut self, request: &Value, interpreter: Option<&Interpreter>) -> io::Result<()> {
+        let arguments = &request["arguments"];
+        let body = match (command(request), interpreter) {
+            ("threads", _) => json!({"threads": [{"id": THREAD_ID, "name": "main"}]}),
+            ("setBreakpoints", _) => {
+                let lines: Vec<u32> = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                            .map(|line| line as u32)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                self.breakpoints = lines.iter().copied().collect();
+                let breakpoints: Vec<_> = lines
+                    .iter()
+                    .map(|line| json!({"verified": true, "line": line}))
+                    .collect();
+                json!({ "breakpoints": breakpoints })
+            }
+            ("stackTrace", Some(interpreter)) => self.stack_trace(interpreter),
+            ("scopes", Some(_)) => {
+                let frame = arguments["frameId"].as_u64().unwrap_or(0);
+                json!({"scopes": [
+                    {"name": "Locals", "variablesReference": FIRST_LOCALS + frame, "expensive": false},
+                    {"name": "Globals", "variablesReference": GLOBALS, "expensive": false},
+                ]})
+            }
+            ("variables", Some(interpreter)) => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+                json!({ "variables": self.variables(interpreter, reference) })
+            }
+            (command, _) => {
+                return self.send(json!({
+                    "type": "response",
+                    "request_seq": request["seq"],
+                    "command": command,
+                    "success": false,
+                    "message": format!("Unsupported request '{}'.", command),
+                }));
+            }
+        };
+        self.respond(request, body)
+    }
+
+    fn stack_trace(&self, interpreter: &Interpreter) -> Value {
+        let source = json!({ "path": self.program });
+        let names = interpreter
+            .call_frames()
+            .into_iter()
+            .map(|frame| frame.name)
+            .chain(std::iter::once("<script>".to_string()));
+        let frames: Vec<_> = names
+            .zip(self.lines.iter().rev())
+            .enumerate()
+            .map(|(id, (name, line))| {
+                json!({"id": id, "name": name, "line": line, "column": 1, "source": source})
+            })
+            .collect();
+        json!({"stackFrames": frames, "totalFrames": frames.len()})
+    }
+
+    fn variables(&self, interpreter: &Interpreter, reference: u64) -> Vec<Value> {
+        let variable = |name: &str, value: String| json!({"name": name, "value": value, "variablesReference": 0});
+        if reference == GLOBALS {
+            return interpreter
+                .globals()
+                .iter()
+                .map(|(name, value)| variable(name, value.to_string()))
+                .collect();
+        }
+        match reference.wrapping_sub(FIRST_LOCALS) as usize {
+            // the running call, whose locals include `this`
+            0 => {
+                let line = self.lines.last().copied().unwrap_or_default();
+                let locals = self.line_locals.get(&line).cloned().unwrap_or_default();
+                locals
+                    .iter()
+                    .filter_map(|(name, slot)| {
+                        let value = interpreter.local(*slot)?;
+                        Some(variable(name, value.to_string()))
+                    })
+                    .collect()
+            }
+            frame => interpreter
+                .call_frames()
+                .get(frame)
+                .and_then(|frame| frame.this.as_ref())
+                .map(|this| variable("this", this.to_string()))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Reports the end of the program, with `message` as its error output.
+    fn exit(&mut self, message: &str, exit_code: i32) -> io::Result<()> {
+        if !message.is_empty() {
+            let output = format!("{}\n", message);
+            self.event("output", json!({"category": "stderr", "output": output}))?;
+        }
+        self.event("exited", json!({ "exitCode": exit_code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    /// Reports a stop and answers requests until the client resumes the program.
+    fn stop(&mut self, interpreter: &Interpreter, reason: &str) -> io::Result<()> {
+        let body = json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true});
+        self.event("stopped", body)?;
+        let depth = interpreter.call_depth();
+        loop {
+            let request = match self.read()? {
+                Some(request) => request,
+                None => {
+                    self.disconnected = true;
+                    return Ok(());
+                }
+            };
+            self.mode = match command(&request) {
+                "continue" => StepMode::Continue,
+                "next" => StepMode::StepOver(depth),
+                "stepIn" => StepMode::StepIn,
+                "stepOut" => StepMode::StepOut(depth),
+                "disconnect" => {
+                    self.disconnected = true;
+                    return self.respond(&request, json!({}));
+                }
+                _ => {
+                    self.answer(&request, Some(interpreter))?;
+                    continue;
+                }
+            };
+            return self.respond(&request, json!({"allThreadsContinued": true}));
+        }
+    }
+}
+
+struct SessionDebugger(Rc<RefCell<Session>>);
+
+impl Debugger for SessionDebugger {
+    fn statement(&mut self, interpreter: &mut Interpreter, line: u32) -> Result<()> {
+        let mut session = self.0.borrow_mut();
+        let depth = interpreter.call_depth();
+        session.lines.resize(depth, 0);
+        session.lines.push(line);
+
+        let reason = match session.mode {
+            _ if session.breakpoints.contains(&line) => Some("breakpoint"),
+            StepMode::Entry => Some("entry"),
+            mode if mode.stops_at(depth) => Some("step"),
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            session.stop(interpreter, reason).map_err(|error| {
+                LoxError::InterpreterError(format!("Debugging failed: {}", error).into())
+            })?;
+        }
+        if session.disconnected {
+            return Err(LoxError::InterpreterError(
+                "The debugger disconnected.".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Sends what the program prints to the client, a line at a time.
+struct Output(Rc<RefCell<Session>>, Vec<u8>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.extend_from_slice(buf);
+        if buf.contains(&b'\n') {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.1.is_empty() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&self.1).into_owned();
+        self.1.clear();
+        self.0
+            .borrow_mut()
+            .event("output", json!({"category": "stdout", "output": output}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::serve;
+    use crate::framing;
+    use crate::interpreter::Output;
+
+    use serde_json::json;
+    use std::io;
+
+    #[test]
+    fn session() {
+        let program = std::env::temp_dir().join("rlox_dap_session.lox");
+        std::fs::write(
+            &program,
+            "var total = 0;\nfun add(n) {\n  var twice = n * 2;\n  total = total + n;\n}\nadd(1);\nadd(2);\nprint total;\n",
+        )
+        .unwrap();
+        let path = program.to_str().unwrap();
+        let requests = [
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "launch", "arguments": {"program": path}}),
+            json!({"command": "setBreakpoints", "arguments": {"breakpoints": [{"line": 4}]}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "variables", "arguments": {"variablesReference": 2}}),
+            json!({"command": "continue", "arguments": {"threadId": 1}}),
+            json!({"command": "variables", "arguments": {"variablesReference": 1}}),
+            json!({"command": "next", "arguments": {"threadId": 1}}),
+            json!({"command": "continue", "arguments": {"threadId": 1}}),
+            json!({"command": "disconnect"}),
+        ];
+        let requests: Vec<_> = requests
+            .iter()
+            .enumerate()
+            .map(|(seq, request)| {
+                let mut request = request.clone();
+                request["seq"] = json!(seq + 1);
+                request["type"] = json!("request");
+                request
+            })
+            .collect();
+        let input = framing::frame_all(&requests);
+
+        let output = Output::default();
+        serve(io::Cursor::new(input), output.clone()).unwrap();
+        std::fs::remove_file(&program).unwrap();
+        let messages = framing::read_all(&output.0.borrow());
+
+        let stops: Vec<_> = messages
+            .iter()
+            .filter(|message| message["event"] == "stopped")
+            .map(|message| message["body"]["reason"].clone())
+            .collect();
+        assert_eq!(stops, ["breakpoint", "breakpoint", "step"]);
+
+        let stack_trace = messages
+            .iter()
+            .find(|message| message["command"] == "stackTrace")
+            .unwrap();
+        let frames: Vec<_> = stack_trace["body"]["stackFrames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| (frame["name"].clone(), frame["line"].clone()))
+            .collect();
+        assert_eq!(
+            frames,
+            [(json!("add()"), json!(4)), (json!("<script>"), json!(6))]
+        );
+
+        let variables: Vec<_> = messages
+            .iter()
+            .filter(|message| message["command"] == "variables")
+            .map(|message| message["body"]["variables"].as_array().unwrap())
+            .collect();
+        assert_eq!(
+            variables[0],
+            &[
+                json!({"name": "n", "value": "1", "variablesReference": 0}),
+                json!({"name": "twice", "value": "2", "variablesReference": 0}),
+            ]
+        );
+        assert!(
+            variables[1].contains(&json!({"name": "total", "value": "1", "variablesReference": 0}))
+        );
+
+        let output: Vec<_> = messages
+            .iter()
+            .filter(|message| message["event"] == "output")
+            .map(|message| message["body"]["output"].clone())
+            .collect();
+        assert_eq!(output, ["3\n"]);
+        assert_eq!(messages.last().unwrap()["command"], "disconnect");
+    }
+}
@@ -0,0 +1,67 @@
+use crate::error::Result;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+/// Hooks the interpreter calls around the statements and calls it runs, e.g. to stop at
+/// breakpoints, trace or measure a program. Several debuggers can watch a program together, see
+/// `Interpreter::add_debugger`; each hook runs in the order they were added.
+pub trait Debugger {
+    /// Called before the statement starting in `line` runs. The interpreter can evaluate
+    /// expressions in the meantime, see `Interpreter::evaluate_in_frame`. Returning an error
+    /// aborts the program with it.
+    fn statement(&mut self, _interpreter: &mut Interpreter, _line: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the statement starting in `line` ran, whether it succeeded or not.
+    /// Statements holding others, like loops, finish after them.
+    fn after_statement(&mut self, _interpreter: &mut Interpreter, _line: u32) {}
+
+    /// Called before the function or class in `frame` is called, with the call already counted
+    /// by `Interpreter::call_depth`. Returning an error aborts the program with it.
+    fn call(&mut self, _interpreter: &mut Interpreter, _frame: &CallFrame) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the call of `frame` returned, whether it succeeded or not.
+    fn after_call(&mut self, _interpreter: &mut Interpreter, _frame: &CallFrame) {}
+
+    /// Called after a `var` statement or an assignment stored `value` in the variable `name`.
+    fn assignment(&mut self, _interpreter: &Interpreter, _name: &str, _value: &Object) {}
+}
+
+/// When a stepping debugger stops next, besides at breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    Continue,
+    /// At the first statement.
+    Entry,
+    StepIn,
+    /// At the next statement at most this many calls deep.
+    StepOver(usize),
+    /// At the next statement less than this many calls deep.
+    StepOut(usize),
+}
+
+impl StepMode {
+    /// Whether to stop at a statement running `depth` calls deep.
+    pub fn stops_at(self, depth: usize) -> bool {
+        match self {
+            StepMode::Continue => false,
+            StepMode::Entry | StepMode::StepIn => true,
+            StepMode::StepOver(stepped) => depth <= stepped,
+            StepMode::StepOut(stepped) => depth < stepped,
+        }
+    }
+}
+
+/// A call in progress, see `Interpreter::call_frames`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    /// How the call appears in stack traces, e.g. `fib()`.
+    pub name: String,
+    /// The line the call is running, or made the next call in, if known.
+    pub line: Option<u32>,
+    /// The instance a method was called on.
+    pub this: Option<Object>,
+}
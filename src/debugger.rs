@@ -0,0 +1,319 @@
+//! The interactive debugger behind `rlox debug`. It stops at the first statement and then at
+//! breakpoints and after steps, and reads commands from its input while stopped:
+//!
+//! ```text
+//! break [file:]line   stop before the statements of a line (also `b`)
+//! delete line         remove a breakpoint
+//! step                run until the next statement (`s`)
+//! next                run until the next statement not in a call made from here (`n`)
+//! finish              run until the running function returns
+//! continue            run until the next breakpoint (`c`)
+//! locals              the variables of the running function
+//! globals             the global variables
+//! backtrace           the calls in progress (`bt`)
+//! print expression    evaluate an expression where the program stopped (`p`)
+//! list                the source around the current line (`l`)
+//! quit                stop the program (`q`)
+//! ```
+//!
+//! Only the locals of the running function can be seen and used in expressions, not those of
+//! enclosing functions.
+
+use crate::debug::{Debugger, StepMode};
+use crate::error::{LoxError, Result};
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+use crate::resolver::{self, ScopeLocals};
+use crate::statement::Program;
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const HELP: &str = "\
+break [file:]line   stop before the statements of a line (also `b`)
+delete line         remove a breakpoint
+step                run until the next statement (`s`)
+next                run until the next statement not in a call made from here (`n`)
+finish              run until the running function returns
+continue            run until the next breakpoint (`c`)
+locals              the variables of the running function
+globals             the global variables
+backtrace           the calls in progress (`bt`)
+print expression    evaluate an expression where the program stopped (`p`)
+list                the source around the current line (`l`)
+quit                stop the program (`q`)";
+
+/// Runs `program`, parsed from `source` in the file `filename`, under the debugger. Commands
+/// are read from `input` and answered on `output`; what the program prints goes to the
+/// interpreter's output as usual.
+pub fn run(
+    interpreter: &mut Interpreter,
+    program: Program,
+    source: &str,
+    filename: &str,
+    input: impl BufRead + 'static,
+    output: impl Write + 'static,
+) -> Result<Object> {
+    let mut resolution = resolver::resolve_for_debugging(&program)?;
+    let session = Session {
+        input: Box::new(input),
+        output: Box::new(output),
+        filename: filename.to_string(),
+        lines: source.lines().map(String::from).collect(),
+        line_locals: std::mem::take(&mut resolution.line_locals),
+        breakpoints: BTreeSet::new(),
+        mode: StepMode::Entry,
+    };
+    interpreter.add_scopes(resolution);
+    interpreter.set_debugger(session);
+    interpreter.interpret(program)
+}
+
+struct Session {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    filename: String,
+    lines: Vec<String>,
+    line_locals: HashMap<u32, ScopeLocals>,
+    breakpoints: BTreeSet<u32>,
+    mode: StepMode,
+}
+
+/// What to do after a command.
+enum Next {
+    Prompt,
+    Resume(StepMode),
+    Quit,
+}
+
+impl Session {
+    /// Answers commands until one resumes the program. Returns false if it should stop instead.
+    fn stop(&mut self, interpreter: &mut Interpreter, line: u32) -> io::Result<bool> {
+        if self.breakpoints.contains(&line) {
+            writeln!(self.output, "Breakpoint at {}:{}", self.filename, line)?;
+        }
+        self.show_line(line)?;
+        loop {
+            write!(self.output, "(rlox) ")?;
+            self.output.flush()?;
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                return Ok(false);
+            }
+            match self.command(interpreter, line, command.trim())? {
+                Next::Prompt => {}
+                Next::Resume(mode) => {
+                    self.mode = mode;
+                    return Ok(true);
+                }
+                Next::Quit => return Ok(false),
+            }
+        }
+    }
+
+    fn command(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: u32,
+        command: &str,
+    ) -> io::Result<Next> {
+        let (name, argument) = match command.split_once(' ') {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+        let depth = interpreter.call_depth();
+        match name {
+            "" => {}
+            "s" | "step" => return Ok(Next::Resume(StepMode::StepIn)),
+            "n" | "next" => return Ok(Next::Resume(StepMode::StepOver(depth))),
+            "finish" => return Ok(Next::Resume(StepMode::StepOut(depth))),
+            "c" | "continue" => return Ok(Next::Resume(StepMode::Continue)),
+            "q" | "quit" => return Ok(Next::Quit),
+            "b" | "break" => match self.breakpoint_line(argument) {
+                Some(line) => {
+                    self.breakpoints.insert(line);
+                    writeln!(self.output, "Breakpoint at {}:{}", self.filename, line)?;
+                }
+                None => writeln!(self.output, "Expected a line of {}.", self.filename)?,
+            },
+            "delete" => match self.breakpoint_line(argument) {
+                Some(line) if self.breakpoints.remove(&line) => {}
+                _ => writeln!(self.output, "No breakpoint at '{}'.", argument)?,
+            },
+            "locals" => {
+                let locals = self.line_locals.get(&line).cloned().unwrap_or_default();
+                for (name, slot) in locals.iter() {
+                    if let Some(value) = interpreter.local(*slot) {
+                        writeln!(self.output, "{} = {}", name, value)?;
+                    }
+                }
+            }
+            "globals" => {
+                for (name, value) in interpreter.globals() {
+                    writeln!(self.output, "{} = {}", name, value)?;
+                }
+            }
+            "bt" | "backtrace" => {
+                for frame in interpreter.call_frames() {
+                    writeln!(self.output, "  in {}", frame.name)?;
+                }
+                writeln!(self.output, "  in <script>")?;
+            }
+            "p" | "print" => {
+                let locals = self.line_locals.get(&line).cloned().unwrap_or_default();
+                match interpreter.evaluate_in_frame(argument, &locals) {
+                    Ok(value) => writeln!(self.output, "{}", value)?,
+                    Err(error) => writeln!(self.output, "{}", error)?,
+                }
+            }
+            "l" | "list" => {
+                let first = line.saturating_sub(3).max(1);
+                for number in first..=line + 3 {
+                    if let Some(text) = self.lines.get(number as usize - 1) {
+                        let marker = if number == line { "->" } else { "  " };
+                        writeln!(self.output, "{} {:>4}  {}", marker, number, text)?;
+                    }
+                }
+            }
+            "h" | "help" => writeln!(self.output, "{}", HELP)?,
+            _ => writeln!(self.output, "Unknown command '{}', try 'help'.", name)?,
+        }
+        Ok(Next::Prompt)
+    }
+
+    /// Reads `line` or `file:line`, where the file has to be the one being debugged.
+    fn breakpoint_line(&self, argument: &str) -> Option<u32> {
+        let line = match argument.rsplit_once(':') {
+            Some((file, line)) => {
+                let same_file = file == self.filename
+                    || Path::new(&self.filename).file_name() == Some(file.as_ref());
+                if !same_file {
+                    return None;
+                }
+                line
+            }
+            None => argument,
+        };
+        line.parse().ok().filter(|line| *line > 0)
+    }
+
+    fn show_line(&mut self, line: u32) -> io::Result<()> {
+        let text = self.lines.get(line as usize - 1).map_or("", String::as_str);
+        writeln!(self.output, "{:>4}  {}", line, text)
+    }
+}
+
+impl Debugger for Session {
+    fn statement(&mut self, interpreter: &mut Interpreter, line: u32) -> Result<()> {
+        let depth = interpreter.call_depth();
+        if !self.breakpoints.contains(&line) && !self.mode.stops_at(depth) {
+            return Ok(());
+        }
+        let resumed = self.stop(interpreter, line).map_err(|error| {
+            LoxError::InterpreterError(format!("Debugging failed: {}", error).into())
+        })?;
+        if resumed {
+            Ok(())
+        } else {
+            Err(LoxError::InterpreterError(
+                "Stopped by the debugger.".into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::run;
+    use crate::error::LoxError;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::Output;
+    use crate::{lexer, parser};
+
+    use std::io;
+
+    const SOURCE: &str = "\
+var total = 0;
+fun add(a, b) {
+  var sum = a + b;
+  return sum;
+}
+{
+  var x = 1;
+  total = add(x, 2);
+}
+print total;
+";
+
+    /// Debugs `SOURCE` with `commands` and returns the transcript.
+    fn session(commands: &str) -> (Result<(), LoxError>, String) {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(output.clone());
+        let (tokens, _) = lexer::lex(SOURCE);
+        let (program, _) = parser::parse(&tokens);
+        let input = io::Cursor::new(commands.to_string());
+        let result = run(
+            &mut interpreter,
+            program,
+            SOURCE,
+            "test.lox",
+            input,
+            output.clone(),
+        );
+        let transcript = String::from_utf8(output.0.borrow().clone()).unwrap();
+        (result.map(|_| ()), transcript)
+    }
+
+    #[test]
+    fn breakpoints_and_locals() {
+        let commands = "b test.lox:4\nb other.lox:4\nc\nlocals\np sum * 10\np a = 5\nlocals\nbt\nfinish\nlocals\nc\n";
+        let (result, transcript) = session(commands);
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            transcript,
+            "   1  var total = 0;
+(rlox) Breakpoint at test.lox:4
+(rlox) Expected a line of test.lox.
+(rlox) Breakpoint at test.lox:4
+   4    return sum;
+(rlox) a = 1
+b = 2
+sum = 3
+(rlox) 30
+(rlox) 5
+(rlox) a = 5
+b = 2
+sum = 3
+(rlox)   in add()
+  in <script>
+(rlox)   10  print total;
+(rlox) (rlox) 3
+"
+        );
+    }
+
+    #[test]
+    fn stepping() {
+        let (result, transcript) = session("n\nn\ns\ns\nlocals\nq\n");
+        assert_eq!(
+            result,
+            Err(LoxError::InterpreterError(
+                "Stopped by the debugger.".into()
+            ))
+        );
+        assert_eq!(
+            transcript,
+            "   1  var total = 0;
+(rlox)    2  fun add(a, b) {
+(rlox)    7    var x = 1;
+(rlox)    8    total = add(x, 2);
+(rlox)    3    var sum = a + b;
+(rlox) a = 1
+b = 2
+(rlox) "
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Doc comments and the documentation generator behind `rlox doc`.
+//!
+//! A run of `///` comments directly above a function, class, enum or method documents it:
+//!
+//! ```text
+//! /// Greets whoever is named.
+//! ///
+//! /// Prints a single line.
+//! fun greet(name) { print "Hi, " + name; }
+//! ```
+//!
+//! [`attach`] stores these comments in the [`Ast`], [`items`] collects the documented
+//! declarations of a program and [`markdown`] and [`html`] render them.
+
+use crate::ast_printer;
+use crate::statement::{Ast, Program, Stmt, StmtRef};
+
+use std::fmt::Write;
+
+/// Attaches the doc comments of `source` to the functions, classes, enums and methods
+/// `program` was parsed from.
+pub fn attach(source: &str, program: &mut Program) {
+    let lines: Vec<_> = source.lines().collect();
+    let mut declarations = vec![];
+    collect_declarations(&program.ast, &program.statements, &mut declarations);
+    for stmt in declarations {
+        let doc = program
+            .ast
+            .line(stmt)
+            .and_then(|line| comment_above(&lines, line));
+        if let Some(doc) = doc {
+            program.ast.set_doc(stmt, doc);
+        }
+    }
+}
+
+/// The run of `///` comments right above line `line` (counting from 1) of `lines`, without the
+/// slashes.
+pub(crate) fn comment_above(lines: &[&str], line: u32) -> Option<String> {
+    let line = line as usize;
+    let comments: Vec<_> = lines[..line.saturating_sub(1).min(lines.len())]
+        .iter()
+        .rev()
+        .map_while(|line| line.trim_start().strip_prefix("///"))
+        .collect();
+    if comments.is_empty() {
+        return None;
+    }
+    let doc: Vec<_> = comments
+        .iter()
+        .rev()
+        .map(|comment| comment.strip_prefix(' ').unwrap_or(comment).trim_end())
+        .collect();
+    Some(doc.join("\n"))
+}
+
+/// The functions, classes and enums among `stmts`, including nested ones and methods.
+fn collect_declarations(ast: &Ast, stmts: &[StmtRef], declarations: &mut Vec<StmtRef>) {
+    for &stmt in stmts {
+        match &ast[stmt] {
+            Stmt::Function { body, .. } => {
+                declarations.push(stmt);
+                collect_declarations(ast, body, declarations);
+            }
+            Stmt::Class { methods, .. } => {
+                declarations.push(stmt);
+                collect_declarations(ast, methods, declarations);
+            }
+            Stmt::Enum { .. } => declarations.push(stmt),
+            Stmt::Block { statements } => collect_declarations(ast, statements, declarations),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                collect_declarations(ast, &[*then_branch], declarations);
+                collect_declarations(ast, else_branch.as_slice(), declarations);
+            }
+            Stmt::While { body, .. } | Stmt::DoWhile { body, .. } | Stmt::Labeled { body, .. } => {
+                collect_declarations(ast, &[*body], declarations)
+            }
+            Stmt::Switch { cases, default, .. } => {
+                let bodies: Vec<_> = cases
+                    .iter()
+                    .map(|(_, body)| *body)
+                    .chain(*default)
+                    .collect();
+                collect_declarations(ast, &bodies, declarations);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A documented declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    /// `fun name(a, b)`, `class Name < Base`, `enum Name { A, B }` or, for methods, `name(a, b)`.
+    pub signature: String,
+    pub doc: Option<String>,
+    pub methods: Vec<Item>,
+}
+
+/// The top-level functions, classes and enums of `program`, with the methods of the classes.
+pub fn items(program: &Program) -> Vec<Item> {
+    let ast = &program.ast;
+    let item = |stmt: StmtRef, signature: String| Item {
+        signature,
+        doc: ast.doc(stmt).map(String::from),
+        methods: vec![],
+    };
+    program
+        .statements
+        .iter()
+        .filter_map(|&stmt| match &ast[stmt] {
+            Stmt::Function { .. } => Some(item(
+                stmt,
+                format!("fun {}", ast_printer::signature(ast, stmt)),
+            )),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let mut signature = format!("class {}", name);
+                if let Some(superclass) = superclass {
+                    let superclass = ast_printer::expr_to_string(ast, *superclass);
+                    write!(signature, " < {}", superclass).unwrap();
+                }
+                let mut class = item(stmt, signature);
+                class.methods = methods
+                    .iter()
+                    .map(|&method| item(method, ast_printer::signature(ast, method)))
+                    .collect();
+                Some(class)
+            }
+            Stmt::Enum { name, members, .. } => Some(item(
+                stmt,
+                format!("enum {} {{ {} }}", name, members.join(", ")),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A Markdown page with a section per module, as pairs of the module's name and items.
+pub fn markdown(modules: &[(String, Vec<Item>)]) -> String {
+    let mut out = String::new();
+    for (name, items) in modules {
+        writeln!(out, "# {}\n", name).unwrap();
+        for item in items {
+            writeln!(out, "## `{}`\n", item.signature).unwrap();
+            write_markdown_doc(&mut out, &item.doc);
+            for method in &item.methods {
+                writeln!(out, "### `{}`\n", method.signature).unwrap();
+                write_markdown_doc(&mut out, &method.doc);
+            }
+        }
+    }
+    out
+}
+
+fn write_markdown_doc(out: &mut String, doc: &Option<String>) {
+    if let Some(doc) = doc {
+        writeln!(out, "{}\n", doc).unwrap();
+    }
+}
+
+/// An HTML page with a section per module, as pairs of the module's name and items.
+pub fn html(modules: &[(String, Vec<Item>)]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Documentation</title>\n</head>\n<body>\n",
+    );
+    for (name, items) in modules {
+        writeln!(out, "<h1>{}</h1>", escape(name)).unwrap();
+        for item in items {
+            writeln!(out, "<h2><code>{}</code></h2>", escape(&item.signature)).unwrap();
+            write_html_doc(&mut out, &item.doc);
+            for method in &item.methods {
+                writeln!(out, "<h3><code>{}</code></h3>", escape(&method.signature)).unwrap();
+                write_html_doc(&mut out, &method.doc);
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Blank lines separate the paragraphs of a doc comment.
+fn write_html_doc(out: &mut String, doc: &Option<String>) {
+    let doc = match doc {
+        Some(doc) => doc,
+        None => return,
+    };
+    for paragraph in doc.split("\n\n").filter(|text| !text.trim().is_empty()) {
+        writeln!(out, "<p>{}</p>", escape(paragraph.trim())).unwrap();
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{attach, html, items, markdown, Item};
+    use crate::lexer;
+    use crate::parser;
+
+    const SOURCE: &str = r#"
+/// Greets people.
+///
+/// Has two lines.
+class Greeter < Base {
+    /// Remembers `name`.
+    init(name) { this.name = name; }
+
+    greet() -> Nil {
+        /// Not a top-level item.
+        fun inner() {}
+    }
+}
+
+/// Separated by a blank line.
+
+fun undocumented(a: Number, b) {}
+// not a doc comment
+fun plain() {}
+/// The primary colors.
+enum Color { Red, Green, Blue, }
+"#;
+
+    fn documented_items() -> Vec<Item> {
+        let (tokens, _) = lexer::lex(SOURCE);
+        let (mut program, errors) = parser::parse(&tokens);
+        assert!(errors.is_empty());
+        attach(SOURCE, &mut program);
+        items(&program)
+    }
+
+    #[test]
+    fn attached() {
+        let items = documented_items();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].signature, "class Greeter < Base");
+        assert_eq!(
+            items[0].doc.as_deref(),
+            Some("Greets people.\n\nHas two lines.")
+        );
+        assert_eq!(items[0].methods[0].signature, "init(name)");
+        assert_eq!(
+            items[0].methods[0].doc.as_deref(),
+            Some("Remembers `name`.")
+        );
+        assert_eq!(items[0].methods[1].signature, "greet() -> Nil");
+        assert_eq!(items[0].methods[1].doc, None);
+        assert_eq!(items[1].signature, "fun undocumented(a: Number, b)");
+        assert_eq!(items[1].doc, None);
+        assert_eq!(items[2].doc, None);
+        assert_eq!(items[3].signature, "enum Color { Red, Green, Blue }");
+        assert_eq!(items[3].doc.as_deref(), Some("The primary colors."));
+    }
+
+    #[test]
+    fn rendered() {
+        let modules = vec![("greeter.lox".to_string(), documented_items())];
+        let markdown = markdown(&modules);
+        assert!(markdown.starts_with(
+            "# greeter.lox\n\n## `class Greeter < Base`\n\nGreets people.\n\nHas two lines.\n\n### `init(name)`\n\nRemembers `name`.\n\n### `greet() -> Nil`\n\n"
+        ));
+
+        let html = html(&modules);
+        assert!(html.contains("<h2><code>class Greeter &lt; Base</code></h2>\n<p>Greets people.</p>\n<p>Has two lines.</p>\n"));
+        assert!(html.contains("<h3><code>greet() -&gt; Nil</code></h3>\n<h2>"));
+    }
+}
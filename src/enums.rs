@@ -0,0 +1,60 @@
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// An `enum` declaration at runtime, holding its members in the order they were declared.
+#[derive(Debug)]
+pub struct LoxEnum {
+    name: Rc<str>,
+    members: Vec<Rc<EnumMember>>,
+}
+
+impl LoxEnum {
+    pub fn new(name: Rc<str>, members: &[Rc<str>]) -> Self {
+        let members = members
+            .iter()
+            .map(|member| {
+                Rc::new(EnumMember {
+                    enum_name: Rc::clone(&name),
+                    name: Rc::clone(member),
+                })
+            })
+            .collect();
+        Self { name, members }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn member(&self, name: &str) -> Option<Rc<EnumMember>> {
+        self.members
+            .iter()
+            .find(|member| &*member.name == name)
+            .cloned()
+    }
+}
+
+impl Display for LoxEnum {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// A member of an enum. Every member exists once and only equals itself.
+#[derive(Debug)]
+pub struct EnumMember {
+    enum_name: Rc<str>,
+    name: Rc<str>,
+}
+
+impl EnumMember {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for EnumMember {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.enum_name, self.name)
+    }
+}
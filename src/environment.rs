@@ -1,102 +1,97 @@
 use crate::error::{LoxError, Result};
+use crate::gc::Marker;
 use crate::object::Object;
-use crate::resolver::Depth;
 
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
-#[derive(Debug)]
+/// The global variables, looked up by name. Locals live on the `Stack`.
+#[derive(Debug, Clone)]
 pub struct Environment {
-    enclosing: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Rc<Object>>,
+    values: HashMap<Rc<str>, Object>,
+    /// The globals declared with `const`, until they are declared again.
+    constants: HashSet<Rc<str>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
             values: HashMap::new(),
-            enclosing: None,
+            constants: HashSet::new(),
         }
     }
 
-    pub fn with_enclosing(environment: Rc<RefCell<Environment>>) -> Self {
-        Environment {
-            values: HashMap::new(),
-            enclosing: Some(environment),
-        }
+    pub fn trace(&self, marker: &mut Marker) {
+        self.values
+            .values()
+            .for_each(|value| marker.mark_object(value));
     }
 
-    pub fn define(&mut self, name: &str, value: Rc<Object>) {
-        self.values.insert(name.to_owned(), value);
+    pub fn define(&mut self, name: Rc<str>, value: Object) {
+        self.constants.remove(&name);
+        self.values.insert(name, value);
     }
 
-    pub fn assign(&mut self, depth: Depth, name: &str, value: Rc<Object>) -> Result<()> {
-        if depth == 0 {
-            self.assign_here(name, value)
-        } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .assign(depth - 1, name, value)
-        }
+    pub fn define_constant(&mut self, name: Rc<str>, value: Object) {
+        self.constants.insert(Rc::clone(&name));
+        self.values.insert(name, value);
     }
 
-    fn assign_here(&mut self, name: &str, value: Rc<Object>) -> Result<()> {
-        if self.values.contains_key(name) {
-            self.values.insert(name.to_owned(), value);
+    pub fn assign(&mut self, name: &str, value: Object) -> Result<()> {
+        if self.constants.contains(name) {
+            return Err(LoxError::EnvironmentError(format!(
+                "Cannot assign to constant '{}'.",
+                name
+            )));
+        }
+        if let Some(current) = self.values.get_mut(name) {
+            *current = value;
             Ok(())
         } else {
-            Err(LoxError::EnvironmentError(format!(
-                "Undefined variable '{}'.",
-                name
-            )))
+            Err(undefined_variable(name))
         }
     }
 
-    pub fn get(&self, depth: Depth, name: &str) -> Result<Rc<Object>> {
-        if depth == 0 {
-            self.get_here(name)
-        } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get(depth - 1, name)
-        }
+    pub fn iter(&self) -> impl Iterator<Item = (&Rc<str>, &Object)> {
+        self.values.iter()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Object> {
+        self.values.values_mut()
     }
 
-    fn get_here(&self, name: &str) -> Result<Rc<Object>> {
+    pub fn get(&self, name: &str) -> Result<Object> {
         if let Some(value) = self.values.get(name) {
             Ok(value.clone())
         } else {
-            Err(LoxError::EnvironmentError(format!(
-                "Undefined variable '{}'.",
-                name
-            )))
+            Err(undefined_variable(name))
         }
     }
 }
 
+pub fn undefined_variable(name: &str) -> LoxError {
+    LoxError::EnvironmentError(format!("Undefined variable '{}'.", name))
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::Environment;
     use crate::object::Object;
 
-    use std::cell::RefCell;
-    use std::rc::Rc;
-
     #[test]
     fn get() {
-        let first = Rc::new(RefCell::new(Environment::new()));
-        let second = Environment::with_enclosing(first.clone());
+        let mut environment = Environment::new();
+        environment.define("answer".into(), Object::Number(42.0));
+        environment.assign("answer", Object::Number(21.0)).unwrap();
 
-        first
-            .borrow_mut()
-            .define("answer", Rc::new(Object::Number(42.0)));
+        assert_eq!(environment.get("answer").unwrap(), Object::Number(21.0));
+        assert!(environment.get("question").is_err());
+        assert!(environment.assign("question", Object::Nil).is_err());
 
-        assert_eq!(*second.get(1, "answer").unwrap(), Object::Number(42.0));
+        environment.define_constant("limit".into(), Object::Number(10.0));
+        assert!(environment.assign("limit", Object::Number(5.0)).is_err());
+        environment.define("limit".into(), Object::Number(5.0));
+        assert!(environment.assign("limit", Object::Number(6.0)).is_ok());
     }
 }
@@ -7,11 +7,16 @@ use std::rc::Rc;
 #[derive(Debug, PartialEq)]
 pub enum LoxError {
     ParserError(Option<u32>, Cow<'static, str>),
-    LexerError(u32, Cow<'static, str>),
+    /// The line and column, counting characters from 1, where the bad token starts.
+    LexerError(u32, u32, Cow<'static, str>),
     InterpreterError(Cow<'static, str>),
     EnvironmentError(String),
     ResolverError(&'static str),
-    Return(Rc<Object>),
+    TypeError(Option<u32>, Cow<'static, str>),
+    JsonError(Cow<'static, str>),
+    Return(Object),
+    /// A `break` on its way to the loop it ends, the innermost one unless it names a label.
+    Break(Option<Rc<str>>),
 }
 
 impl Display for LoxError {
@@ -23,16 +28,40 @@ impl Display for LoxError {
             LoxError::ParserError(None, ref reason) => {
                 write!(f, "Parser error in last line: {}", reason)
             }
-            LoxError::LexerError(line, ref reason) => {
-                write!(f, "Lexer error in line {}: {}", line, reason)
+            LoxError::LexerError(line, column, ref reason) => {
+                write!(
+                    f,
+                    "Lexer error in line {}, column {}: {}",
+                    line, column, reason
+                )
             }
             LoxError::InterpreterError(ref reason) => write!(f, "{}", reason),
             LoxError::EnvironmentError(ref reason) => write!(f, "{}", reason),
             LoxError::ResolverError(ref reason) => write!(f, "{}", reason),
+            LoxError::TypeError(Some(line), ref reason) => {
+                write!(f, "Type error in line {}: {}", line, reason)
+            }
+            LoxError::TypeError(None, ref reason) => write!(f, "Type error: {}", reason),
+            LoxError::JsonError(ref reason) => write!(f, "{}", reason),
             LoxError::Return(_value) => write!(
                 f,
                 "Forgot to handle return statement, this should not happen"
             ),
+            LoxError::Break(_label) => write!(
+                f,
+                "Forgot to handle break statement, this should not happen"
+            ),
+        }
+    }
+}
+
+impl LoxError {
+    /// The line the error was found in, if it is known.
+    pub fn line(&self) -> Option<u32> {
+        match self {
+            LoxError::ParserError(line, _) | LoxError::TypeError(line, _) => *line,
+            LoxError::LexerError(line, ..) => Some(*line),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,125 @@
+use crate::lox::LoxThread;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+/// An interpreter owned by a C host. Code runs on a thread of its own, so deep recursion in Lox
+/// does not depend on the stack size of the host's thread.
+pub struct RloxInterpreter {
+    lox: LoxThread,
+    result: Option<CString>,
+    error: Option<CString>,
+}
+
+/// Turns a message into a C string, replacing interior nul bytes which C strings cannot hold.
+fn c_string(message: String) -> CString {
+    CString::new(message.replace('\0', "\\0")).unwrap()
+}
+
+/// Creates an interpreter. It has to be freed with `rlox_free`.
+#[no_mangle]
+pub extern "C" fn rlox_new() -> *mut RloxInterpreter {
+    Box::into_raw(Box::new(RloxInterpreter {
+        lox: LoxThread::spawn(),
+        result: None,
+        error: None,
+    }))
+}
+
+/// Runs `source`, with the globals of earlier calls still defined. Returns 0 on success and 1 if
+/// the code could not be compiled or failed at runtime, see `rlox_get_error`.
+///
+/// # Safety
+///
+/// `rlox` has to come from `rlox_new` and `source` has to be a nul terminated UTF-8 string. The
+/// string stays owned by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn rlox_eval(rlox: *mut RloxInterpreter, source: *const c_char) -> c_int {
+    let rlox = &mut *rlox;
+    let outcome = match CStr::from_ptr(source).to_str() {
+        Ok(source) => rlox.lox.eval(source),
+        Err(_) => Err("Source code is not valid UTF-8.".to_string()),
+    };
+    match outcome {
+        Ok(value) => {
+            rlox.result = Some(c_string(value));
+            rlox.error = None;
+            0
+        }
+        Err(message) => {
+            rlox.result = None;
+            rlox.error = Some(c_string(message));
+            1
+        }
+    }
+}
+
+/// The value of the last statement run by a successful `rlox_eval`, printed as `print` would,
+/// or NULL if the last call failed.
+///
+/// # Safety
+///
+/// `rlox` has to come from `rlox_new`. The string is owned by the interpreter and valid until
+/// the next call to `rlox_eval` or `rlox_free`.
+#[no_mangle]
+pub unsafe extern "C" fn rlox_get_result(rlox: *const RloxInterpreter) -> *const c_char {
+    (*rlox)
+        .result
+        .as_ref()
+        .map_or(ptr::null(), |result| result.as_ptr())
+}
+
+/// The error message of the last `rlox_eval`, or NULL if it succeeded.
+///
+/// # Safety
+///
+/// `rlox` has to come from `rlox_new`. The string is owned by the interpreter and valid until
+/// the next call to `rlox_eval` or `rlox_free`.
+#[no_mangle]
+pub unsafe extern "C" fn rlox_get_error(rlox: *const RloxInterpreter) -> *const c_char {
+    (*rlox)
+        .error
+        .as_ref()
+        .map_or(ptr::null(), |error| error.as_ptr())
+}
+
+/// Frees an interpreter and all strings it handed out. Passing NULL does nothing.
+///
+/// # Safety
+///
+/// `rlox` has to come from `rlox_new` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rlox_free(rlox: *mut RloxInterpreter) {
+    if !rlox.is_null() {
+        drop(Box::from_raw(rlox));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{rlox_eval, rlox_free, rlox_get_error, rlox_get_result, rlox_new};
+
+    use std::ffi::{CStr, CString};
+
+    #[test]
+    fn eval() {
+        unsafe {
+            let rlox = rlox_new();
+            let source = CString::new("var answer = 6 * 7; answer;").unwrap();
+            assert_eq!(rlox_eval(rlox, source.as_ptr()), 0);
+            assert_eq!(CStr::from_ptr(rlox_get_result(rlox)).to_str(), Ok("42"));
+            assert!(rlox_get_error(rlox).is_null());
+
+            let source = CString::new("answer();").unwrap();
+            assert_eq!(rlox_eval(rlox, source.as_ptr()), 1);
+            assert!(rlox_get_result(rlox).is_null());
+            assert_eq!(
+                CStr::from_ptr(rlox_get_error(rlox)).to_str(),
+                Ok("Can only call functions and classes.")
+            );
+            rlox_free(rlox);
+        }
+    }
+}
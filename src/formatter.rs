@@ -0,0 +1,916 @@
+//! The formatter behind `rlox fmt`. It prints the [`cst`](crate::cst) of a program with
+//! canonical spacing, four spaces of indentation per block and one statement per line.
+//!
+//! Comments stay where they were: on a line of their own or after the code they follow. Single
+//! blank lines between statements are kept, longer runs are shortened to one. Argument and
+//! parameter lists that would make a line longer than [`MAX_WIDTH`] are put one item per line.
+//! Formatting formatted code changes nothing.
+//!
+//! `fun add(a,b){return a+b;}` becomes
+//!
+//! ```text
+//! fun add(a, b) {
+//!     return a + b;
+//! }
+//! ```
+
+use crate::cst::{self, NodeKind, SyntaxElement, SyntaxNode, SyntaxToken, TokenKind};
+use crate::error::LoxError;
+use crate::recursion;
+use crate::token::TokenType;
+
+/// The length of a line from which on lists are wrapped.
+pub const MAX_WIDTH: usize = 100;
+
+const INDENT: &str = "    ";
+
+/// Formats `source`, or returns the errors that keep it from being parsed.
+pub fn format(source: &str) -> Result<String, Vec<LoxError>> {
+    let cst = cst::parse(source);
+    if !cst.errors.is_empty() {
+        return Err(cst.errors);
+    }
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+        continuation: false,
+        pending: vec![],
+        newline_seen: true,
+        statement_start: true,
+    };
+    printer.program(&cst.root);
+    Ok(printer.out)
+}
+
+#[derive(Debug, Clone)]
+enum Trivia {
+    Comment { text: String, own_line: bool },
+    BlankLine,
+}
+
+#[derive(Clone, Copy)]
+enum Element<'a> {
+    Token(&'a SyntaxToken),
+    Node(&'a SyntaxNode),
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+    /// Whether a comment broke the current statement, so its rest is indented once more.
+    continuation: bool,
+    /// Comments and blank lines not printed yet, they go before the next token.
+    pending: Vec<Trivia>,
+    /// Whether there was a line break since the last token.
+    newline_seen: bool,
+    /// Whether the next token starts a statement, the only place blank lines are kept.
+    statement_start: bool,
+}
+
+/// What `Printer::list` restores to print a list again.
+struct Snapshot {
+    length: usize,
+    indent: usize,
+    continuation: bool,
+    pending: Vec<Trivia>,
+    newline_seen: bool,
+    statement_start: bool,
+}
+
+impl Printer {
+    /// Calls `f` for every token and node in `children`, collecting the trivia between them.
+    fn walk<'a>(
+        &mut self,
+        children: &'a [SyntaxElement],
+        mut f: impl FnMut(&mut Self, Element<'a>),
+    ) {
+        for child in children {
+            match child {
+                SyntaxElement::Token(token) if token.kind.is_trivia() => self.trivia(token),
+                SyntaxElement::Token(token) => f(self, Element::Token(token)),
+                SyntaxElement::Node(node) => f(self, Element::Node(node)),
+            }
+        }
+    }
+
+    fn trivia(&mut self, token: &SyntaxToken) {
+        match token.kind {
+            TokenKind::Whitespace => {
+                let newlines = token.text.matches('\n').count();
+                if newlines > 0 {
+                    self.newline_seen = true;
+                }
+                if newlines > 1 {
+                    self.pending.push(Trivia::BlankLine);
+                }
+            }
+            TokenKind::Comment => self.pending.push(Trivia::Comment {
+                text: token.text.trim_end().to_string(),
+                own_line: self.newline_seen,
+            }),
+            // only in code with errors, which is not formatted
+            _ => {}
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.at_line_start() {
+            let indent = self.indent + self.continuation as usize;
+            self.out.push_str(&INDENT.repeat(indent));
+        }
+        self.out.push_str(text);
+    }
+
+    fn space(&mut self) {
+        if !self.at_line_start() && !self.out.ends_with(' ') {
+            self.out.push(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+        self.continuation = false;
+    }
+
+    /// Prints the pending comments and blank lines.
+    fn flush(&mut self) {
+        for trivia in std::mem::take(&mut self.pending) {
+            match trivia {
+                Trivia::BlankLine => {
+                    let keep = self.statement_start
+                        && !self.out.is_empty()
+                        && !self.out.ends_with("{\n")
+                        && !self.out.ends_with("\n\n");
+                    if keep {
+                        self.newline();
+                        self.out.push('\n');
+                    }
+                }
+                Trivia::Comment {
+                    text,
+                    own_line: false,
+                } => {
+                    // a comment after code stays on the line of that code
+                    if self.out.ends_with('\n') {
+                        self.out.pop();
+                    }
+                    let trimmed = self.out.trim_end_matches(' ').len();
+                    self.out.truncate(trimmed);
+                    self.out.push(' ');
+                    self.out.push_str(&text);
+                    self.out.push('\n');
+                    self.continuation = !self.statement_start;
+                }
+                Trivia::Comment {
+                    text,
+                    own_line: true,
+                } => {
+                    let continuation = !self.statement_start;
+                    self.newline();
+                    self.continuation = continuation;
+                    self.write(&text);
+                    self.out.push('\n');
+                }
+            }
+        }
+    }
+
+    fn token(&mut self, token: &SyntaxToken) {
+        self.flush();
+        self.write(&token.text);
+        self.newline_seen = false;
+        self.statement_start = false;
+    }
+
+    fn program(&mut self, program: &SyntaxNode) {
+        self.walk(&program.children, |printer, element| {
+            if let Element::Node(statement) = element {
+                printer.statement_start = true;
+                printer.statement(statement);
+                printer.newline();
+            }
+        });
+        // blank lines at the end of the file go
+        self.pending
+            .retain(|trivia| !matches!(trivia, Trivia::BlankLine));
+        self.statement_start = true;
+        self.flush();
+        self.newline();
+    }
+
+    fn statement(&mut self, node: &SyntaxNode) {
+        recursion::grow_stack(|| match node.kind {
+            NodeKind::Var => self.walk(&node.children, |printer, element| match element {
+                Element::Token(token) => match token_type(token) {
+                    Some(TokenType::Var) | Some(TokenType::Const) | Some(TokenType::Comma) => {
+                        printer.token(token);
+                        printer.space();
+                    }
+                    Some(TokenType::Equal) => printer.spaced(token),
+                    _ => printer.token(token),
+                },
+                Element::Node(node) if node.kind == NodeKind::TypeAnnotation => {
+                    printer.type_annotation(node)
+                }
+                Element::Node(node) if node.kind == NodeKind::Pattern => {
+                    printer.walk(&node.children, |printer, element| {
+                        if let Element::Token(token) = element {
+                            printer.token(token);
+                            if token_type(token) == Some(&TokenType::Comma) {
+                                printer.space();
+                            }
+                        }
+                    })
+                }
+                Element::Node(node) => printer.expression(node),
+            }),
+            NodeKind::Print | NodeKind::Return => {
+                self.walk(&node.children, |printer, element| match element {
+                    Element::Token(token) => printer.token(token),
+                    Element::Node(node) => {
+                        printer.space();
+                        printer.expression(node);
+                    }
+                })
+            }
+            NodeKind::ExpressionStatement => {
+                self.walk(&node.children, |printer, element| match element {
+                    Element::Token(token) => printer.token(token),
+                    Element::Node(node) => printer.expression(node),
+                })
+            }
+            NodeKind::Block => self.block(node),
+            NodeKind::If | NodeKind::While => self.conditional(node),
+            NodeKind::For => self.for_statement(node),
+            NodeKind::Switch => self.switch(node),
+            NodeKind::DoWhile => self.do_while(node),
+            NodeKind::Loop => self.walk(&node.children, |printer, element| match element {
+                Element::Token(token) => printer.token(token),
+                Element::Node(node) => printer.body(node),
+            }),
+            NodeKind::Labeled => self.walk(&node.children, |printer, element| match element {
+                Element::Token(token) if token_type(token) == Some(&TokenType::Colon) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Element::Token(token) => printer.token(token),
+                Element::Node(node) => printer.statement(node),
+            }),
+            NodeKind::Break => self.walk(&node.children, |printer, element| match element {
+                Element::Token(token) if token_type(token) == Some(&TokenType::Identifier) => {
+                    printer.space();
+                    printer.token(token);
+                }
+                Element::Token(token) => printer.token(token),
+                Element::Node(node) => printer.expression(node),
+            }),
+            NodeKind::Function => self.function(node),
+            NodeKind::Class => self.class(node),
+            NodeKind::Enum => self.enum_declaration(node),
+            _ => self.verbatim(node),
+        })
+    }
+
+    fn open_brace(&mut self, token: &SyntaxToken) {
+        self.space();
+        self.token(token);
+        self.indent += 1;
+        self.newline();
+    }
+
+    fn close_brace(&mut self, token: &SyntaxToken) {
+        self.statement_start = true;
+        self.flush();
+        self.indent -= 1;
+        self.newline();
+        if self.out.ends_with("{\n") {
+            self.out.pop();
+        }
+        self.token(token);
+    }
+
+    /// Prints the statements of a block or the methods of a class, each on a line of its own.
+    fn member(&mut self, node: &SyntaxNode) {
+        self.statement_start = true;
+        self.statement(node);
+        self.newline();
+    }
+
+    fn block(&mut self, node: &SyntaxNode) {
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) if token_type(token) == Some(&TokenType::LeftBrace) => {
+                printer.open_brace(token)
+            }
+            Element::Token(token) => printer.close_brace(token),
+            Element::Node(node) => printer.member(node),
+        })
+    }
+
+    /// `while` follows a block body on its line, and any other body on the next line.
+    fn do_while(&mut self, node: &SyntaxNode) {
+        let mut block_body = false;
+        let mut body_done = false;
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::Do) => printer.token(token),
+                Some(TokenType::While) => {
+                    if block_body {
+                        printer.space();
+                    } else {
+                        printer.newline();
+                    }
+                    printer.token(token);
+                    printer.space();
+                }
+                _ => printer.token(token),
+            },
+            Element::Node(child) if !body_done => {
+                body_done = true;
+                block_body = child.kind == NodeKind::Block;
+                printer.body(child);
+            }
+            Element::Node(child) => printer.expression(child),
+        })
+    }
+
+    /// Cases on lines of their own, their statements indented below them.
+    fn switch(&mut self, node: &SyntaxNode) {
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::Switch) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Some(TokenType::LeftBrace) => printer.open_brace(token),
+                Some(TokenType::RightBrace) => printer.close_brace(token),
+                _ => printer.token(token),
+            },
+            Element::Node(node) if node.kind == NodeKind::Case => {
+                printer.statement_start = true;
+                printer.case(node);
+                printer.newline();
+            }
+            Element::Node(node) => printer.expression(node),
+        })
+    }
+
+    fn case(&mut self, node: &SyntaxNode) {
+        let mut colon_seen = false;
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::Case) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Some(TokenType::Colon) => {
+                    printer.token(token);
+                    printer.indent += 1;
+                    printer.newline();
+                    colon_seen = true;
+                }
+                _ => printer.token(token),
+            },
+            Element::Node(node) if colon_seen => printer.member(node),
+            Element::Node(node) => printer.expression(node),
+        });
+        if colon_seen {
+            self.statement_start = true;
+            self.flush();
+            self.indent -= 1;
+        }
+    }
+
+    /// The statement of an `if`, `while`, `do` or `for`: a block on the same line, anything else
+    /// indented on the next one.
+    fn body(&mut self, node: &SyntaxNode) {
+        if node.kind == NodeKind::Block {
+            self.space();
+            self.statement(node);
+        } else {
+            self.indent += 1;
+            self.newline();
+            self.statement_start = true;
+            self.statement(node);
+            self.indent -= 1;
+        }
+    }
+
+    fn conditional(&mut self, node: &SyntaxNode) {
+        let mut nodes = 0;
+        let mut block_before_else = false;
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::If) | Some(TokenType::While) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Some(TokenType::Else) => {
+                    if block_before_else {
+                        printer.space();
+                    } else {
+                        printer.newline();
+                    }
+                    printer.token(token);
+                }
+                _ => printer.token(token),
+            },
+            Element::Node(child) => {
+                nodes += 1;
+                match nodes {
+                    1 => printer.expression(child),
+                    2 => {
+                        block_before_else = child.kind == NodeKind::Block;
+                        printer.body(child);
+                    }
+                    // `else if` stays on one line
+                    _ if child.kind == NodeKind::If => {
+                        printer.space();
+                        printer.statement(child);
+                    }
+                    _ => printer.body(child),
+                }
+            }
+        })
+    }
+
+    fn for_statement(&mut self, node: &SyntaxNode) {
+        let mut clauses_done = false;
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::For) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Some(TokenType::RightParen) => {
+                    clauses_done = true;
+                    printer.token(token);
+                }
+                _ => printer.token(token),
+            },
+            Element::Node(child) if clauses_done => printer.body(child),
+            Element::Node(child) => {
+                if !printer.out.ends_with('(') {
+                    printer.space();
+                }
+                match child.kind {
+                    NodeKind::Var | NodeKind::ExpressionStatement => printer.statement(child),
+                    _ => printer.expression(child),
+                }
+            }
+        })
+    }
+
+    fn function(&mut self, node: &SyntaxNode) {
+        let (open, close) = match list_bounds(node) {
+            Some(bounds) => bounds,
+            None => return self.verbatim(node),
+        };
+        self.walk(&node.children[..open], |printer, element| {
+            if let Element::Token(token) = element {
+                printer.token(token);
+                if token_type(token) == Some(&TokenType::Fun) {
+                    printer.space();
+                }
+            }
+        });
+        self.list(&node.children[open..=close]);
+        self.walk(
+            &node.children[close + 1..],
+            |printer, element| match element {
+                Element::Node(node) if node.kind == NodeKind::TypeAnnotation => {
+                    printer.space();
+                    printer.type_annotation(node);
+                }
+                Element::Node(node) => {
+                    printer.space();
+                    printer.statement(node);
+                }
+                Element::Token(token) => printer.token(token),
+            },
+        );
+    }
+
+    fn class(&mut self, node: &SyntaxNode) {
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::Class) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Some(TokenType::Less) => printer.spaced(token),
+                Some(TokenType::LeftBrace) => printer.open_brace(token),
+                Some(TokenType::RightBrace) => printer.close_brace(token),
+                _ => printer.token(token),
+            },
+            Element::Node(node) if node.kind == NodeKind::Function => printer.member(node),
+            Element::Node(node) => printer.expression(node),
+        })
+    }
+
+    /// All members on the line of the name, without a trailing comma.
+    fn enum_declaration(&mut self, node: &SyntaxNode) {
+        let mut in_body = false;
+        let mut members = 0;
+        let mut comma = None;
+        self.walk(&node.children, |printer, element| {
+            if let Element::Token(token) = element {
+                match token_type(token) {
+                    Some(TokenType::Enum) => {
+                        printer.token(token);
+                        printer.space();
+                    }
+                    Some(TokenType::LeftBrace) => {
+                        in_body = true;
+                        printer.space();
+                        printer.token(token);
+                    }
+                    Some(TokenType::Comma) => comma = Some(token),
+                    Some(TokenType::RightBrace) => {
+                        if members > 0 {
+                            printer.space();
+                        }
+                        printer.token(token);
+                    }
+                    _ if in_body => {
+                        members += 1;
+                        if let Some(comma) = comma.take() {
+                            printer.token(comma);
+                        }
+                        printer.space();
+                        printer.token(token);
+                    }
+                    _ => printer.token(token),
+                }
+            }
+        })
+    }
+
+    fn type_annotation(&mut self, node: &SyntaxNode) {
+        self.walk(&node.children, |printer, element| {
+            if let Element::Token(token) = element {
+                printer.token(token);
+                if token_type(token) != Some(&TokenType::Identifier) {
+                    printer.space();
+                }
+            }
+        })
+    }
+
+    /// Prints a parenthesized, comma separated list of arguments or parameters, or the items of
+    /// a list or map literal, on one line, or with one item per line if that line would get too
+    /// long.
+    fn list(&mut self, children: &[SyntaxElement]) {
+        let snapshot = self.snapshot();
+        self.list_items(children, false);
+
+        let printed = &self.out[snapshot.length..];
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        let has_items = children
+            .iter()
+            .any(|child| matches!(child, SyntaxElement::Node(_)));
+        if has_items && !printed.contains('\n') && line.chars().count() > MAX_WIDTH {
+            self.restore(snapshot);
+            self.list_items(children, true);
+        }
+    }
+
+    /// A trailing comma is left out.
+    fn list_items(&mut self, children: &[SyntaxElement], wrapped: bool) {
+        let items = children
+            .iter()
+            .filter(|child| matches!(child, SyntaxElement::Node(_)))
+            .count();
+        let mut printed = 0;
+        self.walk(children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace) => {
+                    printer.token(token);
+                    if wrapped {
+                        printer.indent += 1;
+                        printer.newline();
+                    }
+                }
+                Some(TokenType::Comma) if printed == items => {}
+                Some(TokenType::Comma) => {
+                    printer.token(token);
+                    if wrapped {
+                        printer.newline();
+                    } else {
+                        printer.space();
+                    }
+                }
+                _ => {
+                    if wrapped {
+                        printer.indent -= 1;
+                        printer.newline();
+                    }
+                    printer.token(token);
+                }
+            },
+            Element::Node(node) => {
+                printed += 1;
+                if node.kind == NodeKind::Parameter {
+                    printer.walk(&node.children, |printer, element| match element {
+                        Element::Token(token) => printer.token(token),
+                        Element::Node(node) => printer.type_annotation(node),
+                    })
+                } else {
+                    printer.expression(node)
+                }
+            }
+        })
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            length: self.out.len(),
+            indent: self.indent,
+            continuation: self.continuation,
+            pending: self.pending.clone(),
+            newline_seen: self.newline_seen,
+            statement_start: self.statement_start,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.out.truncate(snapshot.length);
+        self.indent = snapshot.indent;
+        self.continuation = snapshot.continuation;
+        self.pending = snapshot.pending;
+        self.newline_seen = snapshot.newline_seen;
+        self.statement_start = snapshot.statement_start;
+    }
+
+    /// Prints an operator with a space on either side.
+    fn spaced(&mut self, token: &SyntaxToken) {
+        self.space();
+        self.token(token);
+        self.space();
+    }
+
+    fn expression(&mut self, node: &SyntaxNode) {
+        recursion::grow_stack(|| match node.kind {
+            NodeKind::Binary | NodeKind::Logical | NodeKind::Assign => {
+                self.walk(&node.children, |printer, element| match element {
+                    Element::Token(token) => printer.spaced(token),
+                    Element::Node(node) => printer.expression(node),
+                })
+            }
+            NodeKind::Call => {
+                let callee = node
+                    .children
+                    .iter()
+                    .position(|child| matches!(child, SyntaxElement::Node(_)))
+                    .map_or(0, |index| index + 1);
+                self.walk(&node.children[..callee], |printer, element| {
+                    if let Element::Node(node) = element {
+                        printer.expression(node);
+                    }
+                });
+                self.list(&node.children[callee..]);
+            }
+            NodeKind::Unary => self.walk(&node.children, |printer, element| match element {
+                Element::Token(token) if token_type_is(token, &TokenType::Typeof) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Element::Token(token) => printer.token(token),
+                Element::Node(node) => printer.expression(node),
+            }),
+            NodeKind::List | NodeKind::Map => self.list(&node.children),
+            NodeKind::MapEntry => self.walk(&node.children, |printer, element| match element {
+                Element::Token(token) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Element::Node(node) => printer.expression(node),
+            }),
+            NodeKind::Match => self.match_expression(node),
+            NodeKind::MatchArm => self.walk(&node.children, |printer, element| match element {
+                Element::Token(token) if token_type_is(token, &TokenType::FatArrow) => {
+                    printer.spaced(token)
+                }
+                Element::Token(token) => printer.token(token),
+                Element::Node(node) => printer.expression(node),
+            }),
+            _ => self.verbatim(node),
+        })
+    }
+
+    /// Arms on lines of their own, without a trailing comma.
+    fn match_expression(&mut self, node: &SyntaxNode) {
+        let arms = node
+            .nodes()
+            .filter(|node| node.kind == NodeKind::MatchArm)
+            .count();
+        let mut printed = 0;
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => match token_type(token) {
+                Some(TokenType::Match) => {
+                    printer.token(token);
+                    printer.space();
+                }
+                Some(TokenType::LeftBrace) => printer.open_brace(token),
+                Some(TokenType::RightBrace) => printer.close_brace(token),
+                Some(TokenType::Comma) if printed == arms => {}
+                Some(TokenType::Comma) => {
+                    printer.token(token);
+                    printer.newline();
+                }
+                _ => printer.token(token),
+            },
+            Element::Node(node) => {
+                if node.kind == NodeKind::MatchArm {
+                    printed += 1;
+                }
+                printer.expression(node);
+            }
+        })
+    }
+
+    /// Prints the tokens of `node` without any space between them, as in `-a`, `a.b` or `(a)`.
+    fn verbatim(&mut self, node: &SyntaxNode) {
+        self.walk(&node.children, |printer, element| match element {
+            Element::Token(token) => printer.token(token),
+            Element::Node(node) => printer.expression(node),
+        })
+    }
+}
+
+fn token_type(token: &SyntaxToken) -> Option<&TokenType> {
+    match &token.kind {
+        TokenKind::Token(token_type) => Some(token_type),
+        _ => None,
+    }
+}
+
+/// The positions of the parentheses around the parameters of a function.
+fn list_bounds(node: &SyntaxNode) -> Option<(usize, usize)> {
+    let position = |token_type: TokenType| {
+        node.children.iter().position(|child| match child {
+            SyntaxElement::Token(token) => token_type_is(token, &token_type),
+            _ => false,
+        })
+    };
+    Some((
+        position(TokenType::LeftParen)?,
+        position(TokenType::RightParen)?,
+    ))
+}
+
+fn token_type_is(token: &SyntaxToken, expected: &TokenType) -> bool {
+    token_type(token) == Some(expected)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::format;
+
+    const MESSY: &str = r#"
+// greets people
+class Greeter<Base{
+init(name){this.name=name;}
+
+
+  greet ( ) -> Nil {  print "Hi, "+this.name ; } // friendly
+}
+var g:Greeter=Greeter( "you" );
+for(var i=0;i<2;i=i+1)g.greet();
+if(!done)print 1;else if (x and y or -z) { } else print 2;
+while (true) {
+    // nothing to see
+}
+"#;
+
+    const FORMATTED: &str = r#"// greets people
+class Greeter < Base {
+    init(name) {
+        this.name = name;
+    }
+
+    greet() -> Nil {
+        print "Hi, " + this.name;
+    } // friendly
+}
+var g: Greeter = Greeter("you");
+for (var i = 0; i < 2; i = i + 1)
+    g.greet();
+if (!done)
+    print 1;
+else if (x and y or -z) {} else
+    print 2;
+while (true) {
+    // nothing to see
+}
+"#;
+
+    #[test]
+    fn canonical() {
+        assert_eq!(format(MESSY).unwrap(), FORMATTED);
+        assert_eq!(format("").unwrap(), "");
+        assert_eq!(format("// only\n\n\n").unwrap(), "// only\n");
+        assert_eq!(
+            format("for(;;){a=1;}").unwrap(),
+            "for (;;) {\n    a = 1;\n}\n"
+        );
+        assert_eq!(
+            format("fun f(a,b,){}\nf(\n  1,\n  2,\n);").unwrap(),
+            "fun f(a, b) {}\nf(1, 2);\n"
+        );
+        assert_eq!(
+            format("switch(x){case 1:print 1;print 2;case \"a\": default:{}}").unwrap(),
+            "switch (x) {\n    case 1:\n        print 1;\n        print 2;\n    case \"a\":\n    default:\n        {}\n}\n"
+        );
+        assert_eq!(
+            format("do{a=a+1;}while(a<3);do a=a+1;while(a<3);").unwrap(),
+            "do {\n    a = a + 1;\n} while (a < 3);\ndo\n    a = a + 1;\nwhile (a < 3);\n"
+        );
+        assert_eq!(
+            format("outer:loop{while(x)break outer;break;}").unwrap(),
+            "outer: loop {\n    while (x)\n        break outer;\n    break;\n}\n"
+        );
+        assert_eq!(
+            format("var a=1,b:Number,c=a;").unwrap(),
+            "var a = 1, b: Number, c = a;\n"
+        );
+        assert_eq!(format("const a=1,b=a;").unwrap(), "const a = 1, b = a;\n");
+        assert_eq!(
+            format("enum Color{Red,Green,\nBlue,}enum Empty{}").unwrap(),
+            "enum Color { Red, Green, Blue }\nenum Empty {}\n"
+        );
+        assert_eq!(
+            format("print typeof(x)==\"nil\"and!typeof -y;").unwrap(),
+            "print typeof (x) == \"nil\" and !typeof -y;\n"
+        );
+        assert_eq!(
+            format("print [1,[],[ 2 ,3,],];").unwrap(),
+            "print [1, [], [2, 3]];\n"
+        );
+        assert_eq!(
+            format("print {\"a\" :1,2:{},};").unwrap(),
+            "print {\"a\": 1, 2: {}};\n"
+        );
+        assert_eq!(
+            format("var {x,y}=point,z=1;").unwrap(),
+            "var {x, y} = point, z = 1;\n"
+        );
+        assert_eq!(
+            format("print match(s){Circle(c)=>c.r*2,1=>\"one\",_=>nil,};match(s){};").unwrap(),
+            "print match (s) {\n    Circle(c) => c.r * 2,\n    1 => \"one\",\n    _ => nil\n};\nmatch (s) {};\n"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let source = "var a = f(1, // first\n2);\n{ // block\n  b; // after\n\n\n  // own\n}\n";
+        let expected =
+            "var a = f(1, // first\n    2);\n{ // block\n    b; // after\n\n    // own\n}\n";
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn wrapping() {
+        let arguments: Vec<_> = (0..12).map(|i| format!("argument{}", i)).collect();
+        let source = format!("print f({});", arguments.join(","));
+        let formatted = format(&source).unwrap();
+        let expected = format!("print f(\n    {}\n);\n", arguments.join(",\n    "));
+        assert_eq!(formatted, expected);
+
+        let source = format!("fun g({}) {{}}", arguments.join(","));
+        let expected = format!("fun g(\n    {}\n) {{}}\n", arguments.join(",\n    "));
+        assert_eq!(format(&source).unwrap(), expected);
+    }
+
+    #[test]
+    fn idempotent() {
+        let long_call = format!("f({});", ["somewhat_long_name"; 8].join(", "));
+        let sources = [MESSY, FORMATTED, "a = b; // x\n\n// y\nc;", &long_call];
+        for source in sources.iter() {
+            let once = format(source).unwrap();
+            assert_eq!(format(&once).unwrap(), once);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let errors = format("print (;").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let source = format!("print {}1{};\n", "(".repeat(9_000), ")".repeat(9_000));
+        assert_eq!(format(&source).unwrap(), source);
+        let nested = format!("{}print 1;{}", "{".repeat(2_000), "}".repeat(2_000));
+        assert_eq!(format(&nested).unwrap().lines().count(), 4_001);
+    }
+}
@@ -0,0 +1,53 @@
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+/// Reads one message of the base protocol shared by LSP and DAP: headers, an empty line, then
+/// a JSON body of `Content-Length` bytes. Returns `None` at the end of `input`.
+pub(crate) fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| invalid_data("Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|error| invalid_data(&error.to_string()))
+}
+
+pub(crate) fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Frames `messages` one after the other, as a client sends them, for the servers' tests.
+#[cfg(test)]
+pub(crate) fn frame_all(messages: &[Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for message in messages {
+        write_message(&mut input, message).unwrap();
+    }
+    input
+}
+
+/// The messages a server wrote to `output`, for its tests.
+#[cfg(test)]
+pub(crate) fn read_all(mut output: &[u8]) -> Vec<Value> {
+    std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+}
@@ -1,77 +1,894 @@
-use crate::environment::Environment;
 use crate::error::{LoxError, Result};
+use crate::gc::Marker;
 use crate::interpreter::Interpreter;
+#[cfg(feature = "values")]
+use crate::maps::Key;
 use crate::object::Object;
-use crate::statement::Stmt;
+use crate::stack::Upvalue;
+use crate::statement::{Ast, StmtRef};
 
-use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Where `clock()` gets the time from, in seconds.
+pub type TimeSource = dyn Fn() -> f64;
+
+/// The time `clock()` returns unless the host sets another source.
+pub fn system_time() -> f64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_secs() as f64
+}
+
 pub trait Function {
+    fn name(&self) -> &str;
     fn arity(&self) -> usize;
-    fn call(
-        &self,
-        interpreter: &mut Interpreter,
-        arguments: &Vec<Rc<Object>>,
-    ) -> Result<Rc<Object>>;
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object>;
+
+    /// Marks the values this function keeps alive.
+    fn trace(&self, _marker: &mut Marker) {}
+
+    /// The instance a bound method was bound to.
+    fn this(&self) -> Option<&Object> {
+        None
+    }
+
+    /// Whether it is implemented in Rust rather than in Lox.
+    fn is_native(&self) -> bool {
+        true
+    }
 }
 
+/// How functions print, e.g. `<fn add>` or `<native fn clock>`.
 impl std::fmt::Debug for dyn Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<lox fn>")
+        if self.is_native() {
+            write!(f, "<native fn {}>", self.name())
+        } else {
+            write!(f, "<fn {}>", self.name())
+        }
     }
 }
 
+#[cfg(feature = "time")]
 pub struct Clock;
 
+#[cfg(feature = "time")]
 impl Function for Clock {
+    fn name(&self) -> &str {
+        "clock"
+    }
+
     fn arity(&self) -> usize {
         0
     }
 
-    fn call(&self, _: &mut Interpreter, _: &Vec<Rc<Object>>) -> Result<Rc<Object>> {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        Ok(Rc::new(Object::Number(now.as_secs() as f64)))
+    fn call(&self, interpreter: &mut Interpreter, _: &[Object]) -> Result<Object> {
+        Ok(Object::Number(interpreter.now()))
     }
 }
 
+#[cfg(feature = "time")]
 impl std::fmt::Debug for Clock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "<native fn>")
     }
 }
 
+/// `globals()` prints the global variables, one `name = value` per line.
+#[cfg(feature = "introspection")]
+pub struct Globals;
+
+#[cfg(feature = "introspection")]
+impl Function for Globals {
+    fn name(&self) -> &str {
+        "globals"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(0, arguments)?;
+        for (name, value) in interpreter.globals() {
+            interpreter.print(&format!("{} = {}", name, value))?;
+        }
+        Ok(Object::Nil)
+    }
+}
+
+/// `locals()` prints the local variables of the running function in scope where it is called,
+/// one `name = value` per line. Only calls naming `locals` directly know which ones those are.
+#[cfg(feature = "introspection")]
+pub struct Locals;
+
+#[cfg(feature = "introspection")]
+impl Function for Locals {
+    fn name(&self) -> &str {
+        "locals"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(0, arguments)?;
+        let locals = interpreter
+            .call_site_locals()
+            .unwrap_or_else(|| Rc::new([]));
+        for (name, slot) in locals.iter() {
+            if let Some(value) = interpreter.local(*slot) {
+                interpreter.print(&format!("{} = {}", name, value))?;
+            }
+        }
+        Ok(Object::Nil)
+    }
+}
+
+/// `stats()` prints the interpreter's `RuntimeStats`.
+#[cfg(feature = "introspection")]
+pub struct Stats;
+
+#[cfg(feature = "introspection")]
+impl Function for Stats {
+    fn name(&self) -> &str {
+        "stats"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(0, arguments)?;
+        let stats = interpreter.stats().to_string();
+        interpreter.print(&stats)?;
+        Ok(Object::Nil)
+    }
+}
+
+/// `backtrace()` returns the calls in progress as a string, innermost first, one `in f() [line
+/// N]` per line, ending with the top-level code as `<script>`.
+#[cfg(feature = "introspection")]
+pub struct Backtrace;
+
+#[cfg(feature = "introspection")]
+impl Function for Backtrace {
+    fn name(&self) -> &str {
+        "backtrace"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(0, arguments)?;
+        let line =
+            |line: Option<u32>| line.map_or(String::new(), |line| format!(" [line {}]", line));
+        // the innermost call is this one
+        let mut frames: Vec<_> = interpreter
+            .call_frames()
+            .into_iter()
+            .skip(1)
+            .map(|frame| format!("in {}{}", frame.name, line(frame.line)))
+            .collect();
+        frames.push(format!("in <script>{}", line(interpreter.script_line())));
+        Ok(Object::String(frames.join("\n").into()))
+    }
+}
+
+#[cfg(feature = "introspection")]
+impl std::fmt::Debug for Backtrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(feature = "introspection")]
+impl std::fmt::Debug for Globals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(feature = "introspection")]
+impl std::fmt::Debug for Locals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(feature = "introspection")]
+impl std::fmt::Debug for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// `charCodeAt(string, index)` returns the Unicode scalar value of the character at `index`,
+/// counting characters rather than bytes.
+#[cfg(feature = "strings")]
+pub struct CharCodeAt;
+
+#[cfg(feature = "strings")]
+impl Function for CharCodeAt {
+    fn name(&self) -> &str {
+        "charCodeAt"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(2, arguments)?;
+        let string = match &arguments[0] {
+            Object::String(string) => string,
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "charCodeAt() expects a string, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let index = whole_number("charCodeAt", &arguments[1])?;
+        let character = string.chars().nth(index as usize).filter(|_| index >= 0.0);
+        match character {
+            Some(character) => Ok(Object::Number(u32::from(character) as f64)),
+            None => Err(LoxError::InterpreterError(
+                format!(
+                    "charCodeAt() got index {} for a string of {} characters.",
+                    index,
+                    string.chars().count()
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "strings")]
+impl std::fmt::Debug for CharCodeAt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// `fromCharCode(code)` returns the one character string of a Unicode scalar value.
+#[cfg(feature = "strings")]
+pub struct FromCharCode;
+
+#[cfg(feature = "strings")]
+impl Function for FromCharCode {
+    fn name(&self) -> &str {
+        "fromCharCode"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(1, arguments)?;
+        let code = whole_number("fromCharCode", &arguments[0])?;
+        let character = Some(code)
+            .filter(|code| (0.0..=u32::MAX as f64).contains(code))
+            .and_then(|code| char::from_u32(code as u32));
+        match character {
+            Some(character) => Ok(Object::String(character.to_string().into())),
+            None => Err(LoxError::InterpreterError(
+                format!(
+                    "fromCharCode() got {}, which is not a Unicode scalar value.",
+                    code
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "strings")]
+impl std::fmt::Debug for FromCharCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// The natives converting a string to and from base64 and hex: `base64Encode(string)`,
+/// `base64Decode(string)`, `hexEncode(string)` and `hexDecode(string)`. Encoding works on the
+/// UTF-8 bytes of the string, and decoding fails if the bytes are not UTF-8.
+#[cfg(feature = "strings")]
+pub struct Transcode {
+    name: &'static str,
+    /// The converted string, or why the argument cannot be converted.
+    function: fn(&str) -> std::result::Result<String, &'static str>,
+}
+
+#[cfg(feature = "strings")]
+impl Transcode {
+    pub const ALL: [Transcode; 4] = [
+        Transcode {
+            name: "base64Encode",
+            function: base64_encode,
+        },
+        Transcode {
+            name: "base64Decode",
+            function: base64_decode,
+        },
+        Transcode {
+            name: "hexEncode",
+            function: hex_encode,
+        },
+        Transcode {
+            name: "hexDecode",
+            function: hex_decode,
+        },
+    ];
+}
+
+#[cfg(feature = "strings")]
+impl Function for Transcode {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(1, arguments)?;
+        let string = match &arguments[0] {
+            Object::String(string) => string,
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "{}() expects a string, but got '{}'.",
+                        self.name,
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        match (self.function)(string) {
+            Ok(converted) => Ok(Object::String(converted.into())),
+            Err(reason) => Err(LoxError::InterpreterError(
+                format!("{}() got a string {}.", self.name, reason).into(),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "strings")]
+impl std::fmt::Debug for Transcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(feature = "strings")]
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding.
+#[cfg(feature = "strings")]
+fn base64_encode(string: &str) -> std::result::Result<String, &'static str> {
+    let mut encoded = String::new();
+    for chunk in string.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (bits >> (18 - 6 * index)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    Ok(encoded)
+}
+
+#[cfg(feature = "strings")]
+fn base64_decode(string: &str) -> std::result::Result<String, &'static str> {
+    const INVALID: &str = "that is not valid base64";
+    if !string.len().is_multiple_of(4) {
+        return Err(INVALID);
+    }
+    let trimmed = string.trim_end_matches('=');
+    if string.len() - trimmed.len() > 2 {
+        return Err(INVALID);
+    }
+    let mut bytes = vec![];
+    for chunk in trimmed.as_bytes().chunks(4) {
+        let mut bits = 0;
+        for (index, &character) in chunk.iter().enumerate() {
+            let sextet = BASE64_ALPHABET
+                .iter()
+                .position(|&letter| letter == character)
+                .ok_or(INVALID)?;
+            bits |= (sextet as u32) << (18 - 6 * index);
+        }
+        let decoded = bits.to_be_bytes();
+        bytes.extend_from_slice(&decoded[1..chunk.len()]);
+    }
+    String::from_utf8(bytes).map_err(|_| "that does not decode to UTF-8")
+}
+
+/// Two lowercase digits per byte.
+#[cfg(feature = "strings")]
+fn hex_encode(string: &str) -> std::result::Result<String, &'static str> {
+    Ok(string.bytes().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Digits may be upper or lower case.
+#[cfg(feature = "strings")]
+fn hex_decode(string: &str) -> std::result::Result<String, &'static str> {
+    const INVALID: &str = "that is not valid hex";
+    if !string.len().is_multiple_of(2) {
+        return Err(INVALID);
+    }
+    let bytes = string
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digits = std::str::from_utf8(pair).map_err(|_| INVALID)?;
+            u8::from_str_radix(digits, 16).map_err(|_| INVALID)
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    String::from_utf8(bytes).map_err(|_| "that does not decode to UTF-8")
+}
+
+/// `toFixed(number, digits)` formats a number with `digits` digits after the decimal point.
+#[cfg(feature = "numbers")]
+pub struct ToFixed;
+
+#[cfg(feature = "numbers")]
+impl Function for ToFixed {
+    fn name(&self) -> &str {
+        "toFixed"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(2, arguments)?;
+        let number = number("toFixed", &arguments[0])?;
+        let digits = whole_number("toFixed", &arguments[1])?;
+        if !(0.0..=MAX_DIGITS).contains(&digits) {
+            return Err(LoxError::InterpreterError(
+                format!(
+                    "toFixed() expects between 0 and {} digits, but got {}.",
+                    MAX_DIGITS, digits
+                )
+                .into(),
+            ));
+        }
+        Ok(Object::String(
+            format!("{:.*}", digits as usize, number).into(),
+        ))
+    }
+}
+
+#[cfg(feature = "numbers")]
+impl std::fmt::Debug for ToFixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// The most digits `toFixed()` prints after the decimal point.
+#[cfg(feature = "numbers")]
+const MAX_DIGITS: f64 = 100.0;
+
+/// `toStringRadix(number, base)` writes a whole number in a base from 2 to 36, with lowercase
+/// letters for the digits above 9.
+#[cfg(feature = "numbers")]
+pub struct ToStringRadix;
+
+#[cfg(feature = "numbers")]
+impl Function for ToStringRadix {
+    fn name(&self) -> &str {
+        "toStringRadix"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(2, arguments)?;
+        let number = whole_number("toStringRadix", &arguments[0])?;
+        let base = base("toStringRadix", &arguments[1])?;
+        let mut magnitude = number.abs();
+        let mut digits = vec![];
+        loop {
+            let digit = magnitude % base as f64;
+            digits.push(std::char::from_digit(digit as u32, base).unwrap());
+            // exact, since the difference is a multiple of the base
+            magnitude = (magnitude - digit) / base as f64;
+            if magnitude == 0.0 {
+                break;
+            }
+        }
+        if number < 0.0 {
+            digits.push('-');
+        }
+        Ok(Object::String(
+            digits.iter().rev().collect::<String>().into(),
+        ))
+    }
+}
+
+#[cfg(feature = "numbers")]
+impl std::fmt::Debug for ToStringRadix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// `parseInt(string, base)` reads a whole number in a base from 2 to 36, with an optional sign.
+/// Letters may be upper or lower case. Returns nil if the string is not such a number.
+#[cfg(feature = "numbers")]
+pub struct ParseInt;
+
+#[cfg(feature = "numbers")]
+impl Function for ParseInt {
+    fn name(&self) -> &str {
+        "parseInt"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(2, arguments)?;
+        let string = match &arguments[0] {
+            Object::String(string) => string,
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "parseInt() expects a string, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let base = base("parseInt", &arguments[1])?;
+        let (sign, digits) = match string.strip_prefix('-') {
+            Some(digits) => (-1.0, digits),
+            None => (1.0, string.strip_prefix('+').unwrap_or(string)),
+        };
+        if digits.is_empty() {
+            return Ok(Object::Nil);
+        }
+        let mut number = 0.0;
+        for character in digits.chars() {
+            match character.to_digit(base) {
+                Some(digit) => number = number * base as f64 + digit as f64,
+                None => return Ok(Object::Nil),
+            }
+        }
+        Ok(Object::Number(sign * number))
+    }
+}
+
+#[cfg(feature = "numbers")]
+impl std::fmt::Debug for ParseInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// The argument of the native `function` as a base for `char::to_digit`.
+#[cfg(feature = "numbers")]
+fn base(function: &str, argument: &Object) -> Result<u32> {
+    match argument {
+        Object::Number(base) if base.fract() == 0.0 && (2.0..=36.0).contains(base) => {
+            Ok(*base as u32)
+        }
+        _ => Err(LoxError::InterpreterError(
+            format!(
+                "{}() expects a base from 2 to 36, but got '{}'.",
+                function, argument
+            )
+            .into(),
+        )),
+    }
+}
+
+#[cfg(feature = "numbers")]
+fn number(function: &str, argument: &Object) -> Result<f64> {
+    match argument {
+        Object::Number(number) => Ok(*number),
+        _ => Err(LoxError::InterpreterError(
+            format!(
+                "{}() expects a number, but got '{}'.",
+                function,
+                argument.type_name()
+            )
+            .into(),
+        )),
+    }
+}
+
+/// `clone(value)` copies lists, maps and instances together with the lists, maps and instances
+/// in them. Copies of instances share their class. Values that refer to each other keep doing so
+/// in the copy, so cycles are copied as cycles. Everything else, functions and classes included,
+/// is returned as it is.
+#[cfg(feature = "values")]
+pub struct DeepClone;
+
+#[cfg(feature = "values")]
+impl Function for DeepClone {
+    fn name(&self) -> &str {
+        "clone"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(1, arguments)?;
+        let mut copier = Copier::default();
+        let copy = copier.copy(interpreter, &arguments[0])?;
+        copier.finish(interpreter)?;
+        Ok(copy)
+    }
+}
+
+#[cfg(feature = "values")]
+impl std::fmt::Debug for DeepClone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// `hash(value)` hashes nil, booleans, numbers and strings the way map keys are hashed, so equal
+/// values get equal hashes. Instances are hashed by calling their `hash()` method. Hashes are
+/// whole numbers below 2^53 and stay the same while a program runs.
+#[cfg(feature = "values")]
+pub struct HashValue;
+
+#[cfg(feature = "values")]
+impl Function for HashValue {
+    fn name(&self) -> &str {
+        "hash"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        use std::hash::{Hash, Hasher};
+
+        check_arity(1, arguments)?;
+        if let Object::Instance(instance) = &arguments[0] {
+            let method = instance.borrow().class().find_method("hash");
+            return match method {
+                Some(method) => method.bind(arguments[0].clone()).call(interpreter, &[]),
+                None => Err(LoxError::InterpreterError(
+                    format!("{} has no hash() method.", instance.borrow()).into(),
+                )),
+            };
+        }
+        let key = Key::new(&arguments[0]).map_err(|_| {
+            LoxError::InterpreterError(
+                format!(
+                    "Can only hash nil, booleans, numbers, strings and instances, not '{}'.",
+                    arguments[0].type_name()
+                )
+                .into(),
+            )
+        })?;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        Ok(Object::Number((hasher.finish() & MAX_HASH) as f64))
+    }
+}
+
+#[cfg(feature = "values")]
+impl std::fmt::Debug for HashValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// Keeps hashes to the whole numbers an `f64` holds exactly.
+#[cfg(feature = "values")]
+const MAX_HASH: u64 = (1 << 53) - 1;
+
+/// Deep copies of values, made by `clone()` and by `Interpreter::checkpoint`. Values copied with
+/// the same copier that refer to the same list, map or instance refer to the same copy.
+#[derive(Default)]
+pub(crate) struct Copier {
+    /// The copies made so far, by the address of the original.
+    copies: std::collections::HashMap<*const (), Object>,
+    /// Copies whose contents are still the originals' values.
+    pending: Vec<Object>,
+}
+
+impl Copier {
+    /// A shallow copy of `value`, or the one made before. `finish` copies what is in it.
+    pub(crate) fn copy(&mut self, interpreter: &mut Interpreter, value: &Object) -> Result<Object> {
+        let address = match value {
+            Object::List(list) => Rc::as_ptr(list) as *const (),
+            Object::Map(map) => Rc::as_ptr(map) as *const (),
+            Object::Instance(instance) => Rc::as_ptr(instance) as *const (),
+            _ => return Ok(value.clone()),
+        };
+        if let Some(copy) = self.copies.get(&address) {
+            return Ok(copy.clone());
+        }
+        let copy = match value {
+            Object::List(list) => interpreter.new_list(list.borrow().clone())?,
+            Object::Map(map) => interpreter.new_map(map.borrow().clone())?,
+            Object::Instance(instance) => interpreter.new_instance(instance.borrow().clone())?,
+            _ => unreachable!(),
+        };
+        self.copies.insert(address, copy.clone());
+        self.pending.push(copy.clone());
+        Ok(copy)
+    }
+
+    /// Replaces the contents of the copies made so far by copies, until all of them are deep.
+    pub(crate) fn finish(&mut self, interpreter: &mut Interpreter) -> Result<()> {
+        // works through a queue rather than recursing, like the garbage collector's marker
+        while let Some(copy) = self.pending.pop() {
+            match &copy {
+                Object::List(list) => {
+                    let elements = list.borrow().clone();
+                    for (index, element) in elements.iter().enumerate() {
+                        let element = self.copy(interpreter, element)?;
+                        list.borrow_mut()[index] = element;
+                    }
+                }
+                Object::Map(map) => {
+                    let entries = map.borrow().clone();
+                    for (key, value) in entries {
+                        let value = self.copy(interpreter, &value)?;
+                        map.borrow_mut().insert(key, value);
+                    }
+                }
+                Object::Instance(instance) => {
+                    let fields = instance.borrow().fields().to_vec();
+                    for (index, field) in fields.iter().enumerate() {
+                        let field = self.copy(interpreter, field)?;
+                        instance.borrow_mut().fields_mut()[index] = field;
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The argument of the native `function` as a number without a fractional part.
+#[cfg(any(feature = "strings", feature = "numbers"))]
+fn whole_number(function: &str, argument: &Object) -> Result<f64> {
+    match argument {
+        Object::Number(number) if number.fract() == 0.0 => Ok(*number),
+        _ => Err(LoxError::InterpreterError(
+            format!(
+                "{}() expects a whole number, but got '{}'.",
+                function, argument
+            )
+            .into(),
+        )),
+    }
+}
+
+/// The body of a native function defined by the host, see `Interpreter::define_native`.
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object>;
+
+pub struct NativeFunction {
+    name: Rc<str>,
+    arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(name: Rc<str>, arity: usize, function: Box<NativeFn>) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+impl Function for NativeFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(self.arity, arguments)?;
+        (self.function)(arguments)
+    }
+}
+
+pub(crate) fn check_arity(arity: usize, arguments: &[Object]) -> Result<()> {
+    if arity != arguments.len() {
+        return Err(LoxError::InterpreterError(
+            format!("Expected {} arguments but got {}.", arity, arguments.len()).into(),
+        ));
+    }
+    Ok(())
+}
+
 pub struct LoxFunction {
-    parameters: Rc<Vec<String>>,
-    body: Rc<Vec<Stmt>>,
-    closure: Rc<RefCell<Environment>>,
+    name: Rc<str>,
+    parameters: Rc<Vec<Rc<str>>>,
+    body: Rc<[StmtRef]>,
+    ast: Rc<Ast>,
+    upvalues: Rc<[Upvalue]>,
+    /// The instance a method was bound to.
+    this: Option<Object>,
+    /// The superclass of the class declaring a method.
+    superclass: Option<Object>,
     is_initializer: bool,
 }
 
 impl LoxFunction {
     pub fn new(
-        parameters: Rc<Vec<String>>,
-        body: Rc<Vec<Stmt>>,
-        closure: Rc<RefCell<Environment>>,
+        name: Rc<str>,
+        parameters: Rc<Vec<Rc<str>>>,
+        body: Rc<[StmtRef]>,
+        ast: Rc<Ast>,
+        upvalues: Rc<[Upvalue]>,
         is_initializer: bool,
     ) -> Self {
         LoxFunction {
+            name,
             parameters,
             body,
-            closure,
+            ast,
+            upvalues,
+            this: None,
+            superclass: None,
             is_initializer,
         }
     }
 
-    pub fn bind(&self, instance: Rc<Object>) -> Self {
-        let mut environment = Environment::with_enclosing(self.closure.clone());
-        environment.define("this", instance);
+    pub fn with_superclass(self, superclass: Object) -> Self {
+        Self {
+            superclass: Some(superclass),
+            ..self
+        }
+    }
+
+    pub fn bind(&self, instance: Object) -> Self {
         Self {
+            name: self.name.clone(),
             parameters: self.parameters.clone(),
             body: self.body.clone(),
-            closure: Rc::new(RefCell::new(environment)),
+            ast: self.ast.clone(),
+            upvalues: self.upvalues.clone(),
+            this: Some(instance),
+            superclass: self.superclass.clone(),
             is_initializer: self.is_initializer,
         }
     }
@@ -81,58 +898,51 @@ impl Debug for LoxFunction {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(
             f,
-            "LoxFunction {{ parameters: {:?}, body: {:?} }}",
-            self.parameters, self.body
+            "LoxFunction {{ name: {:?}, parameters: {:?}, body: {:?} }}",
+            self.name, self.parameters, self.body
         )
     }
 }
 
 impl Function for LoxFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     fn arity(&self) -> usize {
         self.parameters.len()
     }
 
-    fn call(
-        &self,
-        interpreter: &mut Interpreter,
-        arguments: &Vec<Rc<Object>>,
-    ) -> Result<Rc<Object>> {
-        if self.arity() != arguments.len() {
-            return Err(LoxError::InterpreterError(
-                format!(
-                    "Expected {} arguments but got {}.",
-                    self.arity(),
-                    arguments.len()
-                )
-                .into(),
-            ));
-        };
-        let mut environment = Environment::with_enclosing(self.closure.clone());
-        self.parameters
+    fn trace(&self, marker: &mut Marker) {
+        marker.mark_upvalues(&self.upvalues);
+        self.this
             .iter()
-            .zip(arguments.iter())
-            .for_each(|(declaration, argument)| {
-                environment.define(declaration, argument.clone());
-            });
+            .chain(&self.superclass)
+            .for_each(|object| marker.mark_object(object));
+    }
 
-        let result = interpreter.execute_block(&self.body, Rc::new(RefCell::new(environment)));
+    fn this(&self) -> Option<&Object> {
+        self.this.as_ref()
+    }
+
+    fn is_native(&self) -> bool {
+        false
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(self.arity(), arguments)?;
+        // the resolver assigns `this`, `super` and the parameters the first slots, in order
+        let locals = self.this.iter().chain(&self.superclass).chain(arguments);
+        let result = interpreter.execute_function(&self.ast, &self.body, locals, &self.upvalues);
         let return_value = match result {
-            Ok(()) => {
-                if self.is_initializer {
-                    self.closure.borrow().get(0, "this")?
-                } else {
-                    Rc::new(Object::Nil)
-                }
-            }
-            Err(LoxError::Return(value)) => {
-                if self.is_initializer {
-                    self.closure.borrow().get(0, "this")?
-                } else {
-                    value
-                }
-            }
+            Ok(()) => Object::Nil,
+            Err(LoxError::Return(value)) => value,
             Err(err) => return Err(err),
         };
+        let return_value = match &self.this {
+            Some(this) if self.is_initializer => this.clone(),
+            _ => return_value,
+        };
 
         Ok(return_value)
     }
@@ -0,0 +1,446 @@
+use crate::classes::{LoxClass, LoxInstance};
+use crate::functions::{Function, LoxFunction};
+use crate::maps::{Entries, Key};
+use crate::object::Object;
+use crate::stack::Upvalue;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::rc::{Rc, Weak};
+
+/// How many tracked allocations there have to be before the first collection.
+const INITIAL_COLLECTION_THRESHOLD: usize = 1024;
+
+/// Breaks the reference cycles `Rc` cannot free on its own.
+///
+/// Every cycle between Lox values runs through a captured variable (an upvalue holding the
+/// closure that captured it, e.g. a recursive local function), an instance field, a list element
+/// or a map value, so only upvalues, instances, lists and maps are tracked.
+///
+/// A collection first takes a census of everything reachable from the tracked allocations,
+/// counting how many references each value gets from the others. A value with more references
+/// than that is held from outside the heap too: by a value on the Rust stack, like the result of
+/// the statement that just ran, or by the host, like a list `Lox::eval` returned. Those are roots
+/// as well as the interpreter's own. Then everything reachable from the roots is marked and the
+/// tracked allocations that were not reached are cleared. Clearing drops their contents, which
+/// breaks the cycle and lets the reference counts free the rest.
+///
+/// Clearing borrows the allocations mutably, so collections may only run when nothing is being
+/// evaluated, i.e. between top-level statements.
+///
+/// The heap also estimates how many bytes the tracked allocations and the strings built at
+/// runtime take up, for the memory limit. Strings cannot form cycles and are never collected.
+#[derive(Default)]
+pub struct Heap {
+    upvalues: Vec<Weak<RefCell<Object>>>,
+    instances: Vec<Weak<RefCell<LoxInstance>>>,
+    lists: Vec<Weak<RefCell<Vec<Object>>>>,
+    maps: Vec<Weak<RefCell<Entries>>>,
+    strings: Vec<Weak<str>>,
+    /// The estimated size of the tracked values, including ones freed since the last prune.
+    bytes: usize,
+    next_collection: usize,
+    next_prune: usize,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            next_collection: INITIAL_COLLECTION_THRESHOLD,
+            next_prune: 2 * INITIAL_COLLECTION_THRESHOLD,
+            ..Default::default()
+        }
+    }
+
+    pub fn track_upvalue(&mut self, upvalue: &Upvalue) {
+        let upvalue = Rc::downgrade(upvalue);
+        // closures declared next to each other often capture the same variable
+        if self
+            .upvalues
+            .last()
+            .is_some_and(|last| last.ptr_eq(&upvalue))
+        {
+            return;
+        }
+        self.upvalues.push(upvalue);
+        self.bytes += UPVALUE_SIZE;
+        self.prune_if_needed();
+    }
+
+    pub fn track_instance(&mut self, instance: &Rc<RefCell<LoxInstance>>) {
+        self.instances.push(Rc::downgrade(instance));
+        self.bytes += instance.borrow().size();
+        self.prune_if_needed();
+    }
+
+    pub fn track_list(&mut self, list: &Rc<RefCell<Vec<Object>>>) {
+        self.lists.push(Rc::downgrade(list));
+        self.bytes += list_size(list.borrow().len());
+        self.prune_if_needed();
+    }
+
+    pub fn track_map(&mut self, map: &Rc<RefCell<Entries>>) {
+        self.maps.push(Rc::downgrade(map));
+        self.bytes += map_size(map.borrow().len());
+        self.prune_if_needed();
+    }
+
+    pub fn track_string(&mut self, string: &Rc<str>) {
+        self.strings.push(Rc::downgrade(string));
+        self.bytes += string_size(string);
+        self.prune_if_needed();
+    }
+
+    /// A tracked value grew by `bytes`, e.g. an instance got a new field or a list an element.
+    pub fn grow(&mut self, bytes: usize) {
+        self.bytes += bytes;
+    }
+
+    /// The estimated size of the tracked values. It only goes down when the heap is pruned.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The number of tracked allocations, including ones freed since the last collection.
+    pub fn tracked(&self) -> usize {
+        self.upvalues.len() + self.instances.len() + self.lists.len() + self.maps.len()
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.tracked() >= self.next_collection
+    }
+
+    /// Clears every tracked allocation that is neither reachable from `roots` nor referred to
+    /// from outside the heap.
+    pub fn collect(&mut self, mut roots: Marker) {
+        let mut census = Marker::census();
+        for upvalue in self.upvalues.iter().filter_map(Weak::upgrade) {
+            census.seed(Rc::as_ptr(&upvalue).cast(), |census| {
+                census.mark_upvalue(&upvalue)
+            });
+        }
+        let instances = self.instances.iter().filter_map(Weak::upgrade);
+        let lists = self.lists.iter().filter_map(Weak::upgrade);
+        let maps = self.maps.iter().filter_map(Weak::upgrade);
+        let objects = (instances.map(Object::Instance))
+            .chain(lists.map(Object::List))
+            .chain(maps.map(Object::Map));
+        for object in objects {
+            census.seed(address(&object), |census| census.mark_object(&object));
+        }
+        for value in census.mark_all().external() {
+            roots.mark_reached(&value);
+        }
+        let marker = roots.mark_all();
+
+        for upvalue in self.upvalues.iter().filter_map(Weak::upgrade) {
+            if !marker.upvalues.contains(&Rc::as_ptr(&upvalue)) {
+                *upvalue.borrow_mut() = Object::Nil;
+            }
+        }
+        for instance in self.instances.iter().filter_map(Weak::upgrade) {
+            if !marker.instances.contains(&Rc::as_ptr(&instance)) {
+                instance.borrow_mut().clear();
+            }
+        }
+        for list in self.lists.iter().filter_map(Weak::upgrade) {
+            if !marker.lists.contains(&Rc::as_ptr(&list)) {
+                list.borrow_mut().clear();
+            }
+        }
+        for map in self.maps.iter().filter_map(Weak::upgrade) {
+            if !marker.maps.contains(&Rc::as_ptr(&map)) {
+                map.borrow_mut().clear();
+            }
+        }
+
+        self.prune();
+        self.next_collection = INITIAL_COLLECTION_THRESHOLD.max(2 * self.tracked());
+    }
+
+    /// Allocations pile up between collections (a long loop is a single top-level statement), so
+    /// entries of values that were already freed are dropped on the way.
+    fn prune_if_needed(&mut self) {
+        if self.tracked() + self.strings.len() >= self.next_prune {
+            self.prune();
+        }
+    }
+
+    /// Drops the entries of values that were freed and recounts the bytes of the others.
+    pub fn prune(&mut self) {
+        self.upvalues.retain(|weak| weak.strong_count() > 0);
+        self.instances.retain(|weak| weak.strong_count() > 0);
+        self.lists.retain(|weak| weak.strong_count() > 0);
+        self.maps.retain(|weak| weak.strong_count() > 0);
+        self.strings.retain(|weak| weak.strong_count() > 0);
+        let instances: usize = self
+            .instances
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|instance| {
+                instance
+                    .try_borrow()
+                    .map_or(INSTANCE_SIZE, |instance| instance.size())
+            })
+            .sum();
+        let lists: usize = self
+            .lists
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|list| list_size(list.try_borrow().map_or(0, |list| list.len())))
+            .sum();
+        let maps: usize = self
+            .maps
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|map| map_size(map.try_borrow().map_or(0, |map| map.len())))
+            .sum();
+        let strings: usize = self
+            .strings
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|string| string_size(&string))
+            .sum();
+        self.bytes = self.upvalues.len() * UPVALUE_SIZE + instances + lists + maps + strings;
+        self.next_prune = (2 * self.next_collection).max(2 * (self.tracked() + self.strings.len()));
+    }
+}
+
+/// The reference counts of an `Rc`.
+const RC_OVERHEAD: usize = 2 * size_of::<usize>();
+
+const UPVALUE_SIZE: usize = RC_OVERHEAD + size_of::<RefCell<Object>>();
+
+/// The size of an instance without fields.
+pub const INSTANCE_SIZE: usize = RC_OVERHEAD + size_of::<RefCell<LoxInstance>>();
+
+fn list_size(elements: usize) -> usize {
+    RC_OVERHEAD + size_of::<RefCell<Vec<Object>>>() + elements * size_of::<Object>()
+}
+
+fn map_size(entries: usize) -> usize {
+    RC_OVERHEAD + size_of::<RefCell<Entries>>() + entries * size_of::<(Key, Object)>()
+}
+
+fn string_size(string: &str) -> usize {
+    RC_OVERHEAD + string.len()
+}
+
+/// A value a census reached, see `Heap`.
+enum Reached {
+    Upvalue(Upvalue),
+    /// The variables a closure captured, shared with the methods bound from it.
+    Upvalues(Rc<[Upvalue]>),
+    Object(Object),
+    Method(Rc<LoxFunction>),
+}
+
+/// The references to a value a census reached.
+struct Census {
+    /// All references that keep it alive.
+    references: usize,
+    /// The references that come from values the census reached.
+    internal: usize,
+    value: Reached,
+}
+
+/// Finds everything reachable from a set of roots.
+#[derive(Default)]
+pub struct Marker {
+    upvalues: HashSet<*const RefCell<Object>>,
+    instances: HashSet<*const RefCell<LoxInstance>>,
+    lists: HashSet<*const RefCell<Vec<Object>>>,
+    maps: HashSet<*const RefCell<Entries>>,
+    classes: HashSet<*const LoxClass>,
+    /// Functions and the upvalues closures captured, by address.
+    closures: HashSet<*const ()>,
+    pending_upvalues: Vec<Upvalue>,
+    pending_objects: Vec<Object>,
+    /// While taking a census, the values reached by their address.
+    census: Option<HashMap<*const (), Census>>,
+}
+
+impl Marker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn census() -> Self {
+        Self {
+            census: Some(HashMap::new()),
+            ..Self::default()
+        }
+    }
+
+    /// Starts the census at a tracked allocation, which `mark` marks through an `Rc` upgraded
+    /// for it. Neither that `Rc` nor the marking itself is a reference from the heap.
+    fn seed(&mut self, address: *const (), mark: impl FnOnce(&mut Self)) {
+        let seen = self.census.as_ref().unwrap().contains_key(&address);
+        mark(self);
+        let census = self.census.as_mut().unwrap().get_mut(&address).unwrap();
+        census.internal -= 1;
+        if !seen {
+            census.references -= 1;
+        }
+    }
+
+    /// Counts a reference to the value at `address`, which `references` references keep alive
+    /// in all. Has to be called before the marker clones the value.
+    fn count(&mut self, address: *const (), references: usize, value: impl FnOnce() -> Reached) {
+        if let Some(census) = &mut self.census {
+            census
+                .entry(address)
+                .or_insert_with(|| Census {
+                    references,
+                    internal: 0,
+                    value: value(),
+                })
+                .internal += 1;
+        }
+    }
+
+    /// The values the census reached that something outside of them refers to as well.
+    fn external(self) -> impl Iterator<Item = Reached> {
+        self.census
+            .unwrap_or_default()
+            .into_values()
+            .filter(|census| census.references > census.internal)
+            .map(|census| census.value)
+    }
+
+    fn mark_reached(&mut self, value: &Reached) {
+        match value {
+            Reached::Upvalue(upvalue) => self.mark_upvalue(upvalue),
+            Reached::Upvalues(upvalues) => self.mark_upvalues(upvalues),
+            Reached::Object(object) => self.mark_object(object),
+            Reached::Method(method) => self.mark_method(method),
+        }
+    }
+
+    pub fn mark_upvalue(&mut self, upvalue: &Upvalue) {
+        self.count(
+            Rc::as_ptr(upvalue).cast(),
+            Rc::strong_count(upvalue),
+            || Reached::Upvalue(Rc::clone(upvalue)),
+        );
+        if self.upvalues.insert(Rc::as_ptr(upvalue)) {
+            self.pending_upvalues.push(Rc::clone(upvalue));
+        }
+    }
+
+    /// Marks the variables a closure captured.
+    pub fn mark_upvalues(&mut self, upvalues: &Rc<[Upvalue]>) {
+        let address = Rc::as_ptr(upvalues).cast();
+        self.count(address, Rc::strong_count(upvalues), || {
+            Reached::Upvalues(Rc::clone(upvalues))
+        });
+        if self.closures.insert(address) {
+            upvalues
+                .iter()
+                .for_each(|upvalue| self.mark_upvalue(upvalue));
+        }
+    }
+
+    /// Marks a method as its class holds it.
+    pub fn mark_method(&mut self, method: &Rc<LoxFunction>) {
+        let address = Rc::as_ptr(method).cast();
+        self.count(address, Rc::strong_count(method), || {
+            Reached::Method(Rc::clone(method))
+        });
+        if self.closures.insert(address) {
+            method.trace(self);
+        }
+    }
+
+    pub fn mark_class(&mut self, class: &Rc<LoxClass>) {
+        self.count(Rc::as_ptr(class).cast(), Rc::strong_count(class), || {
+            Reached::Object(Object::Class(Rc::clone(class)))
+        });
+        if self.classes.insert(Rc::as_ptr(class)) {
+            self.pending_objects.push(Object::Class(Rc::clone(class)));
+        }
+    }
+
+    pub fn mark_object(&mut self, object: &Object) {
+        let references = match object {
+            Object::Function(function) => Rc::strong_count(function),
+            Object::Instance(instance) => Rc::strong_count(instance),
+            Object::List(list) => Rc::strong_count(list),
+            Object::Map(map) => Rc::strong_count(map),
+            _ => 0,
+        };
+        if references > 0 {
+            self.count(address(object), references, || {
+                Reached::Object(object.clone())
+            });
+        }
+        match object {
+            Object::Function(function) => {
+                if self.closures.insert(address(object)) {
+                    function.trace(self);
+                }
+            }
+            Object::Class(class) => self.mark_class(class),
+            Object::Instance(instance) => {
+                if self.instances.insert(Rc::as_ptr(instance)) {
+                    self.pending_objects.push(object.clone());
+                }
+            }
+            Object::List(list) => {
+                if self.lists.insert(Rc::as_ptr(list)) {
+                    self.pending_objects.push(object.clone());
+                }
+            }
+            Object::Map(map) => {
+                if self.maps.insert(Rc::as_ptr(map)) {
+                    self.pending_objects.push(object.clone());
+                }
+            }
+            Object::Boolean(_)
+            | Object::Nil
+            | Object::Number(_)
+            | Object::String(_)
+            | Object::Enum(_)
+            | Object::EnumMember(_)
+            | Object::Userdata(_) => {}
+        }
+    }
+
+    /// Works through a queue instead of recursing, so long chains of values (a linked list of
+    /// instances) cannot overflow the stack.
+    fn mark_all(mut self) -> Self {
+        loop {
+            if let Some(upvalue) = self.pending_upvalues.pop() {
+                self.mark_object(&upvalue.borrow());
+            } else if let Some(object) = self.pending_objects.pop() {
+                match &object {
+                    Object::Class(class) => class.trace(&mut self),
+                    Object::Instance(instance) => instance.borrow().trace(&mut self),
+                    Object::List(list) => list
+                        .borrow()
+                        .iter()
+                        .for_each(|element| self.mark_object(element)),
+                    Object::Map(map) => map
+                        .borrow()
+                        .values()
+                        .for_each(|value| self.mark_object(value)),
+                    _ => unreachable!(),
+                }
+            } else {
+                return self;
+            }
+        }
+    }
+}
+
+/// Where the value behind the `Rc` of a function, instance, list or map lives.
+fn address(object: &Object) -> *const () {
+    match object {
+        Object::Function(function) => Rc::as_ptr(function).cast(),
+        Object::Instance(instance) => Rc::as_ptr(instance).cast(),
+        Object::List(list) => Rc::as_ptr(list).cast(),
+        Object::Map(map) => Rc::as_ptr(map).cast(),
+        _ => unreachable!(),
+    }
+}
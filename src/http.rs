@@ -0,0 +1,189 @@
+use crate::error::{LoxError, Result};
+use crate::functions::{check_arity, Function};
+use crate::interpreter::Interpreter;
+use crate::maps::{Entries, Key};
+use crate::object::Object;
+
+use std::rc::Rc;
+
+/// `httpGet(url)` sends a GET request and waits for the response.
+pub struct HttpGet;
+
+/// `httpPost(url, body)` sends a POST request with a text body and waits for the response.
+pub struct HttpPost;
+
+impl Function for HttpGet {
+    fn name(&self) -> &str {
+        "httpGet"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(1, arguments)?;
+        let url = string(self.name(), &arguments[0])?;
+        response(interpreter, self.name(), ureq::get(url).call())
+    }
+}
+
+impl Function for HttpPost {
+    fn name(&self) -> &str {
+        "httpPost"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(2, arguments)?;
+        let url = string(self.name(), &arguments[0])?;
+        let body = string(self.name(), &arguments[1])?;
+        response(interpreter, self.name(), ureq::post(url).send_string(body))
+    }
+}
+
+fn string<'a>(function: &str, argument: &'a Object) -> Result<&'a str> {
+    match argument {
+        Object::String(string) => Ok(string),
+        _ => Err(LoxError::InterpreterError(
+            format!(
+                "{}() expects a string, but got '{}'.",
+                function,
+                argument.type_name()
+            )
+            .into(),
+        )),
+    }
+}
+
+/// The response as a map with the `status`, the `headers` (a map from lowercase names to
+/// values) and the `body`. Error statuses are responses too, only requests that got no response
+/// at all fail.
+fn response(
+    interpreter: &mut Interpreter,
+    function: &str,
+    response: std::result::Result<ureq::Response, ureq::Error>,
+) -> Result<Object> {
+    let failed = |error: &dyn std::fmt::Display| {
+        LoxError::InterpreterError(format!("{}() failed: {}.", function, error).into())
+    };
+    let response = match response {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(error) => return Err(failed(&error)),
+    };
+
+    let mut headers = Entries::new();
+    for name in response.headers_names() {
+        let value = response.header(&name).unwrap_or_default();
+        headers.insert(
+            Key::String(name.to_lowercase().into()),
+            interpreter.new_string(value.to_string())?,
+        );
+    }
+    let status = response.status();
+    let body = response.into_string().map_err(|error| failed(&error))?;
+
+    let mut entries = Entries::new();
+    entries.insert(
+        Key::String(Rc::from("status")),
+        Object::Number(status as f64),
+    );
+    entries.insert(
+        Key::String(Rc::from("headers")),
+        interpreter.new_map(headers)?,
+    );
+    entries.insert(Key::String(Rc::from("body")), interpreter.new_string(body)?);
+    interpreter.new_map(entries)
+}
+
+impl std::fmt::Debug for HttpGet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl std::fmt::Debug for HttpPost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::lox::Lox;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers one request with `status` and a body made of the request line and the request's
+    /// body, returning the URL to send it to.
+    fn serve_once(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/path", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buffer = [0; 1024];
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+                if let Some((head, body)) = request.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length: ")
+                                .map(str::to_string)
+                        })
+                        .map_or(0, |length| length.parse().unwrap());
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            let (head, body) = request.split_once("\r\n\r\n").unwrap();
+            let echo = format!("{}{}", head.lines().next().unwrap(), body);
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+                status,
+                echo.len(),
+                echo
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn get() {
+        let source = format!(
+            r#"
+            var response = httpGet("{}");
+            [response.get("status"), response.get("headers").get("content-type"),
+                response.get("body")];
+        "#,
+            serve_once("200 OK")
+        );
+        assert_eq!(
+            Lox::new().eval(&source).unwrap().to_string(),
+            "[200, text/plain, GET /path HTTP/1.1]"
+        );
+    }
+
+    #[test]
+    fn post() {
+        let source = format!(
+            r#"var response = httpPost("{}", "data"); [response.get("status"), response.get("body")];"#,
+            serve_once("404 Not Found")
+        );
+        assert_eq!(
+            Lox::new().eval(&source).unwrap().to_string(),
+            "[404, POST /path HTTP/1.1data]"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Re-parsing after edits, for the language server. A [`Document`] keeps a source file with
+//! its program, so an edit only lexes and parses the top-level statements it touches again and
+//! patches them into the program.
+//!
+//! Statements after the edit keep their nodes, their spans and lines are shifted instead.
+//! Whenever the result could differ from parsing the whole source, e.g. because the edited
+//! statements do not parse on their own or the document had errors before, the whole source is
+//! parsed again.
+
+use crate::error::{LoxError, Result};
+use crate::lexer;
+use crate::parser::{self, ParserOptions};
+use crate::statement::{Ast, Program};
+use crate::token::Span;
+
+use std::ops::Range;
+
+/// A source file and its parse, kept up to date with `edit`.
+pub struct Document {
+    source: String,
+    program: Program,
+    errors: Vec<LoxError>,
+    /// The source of each error, if known.
+    error_spans: Vec<Option<Span>>,
+    /// The nodes of the last full parse. Replaced statements leave their nodes behind, so the
+    /// whole source is parsed again once the tree has grown to twice that.
+    parsed_nodes: usize,
+}
+
+impl Document {
+    pub fn new(source: String) -> Self {
+        let mut document = Self {
+            source,
+            program: Program::default(),
+            errors: vec![],
+            error_spans: vec![],
+            parsed_nodes: 0,
+        };
+        document.parse();
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The program, as parsing the whole source would return it.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// The lexer and parser errors of the source.
+    pub fn errors(&self) -> &[LoxError] {
+        &self.errors
+    }
+
+    /// The source each of the `errors` is about: the bad character for the lexer, the token the
+    /// parser stopped at. None where it is not known.
+    pub fn error_spans(&self) -> &[Option<Span>] {
+        &self.error_spans
+    }
+
+    /// Replaces the bytes in `range` of the source with `text` and parses what changed. Returns
+    /// whether only the statements around the edit were parsed again, or an error, leaving the
+    /// document as it was, if `range` is not within the source or does not lie on character
+    /// boundaries.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> Result<bool> {
+        let removed = self.source.get(range.clone()).ok_or_else(|| {
+            LoxError::InterpreterError(
+                format!(
+                    "Cannot edit bytes {}..{} of a source of {} bytes.",
+                    range.start,
+                    range.end,
+                    self.source.len()
+                )
+                .into(),
+            )
+        })?;
+        let removed_lines = newlines(removed);
+        // an edit can only be confined to a few statements if the ones around it are intact
+        let incremental =
+            self.errors.is_empty() && self.program.ast.node_count() < 2 * self.parsed_nodes.max(1);
+        let (statements, region) = if incremental {
+            self.affected(&range)
+        } else {
+            (0..0, 0..0)
+        };
+        self.source.replace_range(range.clone(), text);
+        if !incremental {
+            self.parse();
+            return Ok(false);
+        }
+
+        // the statements after the region move with the text after the edit
+        let growth = text.len() as isize - range.len() as isize;
+        let line_shift = newlines(text) as i64 - removed_lines as i64;
+        self.program.ast.shift(region.end, growth, line_shift);
+        let region = region.start..(region.end as isize + growth) as usize;
+        if !self.reparse(statements, region) {
+            self.parse();
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// The span of the top-level statement `index`.
+    fn span(&self, index: usize) -> Span {
+        let statement = self.program.statements[index];
+        self.program.ast.stmt_span(statement).unwrap_or_default()
+    }
+
+    /// The top-level statements an edit of `range` may change, and the bytes from the end of the
+    /// statement before them to the start of the one after them.
+    fn affected(&self, range: &Range<usize>) -> (Range<usize>, Range<usize>) {
+        let count = self.program.statements.len();
+        // statements end in ';' or '}', so text added after them cannot change their tokens
+        let first = (0..count)
+            .position(|index| self.span(index).end > range.start)
+            .unwrap_or(count);
+        // a statement on the line of the edit could be commented out by it
+        let last = (first..count)
+            .find(|&index| {
+                let span = self.span(index);
+                span.start >= range.end && self.source[range.end..span.start].contains('\n')
+            })
+            .unwrap_or(count);
+        let start = first
+            .checked_sub(1)
+            .map_or(0, |previous| self.span(previous).end);
+        let end = if last < count {
+            self.span(last).start
+        } else {
+            self.source.len()
+        };
+        (first..last, start..end)
+    }
+
+    /// Parses `region` of the source in place of the top-level statements `statements`. Returns
+    /// false, leaving the program to be parsed again, if the region does not lex and parse
+    /// without errors.
+    fn reparse(&mut self, statements: Range<usize>, region: Range<usize>) -> bool {
+        let (mut tokens, lexer_errors) = lexer::lex(&self.source[region.clone()]);
+        if !lexer_errors.is_empty() {
+            return false;
+        }
+        let first_line = newlines(&self.source[..region.start]) as u32;
+        for token in &mut tokens {
+            token.line += first_line;
+            token.span = token.span.shift(region.start as isize);
+        }
+        let ast = std::mem::take(&mut self.program.ast);
+        let (parsed, errors) = parser::parse_into(&tokens, ast, ParserOptions::default());
+        self.program.ast = parsed.ast;
+        if !errors.is_empty() {
+            return false;
+        }
+        self.program
+            .statements
+            .splice(statements, parsed.statements);
+        true
+    }
+
+    fn parse(&mut self) {
+        let (tokens, mut errors) = lexer::lex(&self.source);
+        let mut error_spans: Vec<_> = errors
+            .iter()
+            .map(|error| match error {
+                LoxError::LexerError(line, column, _) => character_at(&self.source, *line, *column),
+                _ => None,
+            })
+            .collect();
+        let (program, parser_errors) =
+            parser::parse_into(&tokens, Ast::new(), ParserOptions::default());
+        for (error, span) in parser_errors {
+            errors.push(error);
+            error_spans.push(span);
+        }
+        self.parsed_nodes = program.ast.node_count();
+        self.program = program;
+        self.errors = errors;
+        self.error_spans = error_spans;
+    }
+}
+
+fn newlines(text: &str) -> usize {
+    text.bytes().filter(|byte| *byte == b'\n').count()
+}
+
+/// The span of the character in line `line` and column `column` of `source`, both counting from
+/// 1 as the lexer does.
+fn character_at(source: &str, line: u32, column: u32) -> Option<Span> {
+    let line_start: usize = source
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1) as usize)
+        .map(str::len)
+        .sum();
+    let (offset, character) = source[line_start..]
+        .char_indices()
+        .nth(column.saturating_sub(1) as usize)?;
+    Some(Span::new(
+        line_start + offset,
+        line_start + offset + character.len_utf8(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Document;
+    use crate::ast_printer::{print, Style};
+    use crate::error::LoxError;
+    use crate::statement::{Program, Stmt};
+    use crate::{lexer, parser};
+
+    /// The document after replacing `old` with `new` in `source`, checked against parsing the
+    /// edited source from scratch.
+    fn edited(source: &str, old: &str, new: &str) -> (Document, bool) {
+        let mut document = Document::new(source.to_string());
+        let start = source.find(old).unwrap();
+        let incremental = document.edit(start..start + old.len(), new).unwrap();
+
+        let expected = source.replacen(old, new, 1);
+        assert_eq!(document.source(), expected);
+        let (tokens, _) = lexer::lex(&expected);
+        let (program, errors) = parser::parse(&tokens);
+        assert_eq!(document.errors(), &errors[..]);
+        assert_eq!(
+            print(document.program(), Style::Tree),
+            print(&program, Style::Tree)
+        );
+        let lines = |program: &Program| -> Vec<_> {
+            program
+                .statements
+                .iter()
+                .map(|stmt| (program.ast.line(*stmt), program.ast.stmt_span(*stmt)))
+                .collect()
+        };
+        assert_eq!(lines(document.program()), lines(&program));
+        (document, incremental)
+    }
+
+    const SOURCE: &str = "var a = 1;\nfun f(x) {\n  return x + a;\n}\nprint f(2);\n";
+
+    #[test]
+    fn edits_one_statement() {
+        let (document, incremental) = edited(SOURCE, "x + a", "x * a");
+        assert!(incremental);
+        assert_eq!(document.program().statements.len(), 3);
+
+        let (document, incremental) = edited(SOURCE, "var a = 1;\n", "var a = 1;\nvar b = 2;\n\n");
+        assert!(incremental);
+        let program = document.program();
+        match &program.ast[program.statements[2]] {
+            Stmt::Function { body, .. } => assert_eq!(program.ast.line(body[0]), Some(5)),
+            _ => panic!("Expected to be of type Stmt::Function"),
+        }
+        let (_, incremental) = edited(SOURCE, "print f(2);", "");
+        assert!(incremental);
+    }
+
+    #[test]
+    fn comments_out_a_statement() {
+        let (document, incremental) =
+            edited("var a = 1; print a;\nprint 2;\n", "print a", "//print a");
+        assert!(incremental);
+        assert_eq!(document.program().statements.len(), 2);
+    }
+
+    #[test]
+    fn falls_back_to_a_full_parse() {
+        // an unclosed block swallows the statements after it
+        let (document, incremental) = edited(SOURCE, "return x + a;\n}", "return x + a;\n");
+        assert!(!incremental);
+        assert_eq!(document.errors().len(), 1);
+
+        let mut document = Document::new("var a = ;\nprint 1;\n".to_string());
+        assert_eq!(document.edit(8..8, "2"), Ok(false));
+        assert!(document.errors().is_empty());
+        assert_eq!(document.edit(0..0, "print 0;\n"), Ok(true));
+        assert_eq!(document.program().statements.len(), 3);
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        let source = "print \"é\";\n";
+        let mut document = Document::new(source.to_string());
+        let error = |range: &str| {
+            Err(LoxError::InterpreterError(
+                format!("Cannot edit bytes {} of a source of 12 bytes.", range).into(),
+            ))
+        };
+        // past the end, reversed, and within the two bytes of 'é'
+        assert_eq!(document.edit(10..13, ""), error("10..13"));
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 3..1;
+        assert_eq!(document.edit(reversed, ""), error("3..1"));
+        assert_eq!(document.edit(8..9, "e"), error("8..9"));
+        assert_eq!(document.source(), source);
+        assert_eq!(document.edit(7..9, "e"), Ok(true));
+        assert_eq!(document.source(), "print \"e\";\n");
+    }
+}
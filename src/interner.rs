@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Hands out one shared `Rc<str>` per distinct string, so names and literals that appear
+/// many times in a program are allocated once and cloning them is just a reference count bump.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Rc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, string: &str) -> Rc<str> {
+        if let Some(interned) = self.strings.get(string) {
+            return Rc::clone(interned);
+        }
+        let interned: Rc<str> = Rc::from(string);
+        self.strings.insert(Rc::clone(&interned));
+        interned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Interner;
+
+    use std::rc::Rc;
+
+    #[test]
+    fn intern() {
+        let mut interner = Interner::new();
+        let first = interner.intern("answer");
+        let second = interner.intern("answer");
+        let other = interner.intern("question");
+
+        assert!(Rc::ptr_eq(&first, &second));
+        assert!(!Rc::ptr_eq(&first, &other));
+        assert_eq!(&*other, "question");
+    }
+}
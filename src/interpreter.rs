@@ -1,127 +1,881 @@
-use crate::classes::{LoxClass, LoxInstance};
+use crate::classes::{LoxClass, LoxInstance, Shape};
+use crate::debug::{CallFrame, Debugger};
+use crate::enums::LoxEnum;
 use crate::environment::Environment;
 use crate::error::{LoxError, Result};
-use crate::functions::{Clock, Function, LoxFunction};
+use crate::functions::{self, Copier, Function, LoxFunction, NativeFunction, TimeSource};
+use crate::gc::{Heap, Marker};
+use crate::lexer;
+use crate::lists::ListMethod;
+use crate::maps::{Entries, Key, MapMethod};
+use crate::natives::{self, Natives};
 use crate::object::Object;
-use crate::resolver::Depth;
-use crate::statement::{Expr, ExprId, Stmt};
+use crate::parser::{self, ParserOptions};
+use crate::profiler::Profiler;
+use crate::recursion;
+use crate::resolver::{self, Local, Resolution, ScopeLocals, Slot};
+#[cfg(feature = "signals")]
+use crate::signals::Interrupts;
+use crate::stack::{Stack, Upvalue};
+use crate::statement::{Ast, Expr, ExprId, ExprRef, MatchArm, Pattern, Program, Stmt, StmtRef};
+use crate::stats::RuntimeStats;
+use crate::strings::StringMethod;
+use crate::timers::Timers;
 use crate::token::TokenType;
+use crate::userdata::{Userdata, UserdataMethod};
+use crate::vfs::{FileSystem, OsFileSystem};
 
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
 use std::rc::Rc;
+#[cfg(feature = "time")]
+use std::time::Duration;
 
+/// How deeply statements and expressions (and therefore Lox calls) may nest before evaluation is
+/// aborted with a runtime error. Every level costs a few Rust stack frames, the stack grows as
+/// needed (see `recursion::grow_stack`), so this bounds the memory that takes.
+const MAX_NESTING_DEPTH: usize = 24_000;
+
+/// How many Lox calls may be active at once unless configured otherwise.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 2_000;
+
+/// The runtime limits of an interpreter, see `Interpreter::with_config`.
+///
+/// ```
+/// use rlox::{Interpreter, InterpreterConfig};
+///
+/// let config = InterpreterConfig::default().with_max_call_depth(100);
+/// let interpreter = Interpreter::with_config(config);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct InterpreterConfig {
+    max_call_depth: usize,
+    max_steps: Option<u64>,
+    max_memory: Option<usize>,
+    natives: Natives,
+    string_coercion: bool,
+    print_precision: Option<usize>,
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        Self {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_steps: None,
+            max_memory: None,
+            natives: Natives::ALL,
+            string_coercion: false,
+            print_precision: None,
+        }
+    }
+}
+
+impl InterpreterConfig {
+    /// How many Lox calls may be active at once before a call fails with a stack overflow.
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// How many statements and expressions one `Interpreter::interpret` may execute before it
+    /// fails with "Execution budget exceeded.", unlimited by default. Guards hosts against
+    /// scripts that never finish.
+    pub fn with_max_steps(mut self, max_steps: Option<u64>) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Roughly how many bytes the strings, instances and variables of a program may take up
+    /// before an allocation fails with "Memory limit exceeded.", unlimited by default. The
+    /// estimate leaves out the program's own code and the globals.
+    pub fn with_max_memory(mut self, max_memory: Option<usize>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Which groups of native functions are defined, all of them by default.
+    pub fn with_natives(mut self, natives: Natives) -> Self {
+        self.natives = natives;
+        self
+    }
+
+    /// Lets `+` join a string and a number, as in `"count: " + 3`, instead of failing. Off by
+    /// default, other operands still fail either way.
+    pub fn with_string_coercion(mut self, string_coercion: bool) -> Self {
+        self.string_coercion = string_coercion;
+        self
+    }
+
+    /// Rounds the numbers `print` shows, in lists and maps too, to at most this many decimal
+    /// places, so `0.1 + 0.2` prints as `0.3`. By default numbers print with as many digits as
+    /// it takes to read them back exactly.
+    pub fn with_print_precision(mut self, print_precision: Option<usize>) -> Self {
+        self.print_precision = print_precision;
+        self
+    }
+}
+
+/// What a property access resolved to, valid as long as the instance has the same shape. A
+/// shape belongs to a single class, so it also pins down the methods.
+enum PropertyCache {
+    Field {
+        shape: Rc<Shape>,
+        index: usize,
+    },
+    Method {
+        shape: Rc<Shape>,
+        method: Rc<LoxFunction>,
+    },
+}
+
+/// The globals an interpreter had at some point, see `Interpreter::reset`.
+struct Checkpoint {
+    globals: Environment,
+    next_expression_id: ExprId,
+}
+
+/// Collects what a script prints into the shared buffer, where it can be read while the
+/// interpreter still owns the writer, see `Interpreter::set_output`.
+#[derive(Clone, Default)]
+pub(crate) struct Output(pub(crate) Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs programs one after the other, with the globals of earlier ones still defined (as in
+/// the REPL).
 pub struct Interpreter {
-    scopes: HashMap<ExprId, Depth>,
-    globals: Rc<RefCell<Environment>>,
-    environment: Rc<RefCell<Environment>>,
+    scopes: HashMap<ExprId, Local>,
+    captures: HashMap<ExprId, Rc<[Local]>>,
+    super_receivers: HashMap<ExprId, Local>,
+    call_site_locals: HashMap<ExprId, ScopeLocals>,
+    /// Resolutions added for the program run next, see `interpret`.
+    pending_scopes: Vec<Resolution>,
+    /// The locals where the native being called was named, see `Resolution::call_site_locals`.
+    site_locals: Option<ScopeLocals>,
+    property_caches: HashMap<ExprId, PropertyCache>,
+    userdata_methods: HashMap<TypeId, HashMap<Rc<str>, Rc<UserdataMethod>>>,
+    globals: Environment,
+    stack: Stack,
+    nesting_depth: usize,
+    /// The statements and expressions executed by the running program.
+    steps: u64,
+    /// One past the ids of the programs run so far, see `next_expression_id`.
+    next_expression_id: ExprId,
+    config: InterpreterConfig,
+    call_stack: Vec<Object>,
+    /// The line each call in `call_stack` was made in, 0 if unknown.
+    call_lines: Vec<u32>,
+    /// The line of the statement running, 0 if unknown.
+    line: u32,
+    stack_trace: Vec<String>,
+    heap: Heap,
+    output: Box<dyn Write>,
+    time_source: Box<TimeSource>,
+    file_system: Box<dyn FileSystem>,
+    debuggers: Vec<Box<dyn Debugger>>,
+    profiler: Option<Profiler>,
+    stats: RuntimeStats,
+    timers: Timers,
+    #[cfg(feature = "signals")]
+    interrupts: Option<Interrupts>,
+    checkpoint: Checkpoint,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new()));
-        globals
-            .borrow_mut()
-            .define("clock", Rc::new(Object::Function(Rc::new(Clock {}))));
+        Self::with_config(InterpreterConfig::default())
+    }
 
-        Interpreter {
+    pub fn with_config(config: InterpreterConfig) -> Self {
+        let mut globals = Environment::new();
+        natives::define(&mut globals, config.natives);
+
+        let mut interpreter = Interpreter {
             scopes: HashMap::new(),
-            globals: globals.clone(),
-            environment: globals,
+            captures: HashMap::new(),
+            super_receivers: HashMap::new(),
+            call_site_locals: HashMap::new(),
+            pending_scopes: Vec::new(),
+            site_locals: None,
+            property_caches: HashMap::new(),
+            userdata_methods: HashMap::new(),
+            globals,
+            stack: Stack::new(),
+            nesting_depth: 0,
+            steps: 0,
+            next_expression_id: 0,
+            config,
+            call_stack: Vec::new(),
+            call_lines: Vec::new(),
+            line: 0,
+            stack_trace: Vec::new(),
+            heap: Heap::new(),
+            output: Box::new(io::stdout()),
+            time_source: Box::new(functions::system_time),
+            file_system: Box::new(OsFileSystem),
+            debuggers: Vec::new(),
+            profiler: None,
+            stats: RuntimeStats::default(),
+            timers: Timers::default(),
+            #[cfg(feature = "signals")]
+            interrupts: None,
+            checkpoint: Checkpoint {
+                globals: Environment::new(),
+                next_expression_id: 0,
+            },
+        };
+        let natives = interpreter.config.natives;
+        natives::define_methods(&mut interpreter, natives);
+        // natives are functions, which `checkpoint` would not copy either
+        interpreter.checkpoint.globals = interpreter.globals.clone();
+        interpreter
+    }
+
+    /// Makes `print` write to `output` instead of stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Writes `text` and a newline where `print` writes.
+    pub fn print(&mut self, text: &str) -> Result<()> {
+        writeln!(self.output, "{}", text).map_err(|error| {
+            LoxError::InterpreterError(format!("Could not print: {}", error).into())
+        })
+    }
+
+    /// Runs `program`, whose resolution has to be added with `add_scopes` first, and then the
+    /// timers it scheduled until none are left. Returns the value of the last statement if that
+    /// is an expression statement, nil otherwise.
+    ///
+    /// Programs parsed with the default `ParserOptions` all number their nodes from 0, so if the
+    /// ids of `program` clash with those of programs run before, it gets fresh ones first, and
+    /// its resolution with them.
+    pub fn interpret(&mut self, mut program: Program) -> Result<Object> {
+        let first_id = program.ast.first_id();
+        if first_id < self.next_expression_id {
+            let offset = self.next_expression_id - first_id;
+            program.ast.shift_ids(offset);
+            self.pending_scopes
+                .iter_mut()
+                .for_each(|resolution| resolution.shift_ids(offset));
+        }
+        for resolution in std::mem::take(&mut self.pending_scopes) {
+            self.extend_scopes(resolution);
         }
+        let result = self
+            .run_program(program)
+            .and_then(|value| self.run_timers().map(|_| value));
+        if result.is_err() {
+            // they would otherwise run after the next program, e.g. the next line in the REPL
+            self.timers.clear();
+        }
+        result
     }
 
-    pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<()> {
-        for statement in statements {
-            self.execute(&statement)?;
+    fn run_program(&mut self, program: Program) -> Result<Object> {
+        self.stack_trace.clear();
+        self.steps = 0;
+        self.next_expression_id = self.next_expression_id.max(program.ast.next_id());
+        let ast = Rc::new(program.ast);
+        let mut value = Object::Nil;
+        for statement in program.statements {
+            // expression statements do not go through `execute`
+            #[cfg(feature = "signals")]
+            self.check_interrupts()?;
+            value = match &ast[statement] {
+                Stmt::Expression { expression } => {
+                    self.track_line(&ast, statement);
+                    let line = self.debug(&ast, statement)?;
+                    let value = self.evaluate(&ast, *expression);
+                    self.debug_after(line);
+                    value?
+                }
+                _ => {
+                    self.execute(&ast, statement)?;
+                    Object::Nil
+                }
+            };
+            // between top-level statements nothing is left on the Rust stack
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+        }
+        Ok(value)
+    }
+
+    /// Runs the callbacks of the timers in the order they come due, waiting for each one.
+    fn run_timers(&mut self) -> Result<()> {
+        while let Some((callback, arguments)) = self.timers.next() {
+            self.call_callback(&callback, &arguments)?;
+            // as between top-level statements, nothing is left on the Rust stack
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
         }
         Ok(())
     }
 
-    pub fn add_scopes(&mut self, scopes: HashMap<ExprId, Depth>) {
-        scopes.iter().for_each(|(&k, &v)| {
-            self.scopes.insert(k, v);
-        });
+    /// Calls `callback` on behalf of the host rather than Lox code, e.g. when a timer is due.
+    fn call_callback(&mut self, callback: &Object, arguments: &[Object]) -> Result<Object> {
+        self.call_stack.push(callback.clone());
+        self.call_lines.push(0);
+        let result = self.call(callback, arguments);
+        if result.is_err() {
+            self.record_stack_trace();
+        }
+        self.call_stack.pop();
+        self.call_lines.pop();
+        result
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> Result<()> {
-        match stmt {
-            Stmt::Print { expression } => {
-                println!("{}", self.evaluate(&expression)?);
+    /// Frees unreachable reference cycles. Must not be called while Lox code is running.
+    pub fn collect_garbage(&mut self) {
+        let mut roots = Marker::new();
+        self.globals.trace(&mut roots);
+        self.checkpoint.globals.trace(&mut roots);
+        self.stack.trace(&mut roots);
+        self.timers.trace(&mut roots);
+        #[cfg(feature = "signals")]
+        if let Some(interrupts) = &self.interrupts {
+            interrupts.trace(&mut roots);
+        }
+        self.call_stack
+            .iter()
+            .for_each(|callee| roots.mark_object(callee));
+        for cache in self.property_caches.values() {
+            if let PropertyCache::Method { method, .. } = cache {
+                method.trace(&mut roots);
+            }
+        }
+        self.heap.collect(roots);
+    }
+
+    /// Makes `clock()` return the time `time_source` reports, e.g. a fixed one so scripts run
+    /// deterministically.
+    pub fn set_time_source(&mut self, time_source: impl Fn() -> f64 + 'static) {
+        self.time_source = Box::new(time_source);
+    }
+
+    /// Makes scripts read files from `file_system` instead of the operating system's.
+    pub fn set_file_system(&mut self, file_system: impl FileSystem + 'static) {
+        self.file_system = Box::new(file_system);
+    }
+
+    pub fn file_system(&self) -> &dyn FileSystem {
+        &*self.file_system
+    }
+
+    /// The current time in seconds, as `clock()` sees it.
+    pub fn now(&self) -> f64 {
+        (self.time_source)()
+    }
+
+    /// Runs `callback` once `delay` has passed after the program, see `Timers::schedule`.
+    #[cfg(feature = "time")]
+    pub(crate) fn schedule_timer(
+        &mut self,
+        callback: Object,
+        delay: Duration,
+        repeat: bool,
+    ) -> u64 {
+        self.timers.schedule(callback, delay, repeat)
+    }
+
+    /// Runs `callback` whenever the file at `path` changes, see `Timers::watch`.
+    #[cfg(feature = "watch")]
+    pub(crate) fn watch_file(&mut self, path: Rc<str>, callback: Object) -> u64 {
+        self.timers.watch(path, callback)
+    }
+
+    #[cfg(any(feature = "time", feature = "watch"))]
+    pub(crate) fn cancel_timer(&mut self, id: u64) {
+        self.timers.cancel(id);
+    }
+
+    /// Calls `handler` instead of exiting when the process is interrupted, see `OnInterrupt`.
+    #[cfg(feature = "signals")]
+    pub(crate) fn on_interrupt(&mut self, handler: Object) -> Result<()> {
+        match &mut self.interrupts {
+            Some(interrupts) => interrupts.set_handler(handler),
+            None => self.interrupts = Some(Interrupts::register(handler)?),
+        }
+        Ok(())
+    }
+
+    /// Runs the interrupt handler if a signal came in, then stops the program.
+    #[cfg(feature = "signals")]
+    fn check_interrupts(&mut self) -> Result<()> {
+        let (handler, signal) = match self.interrupts.as_ref().and_then(Interrupts::take) {
+            Some(interrupt) => interrupt,
+            None => return Ok(()),
+        };
+        let name = self.new_string(signal.to_string())?;
+        let result = self.call_callback(&handler, &[name]);
+        if let Some(interrupts) = &self.interrupts {
+            interrupts.handled();
+        }
+        result?;
+        Err(LoxError::InterpreterError(
+            format!("Interrupted by {}.", signal).into(),
+        ))
+    }
+
+    /// Defines a global function implemented in Rust. Calls with the wrong number of arguments
+    /// fail before `function` runs.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Object]) -> Result<Object> + 'static,
+    ) {
+        let name: Rc<str> = name.into();
+        let native = NativeFunction::new(Rc::clone(&name), arity, Box::new(function));
+        self.globals.define(name, Object::Function(Rc::new(native)));
+    }
+
+    /// Defines a global variable, e.g. to hand userdata to scripts.
+    pub fn define_global(&mut self, name: &str, value: Object) {
+        self.globals.define(name.into(), value);
+    }
+
+    /// Defines a method on all userdata holding a `T`. Calls with the wrong number of arguments
+    /// fail before `method` runs.
+    pub fn define_method<T: Any>(
+        &mut self,
+        name: &str,
+        arity: usize,
+        method: impl Fn(&T, &[Object]) -> Result<Object> + 'static,
+    ) {
+        let function = move |userdata: &Userdata, arguments: &[Object]| {
+            // only userdata holding a `T` finds this method
+            method(userdata.downcast_ref().unwrap(), arguments)
+        };
+        self.userdata_methods
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .insert(
+                name.into(),
+                Rc::new(UserdataMethod {
+                    arity,
+                    function: Box::new(function),
+                }),
+            );
+    }
+
+    /// The calls that were active when the last runtime error was raised, innermost first.
+    pub fn stack_trace(&self) -> &[String] {
+        &self.stack_trace
+    }
+
+    /// Lets `debugger` watch (and stop) the programs run from now on, instead of the debuggers
+    /// installed so far.
+    pub fn set_debugger(&mut self, debugger: impl Debugger + 'static) {
+        self.debuggers = vec![Box::new(debugger)];
+    }
+
+    /// Lets `debugger` watch the programs run from now on, after the debuggers installed so far.
+    pub fn add_debugger(&mut self, debugger: impl Debugger + 'static) {
+        self.debuggers.push(Box::new(debugger));
+    }
+
+    /// Starts counting the calls of the programs run from now on and how long they take.
+    pub fn enable_profiler(&mut self) {
+        self.profiler.get_or_insert_with(Profiler::new);
+    }
+
+    /// What the profiler measured, if it is enabled.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// What the programs run so far did.
+    pub fn stats(&self) -> &RuntimeStats {
+        &self.stats
+    }
+
+    /// The variable in `slot` of the running function, see `Resolution::line_locals`.
+    pub fn local(&self, slot: Slot) -> Option<Object> {
+        self.stack.get(slot, "").ok()
+    }
+
+    /// Evaluates the expression `source` in the running function, for debuggers stopped in a
+    /// `Debugger::statement`. `locals` names the slots of the function as in
+    /// `Resolution::line_locals`.
+    pub fn evaluate_in_frame(
+        &mut self,
+        source: &str,
+        locals: &[(Rc<str>, Slot)],
+    ) -> Result<Object> {
+        let source = format!("{};", source.trim().trim_end_matches(';'));
+        let (tokens, lexer_errors) = lexer::lex(&source);
+        if let Some(error) = lexer_errors.into_iter().next() {
+            return Err(error);
+        }
+        let options = ParserOptions {
+            first_id: self.next_expression_id,
+            ..ParserOptions::default()
+        };
+        let (program, parser_errors) = parser::parse_with_options(&tokens, options);
+        if let Some(error) = parser_errors.into_iter().next() {
+            return Err(error);
+        }
+        self.next_expression_id = program.ast.next_id();
+        let expression = match program.statements[..] {
+            [stmt] => match program.ast[stmt] {
+                Stmt::Expression { expression } => Some(expression),
+                _ => None,
+            },
+            _ => None,
+        };
+        let expression = expression
+            .ok_or_else(|| LoxError::InterpreterError("Expected a single expression.".into()))?;
+        self.extend_scopes(resolver::resolve_in(&program, locals)?);
+
+        // a failing expression must not leave its stack trace for the paused program
+        let stack_trace = std::mem::take(&mut self.stack_trace);
+        let value = self.evaluate(&Rc::new(program.ast), expression);
+        self.stack_trace = stack_trace;
+        value
+    }
+
+    /// The locals of the running function in scope where the native being called was named, if
+    /// the resolver recorded them.
+    pub fn call_site_locals(&self) -> Option<ScopeLocals> {
+        self.site_locals.clone()
+    }
+
+    /// The first id a program run next has to number its nodes from, so they do not clash with
+    /// those of the programs run before, whose functions may still be called. Pass it as
+    /// `ParserOptions::first_id`.
+    pub fn next_expression_id(&self) -> ExprId {
+        self.next_expression_id
+    }
+
+    /// How many calls are in progress.
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// The calls in progress, innermost first.
+    pub fn call_frames(&self) -> Vec<CallFrame> {
+        // a call is at the line it made the next call in, the innermost one where it is running
+        let lines = self.call_lines.iter().skip(1).chain(Some(&self.line));
+        let mut frames: Vec<_> = self
+            .call_stack
+            .iter()
+            .zip(lines)
+            .map(|(callee, &line)| CallFrame {
+                line: Some(line).filter(|line| *line != 0),
+                ..call_frame(callee)
+            })
+            .collect();
+        frames.reverse();
+        frames
+    }
+
+    /// The line the top-level code is running, or made the outermost call in progress from.
+    pub fn script_line(&self) -> Option<u32> {
+        let line = self.call_lines.first().unwrap_or(&self.line);
+        Some(*line).filter(|line| *line != 0)
+    }
+
+    /// The global variables, sorted by name.
+    pub fn globals(&self) -> Vec<(Rc<str>, Object)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (Rc::clone(name), value.clone()))
+            .collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        globals
+    }
+
+    /// Makes `reset` go back to the globals defined now, e.g. once a prelude ran. Their lists,
+    /// maps and instances are copied as `clone()` copies them, which fails if that goes over
+    /// the memory limit.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let globals = self.globals.clone();
+        self.checkpoint = Checkpoint {
+            globals: self.copy_globals(&globals)?,
+            next_expression_id: self.next_expression_id,
+        };
+        Ok(())
+    }
+
+    /// Forgets the globals defined and the programs run since the last `checkpoint`, or since
+    /// the interpreter was created, along with the timers still pending. The remaining globals
+    /// get fresh copies of the values they had then, so changes made to their lists, maps and
+    /// instances, like pushing onto a list, are undone too. Functions and classes are not
+    /// copied: variables their closures captured keep their changes. Fails like `checkpoint`,
+    /// leaving no globals defined.
+    pub fn reset(&mut self) -> Result<()> {
+        // ids are not handed out again, functions of forgotten programs may still be around
+        let forgotten = self.checkpoint.next_expression_id;
+        self.scopes.retain(|id, _| *id < forgotten);
+        self.captures.retain(|id, _| *id < forgotten);
+        self.super_receivers.retain(|id, _| *id < forgotten);
+        self.call_site_locals.retain(|id, _| *id < forgotten);
+        self.property_caches.retain(|id, _| *id < forgotten);
+        self.pending_scopes.clear();
+        self.timers.clear();
+        self.stack_trace.clear();
+        // frees what the programs left behind before copying
+        self.globals = Environment::new();
+        self.collect_garbage();
+        let globals = self.checkpoint.globals.clone();
+        self.globals = self.copy_globals(&globals)?;
+        Ok(())
+    }
+
+    /// `globals` with deep copies of their values, sharing what they shared.
+    fn copy_globals(&mut self, globals: &Environment) -> Result<Environment> {
+        let mut copy = globals.clone();
+        let mut copier = Copier::default();
+        for value in copy.values_mut() {
+            *value = copier.copy(self, value)?;
+        }
+        copier.finish(self)?;
+        Ok(copy)
+    }
+
+    /// Adds what the resolver found out about the program `interpret` runs next.
+    pub fn add_scopes(&mut self, resolution: Resolution) {
+        self.pending_scopes.push(resolution);
+    }
+
+    fn extend_scopes(&mut self, resolution: Resolution) {
+        self.scopes.extend(resolution.locals);
+        self.captures.extend(resolution.captures);
+        self.super_receivers.extend(resolution.super_receivers);
+        self.call_site_locals.extend(resolution.call_site_locals);
+    }
+
+    fn execute(&mut self, ast: &Rc<Ast>, stmt: StmtRef) -> Result<()> {
+        #[cfg(feature = "signals")]
+        self.check_interrupts()?;
+        self.nested(|interpreter| interpreter.execute_statement(ast, stmt))
+    }
+
+    /// Calls `hook` for every debugger until one fails. The debuggers are taken out of the
+    /// interpreter meanwhile, so code they run is not debugged.
+    fn each_debugger(
+        &mut self,
+        mut hook: impl FnMut(&mut dyn Debugger, &mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let mut debuggers = std::mem::take(&mut self.debuggers);
+        let result = debuggers
+            .iter_mut()
+            .try_for_each(|debugger| hook(debugger.as_mut(), self));
+        // keep the ones a hook added
+        debuggers.append(&mut self.debuggers);
+        self.debuggers = debuggers;
+        result
+    }
+
+    /// Tells the debuggers that `stmt` is about to run. Returns its line if they have to be told
+    /// when it finished, see `debug_after`.
+    fn debug(&mut self, ast: &Ast, stmt: StmtRef) -> Result<Option<u32>> {
+        if self.debuggers.is_empty() || matches!(ast[stmt], Stmt::Block { .. }) {
+            return Ok(None);
+        }
+        match ast.line(stmt) {
+            Some(line) => {
+                self.each_debugger(|debugger, interpreter| debugger.statement(interpreter, line))?;
+                Ok(Some(line))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Tells the debuggers that the statement starting in `line` finished.
+    fn debug_after(&mut self, line: Option<u32>) {
+        if let Some(line) = line {
+            self.each_debugger(|debugger, interpreter| {
+                debugger.after_statement(interpreter, line);
                 Ok(())
+            })
+            .ok();
+        }
+    }
+
+    /// Tells the debuggers that `name` was set to `value`.
+    fn debug_assignment(&mut self, name: &str, value: &Object) {
+        self.each_debugger(|debugger, interpreter| {
+            debugger.assignment(interpreter, name, value);
+            Ok(())
+        })
+        .ok();
+    }
+
+    fn track_line(&mut self, ast: &Ast, stmt: StmtRef) {
+        if let Some(line) = ast.line(stmt) {
+            self.line = line;
+        }
+    }
+
+    fn execute_statement(&mut self, ast: &Rc<Ast>, stmt: StmtRef) -> Result<()> {
+        self.track_line(ast, stmt);
+        let line = self.debug(ast, stmt)?;
+        let result = self.run_statement(ast, stmt);
+        self.debug_after(line);
+        result
+    }
+
+    /// Runs one iteration of a loop. Returns false if a `break` without a label ended the loop.
+    fn run_loop_body(&mut self, ast: &Rc<Ast>, body: StmtRef) -> Result<bool> {
+        match self.execute(ast, body) {
+            Ok(()) => Ok(true),
+            Err(LoxError::Break(None)) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn run_statement(&mut self, ast: &Rc<Ast>, stmt: StmtRef) -> Result<()> {
+        match &ast[stmt] {
+            Stmt::Print { expression } => {
+                let value = self.evaluate(ast, *expression)?;
+                let text = match self.config.print_precision {
+                    Some(precision) => format!("{:.*}", precision, value),
+                    None => value.to_string(),
+                };
+                self.print(&text)
             }
             Stmt::Expression { expression } => {
-                self.evaluate(&expression)?;
+                self.evaluate(ast, *expression)?;
                 Ok(())
             }
-            Stmt::Var { name, initializer } => {
+            Stmt::Var {
+                id,
+                name,
+                initializer,
+                constant,
+                ..
+            } => {
                 let value = if let Some(expression) = initializer {
-                    self.evaluate(&expression)?
+                    self.evaluate(ast, *expression)?
                 } else {
-                    Rc::new(Object::Nil)
+                    Object::Nil
                 };
-                self.environment.borrow_mut().define(&name, value);
+                self.debug_assignment(name, &value);
+                // the resolver already rejects assignments to local constants
+                if *constant && self.get_local(id).is_none() {
+                    self.globals.define_constant(Rc::clone(name), value);
+                } else {
+                    self.define(id, name, value);
+                }
                 Ok(())
             }
-            Stmt::Block { statements } => self.execute_block(
-                statements,
-                Rc::new(RefCell::new(Environment::with_enclosing(
-                    self.environment.clone(),
-                ))),
-            ),
+            Stmt::Block { statements } => statements
+                .iter()
+                .try_for_each(|&statement| self.execute(ast, statement)),
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
             } => {
-                let condition = self.evaluate(condition)?;
+                let condition = self.evaluate(ast, *condition)?;
                 if self.is_truthy(&condition) {
-                    self.execute(then_branch)
+                    self.execute(ast, *then_branch)
                 } else if let Some(else_branch) = else_branch {
-                    self.execute(else_branch)
+                    self.execute(ast, *else_branch)
                 } else {
                     Ok(())
                 }
             }
             Stmt::While { condition, body } => {
-                let mut evaluated_condition = self.evaluate(&condition)?;
+                let mut evaluated_condition = self.evaluate(ast, *condition)?;
                 while self.is_truthy(&evaluated_condition) {
-                    self.execute(body)?;
-                    evaluated_condition = self.evaluate(&condition)?;
+                    if !self.run_loop_body(ast, *body)? {
+                        break;
+                    }
+                    evaluated_condition = self.evaluate(ast, *condition)?;
                 }
                 Ok(())
             }
+            Stmt::DoWhile { body, condition } => loop {
+                if !self.run_loop_body(ast, *body)? {
+                    return Ok(());
+                }
+                let condition = self.evaluate(ast, *condition)?;
+                if !self.is_truthy(&condition) {
+                    return Ok(());
+                }
+            },
+            Stmt::Labeled { label, body } => match self.execute(ast, *body) {
+                Err(LoxError::Break(Some(target))) if target == *label => Ok(()),
+                result => result,
+            },
+            Stmt::Break { label } => Err(LoxError::Break(label.clone())),
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                let subject = self.evaluate(ast, *subject)?;
+                for (value, body) in cases {
+                    if self.evaluate(ast, *value)? == subject {
+                        return self.execute(ast, *body);
+                    }
+                }
+                match default {
+                    Some(body) => self.execute(ast, *body),
+                    None => Ok(()),
+                }
+            }
             Stmt::Function {
+                id,
                 name,
                 parameters,
                 body,
+                ..
             } => {
-                let function = Rc::new(Object::Function(Rc::new(LoxFunction::new(
+                // declared before the function exists, so a local function can capture itself
+                self.define(id, name, Object::Nil);
+                self.stats.functions += 1;
+                let function = Object::Function(Rc::new(LoxFunction::new(
+                    Rc::clone(name),
                     parameters.clone(),
-                    body.clone(),
-                    self.environment.clone(),
+                    Rc::clone(body),
+                    Rc::clone(ast),
+                    self.capture(id),
                     false,
-                ))));
-                self.environment.borrow_mut().define(&name, function);
-                Ok(())
+                )));
+                self.initialize(id, name, function)
             }
             Stmt::Return { value } => {
                 let value = if let Some(value) = value {
-                    self.evaluate(value)?
+                    self.evaluate(ast, *value)?
                 } else {
-                    Rc::new(Object::Nil)
+                    Object::Nil
                 };
                 Err(LoxError::Return(value))
             }
             Stmt::Class {
+                id,
                 name,
                 superclass,
                 methods,
             } => {
                 let (super_object, super_loxclass) = if let Some(superclass) = superclass {
-                    let super_object = self.evaluate(superclass)?;
-                    if let Object::Class(super_loxclass) = super_object.as_ref() {
-                        (Some(Rc::clone(&super_object)), Some(super_loxclass.clone()))
+                    let super_object = self.evaluate(ast, *superclass)?;
+                    if let Object::Class(super_loxclass) = &super_object {
+                        (Some(super_object.clone()), Some(super_loxclass.clone()))
                     } else {
                         return Err(LoxError::InterpreterError(
                             "Superclass must be a class".into(),
@@ -131,114 +885,183 @@ impl Interpreter {
                     (None, None)
                 };
 
-                let method_environment = if super_object.is_some() {
-                    let env = Rc::new(RefCell::new(Environment::with_enclosing(Rc::clone(
-                        &self.environment,
-                    ))));
-                    env.borrow_mut()
-                        .define("super", Rc::clone(&super_object.unwrap()));
-                    env
-                } else {
-                    Rc::clone(&self.environment)
-                };
-
+                self.define(id, name, Object::Nil);
                 let mut name_to_method = HashMap::new();
-                for method in methods.as_ref() {
+                for &method in methods {
                     if let Stmt::Function {
+                        id,
                         name,
                         parameters,
                         body,
-                    } = method
+                        ..
+                    } = &ast[method]
                     {
-                        name_to_method.insert(
-                            name.to_string(),
-                            Rc::new(LoxFunction::new(
-                                parameters.clone(),
-                                body.clone(),
-                                Rc::clone(&method_environment),
-                                name == "init",
-                            )),
+                        self.stats.functions += 1;
+                        let mut method = LoxFunction::new(
+                            Rc::clone(name),
+                            parameters.clone(),
+                            Rc::clone(body),
+                            Rc::clone(ast),
+                            self.capture(id),
+                            &**name == "init",
                         );
+                        if let Some(super_object) = &super_object {
+                            method = method.with_superclass(super_object.clone());
+                        }
+                        name_to_method.insert(Rc::clone(name), Rc::new(method));
                     } else {
                         unreachable!()
                     }
                 }
 
-                let class = Rc::new(Object::Class(Rc::new(LoxClass::new(
-                    name.to_string(),
+                let class = Object::Class(Rc::new(LoxClass::new(
+                    Rc::clone(name),
                     super_loxclass,
                     name_to_method,
-                ))));
-
-                self.environment.borrow_mut().define(name, class);
+                )));
 
+                self.initialize(id, name, class)
+            }
+            Stmt::Enum { id, name, members } => {
+                let value = Object::Enum(Rc::new(LoxEnum::new(Rc::clone(name), members)));
+                self.define(id, name, value);
                 Ok(())
             }
         }
     }
 
-    pub fn execute_block(
+    /// Runs the body of a function in a new frame that starts with `locals`, with `upvalues`
+    /// as the captured variables.
+    pub fn execute_function<'b>(
         &mut self,
-        statements: &Vec<Stmt>,
-        environment: Rc<RefCell<Environment>>,
+        ast: &Rc<Ast>,
+        body: &[StmtRef],
+        locals: impl Iterator<Item = &'b Object>,
+        upvalues: &Rc<[Upvalue]>,
     ) -> Result<()> {
-        let previous = self.environment.clone();
-        self.environment = environment;
+        self.stack.push_frame(Rc::clone(upvalues));
+        self.stats.frames += 1;
+        locals
+            .enumerate()
+            .for_each(|(slot, local)| self.stack.define(slot, local.clone()));
+        let result = body
+            .iter()
+            .try_for_each(|&statement| self.execute(ast, statement));
+        self.stack.pop_frame();
+        result
+    }
 
-        for statement in statements {
-            self.execute(statement).map_err(|err| {
-                self.environment = previous.clone();
-                err
-            })?;
+    fn evaluate(&mut self, ast: &Rc<Ast>, expr: ExprRef) -> Result<Object> {
+        self.nested(|interpreter| interpreter.evaluate_expression(ast, expr))
+    }
+
+    /// Runs `f` one nesting level deeper, failing with a Lox error instead of overflowing the
+    /// Rust stack when statements, expressions or calls nest too deeply. Every statement and
+    /// expression passes through here, so this also counts the steps of the execution budget.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.nesting_depth >= MAX_NESTING_DEPTH {
+            return Err(LoxError::InterpreterError("Stack overflow.".into()));
+        }
+        self.steps += 1;
+        if self
+            .config
+            .max_steps
+            .is_some_and(|max_steps| self.steps > max_steps)
+        {
+            return Err(LoxError::InterpreterError(
+                "Execution budget exceeded.".into(),
+            ));
         }
+        self.nesting_depth += 1;
+        let result = recursion::grow_stack(|| f(self));
+        self.nesting_depth -= 1;
+        result
+    }
 
-        self.environment = previous;
-        Ok(())
+    /// Counts `bytes` more for a tracked value that grew, e.g. a list a native added to.
+    pub(crate) fn grow_heap(&mut self, bytes: usize) -> Result<()> {
+        self.heap.grow(bytes);
+        self.check_memory()
+    }
+
+    /// A new string, tracked by the heap.
+    pub(crate) fn new_string(&mut self, string: String) -> Result<Object> {
+        let string: Rc<str> = string.into();
+        self.heap.track_string(&string);
+        self.stats.strings += 1;
+        self.check_memory()?;
+        Ok(Object::String(string))
+    }
+
+    /// A new list of `elements`, tracked by the heap.
+    pub(crate) fn new_list(&mut self, elements: Vec<Object>) -> Result<Object> {
+        let list = Rc::new(RefCell::new(elements));
+        self.heap.track_list(&list);
+        self.check_memory()?;
+        Ok(Object::List(list))
+    }
+
+    /// A new map of `entries`, tracked by the heap.
+    pub(crate) fn new_map(&mut self, entries: Entries) -> Result<Object> {
+        let map = Rc::new(RefCell::new(entries));
+        self.heap.track_map(&map);
+        self.check_memory()?;
+        Ok(Object::Map(map))
+    }
+
+    /// Puts `instance` on the heap and tracks it.
+    pub(crate) fn new_instance(&mut self, instance: LoxInstance) -> Result<Object> {
+        let instance = Rc::new(RefCell::new(instance));
+        self.heap.track_instance(&instance);
+        self.stats.instances += 1;
+        self.check_memory()?;
+        Ok(Object::Instance(instance))
+    }
+
+    /// Fails once the program's memory estimate goes over the limit. The estimate only drops
+    /// when the heap is pruned, so that happens first, before giving up.
+    fn check_memory(&mut self) -> Result<()> {
+        let max_memory = match self.config.max_memory {
+            Some(max_memory) => max_memory,
+            None => return Ok(()),
+        };
+        if self.heap.bytes() + self.stack.size() <= max_memory {
+            return Ok(());
+        }
+        self.heap.prune();
+        if self.heap.bytes() + self.stack.size() <= max_memory {
+            Ok(())
+        } else {
+            Err(LoxError::InterpreterError("Memory limit exceeded.".into()))
+        }
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> Result<Rc<Object>> {
-        match expr {
-            Expr::Nil => Ok(Rc::new(Object::Nil)),
-            Expr::Boolean(b) => Ok(Rc::new(Object::Boolean(*b))),
-            Expr::String(s) => Ok(Rc::new(Object::String(s.to_string()))),
-            Expr::Number(num) => Ok(Rc::new(Object::Number(*num))),
-            Expr::Grouping { expression } => self.evaluate(expression),
-            Expr::Unary { token_type, right } => self.unary_expression(token_type, right),
+    fn evaluate_expression(&mut self, ast: &Rc<Ast>, expr: ExprRef) -> Result<Object> {
+        match &ast[expr] {
+            Expr::Nil => Ok(Object::Nil),
+            Expr::Boolean(b) => Ok(Object::Boolean(*b)),
+            Expr::String(s) => Ok(Object::String(Rc::clone(s))),
+            Expr::Number(num) => Ok(Object::Number(*num)),
+            Expr::Grouping { expression } => self.evaluate(ast, *expression),
+            Expr::Unary { token_type, right } => self.unary_expression(ast, token_type, *right),
             Expr::Binary {
                 left,
                 token_type,
                 right,
-            } => self.binary_expression(left, token_type, right),
-            Expr::Variable { id, name } => {
-                let depth = self.get_locals_depth(id);
-                if let Some(depth) = depth {
-                    self.environment.borrow().get(depth, name)
-                } else {
-                    self.globals.borrow().get(0, name)
-                }
-            }
-            Expr::This { id, keyword } => {
-                let depth = self.get_locals_depth(id);
-                if let Some(depth) = depth {
-                    self.environment.borrow().get(depth, keyword)
-                } else {
-                    self.globals.borrow().get(0, keyword)
-                }
-            }
+            } => self.binary_expression(ast, *left, token_type, *right),
+            Expr::Variable { id, name } => self.look_up_variable(id, name),
+            Expr::This { id, keyword } => self.look_up_variable(id, keyword),
             Expr::Super {
                 id,
                 keyword,
                 method: method_name,
             } => {
-                let depth = self.get_locals_depth(id).unwrap();
-                let superclass = self.environment.borrow().get(depth, keyword)?;
-
-                // "this" is always one depth closer than "super"'s environment
-                let superobject = self.environment.borrow().get(depth - 1, "this")?;
-                if let Object::Class(superclass) = superclass.as_ref() {
+                let superclass = self.look_up_variable(id, keyword)?;
+                let superobject = self.read_local(self.super_receivers[id], "this")?;
+                if let Object::Class(superclass) = &superclass {
                     let method = superclass.find_method(method_name);
                     if let Some(method) = method {
-                        Ok(Rc::new(Object::Function(Rc::new(method.bind(superobject)))))
+                        Ok(self.bound_method(&method, superobject))
                     } else {
                         Err(LoxError::InterpreterError(
                             format!("Undefined property '{}'.", method_name).into(),
@@ -249,15 +1072,15 @@ impl Interpreter {
                 }
             }
             Expr::Assign { id, name, value } => {
-                let value = self.evaluate(value)?;
-                let depth = self.get_locals_depth(id);
-                if let Some(depth) = depth {
-                    self.environment
-                        .borrow_mut()
-                        .assign(depth, name, value.clone())?;
-                } else {
-                    self.globals.borrow_mut().assign(0, name, value.clone())?;
+                let value = self.evaluate(ast, *value)?;
+                match self.get_local(id) {
+                    Some(Local::Slot(slot)) => self.stack.assign(slot, name, value.clone())?,
+                    Some(Local::Upvalue(index)) => {
+                        *self.stack.upvalue(index).borrow_mut() = value.clone();
+                    }
+                    None => self.globals.assign(name, value.clone())?,
                 }
+                self.debug_assignment(name, &value);
                 Ok(value)
             }
             Expr::Logical {
@@ -265,33 +1088,52 @@ impl Interpreter {
                 operator,
                 right,
             } => {
-                let left = self.evaluate(left)?;
+                let left = self.evaluate(ast, *left)?;
                 if operator == &TokenType::Or {
                     if self.is_truthy(&left) {
                         return Ok(left);
                     }
-                } else {
-                    if !self.is_truthy(&left) {
-                        return Ok(left);
-                    }
+                } else if !self.is_truthy(&left) {
+                    return Ok(left);
                 }
-                self.evaluate(right)
+                self.evaluate(ast, *right)
             }
-            Expr::Call { callee, arguments } => self.call_expression(callee, arguments),
-            Expr::Get { object, name } => {
-                let object = self.evaluate(object)?;
-                LoxInstance::get(object, name)
+            Expr::Call { callee, arguments } => self.call_expression(ast, *callee, arguments),
+            Expr::Match { subject, arms } => self.match_expression(ast, *subject, arms),
+            Expr::List { elements } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.evaluate(ast, *element))
+                    .collect::<Result<Vec<_>>>()?;
+                self.new_list(elements)
+            }
+            Expr::Map { entries } => {
+                let mut map = BTreeMap::new();
+                for &(key, value) in entries {
+                    let key = Key::new(&self.evaluate(ast, key)?)?;
+                    map.insert(key, self.evaluate(ast, value)?);
+                }
+                self.new_map(map)
+            }
+            Expr::Get { id, object, name } => {
+                let object = self.evaluate(ast, *object)?;
+                self.get_property(id, object, name)
             }
             Expr::Set {
                 object,
                 name,
                 value,
             } => {
-                let object = self.evaluate(object)?;
-                let value = self.evaluate(value)?;
-                if let &Object::Instance(ref instance) = object.as_ref() {
-                    instance.borrow_mut().set(name, value);
-                    Ok(Rc::new(Object::Nil))
+                let object = self.evaluate(ast, *object)?;
+                let value = self.evaluate(ast, *value)?;
+                if let Object::Instance(instance) = &object {
+                    let mut instance = instance.borrow_mut();
+                    let size = instance.size();
+                    instance.set(name, value);
+                    self.heap.grow(instance.size() - size);
+                    drop(instance);
+                    self.check_memory()?;
+                    Ok(Object::Nil)
                 } else {
                     Err(LoxError::InterpreterError(
                         "Only instances have fields.".into(),
@@ -301,48 +1143,101 @@ impl Interpreter {
         }
     }
 
-    fn unary_expression(&mut self, token_type: &TokenType, expr: &Expr) -> Result<Rc<Object>> {
-        let right = self.evaluate(expr)?;
+    fn match_expression(
+        &mut self,
+        ast: &Rc<Ast>,
+        subject: ExprRef,
+        arms: &[MatchArm],
+    ) -> Result<Object> {
+        let subject = self.evaluate(ast, subject)?;
+        for arm in arms {
+            let matched = match &arm.pattern {
+                Pattern::Value(value) => self.evaluate(ast, *value)? == subject,
+                Pattern::Class { id, class, binding } => {
+                    let class = match self.evaluate(ast, *class)? {
+                        Object::Class(class) => class,
+                        other => {
+                            return Err(LoxError::InterpreterError(
+                                format!("Can only match instances of classes, not '{}'.", other)
+                                    .into(),
+                            ))
+                        }
+                    };
+                    let matched = matches!(&subject, Object::Instance(instance)
+                        if instance.borrow().class().is_subclass_of(&class));
+                    if matched {
+                        self.define(id, binding, subject.clone());
+                    }
+                    matched
+                }
+                Pattern::Wildcard => true,
+            };
+            if matched {
+                return self.evaluate(ast, arm.body);
+            }
+        }
+        Err(LoxError::InterpreterError(
+            format!("No match arm for '{}'.", subject).into(),
+        ))
+    }
+
+    fn unary_expression(
+        &mut self,
+        ast: &Rc<Ast>,
+        token_type: &TokenType,
+        expr: ExprRef,
+    ) -> Result<Object> {
+        let right = self.evaluate(ast, expr)?;
 
         match token_type {
-            TokenType::Minus => match *right {
-                Object::Number(num) => Ok(Rc::new(Object::Number(-num))),
+            TokenType::Minus => match right {
+                Object::Number(num) => Ok(Object::Number(-num)),
                 _ => Err(LoxError::InterpreterError(
                     format!("Operand must be a number, but got '{}'", right).into(),
                 )),
             },
-            TokenType::Bang => Ok(Rc::new(Object::Boolean(!self.is_truthy(&right)))),
+            TokenType::Bang => Ok(Object::Boolean(!self.is_truthy(&right))),
+            TokenType::Typeof => Ok(Object::String(right.type_name().into())),
             _ => unreachable!(),
         }
     }
 
     fn binary_expression(
         &mut self,
-        left: &Expr,
+        ast: &Rc<Ast>,
+        left: ExprRef,
         token_type: &TokenType,
-        right: &Expr,
-    ) -> Result<Rc<Object>> {
-        let left = self.evaluate(left)?;
-        let right = self.evaluate(right)?;
+        right: ExprRef,
+    ) -> Result<Object> {
+        let left = self.evaluate(ast, left)?;
+        let right = self.evaluate(ast, right)?;
 
         match token_type {
             TokenType::Star => {
                 let (left, right) = self.cast_operands_to_numbers(&left, &right)?;
-                Ok(Rc::new(Object::Number(left * right)))
+                Ok(Object::Number(left * right))
             }
             TokenType::Minus => {
                 let (left, right) = self.cast_operands_to_numbers(&left, &right)?;
-                Ok(Rc::new(Object::Number(left - right)))
+                Ok(Object::Number(left - right))
             }
             TokenType::Slash => {
                 let (left, right) = self.cast_operands_to_numbers(&left, &right)?;
-                Ok(Rc::new(Object::Number(left / right)))
+                Ok(Object::Number(left / right))
             }
             TokenType::Plus => {
                 if let Ok((left, right)) = self.cast_operands_to_numbers(&left, &right) {
-                    Ok(Rc::new(Object::Number(left + right)))
+                    Ok(Object::Number(left + right))
                 } else if let Ok((left, right)) = self.cast_operands_to_strings(&left, &right) {
-                    Ok(Rc::new(Object::String(format!("{}{}", left, right))))
+                    self.new_string(format!("{}{}", left, right))
+                } else if self.config.string_coercion
+                    && matches!(
+                        (&left, &right),
+                        (Object::String(_), Object::Number(_))
+                            | (Object::Number(_), Object::String(_))
+                    )
+                {
+                    self.new_string(format!("{}{}", left, right))
                 } else {
                     Err(LoxError::InterpreterError(format!(
                         "The '+' operator requires either 2 numbers or 2 strings, but got '{}' and '{}'",
@@ -352,52 +1247,240 @@ impl Interpreter {
             }
             TokenType::LessEqual => {
                 let (left, right) = self.cast_operands_to_numbers(&left, &right)?;
-                Ok(Rc::new(Object::Boolean(left <= right)))
+                Ok(Object::Boolean(left <= right))
             }
             TokenType::Less => {
                 let (left, right) = self.cast_operands_to_numbers(&left, &right)?;
-                Ok(Rc::new(Object::Boolean(left < right)))
+                Ok(Object::Boolean(left < right))
             }
             TokenType::GreaterEqual => {
                 let (left, right) = self.cast_operands_to_numbers(&left, &right)?;
-                Ok(Rc::new(Object::Boolean(left >= right)))
+                Ok(Object::Boolean(left >= right))
             }
             TokenType::Greater => {
                 let (left, right) = self.cast_operands_to_numbers(&left, &right)?;
-                Ok(Rc::new(Object::Boolean(left > right)))
+                Ok(Object::Boolean(left > right))
             }
+            TokenType::EqualEqual => Ok(Object::Boolean(left == right)),
+            TokenType::BangEqual => Ok(Object::Boolean(left != right)),
             _ => unreachable!(),
         }
     }
 
-    fn call_expression(&mut self, callee: &Expr, arguments: &Vec<Expr>) -> Result<Rc<Object>> {
-        let callee = self.evaluate(callee)?;
+    fn call_expression(
+        &mut self,
+        ast: &Rc<Ast>,
+        callee: ExprRef,
+        arguments: &[ExprRef],
+    ) -> Result<Object> {
+        let site_locals = match &ast[callee] {
+            Expr::Variable { id, .. } => self.call_site_locals.get(id).cloned(),
+            _ => None,
+        };
+        let callee = self.evaluate(ast, callee)?;
 
         let arguments = arguments
             .iter()
-            .map(|argument| self.evaluate(argument))
+            .map(|argument| self.evaluate(ast, *argument))
             .collect::<Result<Vec<_>>>()?;
+        self.site_locals = site_locals;
+
+        if !matches!(callee, Object::Function(_) | Object::Class(_)) {
+            return Err(LoxError::InterpreterError(
+                "Can only call functions and classes.".into(),
+            ));
+        }
+        if self.call_stack.len() >= self.config.max_call_depth {
+            self.record_stack_trace();
+            return Err(LoxError::InterpreterError("Stack overflow.".into()));
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            match &callee {
+                Object::Function(function) => profiler.enter(function.name(), function.is_native()),
+                Object::Class(class) => profiler.enter(class.name(), false),
+                _ => unreachable!(),
+            }
+        }
+        self.call_stack.push(callee.clone());
+        self.call_lines.push(self.line);
+        self.stats.calls += 1;
+        self.stats.peak_call_depth = self.stats.peak_call_depth.max(self.call_stack.len());
+        let result = if self.debuggers.is_empty() {
+            self.call(&callee, &arguments)
+        } else {
+            let frame = call_frame(&callee);
+            let result = self
+                .each_debugger(|debugger, interpreter| debugger.call(interpreter, &frame))
+                .and_then(|_| self.call(&callee, &arguments));
+            self.each_debugger(|debugger, interpreter| {
+                debugger.after_call(interpreter, &frame);
+                Ok(())
+            })
+            .ok();
+            result
+        };
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+        if matches!(result, Err(ref error) if !matches!(error, LoxError::Return(_) | LoxError::Break(_)))
+        {
+            self.record_stack_trace();
+        }
+        self.call_stack.pop();
+        self.line = self.call_lines.pop().unwrap_or(0);
+        result
+    }
 
-        match callee.as_ref() {
-            Object::Function(function) => Ok(function.call(self, &arguments)?),
+    fn call(&mut self, callee: &Object, arguments: &[Object]) -> Result<Object> {
+        match callee {
+            Object::Function(function) => {
+                let value = function.call(self, arguments)?;
+                // strings built by Lox code are already tracked
+                if let (true, Object::String(string)) = (function.is_native(), &value) {
+                    self.heap.track_string(string);
+                    self.stats.strings += 1;
+                    self.check_memory()?;
+                }
+                Ok(value)
+            }
             Object::Class(class) => {
-                let instance = Rc::new(Object::Instance(Rc::new(RefCell::new(LoxInstance::new(
-                    Rc::clone(class),
-                )))));
                 let constructor = class.find_method("init");
+                match &constructor {
+                    Some(constructor) if constructor.arity() != arguments.len() => {
+                        return Err(LoxError::InterpreterError(
+                            format!(
+                                "Expected {} arguments but got {} for class {}.",
+                                constructor.arity(),
+                                arguments.len(),
+                                class.name()
+                            )
+                            .into(),
+                        ));
+                    }
+                    None => functions::check_arity(0, arguments)?,
+                    _ => {}
+                }
+                let instance = self.new_instance(LoxInstance::new(Rc::clone(class)))?;
                 if let Some(constructor) = constructor {
-                    constructor
-                        .bind(Rc::clone(&instance))
-                        .call(self, &arguments)?;
+                    self.stats.functions += 1;
+                    constructor.bind(instance.clone()).call(self, arguments)?;
                 }
                 Ok(instance)
             }
-            _ => Err(LoxError::InterpreterError(
-                "Can only call functions and classes.".into(),
-            )),
+            _ => unreachable!(),
         }
     }
 
+    /// Keeps the innermost trace: an error is only recorded by the first call it unwinds through.
+    fn record_stack_trace(&mut self) {
+        if !self.stack_trace.is_empty() {
+            return;
+        }
+        self.stack_trace = self.call_stack.iter().rev().map(call_name).collect();
+    }
+
+    /// Looks up a field or, failing that, a method. Lookups are cached per access site by the
+    /// instance's shape, so a property read in a loop only searches when it sees a new shape.
+    fn get_property(&mut self, id: &ExprId, object: Object, name: &str) -> Result<Object> {
+        let instance = match &object {
+            Object::Instance(instance) => Rc::clone(instance),
+            Object::Userdata(userdata) => return self.userdata_method(userdata, name),
+            Object::List(list) => {
+                let method = ListMethod::new(list, name).ok_or_else(|| {
+                    LoxError::InterpreterError(format!("Undefined property {}.", name).into())
+                })?;
+                self.stats.functions += 1;
+                return Ok(Object::Function(Rc::new(method)));
+            }
+            Object::String(string) => {
+                let method = StringMethod::new(string, name).ok_or_else(|| {
+                    LoxError::InterpreterError(format!("Undefined property {}.", name).into())
+                })?;
+                self.stats.functions += 1;
+                return Ok(Object::Function(Rc::new(method)));
+            }
+            Object::Map(map) => {
+                let method = MapMethod::new(map, name).ok_or_else(|| {
+                    LoxError::InterpreterError(format!("Undefined property {}.", name).into())
+                })?;
+                self.stats.functions += 1;
+                return Ok(Object::Function(Rc::new(method)));
+            }
+            Object::Enum(lox_enum) => {
+                return lox_enum
+                    .member(name)
+                    .map(Object::EnumMember)
+                    .ok_or_else(|| {
+                        LoxError::InterpreterError(
+                            format!("Undefined member {} of enum {}.", name, lox_enum.name())
+                                .into(),
+                        )
+                    })
+            }
+            _ => {
+                return Err(LoxError::InterpreterError(
+                    "Only instances have fields.".into(),
+                ))
+            }
+        };
+        let instance = instance.borrow();
+
+        let method = match self.property_caches.get(id) {
+            Some(PropertyCache::Field { shape, index }) if Rc::ptr_eq(shape, instance.shape()) => {
+                return Ok(instance.field_at(*index));
+            }
+            Some(PropertyCache::Method { shape, method })
+                if Rc::ptr_eq(shape, instance.shape()) =>
+            {
+                Rc::clone(method)
+            }
+            _ => {
+                let shape = Rc::clone(instance.shape());
+                if let Some(index) = shape.index(name) {
+                    self.property_caches
+                        .insert(*id, PropertyCache::Field { shape, index });
+                    return Ok(instance.field_at(index));
+                }
+                let method = instance.class().find_method(name).ok_or_else(|| {
+                    LoxError::InterpreterError(format!("Undefined property {}.", name).into())
+                })?;
+                self.property_caches.insert(
+                    *id,
+                    PropertyCache::Method {
+                        shape,
+                        method: Rc::clone(&method),
+                    },
+                );
+                method
+            }
+        };
+        drop(instance);
+        Ok(self.bound_method(&method, object))
+    }
+
+    fn userdata_method(&self, userdata: &Userdata, name: &str) -> Result<Object> {
+        let method = self
+            .userdata_methods
+            .get(&userdata.value_type_id())
+            .and_then(|methods| methods.get(name))
+            .ok_or_else(|| {
+                LoxError::InterpreterError(format!("Undefined property {}.", name).into())
+            })?;
+        let (arity, method, userdata) = (method.arity, Rc::clone(method), userdata.clone());
+        let bound = move |arguments: &[Object]| (method.function)(&userdata, arguments);
+        Ok(Object::Function(Rc::new(NativeFunction::new(
+            name.into(),
+            arity,
+            Box::new(bound),
+        ))))
+    }
+
+    fn bound_method(&mut self, method: &LoxFunction, instance: Object) -> Object {
+        self.stats.functions += 1;
+        Object::Function(Rc::new(method.bind(instance)))
+    }
+
     fn cast_operands_to_numbers(&self, left: &Object, right: &Object) -> Result<(f64, f64)> {
         match (left, right) {
             (Object::Number(a), Object::Number(b)) => Ok((*a, *b)),
@@ -415,7 +1498,7 @@ impl Interpreter {
         &self,
         left: &'b Object,
         right: &'b Object,
-    ) -> Result<(&'b String, &'b String)> {
+    ) -> Result<(&'b str, &'b str)> {
         match (left, right) {
             (Object::String(a), Object::String(b)) => Ok((a, b)),
             _ => Err(LoxError::InterpreterError(
@@ -428,41 +1511,140 @@ impl Interpreter {
         }
     }
 
-    fn is_truthy(&self, object: &Object) -> bool {
-        match *object {
-            Object::Nil => false,
-            Object::Boolean(b) => b,
-            _ => true,
-        }
+    fn is_truthy(&self, object: &Object) -> bool {
+        match *object {
+            Object::Nil => false,
+            Object::Boolean(b) => b,
+            _ => true,
+        }
+    }
+
+    fn look_up_variable(&self, id: &ExprId, name: &str) -> Result<Object> {
+        if let Some(local) = self.get_local(id) {
+            self.read_local(local, name)
+        } else {
+            self.globals.get(name)
+        }
+    }
+
+    fn read_local(&self, local: Local, name: &str) -> Result<Object> {
+        match local {
+            Local::Slot(slot) => self.stack.get(slot, name),
+            Local::Upvalue(index) => Ok(self.stack.upvalue(index).borrow().clone()),
+        }
+    }
+
+    /// Declarations the resolver saw in a local scope go into their slot, everything else is a global.
+    fn define(&mut self, id: &ExprId, name: &Rc<str>, value: Object) {
+        if let Some(Local::Slot(slot)) = self.get_local(id) {
+            self.stack.define(slot, value);
+        } else {
+            self.globals.define(Rc::clone(name), value);
+        }
+    }
+
+    /// Sets a variable that was just defined, through its upvalue if a closure captured it
+    /// in the meantime.
+    fn initialize(&mut self, id: &ExprId, name: &Rc<str>, value: Object) -> Result<()> {
+        if let Some(Local::Slot(slot)) = self.get_local(id) {
+            self.stack.assign(slot, name, value)
+        } else {
+            self.globals.define(Rc::clone(name), value);
+            Ok(())
+        }
+    }
+
+    /// The upvalues of the function declared by `id`, taken from the running function.
+    fn capture(&mut self, id: &ExprId) -> Rc<[Upvalue]> {
+        let captures = self
+            .captures
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| Rc::new([]));
+        captures
+            .iter()
+            .map(|&local| match local {
+                Local::Slot(slot) => {
+                    let upvalue = self.stack.capture(slot);
+                    self.heap.track_upvalue(&upvalue);
+                    self.stats.upvalues += 1;
+                    upvalue
+                }
+                Local::Upvalue(index) => Rc::clone(self.stack.upvalue(index)),
+            })
+            .collect()
     }
 
-    fn get_locals_depth(&self, expression_id: &ExprId) -> Option<u64> {
+    fn get_local(&self, expression_id: &ExprId) -> Option<Local> {
         self.scopes.get(expression_id).copied()
     }
 }
 
+/// How a call appears in stack traces.
+fn call_frame(callee: &Object) -> CallFrame {
+    CallFrame {
+        name: call_name(callee),
+        line: None,
+        this: match callee {
+            Object::Function(function) => function.this().cloned(),
+            _ => None,
+        },
+    }
+}
+
+fn call_name(callee: &Object) -> String {
+    match callee {
+        Object::Function(function) => format!("{}()", function.name()),
+        Object::Class(class) => format!("{}()", class.name()),
+        _ => unreachable!(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::Interpreter;
+    use super::{Interpreter, InterpreterConfig, Output};
+    use crate::debug::{CallFrame, Debugger};
+    use crate::error::LoxError;
+    use crate::error::Result;
     use crate::lexer;
+    use crate::natives::Natives;
     use crate::object::Object;
-    use crate::parser;
+    use crate::parser::{self, ParserOptions};
     use crate::resolver;
     use crate::statement::Stmt;
+    use crate::userdata::Userdata;
+
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    fn run(interpreter: &mut Interpreter, source: &'static str) {
+        try_run(interpreter, source).unwrap();
+    }
+
+    fn try_run(interpreter: &mut Interpreter, source: &str) -> Result<Object> {
+        let (tokens, _) = lexer::lex(source);
+        let options = ParserOptions {
+            first_id: interpreter.next_expression_id(),
+            ..ParserOptions::default()
+        };
+        let (program, _) = parser::parse_with_options(&tokens, options);
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        interpreter.interpret(program)
+    }
 
     fn interpret(source: &'static str) -> Interpreter {
         let (tokens, lexer_errors) = lexer::lex(source);
         assert_eq!(lexer_errors.len(), 0);
-        let (statements, parser_errors) = parser::parse(&tokens);
+        let (program, parser_errors) = parser::parse(&tokens);
         assert_eq!(parser_errors.len(), 0);
 
-        let scopes = resolver::resolve(&statements);
-        assert_eq!(scopes.is_ok(), true);
+        let scopes = resolver::resolve(&program);
+        assert!(scopes.is_ok());
 
         let mut interpreter = Interpreter::new();
         interpreter.add_scopes(scopes.unwrap());
-        interpreter.interpret(statements).unwrap();
+        interpreter.interpret(program).unwrap();
 
         interpreter
     }
@@ -471,12 +1653,13 @@ mod tests {
     fn simple_mathematical_expression() {
         let source = "(3 + 4) * 6;";
         let (tokens, _) = lexer::lex(source);
-        let (statements, _) = parser::parse(&tokens);
+        let (program, _) = parser::parse(&tokens);
+        let ast = Rc::new(program.ast);
 
-        if let Stmt::Expression { expression } = &statements[0] {
+        if let Stmt::Expression { expression } = &ast[program.statements[0]] {
             let mut interpreter = Interpreter::new();
-            let result = interpreter.evaluate(expression).unwrap();
-            assert_eq!(*result, Object::Number(42.0));
+            let result = interpreter.evaluate(&ast, *expression).unwrap();
+            assert_eq!(result, Object::Number(42.0));
         } else {
             unreachable!();
         }
@@ -490,11 +1673,35 @@ mod tests {
         "#;
         let interpreter = interpret(source);
 
-        let half_truth = interpreter.environment.borrow().get(0, "half").unwrap();
-        assert_eq!(*half_truth, Object::Number(21.0));
+        let half_truth = interpreter.globals.get("half").unwrap();
+        assert_eq!(half_truth, Object::Number(21.0));
+
+        let answer = interpreter.globals.get("answer").unwrap();
+        assert_eq!(answer, Object::Number(42.0));
+    }
+
+    #[test]
+    fn several_variables() {
+        let source = r#"
+            var a = 1, b = a + 1, c;
+            var sum = 0;
+            {
+                var d = b, e = d * 10;
+                sum = e;
+            }
+            for (var i = 0, j = 3; i < j; i = i + 1) {
+                sum = sum + i;
+            }
+        "#;
+        let interpreter = interpret(source);
 
-        let answer = interpreter.environment.borrow().get(0, "answer").unwrap();
-        assert_eq!(*answer, Object::Number(42.0));
+        assert_eq!(interpreter.globals.get("b").unwrap(), Object::Number(2.0));
+        assert_eq!(interpreter.globals.get("c").unwrap(), Object::Nil);
+        assert_eq!(
+            interpreter.globals.get("sum").unwrap(),
+            Object::Number(23.0)
+        );
+        assert!(interpreter.globals.get("e").is_err());
     }
 
     #[test]
@@ -510,13 +1717,13 @@ mod tests {
         "#;
         let interpreter = interpret(source);
 
-        let answer = interpreter.environment.borrow().get(0, "answer").unwrap();
-        assert_eq!(*answer, Object::Number(42.0));
+        let answer = interpreter.globals.get("answer").unwrap();
+        assert_eq!(answer, Object::Number(42.0));
 
-        let thirteen = interpreter.environment.borrow().get(0, "thirteen").unwrap();
-        assert_eq!(*thirteen, Object::Number(13.0));
+        let thirteen = interpreter.globals.get("thirteen").unwrap();
+        assert_eq!(thirteen, Object::Number(13.0));
 
-        assert!(interpreter.environment.borrow().get(0, "lost").is_err());
+        assert!(interpreter.globals.get("lost").is_err());
     }
 
     #[test]
@@ -532,8 +1739,8 @@ mod tests {
         "#;
         let interpreter = interpret(source);
 
-        let answer = interpreter.environment.borrow().get(0, "answer").unwrap();
-        assert_eq!(*answer, Object::Number(42.0));
+        let answer = interpreter.globals.get("answer").unwrap();
+        assert_eq!(answer, Object::Number(42.0));
     }
 
     #[test]
@@ -552,8 +1759,8 @@ mod tests {
         "#;
         let interpreter = interpret(source);
 
-        let current_fib = interpreter.environment.borrow().get(0, "current").unwrap();
-        assert_eq!(*current_fib, Object::Number(34.0));
+        let current_fib = interpreter.globals.get("current").unwrap();
+        assert_eq!(current_fib, Object::Number(34.0));
     }
 
     #[test]
@@ -566,10 +1773,11 @@ mod tests {
         "#;
         let interpreter = interpret(source);
 
-        let product = interpreter.environment.borrow().get(0, "product").unwrap();
-        assert_eq!(*product, Object::Number(3628800.0));
+        let product = interpreter.globals.get("product").unwrap();
+        assert_eq!(product, Object::Number(3628800.0));
     }
 
+    #[cfg(feature = "time")]
     #[test]
     fn call_clock() {
         let source = r#"
@@ -577,14 +1785,306 @@ mod tests {
         "#;
         let interpreter = interpret(source);
 
-        let time = interpreter.environment.borrow().get(0, "time").unwrap();
-        if let &Object::Number(time) = &*time {
+        let time = interpreter.globals.get("time").unwrap();
+        if let Object::Number(time) = time {
             assert!(time > 0.0);
         } else {
             panic!("Expected that clock() returns a number");
         }
     }
 
+    #[cfg(feature = "time")]
+    #[test]
+    fn time_source() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_time_source(|| 42.0);
+        run(&mut interpreter, "var time = clock();");
+        assert_eq!(interpreter.globals.get("time"), Ok(Object::Number(42.0)));
+    }
+
+    #[test]
+    fn without_natives() {
+        let config = InterpreterConfig::default().with_natives(Natives::NONE);
+        let interpreter = Interpreter::with_config(config);
+        assert_eq!(
+            interpreter.globals.get("clock"),
+            Err(LoxError::EnvironmentError(
+                "Undefined variable 'clock'.".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn native_functions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_native("max", 2, |arguments| match arguments {
+            [Object::Number(a), Object::Number(b)] => Ok(Object::Number(a.max(*b))),
+            _ => Err(LoxError::InterpreterError("max() takes numbers.".into())),
+        });
+        run(&mut interpreter, "var larger = max(3, 4);");
+        assert_eq!(interpreter.globals.get("larger"), Ok(Object::Number(4.0)));
+
+        let (tokens, _) = lexer::lex("max(1);");
+        let (program, _) = parser::parse(&tokens);
+        assert_eq!(
+            interpreter.interpret(program),
+            Err(LoxError::InterpreterError(
+                "Expected 2 arguments but got 1.".into()
+            ))
+        );
+        let (tokens, _) = lexer::lex("max(1, nil);");
+        let (program, _) = parser::parse(&tokens);
+        assert_eq!(
+            interpreter.interpret(program),
+            Err(LoxError::InterpreterError("max() takes numbers.".into()))
+        );
+    }
+
+    #[test]
+    fn userdata() {
+        struct Counter(Cell<u32>);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.define_global(
+            "counter",
+            Object::Userdata(Userdata::new(Counter(Cell::new(0)))),
+        );
+        interpreter.define_method("add", 1, |counter: &Counter, arguments| {
+            if let [Object::Number(amount)] = arguments {
+                counter.0.set(counter.0.get() + *amount as u32);
+            }
+            Ok(Object::Nil)
+        });
+        interpreter.define_method("get", 0, |counter: &Counter, _| {
+            Ok(Object::Number(counter.0.get().into()))
+        });
+        run(
+            &mut interpreter,
+            r#"
+            var add = counter.add;
+            add(2);
+            counter.add(3);
+            var count = counter.get();
+            var same = counter == counter;
+        "#,
+        );
+        assert_eq!(interpreter.globals.get("count"), Ok(Object::Number(5.0)));
+        assert_eq!(interpreter.globals.get("same"), Ok(Object::Boolean(true)));
+
+        let (tokens, _) = lexer::lex("counter.reset();");
+        let (program, _) = parser::parse(&tokens);
+        assert_eq!(
+            interpreter.interpret(program),
+            Err(LoxError::InterpreterError(
+                "Undefined property reset.".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn profiler() {
+        let mut interpreter = Interpreter::new();
+        interpreter.enable_profiler();
+        let source = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\nclass A { init() { fib(3); } }\nA(); clock();";
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        interpreter.interpret(program).unwrap();
+
+        let mut calls: Vec<_> = interpreter
+            .profiler()
+            .unwrap()
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.name, entry.native, entry.calls))
+            .collect();
+        calls.sort();
+        assert_eq!(
+            calls,
+            [
+                ("A".to_string(), false, 1),
+                ("clock".to_string(), true, 1),
+                ("fib".to_string(), false, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn debugger() {
+        struct Lines(Rc<RefCell<Vec<(u32, usize)>>>);
+
+        impl Debugger for Lines {
+            fn statement(&mut self, interpreter: &mut Interpreter, line: u32) -> Result<()> {
+                self.0.borrow_mut().push((line, interpreter.call_depth()));
+                if line == 6 {
+                    assert_eq!(interpreter.call_frames()[0].name, "twice()");
+                    return Err(LoxError::InterpreterError("stopped".into()));
+                }
+                Ok(())
+            }
+        }
+
+        let lines = Rc::new(RefCell::new(vec![]));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Lines(Rc::clone(&lines)));
+        let source = "fun twice(x) {\n  return x * 2;\n}\ntwice(1);\nfun twice(x) {\n  return x;\n}\ntwice(2);";
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        assert_eq!(
+            interpreter.interpret(program),
+            Err(LoxError::InterpreterError("stopped".into()))
+        );
+        assert_eq!(
+            *lines.borrow(),
+            [(1, 0), (4, 0), (2, 1), (5, 0), (8, 0), (6, 1)]
+        );
+    }
+
+    #[test]
+    fn layered_debuggers() {
+        struct Events(&'static str, Rc<RefCell<Vec<String>>>);
+
+        impl Debugger for Events {
+            fn statement(&mut self, _interpreter: &mut Interpreter, line: u32) -> Result<()> {
+                self.1.borrow_mut().push(format!("{} {}", self.0, line));
+                Ok(())
+            }
+
+            fn after_statement(&mut self, _interpreter: &mut Interpreter, line: u32) {
+                self.1.borrow_mut().push(format!("{} /{}", self.0, line));
+            }
+
+            fn call(&mut self, interpreter: &mut Interpreter, frame: &CallFrame) -> Result<()> {
+                let depth = interpreter.call_depth();
+                let event = format!("{} {} at depth {}", self.0, frame.name, depth);
+                self.1.borrow_mut().push(event);
+                Ok(())
+            }
+
+            fn after_call(&mut self, _interpreter: &mut Interpreter, frame: &CallFrame) {
+                self.1
+                    .borrow_mut()
+                    .push(format!("{} /{}", self.0, frame.name));
+            }
+        }
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Events("a", Rc::clone(&events)));
+        interpreter.add_debugger(Events("b", Rc::clone(&events)));
+        let source = "fun f() {\n  return 1;\n}\nf();";
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        interpreter.interpret(program).unwrap();
+        assert_eq!(
+            *events.borrow(),
+            [
+                "a 1",
+                "b 1",
+                "a /1",
+                "b /1",
+                "a 4",
+                "b 4",
+                "a f() at depth 1",
+                "b f() at depth 1",
+                "a 2",
+                "b 2",
+                "a /2",
+                "b /2",
+                "a /f()",
+                "b /f()",
+                "a /4",
+                "b /4"
+            ]
+        );
+    }
+
+    #[test]
+    fn output() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Output(Rc::clone(&output)));
+        run(&mut interpreter, "print 1 + 2; print \"lox\"; print nil;");
+        assert_eq!(&*output.borrow(), b"3\nlox\nnil\n");
+    }
+
+    #[test]
+    #[cfg(feature = "introspection")]
+    fn introspection() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let config = InterpreterConfig::default().with_natives(Natives::INTROSPECTION);
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.set_output(Output(Rc::clone(&output)));
+        let source = r#"
+            var a = 1;
+            globals();
+            fun f(x) {
+                var y = "two";
+                {
+                    var x = 3;
+                    locals();
+                }
+                var z = 4;
+            }
+            f(0);
+            locals();
+        "#;
+        run(&mut interpreter, source);
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "a = 1\nbacktrace = <native fn backtrace>\nglobals = <native fn globals>\nlocals = <native fn locals>\nstats = <native fn stats>\ny = two\nx = 3\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "introspection")]
+    fn backtrace() {
+        let source = "fun inner() {\n  return backtrace();\n}\nfun outer() {\n  var trace = inner();\n  return trace;\n}\nvar trace = outer();\nvar top = backtrace();";
+        let interpreter = interpret(source);
+        assert_eq!(
+            interpreter.globals.get("trace").unwrap(),
+            Object::String("in inner() [line 2]\nin outer() [line 5]\nin <script> [line 8]".into())
+        );
+        assert_eq!(
+            interpreter.globals.get("top").unwrap(),
+            Object::String("in <script> [line 9]".into())
+        );
+    }
+
+    #[test]
+    fn runtime_stats() {
+        let source = r#"
+            class Counter {
+                init() {
+                    this.count = 0;
+                }
+                increment() {
+                    this.count = this.count + 1;
+                }
+            }
+            fun depth(n) {
+                if (n == 0) return "bottom" + "!";
+                return depth(n - 1);
+            }
+            var counter = Counter();
+            counter.increment();
+            counter.increment();
+            depth(3);
+        "#;
+        let interpreter = interpret(source);
+        let stats = interpreter.stats();
+        assert_eq!(stats.strings, 1);
+        assert_eq!(stats.instances, 1);
+        // 2 methods, `depth`, the bound initializer and 2 bound `increment`s
+        assert_eq!(stats.functions, 6);
+        assert_eq!(stats.calls, 7);
+        assert_eq!(stats.frames, 7);
+        assert_eq!(stats.peak_call_depth, 4);
+        assert!(stats.to_string().ends_with("peak call depth:      4"));
+    }
+
     #[test]
     fn functions() {
         let source = r#"
@@ -597,8 +2097,8 @@ mod tests {
             var fifth = fib(5);
         "#;
         let interpreter = interpret(source);
-        let fifth_fib = interpreter.environment.borrow().get(0, "fifth").unwrap();
-        assert_eq!(*fifth_fib, Object::Number(5.0));
+        let fifth_fib = interpreter.globals.get("fifth").unwrap();
+        assert_eq!(fifth_fib, Object::Number(5.0));
     }
 
     #[test]
@@ -617,10 +2117,44 @@ mod tests {
             var two = counter();
         "#;
         let interpreter = interpret(source);
-        let one = interpreter.environment.borrow().get(0, "one").unwrap();
-        let two = interpreter.environment.borrow().get(0, "two").unwrap();
-        assert_eq!(*one, Object::Number(1.0));
-        assert_eq!(*two, Object::Number(2.0));
+        let one = interpreter.globals.get("one").unwrap();
+        let two = interpreter.globals.get("two").unwrap();
+        assert_eq!(one, Object::Number(1.0));
+        assert_eq!(two, Object::Number(2.0));
+    }
+
+    #[test]
+    fn upvalues() {
+        let source = r#"
+            var first;
+            var last;
+            for (var i = 0; i < 3; i = i + 1) {
+                var j = i;
+                fun get() {
+                    fun inner() {
+                        return j;
+                    }
+                    return inner();
+                }
+                if (first == nil) first = get;
+                last = get;
+            }
+            var values = first() + last();
+
+            var shared;
+            {
+                var a = "before";
+                fun read() {
+                    return a;
+                }
+                a = "after";
+                shared = read();
+            }
+        "#;
+        let interpreter = interpret(source);
+        let get = |name| interpreter.globals.get(name).unwrap();
+        assert_eq!(get("values"), Object::Number(2.0));
+        assert_eq!(get("shared"), Object::String("after".into()));
     }
 
     #[test]
@@ -636,8 +2170,8 @@ mod tests {
             }
         "#;
         let interpreter = interpret(source);
-        let a = interpreter.environment.borrow().get(0, "a").unwrap();
-        assert_eq!(*a, Object::Number(2.0));
+        let a = interpreter.globals.get("a").unwrap();
+        assert_eq!(a, Object::Number(2.0));
     }
 
     #[test]
@@ -649,8 +2183,8 @@ mod tests {
             var field = foo.field;
         "#;
         let interpreter = interpret(source);
-        let field = interpreter.environment.borrow().get(0, "field").unwrap();
-        assert_eq!(*field, Object::String("some value".to_owned()));
+        let field = interpreter.globals.get("field").unwrap();
+        assert_eq!(field, Object::String("some value".into()));
     }
 
     #[test]
@@ -669,8 +2203,8 @@ mod tests {
             var hiAlice = alice.hi();
         "#;
         let interpreter = interpret(source);
-        let hi = interpreter.environment.borrow().get(0, "hiAlice").unwrap();
-        assert_eq!(*hi, Object::String("Hi, my name is Alice".to_owned()));
+        let hi = interpreter.globals.get("hiAlice").unwrap();
+        assert_eq!(hi, Object::String("Hi, my name is Alice".into()));
     }
 
     #[test]
@@ -692,7 +2226,445 @@ mod tests {
             var type = duck.type();
         "#;
         let interpreter = interpret(source);
-        let duck_type = interpreter.environment.borrow().get(0, "type").unwrap();
-        assert_eq!(*duck_type, Object::String("MallardDuck".to_owned()));
+        let duck_type = interpreter.globals.get("type").unwrap();
+        assert_eq!(duck_type, Object::String("MallardDuck".into()));
+    }
+
+    #[test]
+    fn equality() {
+        let source = r#"
+            class Foo {}
+            var foo = Foo();
+            var same = 1 + 1 == 2;
+            var different = "a" != "a";
+            var identical = foo == foo;
+            var distinct = foo == Foo();
+        "#;
+        let interpreter = interpret(source);
+        let get = |name| interpreter.globals.get(name).unwrap();
+        assert_eq!(get("same"), Object::Boolean(true));
+        assert_eq!(get("different"), Object::Boolean(false));
+        assert_eq!(get("identical"), Object::Boolean(true));
+        assert_eq!(get("distinct"), Object::Boolean(false));
+    }
+
+    #[test]
+    fn local_slots() {
+        let source = r#"
+            fun helper() {
+                return "global";
+            }
+            class Foo {
+                helper() {
+                    return helper();
+                }
+            }
+            var fromMethod = Foo().helper();
+            var skipped = 0;
+            {
+                if (false) var a = 1;
+                var b = 2;
+                skipped = b;
+            }
+        "#;
+        let interpreter = interpret(source);
+        let get = |name| interpreter.globals.get(name).unwrap();
+        assert_eq!(get("fromMethod"), Object::String("global".into()));
+        assert_eq!(get("skipped"), Object::Number(2.0));
+    }
+
+    #[test]
+    fn deep_recursion_is_a_runtime_error() {
+        let source = r#"
+            fun count(n) {
+                if (n == 0) return 0;
+                return 1 + count(n - 1);
+            }
+            print count(100000);
+        "#;
+        // the nesting limit, not the call depth, stops it, on the test thread's small stack
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        let config = InterpreterConfig::default().with_max_call_depth(1_000_000);
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        assert_eq!(
+            interpreter.interpret(program),
+            Err(LoxError::InterpreterError("Stack overflow.".into()))
+        );
+        assert_eq!(interpreter.nesting_depth, 0);
+    }
+
+    #[test]
+    fn max_call_depth() {
+        let source = r#"
+            fun count(n) {
+                if (n == 0) return 0;
+                return 1 + count(n - 1);
+            }
+            fun start() {
+                return count(10);
+            }
+            start();
+        "#;
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        let config = InterpreterConfig::default().with_max_call_depth(5);
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+
+        assert_eq!(
+            interpreter.interpret(program),
+            Err(LoxError::InterpreterError("Stack overflow.".into()))
+        );
+        assert_eq!(
+            interpreter.stack_trace(),
+            ["count()", "count()", "count()", "count()", "start()"]
+        );
+    }
+
+    #[test]
+    fn print_precision() {
+        let source = "print 0.1 + 0.2; print [1 / 3, 2]; print [1 / 100000000, 1000000000000000000000 * 10]; print \"0.123\";";
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Output(Rc::clone(&output)));
+        run(&mut interpreter, source);
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "0.30000000000000004\n[0.3333333333333333, 2]\n[1e-8, 1e22]\n0.123\n"
+        );
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let config = InterpreterConfig::default().with_print_precision(Some(2));
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.set_output(Output(Rc::clone(&output)));
+        run(&mut interpreter, source);
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "0.3\n[0.33, 2]\n[0, 10000000000000000000000]\n0.123\n"
+        );
+    }
+
+    #[test]
+    fn string_coercion() {
+        let mut interpreter = Interpreter::new();
+        let (tokens, _) = lexer::lex("print \"count: \" + 3;");
+        let (program, _) = parser::parse(&tokens);
+        assert!(interpreter.interpret(program).is_err());
+
+        let config = InterpreterConfig::default().with_string_coercion(true);
+        let mut interpreter = Interpreter::with_config(config);
+        run(
+            &mut interpreter,
+            "var a = \"count: \" + 3; var b = 1.5 + \"x\"; var c = 1 + 2;",
+        );
+        let global = |name| interpreter.globals.get(name).unwrap();
+        assert_eq!(global("a"), Object::String("count: 3".into()));
+        assert_eq!(global("b"), Object::String("1.5x".into()));
+        assert_eq!(global("c"), Object::Number(3.0));
+
+        let (tokens, _) = lexer::lex("print \"a\" + nil;");
+        let (program, _) = parser::parse_with_options(
+            &tokens,
+            ParserOptions {
+                first_id: interpreter.next_expression_id(),
+                ..ParserOptions::default()
+            },
+        );
+        assert!(interpreter.interpret(program).is_err());
+    }
+
+    #[test]
+    fn max_steps() {
+        let config = InterpreterConfig::default().with_max_steps(Some(1_000));
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.set_output(std::io::sink());
+
+        assert_eq!(
+            try_run(&mut interpreter, "while (true) {}"),
+            Err(LoxError::InterpreterError(
+                "Execution budget exceeded.".into()
+            ))
+        );
+        // the budget is per program, so a later one gets all of it again
+        for _ in 0..3 {
+            assert!(try_run(
+                &mut interpreter,
+                "for (var i = 0; i < 50; i = i + 1) print i;"
+            )
+            .is_ok());
+        }
+    }
+
+    #[test]
+    fn max_memory() {
+        let config = InterpreterConfig::default().with_max_memory(Some(100_000));
+        let mut interpreter = Interpreter::with_config(config);
+
+        // garbage does not count
+        let source = r#"
+            class Node {}
+            for (var i = 0; i < 10000; i = i + 1) {
+                var node = Node();
+                node.name = "node " + "number";
+            }
+        "#;
+        assert_eq!(try_run(&mut interpreter, source), Ok(Object::Nil));
+
+        let exceeded = Err(LoxError::InterpreterError("Memory limit exceeded.".into()));
+        let source = r#"
+            var s = "a";
+            while (true) s = s + s;
+        "#;
+        assert_eq!(try_run(&mut interpreter, source), exceeded);
+        let source = r#"
+            class Node {}
+            var list = nil;
+            while (true) {
+                var node = Node();
+                node.next = list;
+                list = node;
+            }
+        "#;
+        assert_eq!(try_run(&mut interpreter, source), exceeded);
+    }
+
+    #[test]
+    fn with_config_applies_every_option() {
+        let config = InterpreterConfig::default()
+            .with_max_call_depth(5)
+            .with_max_steps(Some(10_000))
+            .with_max_memory(Some(100_000))
+            .with_natives(Natives::NONE)
+            .with_string_coercion(true)
+            .with_print_precision(Some(2));
+        let mut interpreter = Interpreter::with_config(config);
+        let output = Output::default();
+        interpreter.set_output(output.clone());
+        let error = |message: &'static str| Err(LoxError::InterpreterError(message.into()));
+
+        let source = "fun count(n) { if (n == 0) return 0; return 1 + count(n - 1); } count(10);";
+        assert_eq!(try_run(&mut interpreter, source), error("Stack overflow."));
+        assert_eq!(
+            try_run(&mut interpreter, "while (true) {}"),
+            error("Execution budget exceeded.")
+        );
+        assert_eq!(
+            try_run(&mut interpreter, "var s = \"a\"; while (true) s = s + s;"),
+            error("Memory limit exceeded.")
+        );
+        assert_eq!(
+            try_run(&mut interpreter, "clock();"),
+            Err(LoxError::EnvironmentError(
+                "Undefined variable 'clock'.".into()
+            ))
+        );
+        run(&mut interpreter, "print 1 / 3; print \"count: \" + 3;");
+        assert_eq!(
+            String::from_utf8_lossy(&output.0.borrow()),
+            "0.33\ncount: 3\n"
+        );
+    }
+
+    #[test]
+    fn property_caches() {
+        let source = r#"
+            class A {
+                name() {
+                    return "A";
+                }
+            }
+            class B < A {
+                name() {
+                    return "B";
+                }
+            }
+            var names = "";
+            var instances = A();
+            for (var i = 0; i < 4; i = i + 1) {
+                if (i == 2) instances = B();
+                names = names + instances.name();
+            }
+            var shadowed = A();
+            shadowed.name = "field";
+            var fromField = shadowed.name;
+
+            fun y(point) {
+                return point.y;
+            }
+            var xy = A();
+            xy.x = 1;
+            xy.y = 2;
+            var yx = A();
+            yx.y = 3;
+            yx.x = 4;
+            var ys = y(xy) + y(yx) + y(xy);
+        "#;
+        let interpreter = interpret(source);
+        let get = |name| interpreter.globals.get(name).unwrap();
+        assert_eq!(get("names"), Object::String("AABB".into()));
+        assert_eq!(get("fromField"), Object::String("field".into()));
+        assert_eq!(get("ys"), Object::Number(7.0));
+    }
+
+    #[test]
+    fn collects_closure_cycles() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            r#"
+            fun makeCounter() {
+                var i = 0;
+                fun count() {
+                    i = i + 1;
+                    if (i > 10) return count();
+                    return i;
+                }
+                return count;
+            }
+            var counter = makeCounter();
+            counter();
+        "#,
+        );
+        interpreter.collect_garbage();
+        // `i` and `count`, captured by count itself
+        assert_eq!(interpreter.heap.tracked(), 2);
+
+        run(&mut interpreter, "counter = nil;");
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 0);
+    }
+
+    #[test]
+    fn collects_instance_cycles() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            r#"
+            class Node {
+                get() {
+                    return this;
+                }
+            }
+            var a = Node();
+            var b = Node();
+            a.next = b;
+            b.next = a;
+            a.method = a.get;
+        "#,
+        );
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 2);
+
+        run(&mut interpreter, "a = nil; b = nil;");
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 0);
+    }
+
+    #[test]
+    fn collects_list_cycles() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "var a = [1]; a.push(a); var push = a.push; var b = [[a]];",
+        );
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 3);
+
+        run(&mut interpreter, "a = nil; b = nil;");
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 1);
+
+        run(&mut interpreter, "push = nil;");
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 0);
+    }
+
+    #[test]
+    fn collects_map_cycles() {
+        let mut interpreter = Interpreter::new();
+        run(&mut interpreter, "var a = {}; a.set(1, [a]);");
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 2);
+
+        run(&mut interpreter, "a = nil;");
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 0);
+    }
+
+    #[test]
+    fn programs_parsed_on_their_own() {
+        let mut interpreter = Interpreter::new();
+        let mut run = |source| {
+            let (tokens, _) = lexer::lex(source);
+            // both programs number their nodes from 0
+            let (program, _) = parser::parse(&tokens);
+            interpreter.add_scopes(resolver::resolve(&program).unwrap());
+            interpreter.interpret(program)
+        };
+        run("fun add(a, b) { var sum = a + b; return sum; }").unwrap();
+        let value = run("var x = 1; var y = 2; var z = 3; { var w = 4; add(x, w); }");
+        assert_eq!(value, Ok(Object::Nil));
+        assert_eq!(run("add(y, z);"), Ok(Object::Number(5.0)));
+    }
+
+    #[test]
+    fn collections_keep_the_result_of_the_last_statement() {
+        let (tokens, _) = lexer::lex(
+            r#"
+            fun make() {
+                for (var i = 0; i < 2000; i = i + 1) {
+                    var garbage = [i];
+                }
+                return [1, 2, 3];
+            }
+            make();
+        "#,
+        );
+        let (program, _) = parser::parse(&tokens);
+        let mut interpreter = Interpreter::new();
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        // enough allocations for a collection right after the last statement
+        let value = interpreter.interpret(program).unwrap();
+        assert_eq!(value.to_string(), "[1, 2, 3]");
+    }
+
+    #[test]
+    fn collections_keep_values_only_the_host_refers_to() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "var list = [1]; list.push(list); var map = {}; map.set(1, [map]);",
+        );
+        let list = interpreter.globals.get("list").unwrap();
+        let map = interpreter.globals.get("map").unwrap();
+        run(&mut interpreter, "list = nil; map = nil;");
+        interpreter.collect_garbage();
+        assert_eq!(list.to_string(), "[1, [...]]");
+        assert_eq!(map.to_string(), "{1: [{...}]}");
+
+        drop((list, map));
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.heap.tracked(), 0);
+
+        run(
+            &mut interpreter,
+            r#"
+            fun make() {
+                var list = [1];
+                fun get() { return list; }
+                list.push(get);
+                return get;
+            }
+            var get = make();
+        "#,
+        );
+        let get = interpreter.globals.get("get").unwrap();
+        run(&mut interpreter, "get = nil;");
+        interpreter.collect_garbage();
+        interpreter.globals.define("get".into(), get);
+        run(&mut interpreter, "var length = get().len();");
+        assert_eq!(interpreter.globals.get("length"), Ok(Object::Number(2.0)));
     }
 }
@@ -0,0 +1,795 @@
+//! A stable JSON representation of programs, for tools written in other languages. Unlike the
+//! `serde` derives it does not change with the internal representation of the syntax tree:
+//! nodes are nested instead of referring to each other by handle.
+//!
+//! A program is `{"version": 1, "statements": [statement, ...]}`. Every node is an object whose
+//! `"kind"` names the node, optional children are `null` when absent:
+//!
+//! | kind                | fields                                                   |
+//! |---------------------|----------------------------------------------------------|
+//! | `expression`        | `expression`                                             |
+//! | `print`             | `expression`                                             |
+//! | `var`, `const`      | `name`, `type`, `initializer`                            |
+//! | `block`             | `statements`                                             |
+//! | `if`                | `condition`, `then`, `else`                              |
+//! | `while`             | `condition`, `body`                                      |
+//! | `do_while`          | `body`, `condition`                                      |
+//! | `switch`            | `subject`, `cases` (`{"value", "body"}`), `default`      |
+//! | `labeled`           | `label`, `body`                                          |
+//! | `break`             | `label`                                                  |
+//! | `function`          | `name`, `parameters` (`{"name", "type"}`), `return_type`, `body` |
+//! | `return`            | `value`                                                  |
+//! | `class`             | `name`, `superclass`, `methods` (`function` statements)  |
+//! | `enum`              | `name`, `members` (strings)                              |
+//! | `number`            | `value`                                                  |
+//! | `string`            | `value`                                                  |
+//! | `boolean`           | `value`                                                  |
+//! | `nil`               |                                                          |
+//! | `binary`, `logical` | `operator` (as written, e.g. `"<="`), `left`, `right`    |
+//! | `unary`             | `operator`, `operand`                                    |
+//! | `grouping`          | `expression`                                             |
+//! | `call`              | `callee`, `arguments`                                    |
+//! | `get`               | `object`, `name`                                         |
+//! | `set`               | `object`, `name`, `value`                                |
+//! | `this`              |                                                          |
+//! | `super`             | `method`                                                 |
+//! | `variable`          | `name`                                                   |
+//! | `assign`            | `name`, `value`                                          |
+//! | `list`              | `elements`                                               |
+//! | `map`               | `entries` (`{"key", "value"}`)                           |
+//! | `match`             | `subject`, `arms` (`{"pattern", "body"}`)                |
+//!
+//! Type annotations are `{"name", "line"}`. Match patterns are `{"kind": "value", "value"}`,
+//! `{"kind": "class", "class", "binding"}` or `{"kind": "wildcard"}`.
+
+use crate::error::{LoxError, Result};
+use crate::interner::Interner;
+use crate::recursion;
+use crate::statement::{
+    Ast, Expr, ExprRef, MatchArm, Pattern, Program, Stmt, StmtRef, TypeAnnotation,
+};
+use crate::token::TokenType;
+use crate::{lexer, parser};
+
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::rc::Rc;
+
+/// Builds a node like `json!` does, but moves its children in. `json!` serializes them into a
+/// new value, copying every subtree once for each level above it.
+macro_rules! node {
+    ({ $($key:literal: $value:expr),* $(,)? }) => {{
+        let mut node = Map::new();
+        $(node.insert($key.to_string(), Value::from($value));)*
+        Value::Object(node)
+    }};
+}
+
+/// The version of the format written by `to_json`, the only one `from_json` accepts.
+pub const VERSION: u64 = 1;
+
+const OPERATORS: [(TokenType, &str); 14] = [
+    (TokenType::Minus, "-"),
+    (TokenType::Plus, "+"),
+    (TokenType::Slash, "/"),
+    (TokenType::Star, "*"),
+    (TokenType::Bang, "!"),
+    (TokenType::BangEqual, "!="),
+    (TokenType::EqualEqual, "=="),
+    (TokenType::Greater, ">"),
+    (TokenType::GreaterEqual, ">="),
+    (TokenType::Less, "<"),
+    (TokenType::LessEqual, "<="),
+    (TokenType::And, "and"),
+    (TokenType::Or, "or"),
+    (TokenType::Typeof, "typeof"),
+];
+
+fn operator_lexeme(operator: &TokenType) -> &'static str {
+    OPERATORS
+        .iter()
+        .find(|(token_type, _)| token_type == operator)
+        .map_or("?", |(_, lexeme)| lexeme)
+}
+
+/// Parses `source` and returns its JSON representation, or the lexer and parser errors.
+pub fn parse_to_json(source: &str) -> std::result::Result<String, Vec<LoxError>> {
+    let (tokens, mut errors) = lexer::lex(source);
+    let (program, parser_errors) = parser::parse(&tokens);
+    errors.extend(parser_errors);
+    if errors.is_empty() {
+        Ok(to_json(&program))
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn to_json(program: &Program) -> String {
+    let writer = Writer { ast: &program.ast };
+    let value = node!({
+        "version": VERSION,
+        "statements": writer.statements(&program.statements),
+    });
+    let json = serde_json::to_string(&Grown(&value)).expect("a JSON value always serializes");
+    dismantle(value);
+    json
+}
+
+/// Reads a program written by `to_json` (or by another tool following the same format).
+pub fn from_json(json: &str) -> Result<Program> {
+    // nodes nest as deeply as the program, deeper than serde_json's limit of 128 levels
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    deserializer.disable_recursion_limit();
+    let value = Value::deserialize(serde_stacker::Deserializer::new(&mut deserializer))
+        .map_err(|error| invalid(error.to_string()))?;
+    let program = deserializer
+        .end()
+        .map_err(|error| invalid(error.to_string()))
+        .and_then(|()| read(&value));
+    dismantle(value);
+    program
+}
+
+fn read(value: &Value) -> Result<Program> {
+    let version = value.get("version").and_then(Value::as_u64);
+    if version != Some(VERSION) {
+        return Err(invalid(format!("expected version {}", VERSION)));
+    }
+    let mut reader = Reader {
+        ast: Ast::new(),
+        interner: Interner::new(),
+    };
+    let statements = reader.statements(field(value, "statements")?)?;
+    Ok(Program {
+        ast: reader.ast,
+        statements,
+    })
+}
+
+/// Serializes a value one level at a time on a grown stack, nodes nest as deeply as the program.
+struct Grown<'a>(&'a Value);
+
+impl Serialize for Grown<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        recursion::grow_stack(|| match self.0 {
+            Value::Array(elements) => serializer.collect_seq(elements.iter().map(Grown)),
+            Value::Object(node) => {
+                serializer.collect_map(node.iter().map(|(key, child)| (key, Grown(child))))
+            }
+            value => value.serialize(serializer),
+        })
+    }
+}
+
+/// Drops `value` without recursing into it, nodes nest as deeply as the program.
+fn dismantle(value: Value) {
+    let mut values = vec![value];
+    while let Some(value) = values.pop() {
+        match value {
+            Value::Array(elements) => values.extend(elements),
+            Value::Object(node) => values.extend(node.into_iter().map(|(_, child)| child)),
+            _ => {}
+        }
+    }
+}
+
+fn invalid(reason: String) -> LoxError {
+    LoxError::JsonError(format!("Invalid program JSON: {}", reason).into())
+}
+
+struct Writer<'a> {
+    ast: &'a Ast,
+}
+
+impl<'a> Writer<'a> {
+    fn statements(&self, statements: &[StmtRef]) -> Value {
+        Value::Array(statements.iter().map(|stmt| self.stmt(*stmt)).collect())
+    }
+
+    fn stmt(&self, stmt: StmtRef) -> Value {
+        recursion::grow_stack(|| match &self.ast[stmt] {
+            Stmt::Expression { expression } => {
+                node!({"kind": "expression", "expression": self.expr(*expression)})
+            }
+            Stmt::Print { expression } => {
+                node!({"kind": "print", "expression": self.expr(*expression)})
+            }
+            Stmt::Var {
+                name,
+                type_annotation,
+                initializer,
+                constant,
+                ..
+            } => node!({
+                "kind": if *constant { "const" } else { "var" },
+                "name": &**name,
+                "type": type_annotation.as_ref().map(annotation),
+                "initializer": initializer.map(|initializer| self.expr(initializer)),
+            }),
+            Stmt::Block { statements } => {
+                node!({"kind": "block", "statements": self.statements(statements)})
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => node!({
+                "kind": "if",
+                "condition": self.expr(*condition),
+                "then": self.stmt(*then_branch),
+                "else": else_branch.map(|branch| self.stmt(branch)),
+            }),
+            Stmt::While { condition, body } => node!({
+                "kind": "while",
+                "condition": self.expr(*condition),
+                "body": self.stmt(*body),
+            }),
+            Stmt::DoWhile { body, condition } => node!({
+                "kind": "do_while",
+                "body": self.stmt(*body),
+                "condition": self.expr(*condition),
+            }),
+            Stmt::Labeled { label, body } => node!({
+                "kind": "labeled",
+                "label": &**label,
+                "body": self.stmt(*body),
+            }),
+            Stmt::Break { label } => node!({
+                "kind": "break",
+                "label": label.as_deref(),
+            }),
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => node!({
+                "kind": "switch",
+                "subject": self.expr(*subject),
+                "cases": cases
+                    .iter()
+                    .map(|(value, body)| node!({"value": self.expr(*value), "body": self.stmt(*body)}))
+                    .collect::<Vec<_>>(),
+                "default": default.map(|body| self.stmt(body)),
+            }),
+            Stmt::Function {
+                name,
+                parameters,
+                parameter_types,
+                return_type,
+                body,
+                ..
+            } => {
+                let parameters: Vec<_> = parameters
+                        .iter()
+                        .zip(parameter_types)
+                        .map(|(name, annotation_type)| {
+                            node!({"name": &**name, "type": annotation_type.as_ref().map(annotation)})
+                        })
+                        .collect();
+                node!({
+                    "kind": "function",
+                    "name": &**name,
+                    "parameters": parameters,
+                    "return_type": return_type.as_ref().map(annotation),
+                    "body": self.statements(body),
+                })
+            }
+            Stmt::Return { value } => node!({
+                "kind": "return",
+                "value": value.map(|value| self.expr(value)),
+            }),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => node!({
+                "kind": "class",
+                "name": &**name,
+                "superclass": superclass.map(|superclass| self.expr(superclass)),
+                "methods": self.statements(methods),
+            }),
+            Stmt::Enum { name, members, .. } => node!({
+                "kind": "enum",
+                "name": &**name,
+                "members": members.iter().map(|member| &**member).collect::<Vec<_>>(),
+            }),
+        })
+    }
+
+    fn expr(&self, expr: ExprRef) -> Value {
+        recursion::grow_stack(|| match &self.ast[expr] {
+            Expr::Number(value) => node!({"kind": "number", "value": *value}),
+            Expr::String(value) => node!({"kind": "string", "value": &**value}),
+            Expr::Boolean(value) => node!({"kind": "boolean", "value": *value}),
+            Expr::Nil => node!({"kind": "nil"}),
+            Expr::Binary {
+                left,
+                token_type,
+                right,
+            } => node!({
+                "kind": "binary",
+                "operator": operator_lexeme(token_type),
+                "left": self.expr(*left),
+                "right": self.expr(*right),
+            }),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => node!({
+                "kind": "logical",
+                "operator": operator_lexeme(operator),
+                "left": self.expr(*left),
+                "right": self.expr(*right),
+            }),
+            Expr::Unary { token_type, right } => node!({
+                "kind": "unary",
+                "operator": operator_lexeme(token_type),
+                "operand": self.expr(*right),
+            }),
+            Expr::Grouping { expression } => {
+                node!({"kind": "grouping", "expression": self.expr(*expression)})
+            }
+            Expr::Call { callee, arguments } => {
+                let arguments: Vec<_> = arguments.iter().map(|arg| self.expr(*arg)).collect();
+                node!({"kind": "call", "callee": self.expr(*callee), "arguments": arguments})
+            }
+            Expr::Get { object, name, .. } => node!({
+                "kind": "get",
+                "object": self.expr(*object),
+                "name": &**name,
+            }),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => node!({
+                "kind": "set",
+                "object": self.expr(*object),
+                "name": &**name,
+                "value": self.expr(*value),
+            }),
+            Expr::This { .. } => node!({"kind": "this"}),
+            Expr::Super { method, .. } => node!({"kind": "super", "method": &**method}),
+            Expr::Variable { name, .. } => node!({"kind": "variable", "name": &**name}),
+            Expr::Assign { name, value, .. } => node!({
+                "kind": "assign",
+                "name": &**name,
+                "value": self.expr(*value),
+            }),
+            Expr::List { elements } => {
+                let elements: Vec<_> = elements.iter().map(|element| self.expr(*element)).collect();
+                node!({"kind": "list", "elements": elements})
+            }
+            Expr::Map { entries } => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|&(key, value)| node!({"key": self.expr(key), "value": self.expr(value)}))
+                    .collect();
+                node!({"kind": "map", "entries": entries})
+            }
+            Expr::Match { subject, arms } => node!({
+                "kind": "match",
+                "subject": self.expr(*subject),
+                "arms": arms
+                    .iter()
+                    .map(|arm| node!({"pattern": self.pattern(&arm.pattern), "body": self.expr(arm.body)}))
+                    .collect::<Vec<_>>(),
+            }),
+        })
+    }
+
+    fn pattern(&self, pattern: &Pattern) -> Value {
+        match pattern {
+            Pattern::Value(value) => node!({"kind": "value", "value": self.expr(*value)}),
+            Pattern::Class { class, binding, .. } => node!({
+                "kind": "class",
+                "class": self.expr(*class),
+                "binding": &**binding,
+            }),
+            Pattern::Wildcard => node!({"kind": "wildcard"}),
+        }
+    }
+}
+
+fn annotation(annotation: &TypeAnnotation) -> Value {
+    node!({"name": annotation.name.as_str(), "line": annotation.line})
+}
+
+fn field<'v>(node: &'v Value, name: &str) -> Result<&'v Value> {
+    node.get(name)
+        .ok_or_else(|| invalid(format!("missing field '{}'", name)))
+}
+
+/// A field that may be missing or null.
+fn optional<'v>(node: &'v Value, name: &str) -> Option<&'v Value> {
+    node.get(name).filter(|value| !value.is_null())
+}
+
+fn array<'v>(node: &'v Value, name: &str) -> Result<&'v Vec<Value>> {
+    field(node, name)?
+        .as_array()
+        .ok_or_else(|| invalid(format!("'{}' has to be an array", name)))
+}
+
+fn string<'v>(node: &'v Value, name: &str) -> Result<&'v str> {
+    field(node, name)?
+        .as_str()
+        .ok_or_else(|| invalid(format!("'{}' has to be a string", name)))
+}
+
+fn operator(node: &Value, allowed: &[TokenType]) -> Result<TokenType> {
+    let lexeme = string(node, "operator")?;
+    OPERATORS
+        .iter()
+        .find(|(token_type, operator)| *operator == lexeme && allowed.contains(token_type))
+        .map(|(token_type, _)| token_type.clone())
+        .ok_or_else(|| invalid(format!("unknown operator '{}'", lexeme)))
+}
+
+struct Reader {
+    ast: Ast,
+    interner: Interner,
+}
+
+impl Reader {
+    fn name(&mut self, node: &Value, name: &str) -> Result<Rc<str>> {
+        Ok(self.interner.intern(string(node, name)?))
+    }
+
+    fn statements(&mut self, nodes: &Value) -> Result<Vec<StmtRef>> {
+        nodes
+            .as_array()
+            .ok_or_else(|| invalid("statements have to be an array".to_string()))?
+            .iter()
+            .map(|node| self.stmt(node))
+            .collect()
+    }
+
+    fn optional_expr(&mut self, node: &Value, name: &str) -> Result<Option<ExprRef>> {
+        optional(node, name)
+            .map(|value| self.expr(value))
+            .transpose()
+    }
+
+    fn annotation(&self, node: &Value, name: &str) -> Result<Option<TypeAnnotation>> {
+        optional(node, name)
+            .map(|annotation| {
+                let line = field(annotation, "line")?.as_u64();
+                Ok(TypeAnnotation {
+                    name: string(annotation, "name")?.to_string(),
+                    line: line.ok_or_else(|| invalid("'line' has to be a number".to_string()))?
+                        as u32,
+                })
+            })
+            .transpose()
+    }
+
+    fn stmt(&mut self, node: &Value) -> Result<StmtRef> {
+        recursion::grow_stack(|| {
+            let kind = string(node, "kind")?;
+            let stmt = match kind {
+                "expression" => Stmt::Expression {
+                    expression: self.expr(field(node, "expression")?)?,
+                },
+                "print" => Stmt::Print {
+                    expression: self.expr(field(node, "expression")?)?,
+                },
+                "var" | "const" => Stmt::Var {
+                    id: self.ast.new_id(),
+                    name: self.name(node, "name")?,
+                    type_annotation: self.annotation(node, "type")?,
+                    initializer: self.optional_expr(node, "initializer")?,
+                    constant: kind == "const",
+                },
+                "block" => Stmt::Block {
+                    statements: self.statements(field(node, "statements")?)?,
+                },
+                "if" => Stmt::If {
+                    condition: self.expr(field(node, "condition")?)?,
+                    then_branch: self.stmt(field(node, "then")?)?,
+                    else_branch: optional(node, "else")
+                        .map(|branch| self.stmt(branch))
+                        .transpose()?,
+                },
+                "while" => Stmt::While {
+                    condition: self.expr(field(node, "condition")?)?,
+                    body: self.stmt(field(node, "body")?)?,
+                },
+                "do_while" => Stmt::DoWhile {
+                    body: self.stmt(field(node, "body")?)?,
+                    condition: self.expr(field(node, "condition")?)?,
+                },
+                "labeled" => Stmt::Labeled {
+                    label: self.name(node, "label")?,
+                    body: self.stmt(field(node, "body")?)?,
+                },
+                "break" => Stmt::Break {
+                    label: match optional(node, "label") {
+                        Some(_) => Some(self.name(node, "label")?),
+                        None => None,
+                    },
+                },
+                "switch" => {
+                    let subject = self.expr(field(node, "subject")?)?;
+                    let mut cases = Vec::new();
+                    for case in array(node, "cases")? {
+                        let value = self.expr(field(case, "value")?)?;
+                        cases.push((value, self.stmt(field(case, "body")?)?));
+                    }
+                    Stmt::Switch {
+                        subject,
+                        cases,
+                        default: optional(node, "default")
+                            .map(|body| self.stmt(body))
+                            .transpose()?,
+                    }
+                }
+                "function" => {
+                    let mut parameters = Vec::new();
+                    let mut parameter_types = Vec::new();
+                    for parameter in array(node, "parameters")? {
+                        parameters.push(self.name(parameter, "name")?);
+                        parameter_types.push(self.annotation(parameter, "type")?);
+                    }
+                    Stmt::Function {
+                        id: self.ast.new_id(),
+                        name: self.name(node, "name")?,
+                        parameters: Rc::new(parameters),
+                        parameter_types,
+                        return_type: self.annotation(node, "return_type")?,
+                        body: self.statements(field(node, "body")?)?.into(),
+                    }
+                }
+                "return" => Stmt::Return {
+                    value: self.optional_expr(node, "value")?,
+                },
+                "class" => {
+                    let methods = self.statements(field(node, "methods")?)?;
+                    if methods
+                        .iter()
+                        .any(|method| !matches!(self.ast[*method], Stmt::Function { .. }))
+                    {
+                        return Err(invalid("methods have to be functions".to_string()));
+                    }
+                    Stmt::Class {
+                        id: self.ast.new_id(),
+                        name: self.name(node, "name")?,
+                        superclass: self.optional_expr(node, "superclass")?,
+                        methods,
+                    }
+                }
+                "enum" => {
+                    let mut members = vec![];
+                    for member in array(node, "members")? {
+                        let member = member
+                            .as_str()
+                            .ok_or_else(|| invalid("members have to be strings".to_string()))?;
+                        members.push(self.interner.intern(member));
+                    }
+                    Stmt::Enum {
+                        id: self.ast.new_id(),
+                        name: self.name(node, "name")?,
+                        members,
+                    }
+                }
+                kind => return Err(invalid(format!("unknown statement kind '{}'", kind))),
+            };
+            Ok(self.ast.push_stmt(stmt))
+        })
+    }
+
+    fn expr(&mut self, node: &Value) -> Result<ExprRef> {
+        recursion::grow_stack(|| {
+            let value = || field(node, "value");
+            let expr = match string(node, "kind")? {
+                "number" => Expr::Number(
+                    value()?
+                        .as_f64()
+                        .ok_or_else(|| invalid("'value' has to be a number".to_string()))?,
+                ),
+                "string" => Expr::String(self.name(node, "value")?),
+                "boolean" => Expr::Boolean(
+                    value()?
+                        .as_bool()
+                        .ok_or_else(|| invalid("'value' has to be a boolean".to_string()))?,
+                ),
+                "nil" => Expr::Nil,
+                "binary" => Expr::Binary {
+                    left: self.expr(field(node, "left")?)?,
+                    token_type: operator(node, &binary_operators())?,
+                    right: self.expr(field(node, "right")?)?,
+                },
+                "logical" => Expr::Logical {
+                    left: self.expr(field(node, "left")?)?,
+                    operator: operator(node, &[TokenType::And, TokenType::Or])?,
+                    right: self.expr(field(node, "right")?)?,
+                },
+                "unary" => Expr::Unary {
+                    token_type: operator(
+                        node,
+                        &[TokenType::Minus, TokenType::Bang, TokenType::Typeof],
+                    )?,
+                    right: self.expr(field(node, "operand")?)?,
+                },
+                "grouping" => Expr::Grouping {
+                    expression: self.expr(field(node, "expression")?)?,
+                },
+                "call" => Expr::Call {
+                    callee: self.expr(field(node, "callee")?)?,
+                    arguments: array(node, "arguments")?
+                        .iter()
+                        .map(|argument| self.expr(argument))
+                        .collect::<Result<_>>()?,
+                },
+                "get" => Expr::Get {
+                    id: self.ast.new_id(),
+                    object: self.expr(field(node, "object")?)?,
+                    name: self.name(node, "name")?,
+                },
+                "set" => Expr::Set {
+                    object: self.expr(field(node, "object")?)?,
+                    name: self.name(node, "name")?,
+                    value: self.expr(field(node, "value")?)?,
+                },
+                "this" => Expr::This {
+                    id: self.ast.new_id(),
+                    keyword: "this",
+                },
+                "super" => Expr::Super {
+                    id: self.ast.new_id(),
+                    keyword: "super",
+                    method: self.name(node, "method")?,
+                },
+                "variable" => Expr::Variable {
+                    id: self.ast.new_id(),
+                    name: self.name(node, "name")?,
+                },
+                "assign" => Expr::Assign {
+                    id: self.ast.new_id(),
+                    name: self.name(node, "name")?,
+                    value: self.expr(field(node, "value")?)?,
+                },
+                "list" => Expr::List {
+                    elements: array(node, "elements")?
+                        .iter()
+                        .map(|element| self.expr(element))
+                        .collect::<Result<_>>()?,
+                },
+                "map" => Expr::Map {
+                    entries: array(node, "entries")?
+                        .iter()
+                        .map(|entry| {
+                            Ok((
+                                self.expr(field(entry, "key")?)?,
+                                self.expr(field(entry, "value")?)?,
+                            ))
+                        })
+                        .collect::<Result<_>>()?,
+                },
+                "match" => {
+                    let subject = self.expr(field(node, "subject")?)?;
+                    let mut arms = Vec::new();
+                    for arm in array(node, "arms")? {
+                        let pattern = self.pattern(field(arm, "pattern")?)?;
+                        let body = self.expr(field(arm, "body")?)?;
+                        arms.push(MatchArm { pattern, body });
+                    }
+                    Expr::Match { subject, arms }
+                }
+                kind => return Err(invalid(format!("unknown expression kind '{}'", kind))),
+            };
+            Ok(self.ast.push_expr(expr))
+        })
+    }
+
+    fn pattern(&mut self, node: &Value) -> Result<Pattern> {
+        Ok(match string(node, "kind")? {
+            "value" => Pattern::Value(self.expr(field(node, "value")?)?),
+            "class" => Pattern::Class {
+                id: self.ast.new_id(),
+                class: self.expr(field(node, "class")?)?,
+                binding: self.name(node, "binding")?,
+            },
+            "wildcard" => Pattern::Wildcard,
+            kind => return Err(invalid(format!("unknown pattern kind '{}'", kind))),
+        })
+    }
+}
+
+fn binary_operators() -> [TokenType; 10] {
+    [
+        TokenType::Minus,
+        TokenType::Plus,
+        TokenType::Slash,
+        TokenType::Star,
+        TokenType::BangEqual,
+        TokenType::EqualEqual,
+        TokenType::Greater,
+        TokenType::GreaterEqual,
+        TokenType::Less,
+        TokenType::LessEqual,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{from_json, parse_to_json, to_json};
+
+    use crate::error::LoxError;
+    use crate::lox::Lox;
+    use crate::object::Object;
+
+    #[test]
+    fn round_trip() {
+        let source = r#"
+            class Animal {
+                init(name: String) { this.name = name; }
+                speak() { return this.name + " makes a sound"; }
+            }
+            class Dog < Animal {
+                speak() { return super.speak() + " (woof)"; }
+            }
+            var i = 0;
+            const limit = 3;
+            while (i < 3 and !false) { i = i + 1; }
+            if (i == 3) print "three"; else print nil;
+            switch (i) { case 1: print 1; default: i = 0; }
+            do i = i + 1; while (i < 2);
+            outer: while (true) { loop { break outer; } }
+            enum Color { Red, Green }
+            print [1, "two", [nil]];
+            print {"a": 1, 2: {}};
+            print match (Dog("Odie")) { Animal(a) => a.name, 1 => 2, _ => nil };
+            Dog("Rex").speak();
+        "#;
+        let json = parse_to_json(source).unwrap();
+        let program = from_json(&json).unwrap();
+        assert_eq!(to_json(&program), json);
+
+        let mut lox = Lox::new();
+        assert_eq!(
+            lox.interpret_json(&json),
+            Ok(Object::String("Rex makes a sound (woof)".into()))
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let chain = format!("print 1{};", " + 1".repeat(200));
+        let json = parse_to_json(&chain).unwrap();
+        let program = from_json(&json).unwrap();
+        assert_eq!(to_json(&program), json);
+
+        let depth = 9000;
+        let nested = format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        let json = parse_to_json(&nested).unwrap();
+        assert_eq!(to_json(&from_json(&json).unwrap()), json);
+    }
+
+    #[test]
+    fn shape() {
+        let json = parse_to_json("print -x;").unwrap();
+        assert_eq!(
+            json,
+            r#"{"statements":[{"expression":{"kind":"unary","operand":{"kind":"variable","name":"x"},"operator":"-"},"kind":"print"}],"version":1}"#
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_to_json("print;").is_err());
+        assert_eq!(
+            from_json(r#"{"version": 1, "statements": [{"kind": "loop"}]}"#).err(),
+            Some(LoxError::JsonError(
+                "Invalid program JSON: unknown statement kind 'loop'".into()
+            ))
+        );
+        assert!(matches!(
+            from_json(r#"{"version": 2, "statements": []}"#),
+            Err(LoxError::JsonError(_))
+        ));
+    }
+}
@@ -0,0 +1,444 @@
+//! A Jupyter kernel, started by Jupyter as `rlox kernel <connection file>`. All cells of a
+//! notebook run in the same [`Lox`], so declarations carry over from one cell to the next. What
+//! a cell prints is sent as its `stdout` stream, the value of a trailing expression statement
+//! as its result.
+//!
+//! The kernel speaks version 5.3 of the messaging protocol over ZeroMQ. It answers
+//! `kernel_info_request`, `execute_request`, `is_complete_request`, `comm_info_request` and
+//! `shutdown_request`; there is no completion, inspection or input.
+
+use crate::error::LoxError;
+use crate::interpreter::Output;
+use crate::Lox;
+
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PROTOCOL_VERSION: &str = "5.3";
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// Runs the kernel described by the connection file at `path` until a client shuts it down.
+pub fn serve(path: &str) -> io::Result<()> {
+    let connection: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let address = |port: &str| {
+        format!(
+            "{}://{}:{}",
+            connection["transport"].as_str().unwrap_or("tcp"),
+            connection["ip"].as_str().unwrap_or("127.0.0.1"),
+            connection[port]
+        )
+    };
+
+    let context = zmq::Context::new();
+    let shell = bind(&context, zmq::ROUTER, &address("shell_port"))?;
+    let control = bind(&context, zmq::ROUTER, &address("control_port"))?;
+    let iopub = bind(&context, zmq::PUB, &address("iopub_port"))?;
+    // nothing asks for input, but clients expect the socket to exist
+    let _stdin = bind(&context, zmq::ROUTER, &address("stdin_port"))?;
+    let heartbeat = bind(&context, zmq::REP, &address("hb_port"))?;
+    std::thread::spawn(move || -> zmq::Result<()> {
+        loop {
+            let ping = heartbeat.recv_bytes(0)?;
+            heartbeat.send(ping, 0)?;
+        }
+    });
+
+    let key = connection["key"].as_str().unwrap_or_default();
+    let mut kernel = Kernel::new(key.as_bytes());
+    loop {
+        let mut items = [
+            shell.as_poll_item(zmq::POLLIN),
+            control.as_poll_item(zmq::POLLIN),
+        ];
+        zmq::poll(&mut items, -1)?;
+        let socket = if items[0].is_readable() {
+            &shell
+        } else if items[1].is_readable() {
+            &control
+        } else {
+            continue;
+        };
+        let frames = socket.recv_multipart(0)?;
+        let request = match kernel.parse(frames) {
+            Some(request) => request,
+            None => continue,
+        };
+        let response = kernel.handle(&request);
+        for message in &response.broadcasts {
+            iopub.send_multipart(kernel.frames(message), 0)?;
+        }
+        if let Some(reply) = &response.reply {
+            socket.send_multipart(kernel.frames(reply), 0)?;
+        }
+        if response.shutdown {
+            return Ok(());
+        }
+    }
+}
+
+fn bind(context: &zmq::Context, kind: zmq::SocketType, address: &str) -> io::Result<zmq::Socket> {
+    let socket = context.socket(kind)?;
+    socket.bind(address)?;
+    Ok(socket)
+}
+
+/// A message of the protocol, without the signature.
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    /// The routing prefix of the client, sent back with replies.
+    identities: Vec<Vec<u8>>,
+    header: Value,
+    parent_header: Value,
+    metadata: Value,
+    content: Value,
+}
+
+impl Message {
+    fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or_default()
+    }
+}
+
+/// What the kernel sends for a request.
+#[derive(Debug, Default)]
+struct Response {
+    /// Messages for all clients, sent on the iopub socket.
+    broadcasts: Vec<Message>,
+    reply: Option<Message>,
+    shutdown: bool,
+}
+
+struct Kernel {
+    key: Vec<u8>,
+    session: String,
+    sent: u64,
+    lox: Lox,
+    output: Rc<RefCell<Vec<u8>>>,
+    execution_count: u64,
+}
+
+impl Kernel {
+    fn new(key: &[u8]) -> Self {
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut lox = Lox::new();
+        lox.interpreter().set_output(Output(Rc::clone(&output)));
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            key: key.to_vec(),
+            session: format!("rlox-{}-{}", std::process::id(), started.as_nanos()),
+            sent: 0,
+            lox,
+            output,
+            execution_count: 0,
+        }
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac
+    }
+
+    /// The hex encoded HMAC-SHA256 of the parts of a message, empty if there is no key.
+    fn sign(&self, parts: &[&[u8]]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+        hex::encode(self.mac(parts).finalize().into_bytes())
+    }
+
+    fn verify(&self, signature: &[u8], parts: &[&[u8]]) -> bool {
+        if self.key.is_empty() {
+            return signature.is_empty();
+        }
+        match hex::decode(signature) {
+            Ok(signature) => self.mac(parts).verify_slice(&signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Reads a message from its frames. Messages with a wrong signature are dropped.
+    fn parse(&self, mut frames: Vec<Vec<u8>>) -> Option<Message> {
+        let delimiter = frames.iter().position(|frame| frame == DELIMITER)?;
+        let parts = frames.split_off(delimiter + 1);
+        frames.pop();
+        if parts.len() < 5 {
+            return None;
+        }
+        let signed: Vec<&[u8]> = parts[1..5].iter().map(Vec::as_slice).collect();
+        if !self.verify(&parts[0], &signed) {
+            return None;
+        }
+        let part = |index: usize| serde_json::from_slice(&parts[index]).ok();
+        Some(Message {
+            identities: frames,
+            header: part(1)?,
+            parent_header: part(2)?,
+            metadata: part(3)?,
+            content: part(4)?,
+        })
+    }
+
+    /// The frames of a signed message.
+    fn frames(&self, message: &Message) -> Vec<Vec<u8>> {
+        let parts: Vec<Vec<u8>> = [
+            &message.header,
+            &message.parent_header,
+            &message.metadata,
+            &message.content,
+        ]
+        .iter()
+        .map(|part| part.to_string().into_bytes())
+        .collect();
+        let signed: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+        let signature = self.sign(&signed).into_bytes();
+
+        let mut frames = message.identities.clone();
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature);
+        frames.extend(parts);
+        frames
+    }
+
+    /// A new message answering `parent`.
+    fn message(&mut self, parent: &Message, msg_type: &str, content: Value) -> Message {
+        self.sent += 1;
+        Message {
+            identities: parent.identities.clone(),
+            header: json!({
+                "msg_id": format!("{}-{}", self.session, self.sent),
+                "session": self.session,
+                "username": "rlox",
+                "date": timestamp(SystemTime::now()),
+                "msg_type": msg_type,
+                "version": PROTOCOL_VERSION,
+            }),
+            parent_header: parent.header.clone(),
+            metadata: json!({}),
+            content,
+        }
+    }
+
+    /// A message for all clients. Those carry no routing prefix, only a topic.
+    fn broadcast(&mut self, parent: &Message, msg_type: &str, content: Value) -> Message {
+        let mut message = self.message(parent, msg_type, content);
+        message.identities = vec![msg_type.as_bytes().to_vec()];
+        message
+    }
+
+    fn handle(&mut self, request: &Message) -> Response {
+        let mut response = Response::default();
+        let busy = self.broadcast(request, "status", json!({"execution_state": "busy"}));
+        response.broadcasts.push(busy);
+
+        let reply_type = request.msg_type().replace("_request", "_reply");
+        let content = match request.msg_type() {
+            "kernel_info_request" => Some(json!({
+                "status": "ok",
+                "protocol_version": PROTOCOL_VERSION,
+                "implementation": "rlox",
+                "implementation_version": env!("CARGO_PKG_VERSION"),
+                "language_info": {
+                    "name": "lox",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "mimetype": "text/x-lox",
+                    "file_extension": ".lox",
+                },
+                "banner": "rlox, a Lox interpreter",
+            })),
+            "execute_request" => Some(self.execute(request, &mut response)),
+            // cells are run as they are, there is no notion of an unfinished statement
+            "is_complete_request" => Some(json!({"status": "complete"})),
+            "comm_info_request" => Some(json!({"status": "ok", "comms": {}})),
+            "shutdown_request" => {
+                response.shutdown = true;
+                let restart = request.content["restart"].as_bool().unwrap_or(false);
+                Some(json!({"status": "ok", "restart": restart}))
+            }
+            _ => None,
+        };
+        response.reply = content.map(|content| self.message(request, &reply_type, content));
+
+        let idle = self.broadcast(request, "status", json!({"execution_state": "idle"}));
+        response.broadcasts.push(idle);
+        response
+    }
+
+    fn execute(&mut self, request: &Message, response: &mut Response) -> Value {
+        let code = request.content["code"].as_str().unwrap_or_default();
+        let silent = request.content["silent"].as_bool().unwrap_or(false);
+        if !silent {
+            self.execution_count += 1;
+            let input = json!({"code": code, "execution_count": self.execution_count});
+            let input = self.broadcast(request, "execute_input", input);
+            response.broadcasts.push(input);
+        }
+
+        let result = self.lox.eval(code);
+        let printed = String::from_utf8_lossy(&self.output.borrow()).into_owned();
+        self.output.borrow_mut().clear();
+        if !printed.is_empty() && !silent {
+            let stream = json!({"name": "stdout", "text": printed});
+            let stream = self.broadcast(request, "stream", stream);
+            response.broadcasts.push(stream);
+        }
+
+        match result {
+            Ok(value) => {
+                let text = value.to_string();
+                if text != "nil" && !silent {
+                    let result = json!({
+                        "execution_count": self.execution_count,
+                        "data": {"text/plain": text},
+                        "metadata": {},
+                    });
+                    let result = self.broadcast(request, "execute_result", result);
+                    response.broadcasts.push(result);
+                }
+                json!({
+                    "status": "ok",
+                    "execution_count": self.execution_count,
+                    "user_expressions": {},
+                })
+            }
+            Err(error) => {
+                let error = error_content(&error);
+                let broadcast = self.broadcast(request, "error", error.clone());
+                response.broadcasts.push(broadcast);
+                let mut reply = error;
+                reply["status"] = json!("error");
+                reply["execution_count"] = json!(self.execution_count);
+                reply
+            }
+        }
+    }
+}
+
+fn error_content(error: &LoxError) -> Value {
+    let name = match error {
+        LoxError::LexerError(..) | LoxError::ParserError(..) => "SyntaxError",
+        _ => "RuntimeError",
+    };
+    json!({
+        "ename": name,
+        "evalue": error.to_string(),
+        "traceback": [error.to_string()],
+    })
+}
+
+/// `time` in ISO 8601, as the protocol wants dates.
+fn timestamp(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = elapsed.as_secs();
+    let (days, second_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // civil date from days since the epoch, after Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{timestamp, Kernel, Message};
+
+    use serde_json::{json, Value};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn request(msg_type: &str, content: Value) -> Message {
+        Message {
+            identities: vec![b"client".to_vec()],
+            header: json!({"msg_id": "1", "msg_type": msg_type}),
+            parent_header: json!({}),
+            metadata: json!({}),
+            content,
+        }
+    }
+
+    fn broadcasts(kernel: &mut Kernel, code: &str) -> Vec<(String, Value)> {
+        let response = kernel.handle(&request("execute_request", json!({"code": code})));
+        response
+            .broadcasts
+            .iter()
+            .map(|message| (message.msg_type().to_string(), message.content.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn frames() {
+        let kernel = Kernel::new(b"secret");
+        let message = request("kernel_info_request", json!({}));
+        let frames = kernel.frames(&message);
+        assert_eq!(frames[0], b"client");
+        assert_eq!(frames[2].len(), 64);
+        assert_eq!(kernel.parse(frames.clone()), Some(message));
+
+        let mut tampered = frames;
+        tampered[6] = b"{\"code\": \"\"}".to_vec();
+        assert_eq!(kernel.parse(tampered), None);
+    }
+
+    #[test]
+    fn execute() {
+        let mut kernel = Kernel::new(b"");
+        let messages = broadcasts(&mut kernel, "var a = 40; print \"cell\";");
+        let types: Vec<_> = messages.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(types, ["status", "execute_input", "stream", "status"]);
+        assert_eq!(messages[2].1["text"], "cell\n");
+
+        let messages = broadcasts(&mut kernel, "a + 2;");
+        assert_eq!(messages[1].1["execution_count"], 2);
+        assert_eq!(messages[2].0, "execute_result");
+        assert_eq!(messages[2].1["data"]["text/plain"], "42");
+
+        let response = kernel.handle(&request("execute_request", json!({"code": "b;"})));
+        let reply = response.reply.unwrap();
+        assert_eq!(reply.msg_type(), "execute_reply");
+        assert_eq!(reply.content["status"], "error");
+        assert_eq!(reply.content["ename"], "RuntimeError");
+        assert_eq!(reply.identities, [b"client".to_vec()]);
+
+        let response = kernel.handle(&request("shutdown_request", json!({})));
+        assert!(response.shutdown);
+    }
+
+    #[test]
+    fn dates() {
+        let time = UNIX_EPOCH + Duration::from_micros(951_827_696_000_001);
+        assert_eq!(timestamp(time), "2000-02-29T12:34:56.000001Z");
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000000Z");
+    }
+}
@@ -1,6 +1,10 @@
 use crate::error::{LoxError, Result};
-use crate::token::{Token, TokenType};
-use std::str::Chars;
+use crate::interner::Interner;
+use crate::token::{OwnedToken, Span, Token, TokenType};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::str::CharIndices;
 
 impl<'a> std::cmp::PartialEq for Token<'a> {
     fn eq(&self, other: &Self) -> bool {
@@ -8,86 +12,161 @@ impl<'a> std::cmp::PartialEq for Token<'a> {
     }
 }
 
+/// Which trivia the lexer yields as tokens instead of skipping it. The parser does not accept
+/// trivia, it is for tools working on the source text like the formatter.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LexerOptions {
+    /// Yield `//` comments as `TokenType::Comment`.
+    pub comments: bool,
+    /// Yield whitespace as `TokenType::Whitespace`.
+    pub whitespace: bool,
+}
+
 struct Lexer<'a> {
     source: &'a str,
-    source_iter: std::iter::Peekable<std::iter::Enumerate<Chars<'a>>>,
+    options: LexerOptions,
+    /// The characters of `source` with their byte positions.
+    source_iter: std::iter::Peekable<CharIndices<'a>>,
     start: usize,
     line: u32,
+    /// The byte position the current line starts at.
+    line_start: usize,
+    /// Added to the byte positions in token spans, for sources lexed in parts.
+    offset: usize,
     eof_returned: bool,
+    interner: Interner,
 }
 
 impl<'a> Lexer<'a> {
     fn new(source: &'a str) -> Self {
+        Self::with_options(source, LexerOptions::default())
+    }
+
+    fn with_options(source: &'a str, options: LexerOptions) -> Self {
         Self {
             source,
-            source_iter: source.chars().enumerate().peekable(),
+            options,
+            source_iter: source.char_indices().peekable(),
             start: 0,
             line: 1,
+            line_start: 0,
+            offset: 0,
             eof_returned: false,
+            interner: Interner::new(),
         }
     }
 
-    fn string(&mut self, start_pos: usize) -> Result<TokenType> {
+    fn string(&mut self, start_pos: usize) -> std::result::Result<TokenType, Cow<'static, str>> {
         while let Some((pos, ch)) = self.source_iter.next() {
-            if ch == '"' {
-                return Ok(TokenType::String(
-                    (&self.source[start_pos..pos]).to_string(),
-                ));
+            match ch {
+                '"' => {
+                    return Ok(TokenType::String(
+                        self.interner.intern(&self.source[start_pos..pos]),
+                    ))
+                }
+                '\n' => self.newline(pos),
+                _ => {}
             }
         }
-        Err(LoxError::LexerError(
-            self.line,
-            "Unterminated string".into(),
-        ))
+        Err("Unterminated string".into())
     }
 
-    fn number(&mut self, start_pos: usize) -> Result<TokenType> {
+    fn number(&mut self, start_pos: usize) -> TokenType {
         while self.is_digit() {
             self.source_iter.next();
         }
 
+        // a fractional part needs a digit after the dot, `1.foo` is a number and a property
         if self.matches('.') {
-            let &(digit_pos, _) = self.source_iter.peek().unwrap();
-            match self.source_iter.nth(digit_pos + 1) {
-                Some((_, _ch @ '0'..='9')) => {
+            let mut lookahead = self.source_iter.clone();
+            lookahead.next();
+            if let Some((_, '0'..='9')) = lookahead.next() {
+                self.source_iter.next();
+                while self.is_digit() {
                     self.source_iter.next();
-                    while self.is_digit() {
-                        self.source_iter.next();
-                    }
                 }
-                _ => {}
-            };
+            }
         }
 
         let number = &self.source[start_pos..self.end_pos()];
-        Ok(TokenType::Number(number.parse().unwrap()))
+        TokenType::Number(number.parse().unwrap())
     }
 
-    fn identifier(&mut self, start_pos: usize) -> Result<TokenType> {
+    fn identifier(&mut self, start_pos: usize) -> TokenType {
         while self.is_alpha() || self.is_digit() {
             self.source_iter.next();
         }
         let text = &self.source[start_pos..self.end_pos()];
 
-        Ok(match text {
+        match text {
             "and" => TokenType::And,
+            "break" => TokenType::Break,
+            "case" => TokenType::Case,
             "class" => TokenType::Class,
+            "const" => TokenType::Const,
+            "default" => TokenType::Default,
+            "do" => TokenType::Do,
             "else" => TokenType::Else,
+            "enum" => TokenType::Enum,
             "false" => TokenType::False,
             "for" => TokenType::For,
             "fun" => TokenType::Fun,
             "if" => TokenType::If,
+            "loop" => TokenType::Loop,
+            "match" => TokenType::Match,
             "nil" => TokenType::Nil,
             "or" => TokenType::Or,
             "print" => TokenType::Print,
             "return" => TokenType::Return,
             "super" => TokenType::Super,
+            "switch" => TokenType::Switch,
             "this" => TokenType::This,
             "true" => TokenType::True,
+            "typeof" => TokenType::Typeof,
             "var" => TokenType::Var,
             "while" => TokenType::While,
             _ => TokenType::Identifier,
-        })
+        }
+    }
+
+    /// The run of whitespace starting with `first`.
+    fn whitespace(&mut self, first: char) -> Token<'a> {
+        let line = self.line;
+        let (mut pos, mut ch) = (self.start, first);
+        loop {
+            if ch == '\n' {
+                self.newline(pos);
+            }
+            match self.source_iter.peek() {
+                Some(&(next_pos, next @ (' ' | '\r' | '\t' | '\n'))) => {
+                    self.source_iter.next();
+                    (pos, ch) = (next_pos, next);
+                }
+                _ => break,
+            }
+        }
+        Token {
+            token_type: TokenType::Whitespace,
+            lexeme: &self.source[self.start..self.end_pos()],
+            line,
+            span: self.span(),
+        }
+    }
+
+    /// The span of the token being lexed.
+    fn span(&mut self) -> Span {
+        Span::new(self.offset + self.start, self.offset + self.end_pos())
+    }
+
+    /// Moves to the line after the line break at `pos`.
+    fn newline(&mut self, pos: usize) {
+        self.line += 1;
+        self.line_start = pos + 1;
+    }
+
+    /// The column of `pos` in its line, counting characters from 1.
+    fn column(&self, pos: usize) -> u32 {
+        self.source[self.line_start..pos].chars().count() as u32 + 1
     }
 
     fn matches(&mut self, expected: char) -> bool {
@@ -98,25 +177,14 @@ impl<'a> Lexer<'a> {
     }
 
     fn is_digit(&mut self) -> bool {
-        if let Some((_, ch)) = self.source_iter.peek() {
-            match ch {
-                '0'..='9' => true,
-                _ => false,
-            }
-        } else {
-            false
-        }
+        matches!(self.source_iter.peek(), Some((_, '0'..='9')))
     }
 
     fn is_alpha(&mut self) -> bool {
-        if let Some((_, ch)) = self.source_iter.peek() {
-            match ch {
-                'a'..='z' | 'A'..='Z' | '_' => true,
-                _ => false,
-            }
-        } else {
-            false
-        }
+        matches!(
+            self.source_iter.peek(),
+            Some((_, 'a'..='z' | 'A'..='Z' | '_'))
+        )
     }
 
     fn end_pos(&mut self) -> usize {
@@ -134,15 +202,26 @@ impl<'a> Iterator for Lexer<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         if let Some((pos, ch)) = self.source_iter.next() {
             self.start = pos;
+            let (line, column) = (self.line, self.column(pos));
             let token_type = match ch {
                 '(' => Ok(TokenType::LeftParen),
                 ')' => Ok(TokenType::RightParen),
                 '{' => Ok(TokenType::LeftBrace),
                 '}' => Ok(TokenType::RightBrace),
+                '[' => Ok(TokenType::LeftBracket),
+                ']' => Ok(TokenType::RightBracket),
                 ',' => Ok(TokenType::Comma),
+                ':' => Ok(TokenType::Colon),
                 '.' => Ok(TokenType::Dot),
                 '+' => Ok(TokenType::Plus),
-                '-' => Ok(TokenType::Minus),
+                '-' => {
+                    if self.matches('>') {
+                        self.source_iter.next();
+                        Ok(TokenType::Arrow)
+                    } else {
+                        Ok(TokenType::Minus)
+                    }
+                }
                 '*' => Ok(TokenType::Star),
                 ';' => Ok(TokenType::Semicolon),
                 '!' => {
@@ -157,6 +236,9 @@ impl<'a> Iterator for Lexer<'a> {
                     if self.matches('=') {
                         self.source_iter.next();
                         Ok(TokenType::EqualEqual)
+                    } else if self.matches('>') {
+                        self.source_iter.next();
+                        Ok(TokenType::FatArrow)
                     } else {
                         Ok(TokenType::Equal)
                     }
@@ -179,55 +261,66 @@ impl<'a> Iterator for Lexer<'a> {
                 }
                 '/' => {
                     if self.matches('/') {
-                        while !self.matches('\n') {
+                        while self.source_iter.peek().is_some() && !self.matches('\n') {
                             self.source_iter.next();
                         }
-                        return self.next();
+                        if !self.options.comments {
+                            return self.next();
+                        }
+                        Ok(TokenType::Comment)
                     } else {
                         Ok(TokenType::Slash)
                     }
                 }
+                ' ' | '\r' | '\t' | '\n' if self.options.whitespace => {
+                    return Some(Ok(self.whitespace(ch)));
+                }
                 ' ' | '\r' | '\t' => return self.next(),
                 '\n' => {
-                    self.line += 1;
+                    self.newline(pos);
                     return self.next();
                 }
                 '"' => self.string(pos + 1),
-                '0'..='9' => self.number(pos),
-                'a'..='z' | 'A'..='Z' | '_' => self.identifier(pos),
-                _ => {
-                    return Some(Err(LoxError::LexerError(
-                        self.line,
-                        format!("Unexpected character '{}'", ch).into(),
-                    )));
-                }
+                '0'..='9' => Ok(self.number(pos)),
+                'a'..='z' | 'A'..='Z' | '_' => Ok(self.identifier(pos)),
+                _ => Err(format!("Unexpected character '{}'", ch).into()),
             };
-            if token_type.is_err() {
-                return self.next();
-            } else {
-                Some(Ok(Token {
-                    token_type: token_type.unwrap(),
+            // errors are reported where the token starts, and lexing goes on after them
+            Some(match token_type {
+                Ok(token_type) => Ok(Token {
+                    token_type,
                     lexeme: &self.source[self.start..self.end_pos()],
-                    line: self.line,
-                }))
-            }
+                    line,
+                    span: self.span(),
+                }),
+                Err(reason) => Err(LoxError::LexerError(line, column, reason)),
+            })
         } else {
             if self.eof_returned {
                 None
             } else {
                 self.eof_returned = true;
+                let end = self.offset + self.source.len();
                 Some(Ok(Token {
                     token_type: TokenType::Eof,
                     lexeme: "",
                     line: self.line,
+                    span: Span::new(end, end),
                 }))
             }
         }
     }
 }
 
-pub fn lex(source: &str) -> (Vec<Token>, Vec<LoxError>) {
-    let lexer = Lexer::new(source);
+/// Splits `source` into tokens, ending with an `Eof` token. Lexing continues after an error, so
+/// all of them are reported at once.
+pub fn lex(source: &str) -> (Vec<Token<'_>>, Vec<LoxError>) {
+    lex_with_options(source, LexerOptions::default())
+}
+
+/// Like `lex`, but also yields the trivia `options` asks for.
+pub fn lex_with_options(source: &str, options: LexerOptions) -> (Vec<Token<'_>>, Vec<LoxError>) {
+    let lexer = Lexer::with_options(source, options);
 
     let (tokens, errors): (Vec<_>, Vec<_>) = lexer.partition(Result::is_ok);
     let tokens = tokens.into_iter().map(Result::unwrap).collect();
@@ -236,12 +329,149 @@ pub fn lex(source: &str) -> (Vec<Token>, Vec<LoxError>) {
     (tokens, errors)
 }
 
+/// A token with the trivia tokens before it, see `attach_trivia`.
+#[derive(Debug)]
+pub struct TriviaToken<'a> {
+    pub leading: Vec<Token<'a>>,
+    pub token: Token<'a>,
+}
+
+/// Attaches the comment and whitespace tokens lexed with `LexerOptions` to the token following
+/// them. Trivia at the end of the source goes to the `Eof` token.
+pub fn attach_trivia(tokens: Vec<Token<'_>>) -> Vec<TriviaToken<'_>> {
+    let mut attached = vec![];
+    let mut leading = vec![];
+    for token in tokens {
+        if matches!(token.token_type, TokenType::Comment | TokenType::Whitespace) {
+            leading.push(token);
+        } else {
+            attached.push(TriviaToken {
+                leading: std::mem::take(&mut leading),
+                token,
+            });
+        }
+    }
+    attached
+}
+
+/// Lexes source read from `input` a line at a time, so the whole source never has to be in
+/// memory at once, e.g. for huge generated scripts or piped input. Yields the same tokens and
+/// errors as `lex`, just owning their lexemes, and fails once if reading fails.
+///
+/// ```
+/// use rlox::StreamLexer;
+///
+/// let tokens: Vec<_> = StreamLexer::new("print 1;\nprint 2;".as_bytes())
+///     .collect::<Result<_, _>>()?;
+/// assert_eq!(tokens.len(), 7);
+/// assert_eq!(tokens[3].line, 2);
+/// # Ok::<(), rlox::LoxError>(())
+/// ```
+pub struct StreamLexer<R> {
+    input: R,
+    /// The line the next chunk starts in.
+    line: u32,
+    /// The byte position the next chunk starts at.
+    offset: usize,
+    /// Lexed but not yet returned.
+    pending: VecDeque<Result<OwnedToken>>,
+    finished: bool,
+}
+
+impl<R: BufRead> StreamLexer<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: 1,
+            offset: 0,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Reads and lexes the next chunk: a line, or several if a string spans them. Tokens never
+    /// span lines otherwise.
+    fn lex_chunk(&mut self) {
+        let mut chunk = String::new();
+        loop {
+            match self.input.read_line(&mut chunk) {
+                Ok(0) => {
+                    self.finished = true;
+                    break;
+                }
+                Ok(_) if ends_in_string(&chunk) => {}
+                Ok(_) => break,
+                Err(error) => {
+                    self.pending.push_back(Err(LoxError::LexerError(
+                        self.line,
+                        1,
+                        format!("Could not read the source: {}", error).into(),
+                    )));
+                    self.finished = true;
+                    return;
+                }
+            }
+        }
+
+        let mut lexer = Lexer::new(&chunk);
+        lexer.line = self.line;
+        lexer.offset = self.offset;
+        let finished = self.finished;
+        self.pending.extend(
+            lexer
+                .filter(|token| {
+                    finished || !matches!(token, Ok(token) if token.token_type == TokenType::Eof)
+                })
+                .map(|token| token.map(OwnedToken::from)),
+        );
+        self.line += chunk.matches('\n').count() as u32;
+        self.offset += chunk.len();
+    }
+}
+
+impl<R: BufRead> Iterator for StreamLexer<R> {
+    type Item = Result<OwnedToken>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.finished {
+            self.lex_chunk();
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Whether `source` stops in the middle of a string literal.
+fn ends_in_string(source: &str) -> bool {
+    let mut in_string = false;
+    let mut chars = source.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => in_string = !in_string,
+            '/' if !in_string && chars.peek() == Some(&'/') => {
+                chars.find(|&ch| ch == '\n');
+            }
+            _ => {}
+        }
+    }
+    in_string
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::lex;
-    use super::Token;
-    use super::TokenType;
+    use super::{attach_trivia, lex, lex_with_options, LexerOptions, StreamLexer};
+    use super::{Span, Token, TokenType};
+    use crate::error::LoxError;
+
+    /// Tokens compare by their type only.
+    fn token(token_type: TokenType, lexeme: &str, line: u32) -> Token<'_> {
+        Token {
+            token_type,
+            lexeme,
+            line,
+            span: Span::default(),
+        }
+    }
 
     #[test]
     fn foo() {
@@ -253,83 +483,135 @@ mod tests {
         "#;
         let (tokens, errors) = lex(source);
         let expected_tokens = vec![
-            Token {
-                token_type: TokenType::Var,
-                lexeme: "var",
-                line: 1,
-            },
-            Token {
-                token_type: TokenType::Identifier,
-                lexeme: "implemented",
-                line: 1,
-            },
-            Token {
-                token_type: TokenType::Equal,
-                lexeme: "=",
-                line: 1,
-            },
-            Token {
-                token_type: TokenType::String("In Rust!".to_string()),
-                lexeme: r#""In Rust!""#,
-                line: 1,
-            },
-            Token {
-                token_type: TokenType::Semicolon,
-                lexeme: ";",
-                line: 1,
-            },
-            Token {
-                token_type: TokenType::Fun,
-                lexeme: "fun",
-                line: 2,
-            },
-            Token {
-                token_type: TokenType::Identifier,
-                lexeme: "answer",
-                line: 2,
-            },
-            Token {
-                token_type: TokenType::LeftParen,
-                lexeme: "(",
-                line: 2,
-            },
-            Token {
-                token_type: TokenType::RightParen,
-                lexeme: ")",
-                line: 2,
-            },
-            Token {
-                token_type: TokenType::LeftBrace,
-                lexeme: "{",
-                line: 2,
-            },
-            Token {
-                token_type: TokenType::Return,
-                lexeme: "return",
-                line: 3,
-            },
-            Token {
-                token_type: TokenType::Number(42.0),
-                lexeme: "42",
-                line: 3,
-            },
-            Token {
-                token_type: TokenType::Semicolon,
-                lexeme: ";",
-                line: 3,
-            },
-            Token {
-                token_type: TokenType::RightBrace,
-                lexeme: "}",
-                line: 4,
-            },
-            Token {
-                token_type: TokenType::Eof,
-                lexeme: "",
-                line: 5,
-            },
+            token(TokenType::Var, "var", 1),
+            token(TokenType::Identifier, "implemented", 1),
+            token(TokenType::Equal, "=", 1),
+            token(TokenType::String("In Rust!".into()), r#""In Rust!""#, 1),
+            token(TokenType::Semicolon, ";", 1),
+            token(TokenType::Fun, "fun", 2),
+            token(TokenType::Identifier, "answer", 2),
+            token(TokenType::LeftParen, "(", 2),
+            token(TokenType::RightParen, ")", 2),
+            token(TokenType::LeftBrace, "{", 2),
+            token(TokenType::Return, "return", 3),
+            token(TokenType::Number(42.0), "42", 3),
+            token(TokenType::Semicolon, ";", 3),
+            token(TokenType::RightBrace, "}", 4),
+            token(TokenType::Eof, "", 5),
         ];
         assert_eq!(errors.len(), 0);
         assert_eq!(tokens, expected_tokens);
     }
+
+    #[test]
+    fn numbers() {
+        let (tokens, errors) = lex("1.5 2. 3.x");
+        assert!(errors.is_empty());
+        let lexemes: Vec<_> = tokens.iter().map(|token| token.lexeme).collect();
+        assert_eq!(lexemes, ["1.5", "2", ".", "3", ".", "x", ""]);
+        assert_eq!(tokens[0].token_type, TokenType::Number(1.5));
+    }
+
+    #[test]
+    fn non_ascii() {
+        let (tokens, errors) = lex("\"héllo\" + \"ü\"; é");
+        assert_eq!(tokens[0].token_type, TokenType::String("héllo".into()));
+        assert_eq!(tokens[2].lexeme, "\"ü\"");
+        // spans count bytes, not characters
+        assert_eq!(tokens[0].span, Span::new(0, 8));
+        assert_eq!(tokens[2].span, Span::new(11, 15));
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn trivia() {
+        let source = "var a; // one\n\n  // two\nprint a;";
+        let (tokens, errors) = lex_with_options(
+            source,
+            LexerOptions {
+                comments: true,
+                whitespace: false,
+            },
+        );
+        assert!(errors.is_empty());
+        let comments: Vec<_> = tokens
+            .iter()
+            .filter(|token| token.token_type == TokenType::Comment)
+            .map(|token| (token.lexeme, token.line))
+            .collect();
+        assert_eq!(comments, [("// one", 1), ("// two", 3)]);
+
+        let options = LexerOptions {
+            comments: true,
+            whitespace: true,
+        };
+        let (tokens, _) = lex_with_options(source, options);
+        let text: String = tokens.iter().map(|token| token.lexeme).collect();
+        assert_eq!(text, source);
+        let attached = attach_trivia(tokens);
+        let print = &attached[3];
+        assert_eq!((print.token.lexeme, print.token.line), ("print", 4));
+        let leading: Vec<_> = print.leading.iter().map(|token| token.lexeme).collect();
+        assert_eq!(leading, [" ", "// one", "\n\n  ", "// two", "\n"]);
+        assert_eq!(print.leading[2].line, 1);
+    }
+
+    #[test]
+    fn streaming() {
+        let source = "var a = \"two\nlines\"; // \"\nprint a;\n@ 1.5\n\"open";
+        let (streamed, streamed_errors): (Vec<_>, Vec<_>) =
+            StreamLexer::new(source.as_bytes()).partition(Result::is_ok);
+        let streamed: Vec<_> = streamed.into_iter().map(Result::unwrap).collect();
+        let (tokens, errors) = lex(source);
+
+        let lexemes = |tokens: &[Token]| -> Vec<(String, Span)> {
+            tokens
+                .iter()
+                .map(|token| (token.lexeme.to_string(), token.span))
+                .collect()
+        };
+        let streamed_tokens: Vec<_> = streamed.iter().map(|token| token.as_token()).collect();
+        assert_eq!(lexemes(&streamed_tokens), lexemes(&tokens));
+        assert_eq!(streamed.last().unwrap().token_type, TokenType::Eof);
+        // the line after the string spanning two
+        assert_eq!(
+            (streamed[5].lexeme.as_str(), streamed[5].line),
+            ("print", 3)
+        );
+        let streamed_errors: Vec<_> = streamed_errors
+            .into_iter()
+            .map(Result::unwrap_err)
+            .collect();
+        assert_eq!(streamed_errors, errors);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn errors() {
+        let source = "var a = @;\nvar s = \"two\nlines\"; # ok\n  print é$ 1;\nvar b = \"open\n";
+        let (tokens, errors) = lex(source);
+        assert_eq!(
+            errors,
+            [
+                LoxError::LexerError(1, 9, "Unexpected character '@'".into()),
+                LoxError::LexerError(3, 9, "Unexpected character '#'".into()),
+                LoxError::LexerError(4, 9, "Unexpected character 'é'".into()),
+                // columns count characters, not bytes
+                LoxError::LexerError(4, 10, "Unexpected character '$'".into()),
+                LoxError::LexerError(5, 9, "Unterminated string".into()),
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "Lexer error in line 1, column 9: Unexpected character '@'"
+        );
+        // lexing goes on after errors, and tokens after a string spanning lines are counted right
+        let lines: Vec<_> = tokens
+            .iter()
+            .filter(|token| token.token_type == TokenType::Print)
+            .map(|token| token.line)
+            .collect();
+        assert_eq!(lines, [4]);
+        assert_eq!(tokens.last().unwrap().token_type, TokenType::Eof);
+    }
 }
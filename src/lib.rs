@@ -0,0 +1,159 @@
+//! A tree-walking interpreter for Lox, the language from
+//! [Crafting Interpreters](http://craftinginterpreters.com/).
+//!
+//! Source code goes through the same stages the `rlox` binary uses: [`lex`] turns it into
+//! tokens, [`parse`] into a [`Program`], [`resolve`] binds the variables and an [`Interpreter`]
+//! runs it. [`Lox`] does all of that in one call.
+//!
+//! ```
+//! let (tokens, lexer_errors) = rlox::lex("var answer = 6 * 7; print answer;");
+//! assert!(lexer_errors.is_empty());
+//! let (program, parser_errors) = rlox::parse(&tokens);
+//! assert!(parser_errors.is_empty());
+//!
+//! let mut interpreter = rlox::Interpreter::new();
+//! interpreter.add_scopes(rlox::resolve(&program)?);
+//! interpreter.interpret(program)?;
+//! # Ok::<(), rlox::LoxError>(())
+//! ```
+
+/// Syntax trees as text.
+mod ast_printer;
+/// Timing scripts, behind `rlox bench`.
+mod bench;
+/// Classes and their instances.
+mod classes;
+/// The `rlox` binary, public only so `main.rs` can call it.
+#[doc(hidden)]
+pub mod cli;
+/// Conversions between Lox values and Rust types.
+mod convert;
+/// Which statements ran, behind `--coverage`.
+mod coverage;
+/// A syntax tree keeping comments and whitespace.
+pub mod cst;
+/// The debug adapter behind `rlox dap`.
+#[cfg(feature = "dap")]
+mod dap;
+/// Watching programs as they run.
+mod debug;
+/// The interactive debugger behind `rlox debug`.
+mod debugger;
+/// Doc comments and the generator behind `rlox doc`.
+mod doc;
+/// Enums and their members.
+mod enums;
+mod environment;
+/// The errors of all stages.
+pub mod error;
+/// The C interface, see `include/rlox.h`.
+mod ffi;
+/// The code formatter behind `rlox fmt`.
+mod formatter;
+#[cfg(any(feature = "lsp", feature = "dap"))]
+mod framing;
+/// Functions callable from Lox, native and user defined.
+mod functions;
+/// The cycle collector.
+mod gc;
+/// HTTP requests for scripts, see `natives::Natives::HTTP`.
+#[cfg(feature = "http")]
+mod http;
+/// Re-parsing only the statements an edit touches.
+mod incremental;
+mod interner;
+/// Runs resolved programs.
+mod interpreter;
+/// Programs as JSON, for tools written in other languages.
+#[cfg(feature = "json")]
+pub mod json;
+/// The Jupyter kernel behind `rlox kernel`.
+#[cfg(feature = "kernel")]
+mod kernel;
+/// Turns source code into tokens.
+mod lexer;
+/// Style checks.
+mod lint;
+/// The methods of lists.
+mod lists;
+mod lox;
+/// The language server behind `rlox lsp`.
+#[cfg(feature = "lsp")]
+mod lsp;
+/// The map type and its methods.
+mod maps;
+/// The minifier behind `rlox minify`.
+mod minifier;
+/// The native functions scripts can call.
+mod natives;
+/// TCP sockets for scripts, see `natives::Natives::NET`.
+#[cfg(feature = "net")]
+mod net;
+/// The values Lox programs work with.
+pub mod object;
+/// Optimization passes over parsed programs.
+mod optimizer;
+/// Turns tokens into a program.
+mod parser;
+/// Interpreters prepared ahead of time, for hosts running many scripts.
+mod pool;
+/// Child processes for scripts, see `natives::Natives::PROCESS`.
+#[cfg(feature = "process")]
+mod process;
+/// Call counts and timing per function, behind `--profile`.
+mod profiler;
+mod recursion;
+/// Binds variables to their declarations.
+mod resolver;
+/// Signal handlers for scripts, see `natives::Natives::SIGNALS`.
+#[cfg(feature = "signals")]
+mod signals;
+mod stack;
+/// The syntax tree.
+mod statement;
+/// Counters of the allocations and calls of running programs, behind `--stats`.
+mod stats;
+/// The methods of strings.
+mod strings;
+/// Test scripts checking their output against `// expect:` comments, behind `rlox test`.
+mod test_runner;
+/// Helpers shared by the unit tests.
+#[cfg(test)]
+mod test_support;
+/// Callbacks scheduled with `setTimeout()`, `setInterval()` and `watch()`.
+mod timers;
+/// The tokens produced by the lexer.
+mod token;
+/// Printing statements as they run, behind `--trace`.
+mod trace;
+/// Checks optional type annotations.
+mod typechecker;
+/// Rust values exposed to Lox code.
+mod userdata;
+/// Where scripts are read from.
+mod vfs;
+/// Calling scripts back when files change, see `natives::Natives::WATCH`.
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use crate::ast_printer::{expr_to_string, print as print_ast, stmt_to_string, Style};
+pub use crate::convert::{FromLox, ToLox};
+pub use crate::debug::{CallFrame, Debugger};
+pub use crate::error::{LoxError, Result};
+pub use crate::functions::Function;
+pub use crate::incremental::Document;
+pub use crate::interpreter::{Interpreter, InterpreterConfig};
+pub use crate::lexer::{
+    attach_trivia, lex, lex_with_options, LexerOptions, StreamLexer, TriviaToken,
+};
+pub use crate::lox::{Lox, LoxThread};
+pub use crate::natives::Natives;
+pub use crate::parser::parse;
+pub use crate::pool::InterpreterPool;
+pub use crate::resolver::resolve;
+pub use crate::statement::{
+    Ast, Expr, ExprRef, MatchArm, Pattern, Program, Stmt, StmtRef, TypeAnnotation,
+};
+pub use crate::token::{OwnedToken, Span, Token, TokenType};
+pub use crate::userdata::Userdata;
+pub use crate::vfs::{FileSystem, MemoryFileSystem, OsFileSystem};
@@ -0,0 +1,522 @@
+use crate::statement::{Ast, Expr, ExprRef, Pattern, Program, Stmt, StmtRef};
+use crate::token::{Span, TokenType};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub message: String,
+    /// The code the lint is about, unknown for trees that were not parsed from source.
+    pub span: Option<Span>,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "warning: {} [{}]", self.message, self.rule)
+    }
+}
+
+struct Local {
+    used: bool,
+    is_variable: bool,
+    /// Where it was declared.
+    span: Option<Span>,
+}
+
+struct Linter<'a> {
+    ast: &'a Ast,
+    /// The variables, functions, classes and enums the program declares at the top level,
+    /// including those declared after the code being linted.
+    globals: HashSet<&'a str>,
+    scopes: Vec<HashMap<&'a str, Local>>,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(program: &'a Program) -> Self {
+        let ast = &program.ast;
+        let globals = program
+            .statements
+            .iter()
+            .filter_map(|&statement| match &ast[statement] {
+                Stmt::Var { name, .. }
+                | Stmt::Function { name, .. }
+                | Stmt::Class { name, .. }
+                | Stmt::Enum { name, .. } => Some(&**name),
+                _ => None,
+            })
+            .collect();
+        Self {
+            ast,
+            globals,
+            scopes: Vec::new(),
+            lints: Vec::new(),
+        }
+    }
+
+    fn lint(&mut self, statements: &[StmtRef]) -> Vec<Lint> {
+        self.lint_statements(statements);
+        std::mem::take(&mut self.lints)
+    }
+
+    fn lint_statements(&mut self, statements: &[StmtRef]) {
+        for &statement in statements {
+            self.lint_statement(statement);
+        }
+    }
+
+    /// The body of a case, which may be empty to do nothing for a value.
+    fn lint_case(&mut self, body: StmtRef) {
+        let ast = self.ast;
+        if let Stmt::Block { statements } = &ast[body] {
+            self.begin_scope();
+            self.lint_statements(statements);
+            self.end_scope();
+        }
+    }
+
+    fn lint_statement(&mut self, stmt: StmtRef) {
+        let ast = self.ast;
+        match &ast[stmt] {
+            Stmt::Expression { expression } | Stmt::Print { expression } => {
+                self.lint_expression(*expression);
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                if let Some(initializer) = *initializer {
+                    self.lint_expression(initializer);
+                }
+                self.declare(name, true, ast.stmt_span(stmt));
+            }
+            Stmt::Block { statements } => {
+                if statements.is_empty() {
+                    let span = ast.stmt_span(stmt);
+                    self.report("empty-block", "Empty block.".to_string(), span);
+                }
+                self.begin_scope();
+                self.lint_statements(statements);
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.constant_condition("if", *condition);
+                self.lint_expression(*condition);
+                self.lint_statement(*then_branch);
+                if let Some(else_branch) = *else_branch {
+                    self.lint_statement(else_branch);
+                }
+            }
+            Stmt::While { condition, body } => {
+                // `while (true)` is the idiomatic infinite loop, and what `for (;;)` desugars to.
+                if ast[*condition] != Expr::Boolean(true) {
+                    self.constant_condition("while", *condition);
+                }
+                self.lint_expression(*condition);
+                self.lint_statement(*body);
+            }
+            // `do { ... } while (false);` runs its body once on purpose
+            Stmt::DoWhile { body, condition } => {
+                self.lint_statement(*body);
+                self.lint_expression(*condition);
+            }
+            Stmt::Labeled { body, .. } => self.lint_statement(*body),
+            Stmt::Break { .. } => {}
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                self.lint_expression(*subject);
+                for (value, body) in cases {
+                    self.lint_expression(*value);
+                    self.lint_case(*body);
+                }
+                if let Some(default) = *default {
+                    self.lint_case(default);
+                }
+            }
+            Stmt::Function {
+                name,
+                parameters,
+                body,
+                ..
+            } => {
+                self.declare(name, false, ast.stmt_span(stmt));
+                self.lint_function(parameters, body, ast.stmt_span(stmt));
+            }
+            Stmt::Return { value } => {
+                if let Some(value) = *value {
+                    self.lint_expression(value);
+                }
+            }
+            Stmt::Enum { name, .. } => self.declare(name, false, ast.stmt_span(stmt)),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                self.declare(name, false, ast.stmt_span(stmt));
+                if let Some(superclass) = *superclass {
+                    self.lint_expression(superclass);
+                }
+                for &method in methods {
+                    if let Stmt::Function {
+                        parameters, body, ..
+                    } = &ast[method]
+                    {
+                        self.lint_function(parameters, body, ast.stmt_span(method));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lints a function or method, declared at `span`. Parameters have no span of their own.
+    fn lint_function(&mut self, parameters: &'a [Rc<str>], body: &[StmtRef], span: Option<Span>) {
+        self.begin_scope();
+        for parameter in parameters {
+            self.declare(parameter, false, span);
+        }
+        self.lint_statements(body);
+        self.end_scope();
+    }
+
+    fn lint_expression(&mut self, expr: ExprRef) {
+        let ast = self.ast;
+        match &ast[expr] {
+            Expr::Variable { name, .. } => self.use_variable(name),
+            Expr::Assign { value, .. } => self.lint_expression(*value),
+            Expr::Binary {
+                left,
+                token_type,
+                right,
+            } => {
+                if is_comparison(token_type) && same_value(ast, *left, *right) {
+                    self.report(
+                        "self-comparison",
+                        "Comparison of a value with itself.".to_string(),
+                        ast.expr_span(expr),
+                    );
+                }
+                self.lint_expression(*left);
+                self.lint_expression(*right);
+            }
+            Expr::Logical { left, right, .. } => {
+                self.lint_expression(*left);
+                self.lint_expression(*right);
+            }
+            Expr::Unary { right, .. } => self.lint_expression(*right),
+            Expr::Grouping { expression } => self.lint_expression(*expression),
+            Expr::Call { callee, arguments } => {
+                self.lint_expression(*callee);
+                for &argument in arguments {
+                    self.lint_expression(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.lint_expression(*object),
+            Expr::Set { object, value, .. } => {
+                self.lint_expression(*object);
+                self.lint_expression(*value);
+            }
+            Expr::List { elements } => {
+                for &element in elements {
+                    self.lint_expression(element);
+                }
+            }
+            Expr::Map { entries } => {
+                for &(key, value) in entries {
+                    self.lint_expression(key);
+                    self.lint_expression(value);
+                }
+            }
+            Expr::Match { subject, arms } => {
+                self.lint_expression(*subject);
+                for arm in arms {
+                    self.begin_scope();
+                    match &arm.pattern {
+                        Pattern::Value(value) => self.lint_expression(*value),
+                        Pattern::Class { class, binding, .. } => {
+                            self.lint_expression(*class);
+                            // like a parameter, a binding may go unused
+                            self.declare(binding, false, ast.expr_span(*class));
+                        }
+                        Pattern::Wildcard => {}
+                    }
+                    self.lint_expression(arm.body);
+                    self.end_scope();
+                }
+            }
+            Expr::This { .. }
+            | Expr::Super { .. }
+            | Expr::Nil
+            | Expr::Boolean(_)
+            | Expr::Number(_)
+            | Expr::String(_) => {}
+        }
+    }
+
+    fn constant_condition(&mut self, statement: &str, condition: ExprRef) {
+        if is_literal(self.ast, &self.ast[condition]) {
+            self.report(
+                "constant-condition",
+                format!(
+                    "The condition of this '{}' statement is constant.",
+                    statement
+                ),
+                self.ast.expr_span(condition),
+            );
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            let mut unused: Vec<_> = scope
+                .into_iter()
+                .filter(|(_, local)| local.is_variable && !local.used)
+                .map(|(name, local)| (local.span.map(|span| span.start), name, local.span))
+                .collect();
+            unused.sort_unstable_by_key(|&(start, name, _)| (start, name));
+            for (_, name, span) in unused {
+                self.report(
+                    "unused-variable",
+                    format!("Local variable '{}' is never read.", name),
+                    span,
+                );
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &'a str, is_variable: bool, span: Option<Span>) {
+        let depth = self.scopes.len();
+        if depth == 0 {
+            return;
+        }
+        let shadowed = if self.scopes[..depth - 1]
+            .iter()
+            .any(|scope| scope.contains_key(name))
+        {
+            Some("a variable of an enclosing scope")
+        } else if self.globals.contains(name) {
+            Some("a global")
+        } else {
+            None
+        };
+        if let Some(shadowed) = shadowed {
+            self.report(
+                "shadowing",
+                format!("'{}' shadows {}.", name, shadowed),
+                span,
+            );
+        }
+        self.scopes[depth - 1].insert(
+            name,
+            Local {
+                used: false,
+                is_variable,
+                span,
+            },
+        );
+    }
+
+    fn use_variable(&mut self, name: &str) {
+        if let Some(local) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+        {
+            local.used = true;
+        }
+    }
+
+    fn report(&mut self, rule: &'static str, message: String, span: Option<Span>) {
+        self.lints.push(Lint {
+            rule,
+            message,
+            span,
+        });
+    }
+}
+
+fn is_comparison(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::EqualEqual
+            | TokenType::BangEqual
+            | TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+    )
+}
+
+fn is_literal(ast: &Ast, expr: &Expr) -> bool {
+    match expr {
+        Expr::Nil | Expr::Boolean(_) | Expr::Number(_) | Expr::String(_) => true,
+        Expr::Grouping { expression } => is_literal(ast, &ast[*expression]),
+        _ => false,
+    }
+}
+
+/// Whether both expressions name the same variable or property, ignoring expression ids.
+fn same_value(ast: &Ast, left: ExprRef, right: ExprRef) -> bool {
+    match (&ast[left], &ast[right]) {
+        (Expr::Variable { name: a, .. }, Expr::Variable { name: b, .. }) => a == b,
+        (Expr::This { .. }, Expr::This { .. }) => true,
+        (
+            Expr::Get {
+                object: a,
+                name: a_name,
+                ..
+            },
+            Expr::Get {
+                object: b,
+                name: b_name,
+                ..
+            },
+        ) => a_name == b_name && same_value(ast, *a, *b),
+        (Expr::Grouping { expression }, _) => same_value(ast, *expression, right),
+        (_, Expr::Grouping { expression }) => same_value(ast, left, *expression),
+        _ => false,
+    }
+}
+
+/// Style checks on top of what the resolver enforces.
+pub fn lint(program: &Program) -> Vec<Lint> {
+    let mut linter = Linter::new(program);
+    linter.lint(&program.statements)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::lint;
+
+    use crate::lexer;
+    use crate::parser;
+
+    fn rules(source: &'static str) -> Vec<&'static str> {
+        let (tokens, lexer_errors) = lexer::lex(source);
+        assert_eq!(lexer_errors.len(), 0);
+        let (program, parser_errors) = parser::parse(&tokens);
+        assert_eq!(parser_errors.len(), 0);
+
+        lint(&program).into_iter().map(|lint| lint.rule).collect()
+    }
+
+    #[test]
+    fn clean_code() {
+        let source = r#"
+            var a = 1;
+            fun add(x, y) {
+                return x + y;
+            }
+            for (var i = 0; i < 10; i = i + 1) {
+                print add(i, a);
+            }
+        "#;
+        assert_eq!(rules(source), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn shadowing() {
+        let source = r#"
+            {
+                var a = 1;
+                {
+                    var a = 2;
+                    print a;
+                }
+                print a;
+            }
+        "#;
+        assert_eq!(rules(source), vec!["shadowing"]);
+    }
+
+    #[test]
+    fn shadowing_globals() {
+        let source = r#"
+            var a = 1;
+            fun foo(a) {
+                var later = 2;
+                return a + later;
+            }
+            class Bar {
+                baz(foo) { return foo; }
+            }
+            var later = 3;
+        "#;
+        assert_eq!(rules(source), vec!["shadowing", "shadowing", "shadowing"]);
+    }
+
+    #[test]
+    fn positions() {
+        let source = "var a = 1;\nfun foo() {\n    var unused = a;\n    if (true) {}\n}\n";
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        let positions: Vec<_> = lint(&program)
+            .into_iter()
+            .map(|lint| (lint.rule, lint.span.unwrap().position(source)))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                ("constant-condition", (4, 9)),
+                ("empty-block", (4, 15)),
+                ("unused-variable", (3, 5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_variable() {
+        let source = r#"
+            fun foo() {
+                var unused = 1;
+            }
+        "#;
+        assert_eq!(rules(source), vec!["unused-variable"]);
+    }
+
+    #[test]
+    fn empty_block() {
+        let source = "if (clock() > 0) {}";
+        assert_eq!(rules(source), vec!["empty-block"]);
+    }
+
+    #[test]
+    fn constant_condition() {
+        let source = r#"
+            if (false) print 1;
+            while (nil) print 2;
+            while (true) print 3;
+        "#;
+        assert_eq!(
+            rules(source),
+            vec!["constant-condition", "constant-condition"]
+        );
+    }
+
+    #[test]
+    fn self_comparison() {
+        let source = r#"
+            var a = 1;
+            print a == a;
+            print (a) < a;
+            print a == 1;
+        "#;
+        assert_eq!(rules(source), vec!["self-comparison", "self-comparison"]);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::error::{LoxError, Result};
+use crate::functions::Function;
+use crate::gc::Marker;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+use std::cell::RefCell;
+use std::mem::size_of;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    Push,
+    Pop,
+    Insert,
+    RemoveAt,
+    Len,
+    Contains,
+}
+
+/// The methods of lists, with their names and arities.
+const METHODS: [(Method, &str, usize); 6] = [
+    (Method::Push, "push", 1),
+    (Method::Pop, "pop", 0),
+    (Method::Insert, "insert", 2),
+    (Method::RemoveAt, "removeAt", 1),
+    (Method::Len, "len", 0),
+    (Method::Contains, "contains", 1),
+];
+
+/// A method of a list, bound to the list it was looked up on, as in `list.push(1)`.
+pub struct ListMethod {
+    list: Rc<RefCell<Vec<Object>>>,
+    method: Method,
+    name: &'static str,
+    arity: usize,
+}
+
+impl ListMethod {
+    /// The method called `name` of `list`, if lists have one.
+    pub fn new(list: &Rc<RefCell<Vec<Object>>>, name: &str) -> Option<Self> {
+        let &(method, name, arity) = METHODS.iter().find(|(_, method, _)| *method == name)?;
+        Some(Self {
+            list: Rc::clone(list),
+            method,
+            name,
+            arity,
+        })
+    }
+
+    fn error(&self, message: String) -> LoxError {
+        LoxError::InterpreterError(format!("List method '{}' {}", self.name, message).into())
+    }
+
+    /// `index` as a position before `end`.
+    fn index(&self, index: &Object, end: usize) -> Result<usize> {
+        match index {
+            Object::Number(number) if number.fract() == 0.0 && *number >= 0.0 => {
+                if *number < end as f64 {
+                    Ok(*number as usize)
+                } else {
+                    Err(self.error(format!(
+                        "got index {} for a list of {} elements.",
+                        index,
+                        self.list.borrow().len()
+                    )))
+                }
+            }
+            _ => Err(self.error(format!(
+                "expects a whole number index, but got '{}'.",
+                index
+            ))),
+        }
+    }
+}
+
+impl Function for ListMethod {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        if arguments.len() != self.arity {
+            let plural = if self.arity == 1 { "" } else { "s" };
+            return Err(self.error(format!(
+                "expects {} argument{} but got {}.",
+                self.arity,
+                plural,
+                arguments.len()
+            )));
+        }
+        let len = self.list.borrow().len();
+        match self.method {
+            Method::Push => {
+                self.list.borrow_mut().push(arguments[0].clone());
+                interpreter.grow_heap(size_of::<Object>())?;
+                Ok(Object::Nil)
+            }
+            Method::Pop => self
+                .list
+                .borrow_mut()
+                .pop()
+                .ok_or_else(|| self.error("was called on an empty list.".to_string())),
+            Method::Insert => {
+                let index = self.index(&arguments[0], len + 1)?;
+                self.list.borrow_mut().insert(index, arguments[1].clone());
+                interpreter.grow_heap(size_of::<Object>())?;
+                Ok(Object::Nil)
+            }
+            Method::RemoveAt => {
+                let index = self.index(&arguments[0], len)?;
+                Ok(self.list.borrow_mut().remove(index))
+            }
+            Method::Len => Ok(Object::Number(len as f64)),
+            Method::Contains => Ok(Object::Boolean(self.list.borrow().contains(&arguments[0]))),
+        }
+    }
+
+    fn trace(&self, marker: &mut Marker) {
+        marker.mark_object(&Object::List(Rc::clone(&self.list)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::object::Object;
+    use crate::test_support::eval;
+
+    #[test]
+    fn methods() {
+        let source = r#"
+            var list = [1, 2];
+            list.push(3);
+            list.insert(0, "zero");
+            var popped = list.pop();
+            var removed = list.removeAt(1);
+            list.len() * 100 + popped * 10 + removed;
+        "#;
+        assert_eq!(eval(source), Ok(Object::Number(231.0)));
+        assert_eq!(
+            eval("[1, \"a\"].contains(\"a\");"),
+            Ok(Object::Boolean(true))
+        );
+        assert_eq!(eval("[[]].contains([]);"), Ok(Object::Boolean(false)));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |message: &str| Err(LoxError::InterpreterError(message.to_string().into()));
+        assert_eq!(
+            eval("[].push();"),
+            error("List method 'push' expects 1 argument but got 0.")
+        );
+        assert_eq!(
+            eval("[].pop();"),
+            error("List method 'pop' was called on an empty list.")
+        );
+        assert_eq!(
+            eval("[1].removeAt(1);"),
+            error("List method 'removeAt' got index 1 for a list of 1 elements.")
+        );
+        assert_eq!(
+            eval("[1].insert(0.5, 2);"),
+            error("List method 'insert' expects a whole number index, but got '0.5'.")
+        );
+        assert_eq!(eval("[].size;"), error("Undefined property size."));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::error::{LoxError, Result};
+use crate::interpreter::{Interpreter, InterpreterConfig};
+use crate::object::Object;
+use crate::statement::Program;
+use crate::{lexer, parser, resolver};
+
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Runs Lox source code in one call, for hosts embedding Lox as a scripting language. Globals
+/// defined by one call to `eval` are visible to the next ones.
+///
+/// ```
+/// let mut lox = rlox::Lox::new();
+/// lox.eval("fun square(x) { return x * x; }")?;
+/// assert_eq!(lox.eval("square(7);")?, rlox::object::Object::Number(49.0));
+/// # Ok::<(), rlox::LoxError>(())
+/// ```
+#[derive(Default)]
+pub struct Lox {
+    interpreter: Interpreter,
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: InterpreterConfig) -> Self {
+        Self {
+            interpreter: Interpreter::with_config(config),
+        }
+    }
+
+    /// Lexes, parses, resolves and runs `source`. Returns the value of the last statement if
+    /// that is an expression statement (`1 + 2;`), nil otherwise. Fails with the first error of
+    /// whichever stage failed.
+    pub fn eval(&mut self, source: &str) -> Result<Object> {
+        let (tokens, lexer_errors) = lexer::lex(source);
+        if let Some(error) = lexer_errors.into_iter().next() {
+            return Err(error);
+        }
+        let (program, parser_errors) = parser::parse(&tokens);
+        if let Some(error) = parser_errors.into_iter().next() {
+            return Err(error);
+        }
+        self.run(program)
+    }
+
+    /// Reads `path` from the interpreter's file system and runs it like `eval`.
+    pub fn eval_file(&mut self, path: &str) -> Result<Object> {
+        let source = self
+            .interpreter
+            .file_system()
+            .read_to_string(path)
+            .map_err(|error| {
+                LoxError::InterpreterError(
+                    format!("Could not read file '{}': {}", path, error).into(),
+                )
+            })?;
+        self.eval(&source)
+    }
+
+    /// Runs a program in the format of the `json` module, like `eval` runs source code.
+    #[cfg(feature = "json")]
+    pub fn interpret_json(&mut self, json: &str) -> Result<Object> {
+        self.run(crate::json::from_json(json)?)
+    }
+
+    fn run(&mut self, mut program: Program) -> Result<Object> {
+        // functions of earlier programs may still refer to the ids they numbered
+        program
+            .ast
+            .renumber_ids(self.interpreter.next_expression_id());
+        self.interpreter.add_scopes(resolver::resolve(&program)?);
+        self.interpreter.interpret(program)
+    }
+
+    /// The interpreter running the code, e.g. to configure it.
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Lox) + Send>;
+
+/// A [`Lox`] living on a thread of its own, so hosts can run scripts off their main thread.
+/// Lox values (and errors, which can hold them) cannot leave that thread, jobs sent to it return
+/// plain Rust values instead.
+///
+/// ```
+/// use rlox::FromLox;
+///
+/// let lox = rlox::LoxThread::spawn();
+/// lox.eval("var answer = 6 * 7;")?;
+/// assert_eq!(lox.eval("answer;")?, "42");
+///
+/// let doubled = lox.run(|lox| f64::from_lox(lox.eval("answer * 2;").ok()?).ok());
+/// assert_eq!(doubled, Some(84.0));
+/// # Ok::<(), String>(())
+/// ```
+pub struct LoxThread {
+    jobs: Option<Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LoxThread {
+    pub fn spawn() -> Self {
+        let (jobs, received) = mpsc::channel::<Job>();
+        let thread = thread::Builder::new()
+            .name("lox".into())
+            .spawn(move || {
+                let mut lox = Lox::new();
+                for job in received {
+                    job(&mut lox);
+                }
+            })
+            .expect("Could not start interpreter thread");
+        Self {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    /// Evaluates `source` like [`Lox::eval`], returning the printed result or error message.
+    pub fn eval(&self, source: &str) -> std::result::Result<String, String> {
+        let source = source.to_string();
+        self.run(move |lox| {
+            lox.eval(&source)
+                .map(|value| value.to_string())
+                .map_err(|error| error.to_string())
+        })
+    }
+
+    /// Runs `job` on the interpreter thread and waits for its result. Globals defined by one job
+    /// are visible to the next ones.
+    ///
+    /// # Panics
+    ///
+    /// If an earlier job panicked, which stopped the interpreter thread.
+    pub fn run<R: Send + 'static>(&self, job: impl FnOnce(&mut Lox) -> R + Send + 'static) -> R {
+        let (result, received) = mpsc::channel();
+        let job: Job = Box::new(move |lox| {
+            // the caller is blocked on receiving it, so this can't fail
+            let _ = result.send(job(lox));
+        });
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .ok()
+            .and_then(|_| received.recv().ok())
+            .expect("The interpreter thread stopped")
+    }
+}
+
+impl Drop for LoxThread {
+    fn drop(&mut self) {
+        // closing the channel ends the thread once it ran all jobs
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Lox, LoxThread};
+
+    use crate::error::LoxError;
+    use crate::object::Object;
+    use crate::vfs::MemoryFileSystem;
+
+    #[test]
+    fn last_expression() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.eval("1 + 2;"), Ok(Object::Number(3.0)));
+        assert_eq!(lox.eval("1 + 2; print 3;"), Ok(Object::Nil));
+        assert_eq!(lox.eval(""), Ok(Object::Nil));
+    }
+
+    #[test]
+    fn keeps_state() {
+        let mut lox = Lox::new();
+        lox.eval("var greeting = \"hello\";").unwrap();
+        lox.eval("class Greeter { greet(name) { return greeting + \" \" + name; } }")
+            .unwrap();
+        assert_eq!(
+            lox.eval("Greeter().greet(\"world\");"),
+            Ok(Object::String("hello world".into()))
+        );
+    }
+
+    #[test]
+    fn closures_outlive_their_program() {
+        let mut lox = Lox::new();
+        lox.eval("fun counter() { var count = 0; fun next() { count = count + 1; return count; } return next; }")
+            .unwrap();
+        lox.eval("var next = counter();").unwrap();
+        // later programs number their nodes after those of the earlier ones
+        lox.eval("{ var a = 10; var b = 20; }").unwrap();
+        assert_eq!(lox.eval("next(); next();"), Ok(Object::Number(2.0)));
+    }
+
+    #[test]
+    fn results_survive_collections() {
+        let mut lox = Lox::new();
+        let list = lox.eval("[4, 5, 6];").unwrap();
+        let map = lox
+            .eval("fun make() { var map = {}; map.set(\"list\", [7]); return map; } make();")
+            .unwrap();
+        // enough allocations for a collection
+        lox.eval("for (var i = 0; i < 2000; i = i + 1) { var garbage = [i]; }")
+            .unwrap();
+        assert_eq!(list.to_string(), "[4, 5, 6]");
+        assert_eq!(map.to_string(), "{list: [7]}");
+    }
+
+    #[test]
+    fn eval_file() {
+        let mut files = MemoryFileSystem::new();
+        files.insert("answer.lox", "var answer = 42; answer;");
+        let mut lox = Lox::new();
+        lox.interpreter().set_file_system(files);
+
+        assert_eq!(lox.eval_file("answer.lox"), Ok(Object::Number(42.0)));
+        assert_eq!(
+            lox.eval_file("question.lox"),
+            Err(LoxError::InterpreterError(
+                "Could not read file 'question.lox': No such file in memory".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn thread() {
+        let lox = LoxThread::spawn();
+        lox.eval("var count = 0;").unwrap();
+        let counts: Vec<_> = (0..3).map(|_| lox.eval("count = count + 1;")).collect();
+        assert_eq!(counts, [Ok("1".into()), Ok("2".into()), Ok("3".into())]);
+        assert_eq!(
+            lox.eval("count();"),
+            Err("Can only call functions and classes.".into())
+        );
+        assert!(lox.run(|lox| lox.eval("count;").is_ok()));
+    }
+
+    #[test]
+    fn errors() {
+        let mut lox = Lox::new();
+        assert!(matches!(lox.eval("var;"), Err(LoxError::ParserError(..))));
+        assert_eq!(
+            lox.eval("return 1;"),
+            Err(LoxError::ResolverError(
+                "Cannot return from top-level code."
+            ))
+        );
+        assert_eq!(
+            lox.eval("-nil;"),
+            Err(LoxError::InterpreterError(
+                "Operand must be a number, but got 'nil'".into()
+            ))
+        );
+    }
+}
@@ -0,0 +1,606 @@
+//! A language server speaking the Language Server Protocol over stdin and stdout, started with
+//! `rlox lsp`. It reports the errors of the lexer, parser and resolver as diagnostics, lists
+//! the top-level declarations of a document as symbols, and finds the declaration of a variable
+//! for go-to-definition and hover.
+//!
+//! Clients send edits rather than whole documents, which only the statements they touch are
+//! parsed again for, see `incremental::Document`.
+//!
+//! Definitions come from the resolver, see `resolver::resolve_for_editing`, so they follow the
+//! scopes the interpreter uses. Errors the parser or resolver cannot place cover their line.
+
+use crate::error::LoxError;
+use crate::framing::{read_message, write_message};
+use crate::incremental::Document;
+use crate::resolver::Definition;
+use crate::statement::{Ast, Expr, ExprRef, Program, Stmt};
+use crate::token::{Span, TokenType};
+use crate::{ast_printer, doc, lexer, resolver};
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// `SymbolKind`s of the protocol.
+const CLASS: u32 = 5;
+const ENUM: u32 = 10;
+const FUNCTION: u32 = 12;
+const VARIABLE: u32 = 13;
+const CONSTANT: u32 = 14;
+
+/// Answers the messages read from `input` until the client sends `exit` or closes the stream.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        documents: HashMap::new(),
+        output: &mut output,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct Server<'a, W: Write> {
+    documents: HashMap<String, Document>,
+    output: &'a mut W,
+}
+
+impl<'a, W: Write> Server<'a, W> {
+    /// Returns false once the client asked the server to exit.
+    fn handle(&mut self, message: &Value) -> io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    // changes are sent as edits of ranges
+                    "textDocumentSync": 2,
+                    "documentSymbolProvider": true,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                },
+                "serverInfo": {"name": "rlox"},
+            }),
+            "shutdown" => Value::Null,
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                let document = Document::new(text.to_string());
+                return self.update(uri, document).map(|_| true);
+            }
+            "textDocument/didChange" => {
+                let mut document = match self.documents.remove(uri) {
+                    Some(document) => document,
+                    None => return Ok(true),
+                };
+                let changes = params["contentChanges"].as_array();
+                for change in changes.into_iter().flatten() {
+                    let text = change["text"].as_str().unwrap_or_default();
+                    match change.get("range") {
+                        Some(range) => {
+                            let start = offset(document.source(), &range["start"]);
+                            let end = offset(document.source(), &range["end"]).max(start);
+                            // `offset` only returns character boundaries within the source
+                            document
+                                .edit(start..end, text)
+                                .expect("Edit ranges lie on character boundaries");
+                        }
+                        None => document = Document::new(text.to_string()),
+                    }
+                }
+                return self.update(uri, document).map(|_| true);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return self.publish_diagnostics(uri, vec![]).map(|_| true);
+            }
+            "textDocument/documentSymbol" => {
+                let text = self.documents.get(uri).map_or("", Document::source);
+                Value::Array(symbols(uri, text))
+            }
+            "textDocument/definition" => self
+                .documents
+                .get(uri)
+                .and_then(|document| {
+                    let text = document.source();
+                    let program = document.program();
+                    let definition = definition_at(program, offset(text, &params["position"]))?;
+                    let span = definition_span(text, &program.ast, definition)?;
+                    Some(json!({"uri": uri, "range": range(text, span)}))
+                })
+                .unwrap_or(Value::Null),
+            "textDocument/hover" => self
+                .documents
+                .get(uri)
+                .and_then(|document| {
+                    let text = document.source();
+                    let program = document.program();
+                    let definition = definition_at(program, offset(text, &params["position"]))?;
+                    let contents = describe(text, &program.ast, definition);
+                    Some(json!({"contents": {"kind": "markdown", "value": contents}}))
+                })
+                .unwrap_or(Value::Null),
+            _ => {
+                if let Some(id) = message.get("id") {
+                    let error = json!({
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("Unsupported method '{}'", method),
+                    });
+                    self.send(&json!({"jsonrpc": "2.0", "id": id, "error": error}))?;
+                }
+                return Ok(true);
+            }
+        };
+        if let Some(id) = message.get("id") {
+            self.send(&json!({"jsonrpc": "2.0", "id": id, "result": result}))?;
+        }
+        Ok(true)
+    }
+
+    fn update(&mut self, uri: &str, document: Document) -> io::Result<()> {
+        let diagnostics = diagnostics(&document);
+        self.documents.insert(uri.to_string(), document);
+        self.publish_diagnostics(uri, diagnostics)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        }))
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        write_message(self.output, message)
+    }
+}
+
+/// The range of the whole line `line` (counting from 1, as the lexer does) of `text`.
+fn line_range(text: &str, line: u32) -> Value {
+    let index = line.saturating_sub(1);
+    let length = text
+        .lines()
+        .nth(index as usize)
+        .map_or(0, |line| line.encode_utf16().count());
+    json!({
+        "start": {"line": index, "character": 0},
+        "end": {"line": index, "character": length},
+    })
+}
+
+/// The `Range` of the bytes `span` of `text`.
+fn range(text: &str, span: Span) -> Value {
+    json!({"start": position(text, span.start), "end": position(text, span.end)})
+}
+
+/// The `Position` of byte `offset` of `text`.
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+/// The byte offset of the `Position` `position` in `text`. Positions past the end of a line or
+/// of the text are moved back to it.
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+    let line_start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+    let line_text = text[line_start..].split('\n').next().unwrap_or_default();
+    // characters are counted in UTF-16 code units
+    let mut units = 0;
+    for (index, char) in line_text.char_indices() {
+        if units >= character {
+            return line_start + index;
+        }
+        units += char.len_utf16();
+    }
+    line_start + line_text.len()
+}
+
+fn diagnostics(document: &Document) -> Vec<Value> {
+    let text = document.source();
+    let resolver_error = if document.errors().is_empty() {
+        resolver::resolve_for_editing(document.program()).err()
+    } else {
+        None
+    };
+    let last_line = text.matches('\n').count() as u32 + 1;
+    document
+        .errors()
+        .iter()
+        .zip(document.error_spans().iter().copied())
+        .chain(resolver_error.iter().map(|(error, span)| (error, *span)))
+        .map(|(error, span)| {
+            let range = match (span, error) {
+                (Some(span), _) => range(text, span),
+                // errors at the end of the file have no line of their own
+                (None, LoxError::ParserError(None, _)) => line_range(text, last_line),
+                (None, _) => line_range(text, error.line().unwrap_or(1)),
+            };
+            json!({
+                "range": range,
+                "severity": 1,
+                "source": "rlox",
+                "message": error.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The declaration of the variable whose name is at byte `offset`, if the program resolves.
+fn definition_at(program: &Program, offset: usize) -> Option<Definition> {
+    let resolution = resolver::resolve_for_editing(program).ok()?;
+    let ast = &program.ast;
+    resolution
+        .references
+        .into_iter()
+        .find(|&(expr, _)| {
+            name_span(ast, expr).is_some_and(|span| span.start <= offset && offset <= span.end)
+        })
+        .map(|(_, definition)| definition)
+}
+
+/// The name a variable or assignment expression starts with.
+fn name_span(ast: &Ast, expr: ExprRef) -> Option<Span> {
+    let span = ast.expr_span(expr)?;
+    match &ast[expr] {
+        Expr::Variable { name, .. } | Expr::Assign { name, .. } => {
+            Some(Span::new(span.start, span.start + name.len()))
+        }
+        _ => None,
+    }
+}
+
+/// The name `definition` declares in `text`.
+fn definition_span(text: &str, ast: &Ast, definition: Definition) -> Option<Span> {
+    // the tokens of `text` from byte `start` to `end`, with their spans in `text`
+    let tokens = |start: usize, end: usize| {
+        let (tokens, _) = lexer::lex(&text[start..end]);
+        tokens
+            .into_iter()
+            .map(move |token| {
+                (
+                    token.token_type,
+                    token.lexeme,
+                    token.span.shift(start as isize),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    match definition {
+        Definition::Statement(stmt) => {
+            let name = match &ast[stmt] {
+                Stmt::Var { name, .. }
+                | Stmt::Function { name, .. }
+                | Stmt::Class { name, .. }
+                | Stmt::Enum { name, .. } => name,
+                _ => return None,
+            };
+            let span = ast.stmt_span(stmt)?;
+            tokens(span.start, span.end)
+                .into_iter()
+                .find(|(token_type, lexeme, _)| {
+                    *token_type == TokenType::Identifier && *lexeme == &**name
+                })
+                .map(|(_, _, span)| span)
+        }
+        Definition::Parameter(stmt, index) => {
+            let span = ast.stmt_span(stmt)?;
+            // the names follow the '(' and the commas, their types follow them
+            let mut parameter = 0;
+            let mut at_name = true;
+            let tokens = tokens(span.start, span.end);
+            let parameters = tokens
+                .iter()
+                .skip_while(|(token_type, ..)| *token_type != TokenType::LeftParen)
+                .skip(1);
+            for (token_type, _, span) in parameters {
+                match token_type {
+                    TokenType::Identifier if at_name => {
+                        if parameter == index {
+                            return Some(*span);
+                        }
+                        parameter += 1;
+                        at_name = false;
+                    }
+                    TokenType::Comma => at_name = true,
+                    TokenType::RightParen => break,
+                    _ => {}
+                }
+            }
+            None
+        }
+        Definition::Binding(class) => {
+            // `Class(binding)`
+            let start = ast.expr_span(class)?.end;
+            let end = start + text[start..].find(')')?;
+            tokens(start, end)
+                .into_iter()
+                .find(|(token_type, ..)| *token_type == TokenType::Identifier)
+                .map(|(_, _, span)| span)
+        }
+    }
+}
+
+/// Markdown showing what `definition` declares, with its doc comment.
+fn describe(text: &str, ast: &Ast, definition: Definition) -> String {
+    let code = |code: String| format!("```lox\n{}\n```", code);
+    match definition {
+        Definition::Statement(stmt) => {
+            let signature = match &ast[stmt] {
+                Stmt::Var {
+                    name,
+                    type_annotation,
+                    constant,
+                    ..
+                } => {
+                    let keyword = if *constant { "const" } else { "var" };
+                    match type_annotation {
+                        Some(annotation) => format!("{} {}: {}", keyword, name, annotation.name),
+                        None => format!("{} {}", keyword, name),
+                    }
+                }
+                Stmt::Function { .. } => format!("fun {}", ast_printer::signature(ast, stmt)),
+                Stmt::Class {
+                    name, superclass, ..
+                } => match superclass {
+                    Some(superclass) => format!(
+                        "class {} < {}",
+                        name,
+                        ast_printer::expr_to_string(ast, *superclass)
+                    ),
+                    None => format!("class {}", name),
+                },
+                Stmt::Enum { name, members, .. } => {
+                    format!("enum {} {{ {} }}", name, members.join(", "))
+                }
+                _ => unreachable!(),
+            };
+            let lines: Vec<_> = text.lines().collect();
+            let doc = ast
+                .line(stmt)
+                .and_then(|line| doc::comment_above(&lines, line));
+            match doc {
+                Some(doc) => format!("{}\n\n{}", code(signature), doc),
+                None => code(signature),
+            }
+        }
+        Definition::Parameter(stmt, index) => match &ast[stmt] {
+            Stmt::Function {
+                name, parameters, ..
+            } => format!(
+                "{}\n\nParameter of `{}`.",
+                code(parameters[index].to_string()),
+                name
+            ),
+            _ => unreachable!(),
+        },
+        Definition::Binding(class) => {
+            let binding = definition_span(text, ast, definition)
+                .map_or("", |span| &text[span.start..span.end]);
+            format!(
+                "{}\n\nBound by a `match` arm to instances of `{}`.",
+                code(binding.to_string()),
+                ast_printer::expr_to_string(ast, class)
+            )
+        }
+    }
+}
+
+/// The classes, functions and variables declared at the top level of `text`.
+fn symbols(uri: &str, text: &str) -> Vec<Value> {
+    let (tokens, _) = lexer::lex(text);
+    let mut symbols = vec![];
+    let mut depth = 0;
+    for pair in tokens.windows(2) {
+        let (keyword, name) = (&pair[0], &pair[1]);
+        match keyword.token_type {
+            TokenType::LeftBrace => depth += 1,
+            TokenType::RightBrace => depth -= 1,
+            _ => {}
+        }
+        let kind = match keyword.token_type {
+            TokenType::Class => CLASS,
+            TokenType::Enum => ENUM,
+            TokenType::Fun => FUNCTION,
+            TokenType::Var => VARIABLE,
+            TokenType::Const => CONSTANT,
+            _ => continue,
+        };
+        if depth == 0 && name.token_type == TokenType::Identifier {
+            symbols.push(json!({
+                "name": name.lexeme,
+                "kind": kind,
+                "location": {"uri": uri, "range": range(text, name.span)},
+            }));
+        }
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{offset, serve};
+    use crate::framing;
+
+    use serde_json::{json, Value};
+
+    fn range(start_line: u32, start: u32, end_line: u32, end: u32) -> Value {
+        json!({
+            "start": {"line": start_line, "character": start},
+            "end": {"line": end_line, "character": end},
+        })
+    }
+
+    #[test]
+    fn session() {
+        let uri = "file:///test.lox";
+        let input = [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": uri, "text": "var a = 1;\nvar = 2;\n"},
+            }}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": uri},
+                "contentChanges": [{"text": "class A {\n  m() { var b; }\n}\nfun f() {}\n"}],
+            }}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": uri},
+                "contentChanges": [
+                    {"range": range(3, 4, 3, 5), "text": "g"},
+                    {"range": range(4, 0, 4, 0), "text": "var = 3;\n"},
+                ],
+            }}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/documentSymbol", "params": {
+                "textDocument": {"uri": uri},
+            }}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "textDocument/completion", "params": {}}),
+            json!({"jsonrpc": "2.0", "id": 4, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ];
+        let input = framing::frame_all(&input);
+        let mut output = vec![];
+        serve(&input[..], &mut output).unwrap();
+        let responses = framing::read_all(&output);
+        assert_eq!(responses.len(), 7);
+
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["textDocumentSync"],
+            2
+        );
+
+        let diagnostics = &responses[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        // the '=' where the name should be
+        assert_eq!(diagnostics[0]["range"], range(1, 4, 1, 5));
+
+        assert_eq!(responses[2]["params"]["diagnostics"], json!([]));
+        let diagnostics = &responses[3]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["range"], range(4, 4, 4, 5));
+
+        let symbols: Vec<_> = responses[4]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|symbol| (symbol["name"].clone(), symbol["kind"].clone()))
+            .collect();
+        assert_eq!(symbols, [(json!("A"), json!(5)), (json!("g"), json!(12))]);
+        assert_eq!(
+            responses[4]["result"][1]["location"]["range"],
+            range(3, 4, 3, 5)
+        );
+
+        assert_eq!(responses[5]["error"]["code"], -32601);
+        assert_eq!(responses[6]["result"], Value::Null);
+    }
+
+    #[test]
+    fn diagnostics() {
+        let uri = "file:///test.lox";
+        let text = "print 1;\nprint 1 @ 2;\nfun f() {\n  const c = 1;\n  c = 2;\n}\n";
+        let input = [
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": uri, "text": text},
+            }}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": uri},
+                "contentChanges": [{"range": range(1, 8, 1, 12), "text": ";"}],
+            }}),
+        ];
+        let input = framing::frame_all(&input);
+        let mut output = vec![];
+        serve(&input[..], &mut output).unwrap();
+        let responses = framing::read_all(&output);
+
+        let ranges = |response: &Value| -> Vec<Value> {
+            let diagnostics = response["params"]["diagnostics"].as_array().unwrap();
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic["range"].clone())
+                .collect()
+        };
+        // the lexer's bad character, where the parser stopped and the end of the file
+        assert_eq!(
+            ranges(&responses[0]),
+            [range(1, 8, 1, 9), range(1, 10, 1, 11), range(6, 0, 6, 0)]
+        );
+        // the assignment the resolver rejects
+        assert_eq!(ranges(&responses[1]), [range(4, 2, 4, 7)]);
+    }
+
+    #[test]
+    fn definition_and_hover() {
+        let uri = "file:///test.lox";
+        let text =
+            "/// Adds the step.\nfun inc(n: Number) {\n  return n + step;\n}\nvar step = 1;\n\
+            print inc(step);\nclass A {}\nprint match (A()) { A(a) => a, _ => nil };\n";
+        let request = |id: u32, method: &str, line: u32, character: u32| {
+            json!({"jsonrpc": "2.0", "id": id, "method": method, "params": {
+                "textDocument": {"uri": uri},
+                "position": {"line": line, "character": character},
+            }})
+        };
+        let input = [
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": uri, "text": text},
+            }}),
+            request(1, "textDocument/definition", 2, 9),
+            request(2, "textDocument/definition", 2, 15),
+            request(3, "textDocument/definition", 5, 8),
+            request(4, "textDocument/definition", 7, 28),
+            request(5, "textDocument/definition", 4, 0),
+            request(6, "textDocument/hover", 5, 6),
+            request(7, "textDocument/hover", 2, 9),
+            request(8, "textDocument/hover", 7, 28),
+        ];
+        let input = framing::frame_all(&input);
+        let mut output = vec![];
+        serve(&input[..], &mut output).unwrap();
+        let responses = framing::read_all(&output);
+
+        let definition = |index: usize| responses[index]["result"]["range"].clone();
+        // the parameter, a global declared later, a function and a `match` binding
+        assert_eq!(definition(1), range(1, 8, 1, 9));
+        assert_eq!(definition(2), range(4, 4, 4, 8));
+        assert_eq!(definition(3), range(1, 4, 1, 7));
+        assert_eq!(definition(4), range(7, 22, 7, 23));
+        // the keyword of a declaration is no variable
+        assert_eq!(responses[5]["result"], Value::Null);
+
+        let hover = |index: usize| responses[index]["result"]["contents"]["value"].clone();
+        assert_eq!(
+            hover(6),
+            "```lox\nfun inc(n: Number)\n```\n\nAdds the step."
+        );
+        assert_eq!(hover(7), "```lox\nn\n```\n\nParameter of `inc`.");
+        assert_eq!(
+            hover(8),
+            "```lox\na\n```\n\nBound by a `match` arm to instances of `A`."
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let text = "aé😀b\nxy";
+        let position =
+            |line, character| offset(text, &json!({"line": line, "character": character}));
+        assert_eq!(position(0, 2), 3);
+        // the emoji takes two UTF-16 code units
+        assert_eq!(position(0, 4), 7);
+        assert_eq!(position(0, 9), 8);
+        assert_eq!(position(1, 1), 10);
+        assert_eq!(position(5, 0), text.len());
+    }
+}
@@ -0,0 +1,263 @@
+use crate::error::{LoxError, Result};
+use crate::functions::Function;
+use crate::gc::Marker;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::rc::Rc;
+
+/// The entries of a map, ordered by key.
+pub type Entries = BTreeMap<Key, Object>;
+
+/// A map key. Only values that are compared by value can be keys, so looking one up never depends
+/// on which object it is. Keys are ordered nil first, then booleans, numbers and strings.
+#[derive(Debug, Clone)]
+pub enum Key {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+}
+
+impl Key {
+    pub fn new(object: &Object) -> Result<Key> {
+        match object {
+            Object::Nil => Ok(Key::Nil),
+            Object::Boolean(boolean) => Ok(Key::Boolean(*boolean)),
+            // -0 and 0 are equal, so they have to be the same key
+            Object::Number(number) if *number == 0.0 => Ok(Key::Number(0.0)),
+            Object::Number(number) => Ok(Key::Number(*number)),
+            Object::String(string) => Ok(Key::String(Rc::clone(string))),
+            _ => Err(LoxError::InterpreterError(
+                format!(
+                    "Map keys must be nil, booleans, numbers or strings, not '{}'.",
+                    object.type_name()
+                )
+                .into(),
+            )),
+        }
+    }
+
+    pub fn to_object(&self) -> Object {
+        match self {
+            Key::Nil => Object::Nil,
+            Key::Boolean(boolean) => Object::Boolean(*boolean),
+            Key::Number(number) => Object::Number(*number),
+            Key::String(string) => Object::String(Rc::clone(string)),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Nil => 0,
+            Key::Boolean(_) => 1,
+            Key::Number(_) => 2,
+            Key::String(_) => 3,
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.to_object(), f)
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Key::Boolean(a), Key::Boolean(b)) => a.cmp(b),
+            (Key::Number(a), Key::Number(b)) => a.total_cmp(b),
+            (Key::String(a), Key::String(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Key::Nil => {}
+            Key::Boolean(boolean) => boolean.hash(state),
+            // equal numbers have the same bits, since `new` turned -0 into 0
+            Key::Number(number) => number.to_bits().hash(state),
+            Key::String(string) => string.hash(state),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    Get,
+    Set,
+    Keys,
+    Values,
+    ContainsKey,
+    Remove,
+    Size,
+}
+
+/// The methods of maps, with their names and arities.
+const METHODS: [(Method, &str, usize); 7] = [
+    (Method::Get, "get", 1),
+    (Method::Set, "set", 2),
+    (Method::Keys, "keys", 0),
+    (Method::Values, "values", 0),
+    (Method::ContainsKey, "containsKey", 1),
+    (Method::Remove, "remove", 1),
+    (Method::Size, "size", 0),
+];
+
+/// A method of a map, bound to the map it was looked up on, as in `map.set("a", 1)`.
+pub struct MapMethod {
+    map: Rc<RefCell<Entries>>,
+    method: Method,
+    name: &'static str,
+    arity: usize,
+}
+
+impl MapMethod {
+    /// The method called `name` of `map`, if maps have one.
+    pub fn new(map: &Rc<RefCell<Entries>>, name: &str) -> Option<Self> {
+        let &(method, name, arity) = METHODS.iter().find(|(_, method, _)| *method == name)?;
+        Some(Self {
+            map: Rc::clone(map),
+            method,
+            name,
+            arity,
+        })
+    }
+}
+
+impl Function for MapMethod {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        if arguments.len() != self.arity {
+            let plural = if self.arity == 1 { "" } else { "s" };
+            return Err(LoxError::InterpreterError(
+                format!(
+                    "Map method '{}' expects {} argument{} but got {}.",
+                    self.name,
+                    self.arity,
+                    plural,
+                    arguments.len()
+                )
+                .into(),
+            ));
+        }
+        match self.method {
+            Method::Get => {
+                let key = Key::new(&arguments[0])?;
+                Ok(self.map.borrow().get(&key).cloned().unwrap_or(Object::Nil))
+            }
+            Method::Set => {
+                let key = Key::new(&arguments[0])?;
+                let added = self
+                    .map
+                    .borrow_mut()
+                    .insert(key, arguments[1].clone())
+                    .is_none();
+                if added {
+                    interpreter.grow_heap(size_of::<(Key, Object)>())?;
+                }
+                Ok(Object::Nil)
+            }
+            Method::Keys => {
+                let keys = self.map.borrow().keys().map(Key::to_object).collect();
+                interpreter.new_list(keys)
+            }
+            Method::Values => {
+                let values = self.map.borrow().values().cloned().collect();
+                interpreter.new_list(values)
+            }
+            Method::ContainsKey => {
+                let key = Key::new(&arguments[0])?;
+                Ok(Object::Boolean(self.map.borrow().contains_key(&key)))
+            }
+            Method::Remove => {
+                let key = Key::new(&arguments[0])?;
+                Ok(self.map.borrow_mut().remove(&key).unwrap_or(Object::Nil))
+            }
+            Method::Size => Ok(Object::Number(self.map.borrow().len() as f64)),
+        }
+    }
+
+    fn trace(&self, marker: &mut Marker) {
+        marker.mark_object(&Object::Map(Rc::clone(&self.map)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::object::Object;
+    use crate::test_support::{eval, print};
+
+    #[test]
+    fn methods() {
+        let source = r#"
+            var counts = {};
+            var words = ["a", "b", "a", "c", "a"];
+            while (words.len() > 0) {
+                var word = words.pop();
+                counts.set(word, (counts.get(word) or 0) + 1);
+            }
+            counts.remove("c");
+            counts;
+        "#;
+        assert_eq!(print(source), "{a: 3, b: 1}");
+        assert_eq!(
+            print("({2: nil, true: 1, \"x\": 0, nil: 2}).keys();"),
+            "[nil, true, 2, x]"
+        );
+        assert_eq!(print("({1: \"one\", 2: \"two\"}).values();"), "[one, two]");
+        assert_eq!(
+            eval("({-0: 1}).containsKey(0) and ({1: 1}).size() == 1;"),
+            Ok(Object::Boolean(true))
+        );
+        assert_eq!(eval("({}).remove(1);"), Ok(Object::Nil));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |message: &str| Err(LoxError::InterpreterError(message.to_string().into()));
+        assert_eq!(
+            eval("({}).get();"),
+            error("Map method 'get' expects 1 argument but got 0.")
+        );
+        assert_eq!(
+            eval("({[]: 1});"),
+            error("Map keys must be nil, booleans, numbers or strings, not 'list'.")
+        );
+        assert_eq!(eval("({}).length;"), error("Undefined property length."));
+    }
+}
@@ -0,0 +1,413 @@
+//! The minifier behind `rlox minify`. It drops comments and all whitespace that does not keep
+//! two tokens apart, and can give local variables the shortest names available.
+//!
+//! Renaming follows the scoping rules of the [`resolver`](crate::resolver): only variables,
+//! functions, classes and parameters declared inside a block or function get new names. Globals,
+//! properties and methods are part of what other code (and the host) can see and keep theirs.
+//! New names never clash with any identifier already in the source.
+//!
+//! ```text
+//! fun add(first, second) {
+//!     // sum
+//!     return first + second;
+//! }
+//! ```
+//!
+//! becomes `fun add(first,second){return first+second;}`, and `fun add(a,b){return a+b;}` with
+//! `MinifyOptions::rename_locals`.
+
+use crate::cst::{self, NodeKind, SyntaxElement, SyntaxNode, SyntaxToken, TokenKind};
+use crate::error::LoxError;
+use crate::lexer;
+use crate::recursion;
+use crate::resolver;
+use crate::token::TokenType;
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MinifyOptions {
+    /// Give local variables short names.
+    pub rename_locals: bool,
+}
+
+const KEYWORDS: [&str; 26] = [
+    "and", "break", "case", "class", "const", "default", "do", "else", "enum", "false", "for",
+    "fun", "if", "loop", "match", "nil", "or", "print", "return", "super", "switch", "this",
+    "true", "typeof", "var", "while",
+];
+
+/// Minifies `source`, or returns the errors that keep it from being parsed. Renaming locals
+/// also needs the program to pass the resolver.
+pub fn minify(source: &str, options: MinifyOptions) -> Result<String, Vec<LoxError>> {
+    let cst = cst::parse(source);
+    if !cst.errors.is_empty() {
+        return Err(cst.errors);
+    }
+
+    let mut renames = HashMap::new();
+    if options.rename_locals {
+        let (program, errors) = cst.lower();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        resolver::resolve(&program).map_err(|error| vec![error])?;
+
+        let taken = cst
+            .root
+            .tokens()
+            .filter(|token| token.kind == TokenKind::Token(TokenType::Identifier))
+            .map(|token| token.text.clone())
+            .collect();
+        let mut renamer = Renamer {
+            scopes: vec![],
+            renames: &mut renames,
+            names: Names {
+                taken,
+                names: vec![],
+                next: 0,
+            },
+        };
+        renamer.children(&cst.root);
+    }
+
+    let mut out = String::new();
+    let mut previous: Option<&str> = None;
+    for token in cst.root.tokens().filter(|token| !token.kind.is_trivia()) {
+        let text = renames
+            .get(&token.span.start)
+            .map_or(token.text.as_str(), String::as_str);
+        if previous.is_some_and(|previous| needs_space(previous, text)) {
+            out.push(' ');
+        }
+        out.push_str(text);
+        previous = Some(text);
+    }
+    Ok(out)
+}
+
+/// Whether two tokens would lex differently if nothing separated them, as in `var a` or `/ /`.
+fn needs_space(left: &str, right: &str) -> bool {
+    let joined = format!("{}{}", left, right);
+    let (tokens, errors) = lexer::lex(&joined);
+    let lexemes: Vec<_> = tokens.iter().map(|token| token.lexeme).collect();
+    !errors.is_empty() || lexemes != [left, right, ""]
+}
+
+/// Short names, in order of length, leaving out keywords and the identifiers of the source.
+struct Names {
+    taken: HashSet<String>,
+    names: Vec<String>,
+    next: usize,
+}
+
+impl Names {
+    fn get(&mut self, index: usize) -> String {
+        while self.names.len() <= index {
+            let name = spell(self.next);
+            self.next += 1;
+            if !self.taken.contains(&name) && !KEYWORDS.contains(&name.as_str()) {
+                self.names.push(name);
+            }
+        }
+        self.names[index].clone()
+    }
+}
+
+/// The `index`th of `a`, ..., `Z`, `aa`, `ab`, ...
+fn spell(mut index: usize) -> String {
+    const LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut name = vec![];
+    loop {
+        name.push(LETTERS[index % LETTERS.len()]);
+        if index < LETTERS.len() {
+            break;
+        }
+        index = index / LETTERS.len() - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+/// Finds the local declarations and their uses. Every visible local gets a name of its own;
+/// the names are counted from the outermost scope, so sibling scopes reuse them.
+struct Renamer<'a> {
+    /// The local scopes, innermost last. Top-level declarations are globals and in none.
+    scopes: Vec<HashMap<String, String>>,
+    /// New names, by the position of the token to rename.
+    renames: &'a mut HashMap<usize, String>,
+    names: Names,
+}
+
+impl<'a> Renamer<'a> {
+    fn declare(&mut self, token: &SyntaxToken) {
+        let visible: usize = self.scopes.iter().map(HashMap::len).sum();
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => return,
+        };
+        let name = match scope.get(&token.text) {
+            Some(name) => name.clone(),
+            None => {
+                let name = self.names.get(visible);
+                scope.insert(token.text.clone(), name.clone());
+                name
+            }
+        };
+        self.renames.insert(token.span.start, name);
+    }
+
+    /// Declares `token` as a local that keeps its name.
+    fn keep(&mut self, token: &SyntaxToken) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(token.text.clone(), token.text.clone());
+        }
+    }
+
+    fn reference(&mut self, token: &SyntaxToken) {
+        let name = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&token.text));
+        if let Some(name) = name {
+            self.renames.insert(token.span.start, name.clone());
+        }
+    }
+
+    fn children(&mut self, node: &SyntaxNode) {
+        for child in node.nodes() {
+            self.node(child);
+        }
+    }
+
+    fn node(&mut self, node: &SyntaxNode) {
+        recursion::grow_stack(|| {
+            match node.kind {
+                NodeKind::Block | NodeKind::For | NodeKind::Case => {
+                    self.scopes.push(HashMap::new());
+                    self.children(node);
+                    self.scopes.pop();
+                }
+                // each variable is declared after its initializer, like in declarations of its own
+                NodeKind::Var => {
+                    let is_token = |child: &SyntaxElement, token_type: TokenType| matches!(child, SyntaxElement::Token(token) if token.kind == TokenKind::Token(token_type));
+                    for declarator in node
+                        .children
+                        .split(|child| is_token(child, TokenType::Comma))
+                    {
+                        let mut name = None;
+                        let mut pattern = None;
+                        for child in declarator {
+                            match child {
+                                SyntaxElement::Node(child) if child.kind == NodeKind::Pattern => {
+                                    pattern = Some(child)
+                                }
+                                SyntaxElement::Node(child) => self.node(child),
+                                SyntaxElement::Token(token)
+                                    if name.is_none() && is_token(child, TokenType::Identifier) =>
+                                {
+                                    name = Some(token)
+                                }
+                                SyntaxElement::Token(_) => {}
+                            }
+                        }
+                        if let Some(name) = name {
+                            self.declare(name);
+                        }
+                        // destructured variables are named after the fields they read
+                        for field in pattern.into_iter().flat_map(identifiers) {
+                            self.keep(field);
+                        }
+                    }
+                }
+                // members are properties and keep their names
+                NodeKind::Enum => {
+                    if let Some(name) = identifiers(node).next() {
+                        self.declare(name);
+                    }
+                }
+                NodeKind::Class => {
+                    if let Some(name) = identifiers(node).next() {
+                        self.declare(name);
+                    }
+                    for child in node.nodes() {
+                        match child.kind {
+                            NodeKind::Function => self.function(child),
+                            _ => self.node(child),
+                        }
+                    }
+                }
+                NodeKind::Function => {
+                    if let Some(name) = identifiers(node).next() {
+                        self.declare(name);
+                    }
+                    self.function(node);
+                }
+                NodeKind::Variable => {
+                    if let Some(name) = identifiers(node).next() {
+                        self.reference(name);
+                    }
+                }
+                // the binding of an arm is only visible in its body
+                NodeKind::MatchArm => {
+                    self.scopes.push(HashMap::new());
+                    for child in node.nodes() {
+                        match child.kind {
+                            NodeKind::ClassPattern => {
+                                self.children(child);
+                                if let Some(binding) = identifiers(child).next() {
+                                    self.declare(binding);
+                                }
+                            }
+                            _ => self.node(child),
+                        }
+                    }
+                    self.scopes.pop();
+                }
+                // the name of a property stays, only the object is an expression
+                NodeKind::Get => {
+                    if let Some(object) = node.nodes().next() {
+                        self.node(object);
+                    }
+                }
+                _ => self.children(node),
+            }
+        })
+    }
+
+    /// Parameters and the body of a function, which share a scope.
+    fn function(&mut self, node: &SyntaxNode) {
+        self.scopes.push(HashMap::new());
+        for child in node.nodes() {
+            match child.kind {
+                NodeKind::Parameter => {
+                    if let Some(name) = identifiers(child).next() {
+                        self.declare(name);
+                    }
+                }
+                NodeKind::Block => self.children(child),
+                _ => {}
+            }
+        }
+        self.scopes.pop();
+    }
+}
+
+/// The identifier tokens that are direct children of `node`.
+fn identifiers(node: &SyntaxNode) -> impl Iterator<Item = &SyntaxToken> {
+    node.children.iter().filter_map(|child| match child {
+        SyntaxElement::Token(token) if token.kind == TokenKind::Token(TokenType::Identifier) => {
+            Some(token)
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{minify, spell, MinifyOptions};
+    use crate::error::LoxError;
+    use crate::test_support::print;
+
+    const SOURCE: &str = r#"
+        // counts calls
+        fun makeCounter(start) {
+            var count = start;
+            fun increment(step) {
+                count = count + step;
+                return count;
+            }
+            return increment;
+        }
+
+        class Point {
+            init(x, y) { this.x = x; this.y = y; }
+            sum() { var total = this.x + this.y; return total; }
+        }
+
+        var counter = makeCounter(10);
+        counter(1);
+        {
+            var a = Point(1, 2);
+            for (var index = 0; index < 3; index = index + 1) a.x = a.x - -index;
+            var result = counter(a.sum() * 2 / 1);
+            switch (result) {
+                case 23:
+                    var twice = result * 2, same = twice / 2;
+                    counter(same - result);
+            }
+            var {x, y} = a;
+            counter(x - y - 2);
+        }
+        counter(0);
+    "#;
+
+    #[test]
+    fn whitespace_and_comments() {
+        let minified = minify(SOURCE, MinifyOptions::default()).unwrap();
+        assert!(minified.starts_with("fun makeCounter(start){var count=start;"));
+        assert!(minified.contains("a.x=a.x--index;"));
+        assert!(!minified.contains("//"));
+        assert_eq!(print(&minified), print(SOURCE));
+    }
+
+    #[test]
+    fn rename_locals() {
+        let options = MinifyOptions {
+            rename_locals: true,
+        };
+        let minified = minify(SOURCE, options).unwrap();
+        assert!(minified.starts_with("fun makeCounter(b){var c=b;fun d(e){c=c+e;return c;}"));
+        assert!(minified.contains("init(b,c){this.x=b;this.y=c;}"));
+        assert!(minified.contains("{var b=Point(1,2);for(var c=0;c<3;c=c+1)b.x=b.x--c;"));
+        assert!(minified.contains("switch(c){case 23:var d=c*2,e=d/2;counter(e-c);}"));
+        assert!(minified.contains("var{x,y}=b;counter(x-y-2);"));
+        assert_eq!(print(&minified), print(SOURCE));
+        assert_eq!(print(&minified), "23");
+
+        assert!(minify("return 1;", options).is_err());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let options = MinifyOptions {
+            rename_locals: true,
+        };
+        let depth = 9000;
+        let source = format!(
+            "{{ var x = 1; {}{}x{}; }}",
+            "print ",
+            "( ".repeat(depth),
+            " )".repeat(depth)
+        );
+        let minified = minify(&source, options).unwrap();
+        assert_eq!(
+            minified,
+            format!(
+                "{{var a=1;print{}a{};}}",
+                "(".repeat(depth),
+                ")".repeat(depth)
+            )
+        );
+
+        let blocks = format!("{}{}", "{".repeat(depth), "}".repeat(depth));
+        assert_eq!(minify(&blocks, options).unwrap(), blocks);
+
+        let too_deep = "(".repeat(100_000);
+        assert_eq!(
+            minify(&too_deep, options).unwrap_err(),
+            vec![LoxError::ParserError(Some(1), "Too much nesting.".into())]
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(spell(0), "a");
+        assert_eq!(spell(51), "Z");
+        assert_eq!(spell(52), "aa");
+        assert_eq!(spell(53), "ab");
+        assert_eq!(spell(52 + 52 * 52), "aaa");
+    }
+}
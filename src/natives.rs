@@ -0,0 +1,325 @@
+use crate::environment::Environment;
+#[cfg(feature = "time")]
+use crate::functions::Clock;
+use crate::functions::Function;
+#[cfg(any(feature = "net", feature = "process"))]
+use crate::functions::NativeFunction;
+#[cfg(feature = "introspection")]
+use crate::functions::{Backtrace, Globals, Locals, Stats};
+#[cfg(feature = "strings")]
+use crate::functions::{CharCodeAt, FromCharCode, Transcode};
+#[cfg(feature = "values")]
+use crate::functions::{DeepClone, HashValue};
+#[cfg(feature = "numbers")]
+use crate::functions::{ParseInt, ToFixed, ToStringRadix};
+#[cfg(feature = "http")]
+use crate::http::{HttpGet, HttpPost};
+use crate::interpreter::Interpreter;
+#[cfg(feature = "net")]
+use crate::net;
+use crate::object::Object;
+#[cfg(feature = "process")]
+use crate::process;
+#[cfg(feature = "signals")]
+use crate::signals::OnInterrupt;
+#[cfg(feature = "time")]
+use crate::timers::{ClearTimer, SetTimer};
+#[cfg(feature = "watch")]
+use crate::watch::{Unwatch, Watch};
+
+use std::ops::BitOr;
+use std::rc::Rc;
+
+/// Groups of native functions an interpreter may define, so embedders can leave out the ones
+/// scripts must not use. Each group also has a cargo feature of the same name; groups whose
+/// feature is disabled are left out of the build and never defined.
+///
+/// Untrusted scripts should get no more than `Natives::SANDBOX`, the groups that cannot reach
+/// files, the environment, other processes or the network.
+///
+/// ```
+/// use rlox::{Interpreter, InterpreterConfig, Natives};
+///
+/// let config = InterpreterConfig::default().with_natives(Natives::NONE);
+/// let interpreter = Interpreter::with_config(config);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Natives {
+    bits: u32,
+}
+
+impl Natives {
+    pub const NONE: Natives = Natives { bits: 0 };
+    /// `clock()`, `setTimeout()`, `setInterval()` and `clearTimer()`.
+    pub const TIME: Natives = Natives { bits: 1 };
+    /// `backtrace()`, `globals()`, `locals()` and `stats()`.
+    pub const INTROSPECTION: Natives = Natives { bits: 2 };
+    /// `charCodeAt()`, `fromCharCode()`, `base64Encode()`, `base64Decode()`, `hexEncode()` and
+    /// `hexDecode()`.
+    pub const STRINGS: Natives = Natives { bits: 4 };
+    /// `toFixed()`, `toStringRadix()` and `parseInt()`.
+    pub const NUMBERS: Natives = Natives { bits: 8 };
+    /// `clone()` and `hash()`.
+    pub const VALUES: Natives = Natives { bits: 16 };
+    /// `tcpConnect()` and `tcpListen()`.
+    pub const NET: Natives = Natives { bits: 32 };
+    /// `httpGet()` and `httpPost()`. Unlike the other groups, its feature is off by default.
+    pub const HTTP: Natives = Natives { bits: 64 };
+    /// `onInterrupt()`, which replaces how the whole process reacts to SIGINT and SIGTERM. Its
+    /// feature is off by default too.
+    pub const SIGNALS: Natives = Natives { bits: 128 };
+    /// `spawn()`.
+    pub const PROCESS: Natives = Natives { bits: 256 };
+    /// `watch()` and `unwatch()`.
+    pub const WATCH: Natives = Natives { bits: 512 };
+    pub const ALL: Natives = Natives { bits: 1023 };
+    /// The groups that only let scripts compute and print. Groups giving access to the outside
+    /// world must never be added here.
+    pub const SANDBOX: Natives = Natives { bits: 31 };
+
+    pub fn contains(self, natives: Natives) -> bool {
+        self.bits & natives.bits == natives.bits
+    }
+
+    /// The group called `name`, the same as its cargo feature.
+    pub fn from_name(name: &str) -> Option<Natives> {
+        match name {
+            "time" => Some(Natives::TIME),
+            "introspection" => Some(Natives::INTROSPECTION),
+            "strings" => Some(Natives::STRINGS),
+            "numbers" => Some(Natives::NUMBERS),
+            "values" => Some(Natives::VALUES),
+            "net" => Some(Natives::NET),
+            "http" => Some(Natives::HTTP),
+            "signals" => Some(Natives::SIGNALS),
+            "process" => Some(Natives::PROCESS),
+            "watch" => Some(Natives::WATCH),
+            _ => None,
+        }
+    }
+
+    /// The groups in a comma-separated list of names, or the first unknown name.
+    pub fn from_names(names: &str) -> std::result::Result<Natives, String> {
+        names
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .try_fold(Natives::NONE, |natives, name| {
+                Natives::from_name(name)
+                    .map(|group| natives | group)
+                    .ok_or_else(|| name.to_string())
+            })
+    }
+}
+
+impl BitOr for Natives {
+    type Output = Natives;
+
+    fn bitor(self, other: Natives) -> Natives {
+        Natives {
+            bits: self.bits | other.bits,
+        }
+    }
+}
+
+/// The types of the parameters and the result of a native, named as in annotations, for the
+/// type checker. `Any` stands for values of more than one type, e.g. a number or nil.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct NativeSignature {
+    pub(crate) parameters: &'static [&'static str],
+    pub(crate) returns: &'static str,
+}
+
+#[cfg(any(
+    feature = "time",
+    feature = "introspection",
+    feature = "strings",
+    feature = "numbers",
+    feature = "values",
+    feature = "http",
+    feature = "signals",
+    feature = "watch",
+    feature = "net",
+    feature = "process"
+))]
+pub(crate) const fn signature(
+    parameters: &'static [&'static str],
+    returns: &'static str,
+) -> NativeSignature {
+    NativeSignature {
+        parameters,
+        returns,
+    }
+}
+
+/// The natives of the enabled groups that are `Function`s, each with its signature.
+#[allow(unused_mut, unused_variables)]
+fn functions(natives: Natives) -> Vec<(Rc<dyn Function>, NativeSignature)> {
+    let mut functions: Vec<(Rc<dyn Function>, NativeSignature)> = vec![];
+    #[cfg(feature = "time")]
+    if natives.contains(Natives::TIME) {
+        functions.push((Rc::new(Clock {}), signature(&[], "Number")));
+        for set_timer in SetTimer::ALL {
+            let timer = signature(&["Function", "Number"], "Number");
+            functions.push((Rc::new(set_timer), timer));
+        }
+        functions.push((Rc::new(ClearTimer), signature(&["Number"], "Nil")));
+    }
+    #[cfg(feature = "introspection")]
+    if natives.contains(Natives::INTROSPECTION) {
+        functions.push((Rc::new(Backtrace), signature(&[], "String")));
+        // these print what they found
+        functions.push((Rc::new(Globals), signature(&[], "Nil")));
+        functions.push((Rc::new(Locals), signature(&[], "Nil")));
+        functions.push((Rc::new(Stats), signature(&[], "Nil")));
+    }
+    #[cfg(feature = "strings")]
+    if natives.contains(Natives::STRINGS) {
+        let char_code_at = signature(&["String", "Number"], "Number");
+        functions.push((Rc::new(CharCodeAt), char_code_at));
+        functions.push((Rc::new(FromCharCode), signature(&["Number"], "String")));
+        for transcode in Transcode::ALL {
+            functions.push((Rc::new(transcode), signature(&["String"], "String")));
+        }
+    }
+    #[cfg(feature = "numbers")]
+    if natives.contains(Natives::NUMBERS) {
+        let to_string = signature(&["Number", "Number"], "String");
+        functions.push((Rc::new(ToFixed), to_string));
+        functions.push((Rc::new(ToStringRadix), to_string));
+        // nil for strings that are no numbers
+        functions.push((Rc::new(ParseInt), signature(&["String", "Number"], "Any")));
+    }
+    #[cfg(feature = "http")]
+    if natives.contains(Natives::HTTP) {
+        functions.push((Rc::new(HttpGet), signature(&["String"], "Any")));
+        functions.push((Rc::new(HttpPost), signature(&["String", "String"], "Any")));
+    }
+    #[cfg(feature = "signals")]
+    if natives.contains(Natives::SIGNALS) {
+        functions.push((Rc::new(OnInterrupt), signature(&["Function"], "Nil")));
+    }
+    #[cfg(feature = "watch")]
+    if natives.contains(Natives::WATCH) {
+        functions.push((Rc::new(Watch), signature(&["String", "Function"], "Number")));
+        functions.push((Rc::new(Unwatch), signature(&["Number"], "Nil")));
+    }
+    #[cfg(feature = "values")]
+    if natives.contains(Natives::VALUES) {
+        functions.push((Rc::new(DeepClone), signature(&["Any"], "Any")));
+        functions.push((Rc::new(HashValue), signature(&["Any"], "Number")));
+    }
+    #[cfg(feature = "net")]
+    if natives.contains(Natives::NET) {
+        functions.extend(net::functions());
+    }
+    #[cfg(feature = "process")]
+    if natives.contains(Natives::PROCESS) {
+        functions.extend(process::functions());
+    }
+    functions
+}
+
+/// A native running `function`, like those `Interpreter::define_native` defines.
+#[cfg(any(feature = "net", feature = "process"))]
+pub(crate) fn native(
+    name: &str,
+    arity: usize,
+    function: impl Fn(&[Object]) -> crate::error::Result<Object> + 'static,
+) -> Rc<dyn Function> {
+    Rc::new(NativeFunction::new(name.into(), arity, Box::new(function)))
+}
+
+/// Defines the natives of the enabled groups.
+pub(crate) fn define(globals: &mut Environment, natives: Natives) {
+    for (function, _) in functions(natives) {
+        globals.define(function.name().into(), Object::Function(function));
+    }
+}
+
+/// Defines the methods of the userdata the natives of the enabled groups return.
+#[allow(unused_variables)]
+pub(crate) fn define_methods(interpreter: &mut Interpreter, natives: Natives) {
+    #[cfg(feature = "net")]
+    if natives.contains(Natives::NET) {
+        net::define_methods(interpreter);
+    }
+    #[cfg(feature = "process")]
+    if natives.contains(Natives::PROCESS) {
+        process::define_methods(interpreter);
+    }
+}
+
+/// The names and signatures of all natives of the enabled groups.
+pub(crate) fn signatures(natives: Natives) -> Vec<(Rc<str>, NativeSignature)> {
+    functions(natives)
+        .into_iter()
+        .map(|(function, signature)| (function.name().into(), signature))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{signatures, Natives};
+
+    use crate::interpreter::{Interpreter, InterpreterConfig};
+    use crate::object::Object;
+
+    #[test]
+    fn contains() {
+        assert!(Natives::ALL.contains(Natives::TIME));
+        assert!((Natives::NONE | Natives::TIME).contains(Natives::TIME));
+        assert!(!Natives::NONE.contains(Natives::TIME));
+        assert!(Natives::TIME.contains(Natives::NONE));
+        assert!(Natives::ALL.contains(Natives::SANDBOX));
+        assert!(!Natives::SANDBOX.contains(Natives::NET));
+        assert!(!Natives::SANDBOX.contains(Natives::HTTP));
+        assert!(!Natives::SANDBOX.contains(Natives::SIGNALS));
+        assert!(!Natives::SANDBOX.contains(Natives::PROCESS));
+        assert!(!Natives::SANDBOX.contains(Natives::WATCH));
+        assert_eq!(
+            Natives::ALL,
+            Natives::TIME
+                | Natives::INTROSPECTION
+                | Natives::STRINGS
+                | Natives::NUMBERS
+                | Natives::VALUES
+                | Natives::NET
+                | Natives::HTTP
+                | Natives::SIGNALS
+                | Natives::PROCESS
+                | Natives::WATCH
+        );
+    }
+
+    #[test]
+    fn every_native_has_a_signature() {
+        for natives in [Natives::ALL, Natives::SANDBOX, Natives::TIME, Natives::NONE] {
+            let config = InterpreterConfig::default().with_natives(natives);
+            let interpreter = Interpreter::with_config(config);
+            let mut defined: Vec<_> = interpreter
+                .globals()
+                .into_iter()
+                .map(|(name, value)| match value {
+                    Object::Function(function) => (name, function.arity()),
+                    _ => unreachable!(),
+                })
+                .collect();
+            defined.sort();
+            let mut signed: Vec<_> = signatures(natives)
+                .into_iter()
+                .map(|(name, signature)| (name, signature.parameters.len()))
+                .collect();
+            signed.sort();
+            assert_eq!(defined, signed);
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Natives::from_name("time"), Some(Natives::TIME));
+        assert_eq!(Natives::from_names("time,"), Ok(Natives::TIME));
+        assert_eq!(Natives::from_names(""), Ok(Natives::NONE));
+        assert_eq!(Natives::from_names("time,disk"), Err("disk".to_string()));
+    }
+}
@@ -0,0 +1,244 @@
+use crate::error::{LoxError, Result};
+use crate::functions::Function;
+use crate::interpreter::Interpreter;
+use crate::natives::{native, signature, NativeSignature};
+use crate::object::Object;
+use crate::userdata::Userdata;
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+
+/// How many bytes `read()` asks the socket for at most.
+const READ_SIZE: usize = 4096;
+
+/// An open TCP connection, as returned by `tcpConnect()` and `listener.accept()`.
+pub struct Connection {
+    stream: RefCell<Option<TcpStream>>,
+    /// The start of a UTF-8 sequence split between two reads.
+    partial: RefCell<Vec<u8>>,
+}
+
+/// A socket accepting connections, as returned by `tcpListen()`.
+pub struct Listener {
+    listener: RefCell<Option<TcpListener>>,
+}
+
+impl Connection {
+    fn wrap(stream: TcpStream) -> Object {
+        Object::Userdata(Userdata::new(Connection {
+            stream: RefCell::new(Some(stream)),
+            partial: RefCell::new(Vec::new()),
+        }))
+    }
+
+    /// Up to `READ_SIZE` bytes of text, or nil once the other side closed the connection.
+    /// Blocks until there is something to read.
+    fn read(&self) -> Result<Object> {
+        let mut stream = self.stream.borrow_mut();
+        let stream = stream.as_mut().ok_or_else(closed)?;
+        let mut partial = self.partial.borrow_mut();
+        loop {
+            let mut buffer = [0; READ_SIZE];
+            let read = stream
+                .read(&mut buffer)
+                .map_err(|error| failed("read", error))?;
+            if read == 0 {
+                if partial.is_empty() {
+                    return Ok(Object::Nil);
+                }
+                let rest = String::from_utf8_lossy(&partial).into_owned();
+                partial.clear();
+                return Ok(Object::String(rest.into()));
+            }
+            partial.extend_from_slice(&buffer[..read]);
+            let valid = match std::str::from_utf8(&partial) {
+                Ok(text) => text.len(),
+                // only an incomplete sequence at the end is worth waiting for
+                Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                Err(_) => partial.len(),
+            };
+            if valid > 0 {
+                let bytes: Vec<u8> = partial.drain(..valid).collect();
+                let text = String::from_utf8_lossy(&bytes).into_owned();
+                return Ok(Object::String(text.into()));
+            }
+        }
+    }
+
+    fn write(&self, arguments: &[Object]) -> Result<Object> {
+        let text = match &arguments[0] {
+            Object::String(text) => text,
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "write() expects a string, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let mut stream = self.stream.borrow_mut();
+        let stream = stream.as_mut().ok_or_else(closed)?;
+        stream
+            .write_all(text.as_bytes())
+            .map_err(|error| failed("write", error))?;
+        Ok(Object::Nil)
+    }
+
+    /// Closing a closed connection does nothing.
+    fn close(&self) -> Result<Object> {
+        self.stream.borrow_mut().take();
+        Ok(Object::Nil)
+    }
+}
+
+impl Listener {
+    fn accept(&self) -> Result<Object> {
+        let listener = self.listener.borrow();
+        let listener = listener.as_ref().ok_or_else(closed)?;
+        let (stream, _) = listener.accept().map_err(|error| failed("accept", error))?;
+        Ok(Connection::wrap(stream))
+    }
+
+    /// The port the listener is bound to, e.g. the one picked for `tcpListen(0)`.
+    fn port(&self) -> Result<Object> {
+        let listener = self.listener.borrow();
+        let listener = listener.as_ref().ok_or_else(closed)?;
+        let address = listener
+            .local_addr()
+            .map_err(|error| failed("port", error))?;
+        Ok(Object::Number(address.port() as f64))
+    }
+
+    fn close(&self) -> Result<Object> {
+        self.listener.borrow_mut().take();
+        Ok(Object::Nil)
+    }
+}
+
+/// `tcpConnect(host, port)` and `tcpListen(port)`, each with its signature. The connections and
+/// listeners they return get their methods from `define_methods`.
+pub(crate) fn functions() -> Vec<(Rc<dyn Function>, NativeSignature)> {
+    let mut functions = vec![];
+    let connect = signature(&["String", "Number"], "Any");
+    let tcp_connect = native("tcpConnect", 2, |arguments| {
+        let host = match &arguments[0] {
+            Object::String(host) => host,
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "tcpConnect() expects a host name, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let port = port("tcpConnect", &arguments[1])?;
+        let stream =
+            TcpStream::connect((&**host, port)).map_err(|error| failed("tcpConnect", error))?;
+        Ok(Connection::wrap(stream))
+    });
+    functions.push((tcp_connect, connect));
+    let tcp_listen = native("tcpListen", 1, |arguments| {
+        let port = port("tcpListen", &arguments[0])?;
+        let listener =
+            TcpListener::bind(("0.0.0.0", port)).map_err(|error| failed("tcpListen", error))?;
+        Ok(Object::Userdata(Userdata::new(Listener {
+            listener: RefCell::new(Some(listener)),
+        })))
+    });
+    functions.push((tcp_listen, signature(&["Number"], "Any")));
+    functions
+}
+
+/// Defines the methods of connections, `read()`, `write(text)` and `close()`, and those of
+/// listeners, `accept()`, `port()` and `close()`. All of them block until they are done.
+pub(crate) fn define_methods(interpreter: &mut Interpreter) {
+    interpreter.define_method("read", 0, |connection: &Connection, _| connection.read());
+    interpreter.define_method("write", 1, |connection: &Connection, arguments| {
+        connection.write(arguments)
+    });
+    interpreter.define_method("close", 0, |connection: &Connection, _| connection.close());
+    interpreter.define_method("accept", 0, |listener: &Listener, _| listener.accept());
+    interpreter.define_method("port", 0, |listener: &Listener, _| listener.port());
+    interpreter.define_method("close", 0, |listener: &Listener, _| listener.close());
+}
+
+fn port(function: &str, argument: &Object) -> Result<u16> {
+    match argument {
+        Object::Number(port) if port.fract() == 0.0 && (0.0..=65535.0).contains(port) => {
+            Ok(*port as u16)
+        }
+        _ => Err(LoxError::InterpreterError(
+            format!(
+                "{}() expects a port from 0 to 65535, but got '{}'.",
+                function, argument
+            )
+            .into(),
+        )),
+    }
+}
+
+fn failed(operation: &str, error: std::io::Error) -> LoxError {
+    LoxError::InterpreterError(format!("{}() failed: {}.", operation, error).into())
+}
+
+fn closed() -> LoxError {
+    LoxError::InterpreterError("The socket is closed.".into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::interpreter::{Interpreter, InterpreterConfig};
+    use crate::natives::Natives;
+    use crate::object::Object;
+    use crate::test_support::eval;
+
+    #[test]
+    fn loopback() {
+        let source = r#"
+            var listener = tcpListen(0);
+            var client = tcpConnect("127.0.0.1", listener.port());
+            var server = listener.accept();
+            client.write("ping é");
+            server.write(server.read() + "!");
+            var reply = client.read();
+            client.close();
+            var end = server.read();
+            server.close();
+            listener.close();
+            reply + "|" + typeof end;
+        "#;
+        assert_eq!(eval(source), Ok(Object::String("ping é!|nil".into())));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            eval("tcpListen(70000);"),
+            Err(LoxError::InterpreterError(
+                "tcpListen() expects a port from 0 to 65535, but got '70000'.".into()
+            ))
+        );
+        assert_eq!(
+            eval("var listener = tcpListen(0); listener.close(); listener.accept();"),
+            Err(LoxError::InterpreterError("The socket is closed.".into()))
+        );
+    }
+
+    #[test]
+    fn sandboxed() {
+        let config = InterpreterConfig::default().with_natives(Natives::SANDBOX);
+        let interpreter = Interpreter::with_config(config);
+        assert!(!interpreter
+            .globals()
+            .iter()
+            .any(|(name, _)| &**name == "tcpConnect"));
+    }
+}
@@ -1,37 +1,129 @@
 use crate::classes::{LoxClass, LoxInstance};
+use crate::enums::{EnumMember, LoxEnum};
 use crate::functions::Function;
+use crate::maps::Entries;
+use crate::userdata::Userdata;
 
 use std::cell::RefCell;
 use std::fmt::{Display, Formatter};
 use std::rc::Rc;
 
-#[derive(Debug)]
+/// A Lox value. Numbers, booleans and nil are stored inline, everything else is
+/// reference counted, so values are cheap to clone and are passed around by value.
+#[derive(Debug, Clone)]
 pub enum Object {
     Boolean(bool),
     Nil,
     Number(f64),
-    String(String),
+    String(Rc<str>),
     Function(Rc<dyn Function>),
     Class(Rc<LoxClass>),
     Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Object>>>),
+    Map(Rc<RefCell<Entries>>),
+    Enum(Rc<LoxEnum>),
+    EnumMember(Rc<EnumMember>),
+    Userdata(Userdata),
 }
 
 impl Display for Object {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
             Object::Nil => write!(f, "nil"),
-            Object::Number(num) => {
-                if num.fract() == 0.0 {
-                    write!(f, "{:.0}", num)
-                } else {
-                    write!(f, "{}", num)
-                }
-            }
+            Object::Number(number) => write_number(f, *number),
             Object::Boolean(b) => write!(f, "{}", b),
             Object::String(s) => write!(f, "{}", s),
             Object::Function(func) => write!(f, "{:?}", func),
-            Object::Class(class) => write!(f, "{}", class),
+            Object::Class(class) => write!(f, "<class {}>", class.name()),
             Object::Instance(instance) => write!(f, "{}", instance.borrow()),
+            Object::List(_) | Object::Map(_) => write_nested(f, self, &mut vec![]),
+            Object::Enum(lox_enum) => write!(f, "{}", lox_enum),
+            Object::EnumMember(member) => write!(f, "{}", member),
+            Object::Userdata(userdata) => write!(f, "{:?}", userdata),
+        }
+    }
+}
+
+/// Writes `number` with the fewest digits that still read back as the same number, in exponent
+/// notation below 1e-7 and from 1e21 on, as JavaScript does. A precision, as in `{:.2}`, rounds to
+/// that many decimal places instead, without trailing zeros.
+fn write_number(f: &mut Formatter<'_>, number: f64) -> std::fmt::Result {
+    if let Some(precision) = f.precision() {
+        let fixed = format!("{:.*}", precision, number);
+        let fixed = if fixed.contains('.') {
+            fixed.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            &fixed
+        };
+        return f.write_str(fixed);
+    }
+    let magnitude = number.abs();
+    if magnitude.is_finite() && magnitude != 0.0 && !(1e-7..1e21).contains(&magnitude) {
+        write!(f, "{:e}", number)
+    } else {
+        // Rust already picks the shortest digits that round-trip
+        write!(f, "{}", number)
+    }
+}
+
+/// Writes a list or map with the lists and maps in it, and `[...]` or `{...}` for the ones in
+/// `open` that contain themselves.
+fn write_nested(
+    f: &mut Formatter<'_>,
+    object: &Object,
+    open: &mut Vec<*const ()>,
+) -> std::fmt::Result {
+    let (pointer, brackets) = match object {
+        Object::List(list) => (Rc::as_ptr(list) as *const (), ("[", "]")),
+        Object::Map(map) => (Rc::as_ptr(map) as *const (), ("{", "}")),
+        // passing `f` on keeps its precision
+        object => return Display::fmt(object, f),
+    };
+    if open.contains(&pointer) {
+        return write!(f, "{}...{}", brackets.0, brackets.1);
+    }
+    open.push(pointer);
+    write!(f, "{}", brackets.0)?;
+    match object {
+        Object::List(list) => {
+            for (index, element) in list.borrow().iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write_nested(f, element, open)?;
+            }
+        }
+        Object::Map(map) => {
+            for (index, (key, value)) in map.borrow().iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: ", key)?;
+                write_nested(f, value, open)?;
+            }
+        }
+        _ => unreachable!(),
+    }
+    open.pop();
+    write!(f, "{}", brackets.1)
+}
+
+impl Object {
+    /// What `typeof` evaluates to, e.g. `"number"`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Boolean(_) => "boolean",
+            Object::Nil => "nil",
+            Object::Number(_) => "number",
+            Object::String(_) => "string",
+            Object::Function(_) => "function",
+            Object::Class(_) => "class",
+            Object::Instance(_) => "instance",
+            Object::List(_) => "list",
+            Object::Map(_) => "map",
+            Object::Enum(_) => "enum",
+            Object::EnumMember(_) => "enum member",
+            Object::Userdata(_) => "userdata",
         }
     }
 }
@@ -43,6 +135,14 @@ impl PartialEq for Object {
             (Object::Nil, Object::Nil) => true,
             (Object::Number(a), Object::Number(b)) => a == b,
             (Object::String(a), Object::String(b)) => a == b,
+            (Object::Function(a), Object::Function(b)) => Rc::ptr_eq(a, b),
+            (Object::Class(a), Object::Class(b)) => Rc::ptr_eq(a, b),
+            (Object::Instance(a), Object::Instance(b)) => Rc::ptr_eq(a, b),
+            (Object::List(a), Object::List(b)) => Rc::ptr_eq(a, b),
+            (Object::Map(a), Object::Map(b)) => Rc::ptr_eq(a, b),
+            (Object::Enum(a), Object::Enum(b)) => Rc::ptr_eq(a, b),
+            (Object::EnumMember(a), Object::EnumMember(b)) => Rc::ptr_eq(a, b),
+            (Object::Userdata(a), Object::Userdata(b)) => a.ptr_eq(b),
             _ => false,
         }
     }
@@ -0,0 +1,670 @@
+use crate::recursion;
+use crate::statement::{Ast, Expr, ExprRef, Pattern, Program, Stmt, StmtRef};
+use crate::token::TokenType;
+
+/// A transformation of a program that does not change what the program does. Passes run between
+/// parsing and resolution, so the resolver only sees, and the interpreter only looks up, what
+/// they keep.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn run(&mut self, program: Program) -> Program;
+
+    /// What the last run changed, shown in verbose mode.
+    fn report(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// How much optimization to do, as in `-O0` and `-O1`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OptLevel {
+    /// Run the program as written.
+    #[default]
+    O0,
+    O1,
+}
+
+/// Every pass, in the order `-O1` runs them.
+fn all_passes() -> Vec<Box<dyn Pass>> {
+    vec![
+        Box::new(ConstantFolding),
+        Box::new(DeadCodeElimination::default()),
+    ]
+}
+
+/// Passes that run one after the other.
+#[derive(Default)]
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The passes enabled at `level`.
+    pub fn for_level(level: OptLevel) -> Self {
+        match level {
+            OptLevel::O0 => Self::new(),
+            OptLevel::O1 => Self {
+                passes: all_passes(),
+            },
+        }
+    }
+
+    /// The passes called `names`, in that order. Fails with the first name no pass has.
+    pub fn with_passes(names: &[String]) -> Result<Self, String> {
+        let mut available = all_passes();
+        let mut pipeline = Self::new();
+        for name in names {
+            let index = available
+                .iter()
+                .position(|pass| pass.name() == name)
+                .ok_or_else(|| name.clone())?;
+            pipeline.add(available.remove(index));
+        }
+        Ok(pipeline)
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) -> &mut Self {
+        self.passes.push(pass);
+        self
+    }
+
+    pub fn run(&mut self, program: Program) -> Program {
+        self.passes
+            .iter_mut()
+            .fold(program, |program, pass| pass.run(program))
+    }
+
+    /// The reports of all passes, each line prefixed with the name of its pass.
+    pub fn report(&self) -> Vec<String> {
+        self.passes
+            .iter()
+            .flat_map(|pass| {
+                pass.report()
+                    .into_iter()
+                    .map(move |line| format!("{}: {}", pass.name(), line))
+            })
+            .collect()
+    }
+}
+
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&mut self, program: Program) -> Program {
+        fold_constants(program)
+    }
+}
+
+/// Evaluates operations on literals ahead of time, e.g. `1 + 2 * 3` becomes `7` and
+/// `true and x` becomes `x`. Operations that would fail at runtime (like `1 + "a"`) are left
+/// alone so they still report their error when executed.
+pub fn fold_constants(mut program: Program) -> Program {
+    for &statement in &program.statements {
+        fold_statement(&mut program.ast, statement);
+    }
+    program
+}
+
+fn fold_statements(ast: &mut Ast, statements: &[StmtRef]) {
+    for &statement in statements {
+        fold_statement(ast, statement);
+    }
+}
+
+fn fold_statement(ast: &mut Ast, stmt: StmtRef) {
+    recursion::grow_stack(|| match &ast[stmt] {
+        Stmt::Expression { expression } | Stmt::Print { expression } => {
+            fold_expression(ast, *expression);
+        }
+        Stmt::Var { initializer, .. } => {
+            if let Some(initializer) = *initializer {
+                fold_expression(ast, initializer);
+            }
+        }
+        Stmt::Block { statements } => fold_statements(ast, &statements.clone()),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let (condition, then_branch, else_branch) = (*condition, *then_branch, *else_branch);
+            fold_expression(ast, condition);
+            fold_statement(ast, then_branch);
+            if let Some(else_branch) = else_branch {
+                fold_statement(ast, else_branch);
+            }
+        }
+        Stmt::While { condition, body } => {
+            let (condition, body) = (*condition, *body);
+            fold_expression(ast, condition);
+            fold_statement(ast, body);
+        }
+        Stmt::DoWhile { body, condition } => {
+            let (body, condition) = (*body, *condition);
+            fold_statement(ast, body);
+            fold_expression(ast, condition);
+        }
+        Stmt::Labeled { body, .. } => fold_statement(ast, *body),
+        Stmt::Switch {
+            subject,
+            cases,
+            default,
+        } => {
+            let (subject, cases, default) = (*subject, cases.clone(), *default);
+            fold_expression(ast, subject);
+            for (value, body) in cases {
+                fold_expression(ast, value);
+                fold_statement(ast, body);
+            }
+            if let Some(default) = default {
+                fold_statement(ast, default);
+            }
+        }
+        Stmt::Function { body, .. } => fold_statements(ast, &body.clone()),
+        Stmt::Return { value } => {
+            if let Some(value) = *value {
+                fold_expression(ast, value);
+            }
+        }
+        Stmt::Class { methods, .. } => fold_statements(ast, &methods.clone()),
+        Stmt::Break { .. } | Stmt::Enum { .. } => {}
+    })
+}
+
+/// Folds the children of `expr` first, then replaces `expr` itself if it became constant.
+fn fold_expression(ast: &mut Ast, expr: ExprRef) {
+    recursion::grow_stack(|| match &ast[expr] {
+        Expr::Binary {
+            left,
+            token_type,
+            right,
+        } => {
+            let (left, token_type, right) = (*left, token_type.clone(), *right);
+            fold_expression(ast, left);
+            fold_expression(ast, right);
+            if let Some(folded) = fold_binary(&ast[left], &token_type, &ast[right]) {
+                ast[expr] = folded;
+            }
+        }
+        Expr::Unary { token_type, right } => {
+            let (token_type, right) = (token_type.clone(), *right);
+            fold_expression(ast, right);
+            match (&token_type, &ast[right]) {
+                (TokenType::Minus, Expr::Number(number)) => ast[expr] = Expr::Number(-number),
+                (TokenType::Bang, right) if is_literal(right) => {
+                    ast[expr] = Expr::Boolean(!is_truthy(right))
+                }
+                (TokenType::Typeof, right) if is_literal(right) => {
+                    let type_name = match right {
+                        Expr::Nil => "nil",
+                        Expr::Boolean(_) => "boolean",
+                        Expr::Number(_) => "number",
+                        _ => "string",
+                    };
+                    ast[expr] = Expr::String(type_name.into())
+                }
+                _ => {}
+            }
+        }
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, operator, right) = (*left, operator.clone(), *right);
+            fold_expression(ast, left);
+            fold_expression(ast, right);
+            if is_literal(&ast[left]) {
+                // mirror the interpreter: `or` stops at a truthy, `and` at a falsey left operand
+                let result = if (operator == TokenType::Or) == is_truthy(&ast[left]) {
+                    left
+                } else {
+                    right
+                };
+                replace_with(ast, expr, result);
+            }
+        }
+        Expr::Grouping { expression } => {
+            let expression = *expression;
+            fold_expression(ast, expression);
+            if is_literal(&ast[expression]) {
+                replace_with(ast, expr, expression);
+            }
+        }
+        Expr::Call { callee, arguments } => {
+            let (callee, arguments) = (*callee, arguments.clone());
+            fold_expression(ast, callee);
+            for argument in arguments {
+                fold_expression(ast, argument);
+            }
+        }
+        Expr::Get { object, .. } => fold_expression(ast, *object),
+        Expr::Set { object, value, .. } => {
+            let (object, value) = (*object, *value);
+            fold_expression(ast, object);
+            fold_expression(ast, value);
+        }
+        Expr::Assign { value, .. } => fold_expression(ast, *value),
+        Expr::List { elements } => {
+            for element in elements.clone() {
+                fold_expression(ast, element);
+            }
+        }
+        Expr::Map { entries } => {
+            for (key, value) in entries.clone() {
+                fold_expression(ast, key);
+                fold_expression(ast, value);
+            }
+        }
+        Expr::Match { subject, arms } => {
+            let mut children = vec![*subject];
+            for arm in arms {
+                if let Pattern::Value(value) = arm.pattern {
+                    children.push(value);
+                }
+                children.push(arm.body);
+            }
+            for child in children {
+                fold_expression(ast, child);
+            }
+        }
+        Expr::Nil
+        | Expr::Boolean(_)
+        | Expr::Number(_)
+        | Expr::String(_)
+        | Expr::Variable { .. }
+        | Expr::This { .. }
+        | Expr::Super { .. } => {}
+    })
+}
+
+/// Moves the `replacement` node into the place of `expr`, leaving a detached nil behind.
+fn replace_with(ast: &mut Ast, expr: ExprRef, replacement: ExprRef) {
+    ast[expr] = std::mem::replace(&mut ast[replacement], Expr::Nil);
+}
+
+fn fold_binary(left: &Expr, token_type: &TokenType, right: &Expr) -> Option<Expr> {
+    if !is_literal(left) || !is_literal(right) {
+        return None;
+    }
+    let folded = match (left, token_type, right) {
+        (Expr::Number(a), TokenType::Plus, Expr::Number(b)) => Expr::Number(a + b),
+        (Expr::Number(a), TokenType::Minus, Expr::Number(b)) => Expr::Number(a - b),
+        (Expr::Number(a), TokenType::Star, Expr::Number(b)) => Expr::Number(a * b),
+        (Expr::Number(a), TokenType::Slash, Expr::Number(b)) => Expr::Number(a / b),
+        (Expr::Number(a), TokenType::Less, Expr::Number(b)) => Expr::Boolean(a < b),
+        (Expr::Number(a), TokenType::LessEqual, Expr::Number(b)) => Expr::Boolean(a <= b),
+        (Expr::Number(a), TokenType::Greater, Expr::Number(b)) => Expr::Boolean(a > b),
+        (Expr::Number(a), TokenType::GreaterEqual, Expr::Number(b)) => Expr::Boolean(a >= b),
+        (Expr::String(a), TokenType::Plus, Expr::String(b)) => {
+            Expr::String(format!("{}{}", a, b).into())
+        }
+        (left, TokenType::EqualEqual, right) => Expr::Boolean(left == right),
+        (left, TokenType::BangEqual, right) => Expr::Boolean(left != right),
+        _ => return None,
+    };
+    Some(folded)
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Nil | Expr::Boolean(_) | Expr::Number(_) | Expr::String(_)
+    )
+}
+
+fn is_truthy(literal: &Expr) -> bool {
+    !matches!(literal, Expr::Nil | Expr::Boolean(false))
+}
+
+/// Removes statements that can never run: whatever follows a `return` in the same block, the
+/// branch of an `if` with a constant condition that is not taken, and `while` loops with a
+/// falsey constant condition. Best run after constant folding.
+#[derive(Default)]
+pub struct DeadCodeElimination {
+    removed: Vec<String>,
+}
+
+impl Pass for DeadCodeElimination {
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn run(&mut self, mut program: Program) -> Program {
+        self.removed.clear();
+        program.statements = self.eliminate_statements(&mut program.ast, &program.statements);
+        program
+    }
+
+    fn report(&self) -> Vec<String> {
+        self.removed.clone()
+    }
+}
+
+impl DeadCodeElimination {
+    fn eliminate_statements(&mut self, ast: &mut Ast, statements: &[StmtRef]) -> Vec<StmtRef> {
+        let mut kept = Vec::new();
+        for (index, &statement) in statements.iter().enumerate() {
+            self.eliminate(ast, statement);
+            kept.push(statement);
+            if always_returns(ast, statement) {
+                let unreachable = statements.len() - index - 1;
+                if unreachable > 0 {
+                    self.removed.push(format!(
+                        "removed {} statement{} after a return",
+                        unreachable,
+                        if unreachable == 1 { "" } else { "s" }
+                    ));
+                }
+                break;
+            }
+        }
+        kept
+    }
+
+    fn eliminate(&mut self, ast: &mut Ast, stmt: StmtRef) {
+        recursion::grow_stack(|| match &ast[stmt] {
+            Stmt::Block { statements } => {
+                let statements = self.eliminate_statements(ast, &statements.clone());
+                ast[stmt] = Stmt::Block { statements };
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let (condition, then_branch, else_branch) =
+                    (*condition, *then_branch, *else_branch);
+                self.eliminate(ast, then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.eliminate(ast, else_branch);
+                }
+                if is_literal(&ast[condition]) {
+                    let (taken, skipped) = if is_truthy(&ast[condition]) {
+                        (Some(then_branch), else_branch)
+                    } else {
+                        (else_branch, Some(then_branch))
+                    };
+                    if skipped.is_some() {
+                        self.removed
+                            .push("removed an 'if' branch that never runs".to_string());
+                    }
+                    ast[stmt] = match taken {
+                        Some(taken) => std::mem::replace(&mut ast[taken], empty_block()),
+                        None => empty_block(),
+                    };
+                }
+            }
+            Stmt::While { condition, body } => {
+                let (condition, body) = (*condition, *body);
+                self.eliminate(ast, body);
+                if is_literal(&ast[condition]) && !is_truthy(&ast[condition]) {
+                    self.removed
+                        .push("removed a 'while' loop that never runs".to_string());
+                    ast[stmt] = empty_block();
+                }
+            }
+            Stmt::DoWhile { body, .. } | Stmt::Labeled { body, .. } => {
+                let body = *body;
+                self.eliminate(ast, body);
+            }
+            Stmt::Switch { cases, default, .. } => {
+                let bodies = cases.iter().map(|(_, body)| *body).chain(*default);
+                for body in bodies.collect::<Vec<_>>() {
+                    self.eliminate(ast, body);
+                }
+            }
+            Stmt::Function { body, .. } => {
+                let eliminated = self.eliminate_statements(ast, &body.clone());
+                if let Stmt::Function { body, .. } = &mut ast[stmt] {
+                    *body = eliminated.into();
+                }
+            }
+            Stmt::Class { methods, .. } => {
+                for method in methods.clone() {
+                    self.eliminate(ast, method);
+                }
+            }
+            Stmt::Expression { .. }
+            | Stmt::Print { .. }
+            | Stmt::Var { .. }
+            | Stmt::Return { .. }
+            | Stmt::Break { .. }
+            | Stmt::Enum { .. } => {}
+        })
+    }
+}
+
+fn empty_block() -> Stmt {
+    Stmt::Block {
+        statements: Vec::new(),
+    }
+}
+
+/// Whether running `stmt` always ends in a `return`. Loops never do, their body could `break`.
+fn always_returns(ast: &Ast, stmt: StmtRef) -> bool {
+    recursion::grow_stack(|| match &ast[stmt] {
+        Stmt::Return { .. } => true,
+        Stmt::Block { statements } => statements
+            .iter()
+            .any(|&statement| always_returns(ast, statement)),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(ast, *then_branch) && always_returns(ast, *else_branch),
+        Stmt::Switch {
+            cases,
+            default: Some(default),
+            ..
+        } => {
+            always_returns(ast, *default)
+                && cases.iter().all(|(_, body)| always_returns(ast, *body))
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{fold_constants, DeadCodeElimination, OptLevel, Pass, Pipeline};
+
+    use crate::statement::{Expr, Program, Stmt};
+    use crate::test_support::parse;
+    use crate::token::TokenType;
+
+    fn fold_program(source: &'static str) -> Program {
+        fold_constants(parse(source))
+    }
+
+    /// The folded expression of a single print statement.
+    fn fold(source: &'static str) -> Expr {
+        let mut program = fold_program(source);
+        match program.ast[program.statements[0]] {
+            Stmt::Print { expression } => {
+                std::mem::replace(&mut program.ast[expression], Expr::Nil)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let source = format!("print 0{};", " + 1".repeat(9_000));
+        let program = fold_constants(parse(&source));
+        assert_eq!(first_print(&program), &Expr::Number(9_000.0),);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(fold("print 1 + 2 * 3;"), Expr::Number(7.0));
+        assert_eq!(fold("print -(4 - 6) / 2;"), Expr::Number(1.0));
+        assert_eq!(fold("print 1 < 2 == !nil;"), Expr::Boolean(true));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            fold(r#"print "foo" + "bar";"#),
+            Expr::String("foobar".into())
+        );
+        assert_eq!(fold(r#"print "1" == 1;"#), Expr::Boolean(false));
+        assert_eq!(fold(r#"print typeof 1 == "number";"#), Expr::Boolean(true));
+    }
+
+    #[test]
+    fn logical() {
+        assert!(matches!(
+            fold("print true and x;"),
+            Expr::Variable { name, .. } if &*name == "x"
+        ));
+        assert_eq!(fold("print nil and x;"), Expr::Nil);
+        assert_eq!(fold("print 1 or x;"), Expr::Number(1.0));
+        assert!(matches!(fold("print x or true;"), Expr::Logical { .. }));
+    }
+
+    #[test]
+    fn keeps_runtime_errors() {
+        assert!(matches!(
+            fold(r#"print 1 + "a";"#),
+            Expr::Binary {
+                token_type: TokenType::Plus,
+                ..
+            }
+        ));
+        assert!(matches!(fold("print -true;"), Expr::Unary { .. }));
+    }
+
+    #[test]
+    fn nested_in_statements() {
+        let program = fold_program("fun f() { while (1 < 2) return 2 * 21; }");
+        let ast = &program.ast;
+
+        if let Stmt::Function { body, .. } = &ast[program.statements[0]] {
+            if let Stmt::While { condition, body } = &ast[body[0]] {
+                assert_eq!(ast[*condition], Expr::Boolean(true));
+                assert!(matches!(
+                    ast[*body],
+                    Stmt::Return { value: Some(value) } if ast[value] == Expr::Number(42.0)
+                ));
+                return;
+            }
+        }
+        unreachable!();
+    }
+
+    /// Drops the last top-level statement.
+    struct DropLast;
+
+    impl Pass for DropLast {
+        fn name(&self) -> &'static str {
+            "drop-last"
+        }
+
+        fn run(&mut self, mut program: Program) -> Program {
+            program.statements.pop();
+            program
+        }
+    }
+
+    /// The expression printed by the first statement.
+    fn first_print(program: &Program) -> &Expr {
+        match program.ast[program.statements[0]] {
+            Stmt::Print { expression } => &program.ast[expression],
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn pipeline() {
+        let source = "print 1 + 2; print 3; print 4;";
+        let program = Pipeline::for_level(OptLevel::O0).run(parse(source));
+        assert!(matches!(first_print(&program), Expr::Binary { .. }));
+
+        let mut pipeline = Pipeline::for_level(OptLevel::O1);
+        pipeline.add(Box::new(DropLast)).add(Box::new(DropLast));
+        let program = pipeline.run(parse(source));
+        assert_eq!(program.statements.len(), 1);
+        assert_eq!(first_print(&program), &Expr::Number(3.0));
+    }
+
+    #[test]
+    fn named_passes() {
+        let names = ["constant-folding".to_string()];
+        let program = Pipeline::with_passes(&names)
+            .unwrap()
+            .run(parse("print 1 + 2;"));
+        assert_eq!(first_print(&program), &Expr::Number(3.0));
+
+        let names = ["constant-folding".to_string(), "inlining".to_string()];
+        assert_eq!(
+            Pipeline::with_passes(&names).err(),
+            Some("inlining".to_string())
+        );
+    }
+
+    #[test]
+    fn dead_code() {
+        let source = r#"
+            fun f() {
+                if (1 > 2) {
+                    print "never";
+                } else {
+                    return 1;
+                }
+                print "unreachable";
+                return 2;
+            }
+            while (nil) print "never";
+            if (true) print "always";
+        "#;
+        let mut pipeline = Pipeline::for_level(OptLevel::O1);
+        let program = pipeline.run(parse(source));
+        let ast = &program.ast;
+
+        let Stmt::Function { body, .. } = &ast[program.statements[0]] else {
+            unreachable!();
+        };
+        assert_eq!(body.len(), 1);
+        assert!(matches!(&ast[body[0]], Stmt::Block { statements } if statements.len() == 1));
+        assert!(
+            matches!(&ast[program.statements[1]], Stmt::Block { statements } if statements.is_empty())
+        );
+        assert!(matches!(&ast[program.statements[2]], Stmt::Print { .. }));
+
+        assert_eq!(
+            pipeline.report(),
+            vec![
+                "dead-code: removed an 'if' branch that never runs",
+                "dead-code: removed 2 statements after a return",
+                "dead-code: removed a 'while' loop that never runs",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_conditional_returns() {
+        let source = r#"
+            fun f(x) {
+                if (x) return 1;
+                return 2;
+            }
+        "#;
+        let mut pass = DeadCodeElimination::default();
+        let program = pass.run(parse(source));
+        let Stmt::Function { body, .. } = &program.ast[program.statements[0]] else {
+            unreachable!();
+        };
+        assert_eq!(body.len(), 2);
+        assert!(pass.report().is_empty());
+    }
+}
@@ -1,42 +1,95 @@
 use crate::error::{LoxError, Result};
-use crate::statement::{Expr, Stmt};
-use crate::token::{Token, TokenType};
-
+use crate::interner::Interner;
+use crate::recursion::{self, MAX_SYNTAX_DEPTH};
+use crate::statement::{
+    Ast, Expr, ExprId, ExprRef, MatchArm, Pattern, Program, Stmt, StmtRef, TypeAnnotation,
+};
+use crate::token::{Span, Token, TokenType};
+
+use std::collections::VecDeque;
 use std::rc::Rc;
-use std::sync::atomic::{AtomicU64, Ordering};
-
-// TODO find a better solution
-static NEXT_EXPRESSION_ID: AtomicU64 = AtomicU64::new(0);
 
-fn next_id() -> u64 {
-    NEXT_EXPRESSION_ID.fetch_add(1, Ordering::Relaxed)
+/// The variable holding the object of a destructuring declaration, named so that no code can
+/// refer to it.
+const DESTRUCTURED: &str = "<destructured>";
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParserOptions {
+    /// Accept type annotations but leave them out of the AST.
+    pub ignore_type_annotations: bool,
+    /// The id of the first node the resolver binds, see `ExprId`.
+    pub first_id: ExprId,
+    /// Let a line break, a `}` or the end of the source end a statement without `;`, as in the
+    /// REPL and with `--relaxed`. An expression continued on the next line still continues.
+    pub optional_semicolons: bool,
 }
 
 struct Parser<'a> {
+    tokens: &'a [Token<'a>],
     token_iter: std::iter::Peekable<std::slice::Iter<'a, Token<'a>>>,
+    /// Statements parsed along with the last one returned, like the variables of a `var`
+    /// declaration.
+    queued: VecDeque<StmtRef>,
+    options: ParserOptions,
+    interner: Interner,
+    ast: Ast,
+    /// How many statements and expressions the one being parsed is nested in.
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
-    fn new(tokens: &'a Vec<Token<'a>>) -> Self {
+    fn new(tokens: &'a [Token<'a>], options: ParserOptions) -> Self {
         Self {
+            tokens,
             token_iter: tokens.iter().peekable(),
+            queued: VecDeque::new(),
+            options,
+            interner: Interner::new(),
+            ast: Ast::with_first_id(options.first_id),
+            depth: 0,
+        }
+    }
+
+    /// Parses a statement or an expression inside the one being parsed. Nesting them too deeply
+    /// is an error, which ends the parse: what follows would not parse on its own anyway.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_SYNTAX_DEPTH {
+            let line = self.token_iter.peek().map(|token| token.line);
+            self.token_iter.by_ref().for_each(drop);
+            return Err(LoxError::ParserError(line, "Too much nesting.".into()));
         }
+        self.depth += 1;
+        let result = recursion::grow_stack(|| parse(self));
+        self.depth -= 1;
+        result
     }
 
     fn statement(&mut self) -> Result<Stmt> {
+        self.nested(Self::parse_statement)
+    }
+
+    fn parse_statement(&mut self) -> Result<Stmt> {
         if let Some(token) = self.token_iter.peek() {
             match &token.token_type {
                 TokenType::Print => self.print_statement(),
-                TokenType::Var => self.var_declaration(),
+                TokenType::Var | TokenType::Const => self.var_statement(),
                 TokenType::LeftBrace => self.block(),
                 TokenType::If => self.if_statement(),
                 TokenType::While => self.while_statement(),
+                TokenType::Do => self.do_while_statement(),
+                TokenType::Loop => self.loop_statement(),
+                TokenType::Break => self.break_statement(),
+                TokenType::Identifier if self.peek_second() == Some(&TokenType::Colon) => {
+                    self.labeled_statement()
+                }
                 TokenType::For => self.for_statement(),
+                TokenType::Switch => self.switch_statement(),
                 TokenType::Fun => {
                     self.token_iter.next();
                     self.function()
                 }
                 TokenType::Class => self.class(),
+                TokenType::Enum => self.enum_declaration(),
                 TokenType::Return => self.return_statement(),
                 _ => self.expression_statement(),
             }
@@ -51,58 +104,98 @@ impl<'a> Parser<'a> {
 
         let superclass = if self.matches(&[TokenType::Less]) {
             self.token_iter.next();
+            let start = self.next_start();
             let superclass_identifier = self.identifier_name("class")?;
-            Some(Box::new(Expr::Variable {
-                id: next_id(),
-                name: superclass_identifier.to_string(),
-            }))
+            let superclass = Expr::Variable {
+                id: self.ast.new_id(),
+                name: self.interner.intern(superclass_identifier),
+            };
+            Some(self.push_expr_from(superclass, start))
         } else {
             None
         };
 
-        self.consume(TokenType::LeftBrace, "Expect '{' before class body".into())?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body")?;
 
         let mut methods = vec![];
         while !self.matches(&[TokenType::RightBrace]) {
-            methods.push(self.function()?);
+            let line = self.token_iter.peek().map(|token| token.line);
+            let start = self.next_start();
+            let method = self.function()?;
+            let span = self.span_from(start);
+            methods.push(self.push_stmt_at(method, line, span));
         }
-        self.consume(TokenType::RightBrace, "Expect '}' after class body".into())?;
+        self.consume(TokenType::RightBrace, "Expect '}' after class body")?;
 
         Ok(Stmt::Class {
-            name: name.to_string(),
+            id: self.ast.new_id(),
+            name: self.interner.intern(name),
             superclass,
-            methods: Box::new(methods),
+            methods,
+        })
+    }
+
+    /// Parses `enum Name { First, Second }`, a trailing comma after the members allowed.
+    fn enum_declaration(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Enum, "Enums begin with 'enum'.")?;
+        let name = self.identifier_name("enum")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before enum members.")?;
+
+        let mut members: Vec<Rc<str>> = vec![];
+        while !self.matches(&[TokenType::RightBrace]) {
+            let line = self.token_iter.peek().map(|token| token.line);
+            let member = self.identifier_name("member")?;
+            let member = self.interner.intern(member);
+            if members.contains(&member) {
+                return Err(LoxError::ParserError(
+                    line,
+                    "Already a member with this name in this enum.".into(),
+                ));
+            }
+            members.push(member);
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+            self.token_iter.next();
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after enum members.")?;
+
+        Ok(Stmt::Enum {
+            id: self.ast.new_id(),
+            name: self.interner.intern(name),
+            members,
         })
     }
 
     fn return_statement(&mut self) -> Result<Stmt> {
         self.consume(TokenType::Return, "Return statements begin with 'return'")?;
-        let value = if self.matches(&[TokenType::Semicolon]) {
+        let value = if self.matches(&[TokenType::Semicolon]) || self.at_implicit_end() {
             None
         } else {
             Some(self.expression()?)
         };
-        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        self.end_statement("Expect ';' after return value.")?;
 
         Ok(Stmt::Return { value })
     }
 
     fn function(&mut self) -> Result<Stmt> {
         let name = self.identifier_name("function")?;
-        self.consume(
-            TokenType::LeftParen,
-            "Expect '(' after function name".into(),
-        )?;
+        self.consume(TokenType::LeftParen, "Expect '(' after function name")?;
 
         let mut parameters = vec![];
+        let mut parameter_types = vec![];
         while !self.matches(&[TokenType::RightParen]) {
             let parameter_name = self.identifier_name("parameter")?;
-            parameters.push(parameter_name.to_string());
-            if self.matches(&[TokenType::Comma]) {
-                self.token_iter.next();
+            parameters.push(self.interner.intern(parameter_name));
+            parameter_types.push(self.type_annotation(TokenType::Colon)?);
+            if !self.matches(&[TokenType::Comma]) {
+                break;
             }
+            self.token_iter.next();
         }
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        let return_type = self.type_annotation(TokenType::Arrow)?;
 
         let statements = if let Stmt::Block { statements } = self.block()? {
             statements
@@ -111,9 +204,12 @@ impl<'a> Parser<'a> {
         };
 
         Ok(Stmt::Function {
-            name: name.to_string(),
+            id: self.ast.new_id(),
+            name: self.interner.intern(name),
             parameters: Rc::new(parameters),
-            body: Rc::from(statements),
+            parameter_types,
+            return_type,
+            body: statements.into(),
         })
     }
 
@@ -124,29 +220,96 @@ impl<'a> Parser<'a> {
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after while condition.")?;
 
-        let body = self.statement()?;
+        let body = self.statement_ref()?;
 
-        Ok(Stmt::While {
-            condition,
-            body: Box::new(body),
+        Ok(Stmt::While { condition, body })
+    }
+
+    /// Desugars into `while (true)`.
+    fn loop_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Loop, "Loops begin with 'loop'.")?;
+        let condition = self.ast.push_expr(Expr::Boolean(true));
+        let body = self.statement_ref()?;
+
+        Ok(Stmt::While { condition, body })
+    }
+
+    fn labeled_statement(&mut self) -> Result<Stmt> {
+        let label = self.identifier_name("label")?;
+        self.consume(TokenType::Colon, "Expect ':' after label.")?;
+        let loops = [
+            TokenType::While,
+            TokenType::For,
+            TokenType::Do,
+            TokenType::Loop,
+        ];
+        if !self.matches(&loops) {
+            let line = self.token_iter.peek().map(|token| token.line);
+            return Err(LoxError::ParserError(
+                line,
+                "Only loops can have labels.".into(),
+            ));
+        }
+        let body = self.statement_ref()?;
+
+        Ok(Stmt::Labeled {
+            label: self.interner.intern(label),
+            body,
+        })
+    }
+
+    fn break_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Break, "Break statements begin with 'break'.")?;
+        let label = if self.matches(&[TokenType::Identifier]) && !self.at_implicit_end() {
+            Some(self.identifier_name("label")?)
+        } else {
+            None
+        };
+        self.end_statement("Expect ';' after 'break'.")?;
+
+        Ok(Stmt::Break {
+            label: label.map(|label| self.interner.intern(label)),
         })
     }
 
+    fn do_while_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Do, "Do loops begin with 'do'.")?;
+        let body = self.statement_ref()?;
+
+        self.consume(TokenType::While, "Expect 'while' after do loop body.")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after while condition.")?;
+        self.end_statement("Expect ';' after do loop condition.")?;
+
+        Ok(Stmt::DoWhile { body, condition })
+    }
+
+    /// Desugars into a `while` loop. The statements it adds get the line of the `for`, and the
+    /// span of the whole loop unless they stand for one of its clauses.
     fn for_statement(&mut self) -> Result<Stmt> {
+        let line = self.token_iter.peek().map(|token| token.line);
+        let start = self.next_start();
         self.consume(TokenType::For, "For loops begin with 'for'.")?;
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
-        let initializer = if self.matches(&[TokenType::Semicolon]) {
+        let initializer_start = self.next_start();
+        let initializers = if self.matches(&[TokenType::Semicolon]) {
             self.token_iter.next();
-            None
-        } else if self.matches(&[TokenType::Var]) {
-            Some(self.var_declaration()?)
+            vec![]
+        } else if self.matches(&[TokenType::Var, TokenType::Const]) {
+            self.var_declaration()?
         } else {
-            Some(self.expression_statement()?)
+            vec![self.expression_statement()?]
         };
+        let span = self.span_from(initializer_start);
+        let initializers: Vec<_> = initializers
+            .into_iter()
+            .map(|initializer| self.push_stmt_at(initializer, line, span))
+            .collect();
 
         let condition = if self.matches(&[TokenType::Semicolon]) {
-            Expr::Boolean(true)
+            self.ast.push_expr(Expr::Boolean(true))
         } else {
             self.expression()?
         };
@@ -159,30 +322,81 @@ impl<'a> Parser<'a> {
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        if increment.is_some() {
-            body = Stmt::Block {
-                statements: Box::new(vec![
-                    body,
-                    Stmt::Expression {
-                        expression: increment.unwrap(),
-                    },
-                ]),
+        let mut body = self.statement_ref()?;
+
+        let span = self.span_from(start);
+        if let Some(increment) = increment {
+            let increment_span = self.ast.expr_span(increment).unwrap_or(span);
+            let increment = self.push_stmt_at(
+                Stmt::Expression {
+                    expression: increment,
+                },
+                line,
+                increment_span,
+            );
+            let block = Stmt::Block {
+                statements: vec![body, increment],
             };
+            body = self.ast.push_stmt(block);
+            self.ast.set_stmt_span(body, span);
         };
-        body = Stmt::While {
-            condition,
-            body: Box::new(body),
-        };
+        let body = Stmt::While { condition, body };
 
-        if initializer.is_some() {
-            body = Stmt::Block {
-                statements: Box::new(vec![initializer.unwrap(), body]),
-            };
-        };
+        if initializers.is_empty() {
+            Ok(body)
+        } else {
+            let mut statements = initializers;
+            statements.push(self.push_stmt_at(body, line, span));
+            Ok(Stmt::Block { statements })
+        }
+    }
+
+    fn switch_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Switch, "Switch statements begin with 'switch'.")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'switch'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after switch value.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before switch cases.")?;
+
+        let mut cases = vec![];
+        let mut default = None;
+        while !self.matches(&[TokenType::RightBrace]) {
+            let line = self.token_iter.peek().map(|token| token.line);
+            if default.is_some() {
+                return Err(LoxError::ParserError(
+                    line,
+                    "The default case must come last.".into(),
+                ));
+            }
+            let start = self.next_start();
+            if self.matches(&[TokenType::Default]) {
+                self.token_iter.next();
+                self.consume(TokenType::Colon, "Expect ':' after 'default'.")?;
+                default = Some(self.case_body(line, start)?);
+            } else {
+                self.consume(TokenType::Case, "Expect 'case' or 'default'.")?;
+                let value = self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after case value.")?;
+                cases.push((value, self.case_body(line, start)?));
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after switch cases.")?;
+
+        Ok(Stmt::Switch {
+            subject,
+            cases,
+            default,
+        })
+    }
 
-        Ok(body)
+    /// The statements up to the next case, as a block spanning the whole case.
+    fn case_body(&mut self, line: Option<u32>, start: usize) -> Result<StmtRef> {
+        let mut statements = vec![];
+        while !self.matches(&[TokenType::Case, TokenType::Default, TokenType::RightBrace]) {
+            statements.extend(self.declarations()?);
+        }
+        let span = self.span_from(start);
+        Ok(self.push_stmt_at(Stmt::Block { statements }, line, span))
     }
 
     fn if_statement(&mut self) -> Result<Stmt> {
@@ -192,10 +406,10 @@ impl<'a> Parser<'a> {
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
 
-        let then_branch = Box::new(self.statement()?);
+        let then_branch = self.statement_ref()?;
         let else_branch = if self.matches(&[TokenType::Else]) {
             self.token_iter.next();
-            Some(Box::new(self.statement()?))
+            Some(self.statement_ref()?)
         } else {
             None
         };
@@ -209,10 +423,10 @@ impl<'a> Parser<'a> {
 
     fn block(&mut self) -> Result<Stmt> {
         self.consume(TokenType::LeftBrace, "Blocks begin with '{'.")?;
-        let mut statements = Box::new(vec![]);
+        let mut statements = vec![];
 
         while !self.matches(&[TokenType::RightBrace]) {
-            statements.push(self.statement()?);
+            statements.extend(self.declarations()?);
         }
         self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
 
@@ -222,26 +436,58 @@ impl<'a> Parser<'a> {
     fn print_statement(&mut self) -> Result<Stmt> {
         self.consume(TokenType::Print, "Print statements begin with 'print'.")?;
         let expression = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        self.end_statement("Expect ';' after value.")?;
         Ok(Stmt::Print { expression })
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt> {
-        self.consume(TokenType::Var, "Var declarations begin with 'var'.")?;
+    /// Parses `var a = 1, b;` into one `Stmt::Var` per variable, declared one after the other
+    /// as if each had a declaration of its own. `const` declares constants the same way.
+    fn var_declaration(&mut self) -> Result<Vec<Stmt>> {
+        let constant = self.matches(&[TokenType::Const]);
+        if constant {
+            self.token_iter.next();
+        } else {
+            self.consume(TokenType::Var, "Var declarations begin with 'var'.")?;
+        }
+        let mut declarations = vec![];
+        loop {
+            if self.matches(&[TokenType::LeftBrace]) {
+                declarations.extend(self.destructuring_declarator(constant)?);
+            } else {
+                declarations.push(self.var_declarator(constant)?);
+            }
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+            self.token_iter.next();
+        }
+        self.end_statement("Expect ';' after expression.")?;
+        Ok(declarations)
+    }
+
+    fn var_declarator(&mut self, constant: bool) -> Result<Stmt> {
         if let Some(token) = self.token_iter.next() {
             match token.token_type {
                 TokenType::Identifier => {
                     let name = token.lexeme;
+                    let type_annotation = self.type_annotation(TokenType::Colon)?;
                     let initializer = if self.matches(&[TokenType::Equal]) {
                         self.token_iter.next();
                         Some(self.expression()?)
+                    } else if constant {
+                        return Err(LoxError::ParserError(
+                            self.token_iter.peek().map(|token| token.line),
+                            "Expect '=' after constant name.".into(),
+                        ));
                     } else {
                         None
                     };
-                    self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
                     Ok(Stmt::Var {
-                        name: name.to_string(),
+                        id: self.ast.new_id(),
+                        name: self.interner.intern(name),
+                        type_annotation,
                         initializer,
+                        constant,
                     })
                 }
                 _ => Err(LoxError::ParserError(
@@ -257,89 +503,240 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses `{x, y} = point` into a variable per field, initialized with the field of the
+    /// same name. Unless the object is a variable, it is stored in a hidden variable first, so
+    /// it is evaluated only once.
+    fn destructuring_declarator(&mut self, constant: bool) -> Result<Vec<Stmt>> {
+        self.consume(TokenType::LeftBrace, "Expect '{' before field names.")?;
+        let mut fields = vec![];
+        loop {
+            let start = self.next_start();
+            let name = self.identifier_name("field")?;
+            fields.push((self.interner.intern(name), self.span_from(start)));
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+            self.token_iter.next();
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after field names.")?;
+        self.consume(TokenType::Equal, "Expect '=' after destructuring pattern.")?;
+        let object = self.expression()?;
+
+        let mut declarations = vec![];
+        let object = match &self.ast[object] {
+            Expr::Variable { name, .. } => Rc::clone(name),
+            _ => {
+                let name = self.interner.intern(DESTRUCTURED);
+                declarations.push(Stmt::Var {
+                    id: self.ast.new_id(),
+                    name: Rc::clone(&name),
+                    type_annotation: None,
+                    initializer: Some(object),
+                    constant: false,
+                });
+                name
+            }
+        };
+        for (name, span) in fields {
+            let variable = Expr::Variable {
+                id: self.ast.new_id(),
+                name: Rc::clone(&object),
+            };
+            let variable = self.ast.push_expr(variable);
+            self.ast.set_expr_span(variable, span);
+            let get = Expr::Get {
+                id: self.ast.new_id(),
+                object: variable,
+                name: Rc::clone(&name),
+            };
+            let get = self.ast.push_expr(get);
+            self.ast.set_expr_span(get, span);
+            declarations.push(Stmt::Var {
+                id: self.ast.new_id(),
+                name,
+                type_annotation: None,
+                initializer: Some(get),
+                constant,
+            });
+        }
+        Ok(declarations)
+    }
+
+    /// A `var` declaration where only a single statement may stand, like the body of an `if`.
+    /// Several variables are declared in a block of their own.
+    fn var_statement(&mut self) -> Result<Stmt> {
+        let line = self.token_iter.peek().map(|token| token.line);
+        let start = self.next_start();
+        let mut declarations = self.var_declaration()?;
+        if declarations.len() == 1 {
+            return Ok(declarations.remove(0));
+        }
+        let span = self.span_from(start);
+        let statements = declarations
+            .into_iter()
+            .map(|declaration| self.push_stmt_at(declaration, line, span))
+            .collect();
+        Ok(Stmt::Block { statements })
+    }
+
+    /// Parses the next statement, or all variables of a `var` declaration. The variables share
+    /// the line and span of the declaration.
+    fn declarations(&mut self) -> Result<Vec<StmtRef>> {
+        if !self.matches(&[TokenType::Var, TokenType::Const]) {
+            return Ok(vec![self.statement_ref()?]);
+        }
+        let line = self.token_iter.peek().map(|token| token.line);
+        let start = self.next_start();
+        let declarations = self.var_declaration()?;
+        let span = self.span_from(start);
+        Ok(declarations
+            .into_iter()
+            .map(|declaration| self.push_stmt_at(declaration, line, span))
+            .collect())
+    }
+
     fn expression_statement(&mut self) -> Result<Stmt> {
         let expression = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        self.end_statement("Expect ';' after expression.")?;
         Ok(Stmt::Expression { expression })
     }
 
-    fn expression(&mut self) -> Result<Expr> {
-        self.assignment()
+    /// Parses an expression and adds it to the tree.
+    fn expression(&mut self) -> Result<ExprRef> {
+        self.nested(Self::assignment)
+    }
+
+    fn statement_ref(&mut self) -> Result<StmtRef> {
+        let line = self.token_iter.peek().map(|token| token.line);
+        let start = self.next_start();
+        let statement = self.statement()?;
+        let span = self.span_from(start);
+        Ok(self.push_stmt_at(statement, line, span))
+    }
+
+    fn push_stmt_at(&mut self, statement: Stmt, line: Option<u32>, span: Span) -> StmtRef {
+        let statement = self.ast.push_stmt(statement);
+        if let Some(line) = line {
+            self.ast.set_line(statement, line);
+        }
+        self.ast.set_stmt_span(statement, span);
+        statement
+    }
+
+    /// Adds `expr`, parsed from the tokens from `start` on.
+    fn push_expr_from(&mut self, expr: Expr, start: usize) -> ExprRef {
+        let span = self.span_from(start);
+        let expr = self.ast.push_expr(expr);
+        self.ast.set_expr_span(expr, span);
+        expr
+    }
+
+    /// Where the next token starts.
+    fn next_start(&mut self) -> usize {
+        match self.token_iter.peek() {
+            Some(token) => token.span.start,
+            None => self.previous_end(),
+        }
+    }
+
+    /// Where the last token consumed ends.
+    fn previous_end(&self) -> usize {
+        let consumed = self.tokens.len() - self.token_iter.len();
+        consumed
+            .checked_sub(1)
+            .map_or(0, |last| self.tokens[last].span.end)
+    }
+
+    /// The span from `start` to the end of the last token consumed.
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.previous_end())
     }
 
-    fn assignment(&mut self) -> Result<Expr> {
+    fn assignment(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let expr = self.or()?;
 
         if self.matches(&[TokenType::Equal]) {
             self.token_iter.next();
-            let value = self.assignment()?;
-
-            match expr {
-                Expr::Variable { id: _, name } => Ok(Expr::Assign {
-                    id: next_id(),
-                    name,
-                    value: Box::new(value),
-                }),
-                Expr::Get { object, name } => Ok(Expr::Set {
-                    object,
-                    name,
-                    value: Rc::new(value),
-                }),
-                _ => Err(LoxError::ParserError(
-                    None,
-                    "Invalid assignment target".into(),
-                )),
-            }
-        } else {
-            Ok(expr)
+            let value = self.nested(Self::assignment)?;
+
+            let assignment = match &self.ast[expr] {
+                Expr::Variable { id, name } => Expr::Assign {
+                    id: *id,
+                    name: Rc::clone(name),
+                    value,
+                },
+                Expr::Get { object, name, .. } => Expr::Set {
+                    object: *object,
+                    name: Rc::clone(name),
+                    value,
+                },
+                _ => {
+                    return Err(LoxError::ParserError(
+                        None,
+                        "Invalid assignment target".into(),
+                    ))
+                }
+            };
+            // the target turns into the assignment
+            self.ast[expr] = assignment;
+            let span = self.span_from(start);
+            self.ast.set_expr_span(expr, span);
         }
+        Ok(expr)
     }
 
-    fn or(&mut self) -> Result<Expr> {
+    fn or(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let mut expr = self.and()?;
 
         while self.matches(&[TokenType::Or]) {
             self.token_iter.next();
             let right = self.and()?;
-            expr = Expr::Logical {
-                left: Box::new(expr),
+            let logical = Expr::Logical {
+                left: expr,
                 operator: TokenType::Or,
-                right: Box::new(right),
+                right,
             };
+            expr = self.push_expr_from(logical, start);
         }
 
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr> {
+    fn and(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let mut expr = self.equality()?;
 
         while self.matches(&[TokenType::And]) {
             self.token_iter.next();
             let right = self.equality()?;
-            expr = Expr::Logical {
-                left: Box::new(expr),
+            let logical = Expr::Logical {
+                left: expr,
                 operator: TokenType::And,
-                right: Box::new(right),
+                right,
             };
+            expr = self.push_expr_from(logical, start);
         }
 
         Ok(expr)
     }
 
-    fn equality(&mut self) -> Result<Expr> {
+    fn equality(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let mut expr = self.comparison()?;
 
         while let Some(&token) = self.token_iter.peek() {
             match &token.token_type {
-                TokenType::BangEqual | TokenType::LessEqual => {
+                TokenType::BangEqual | TokenType::EqualEqual => {
                     self.token_iter.next();
-                    let right = self.addition()?;
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
+                    let right = self.comparison()?;
+                    let binary = Expr::Binary {
+                        left: expr,
                         token_type: token.token_type.clone(),
-                        right: Box::new(right),
+                        right,
                     };
+                    expr = self.push_expr_from(binary, start);
                 }
                 _ => break,
             };
@@ -348,7 +745,8 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr> {
+    fn comparison(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let mut expr = self.addition()?;
 
         while let Some(&token) = self.token_iter.peek() {
@@ -359,11 +757,12 @@ impl<'a> Parser<'a> {
                 | TokenType::LessEqual => {
                     self.token_iter.next();
                     let right = self.addition()?;
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
+                    let binary = Expr::Binary {
+                        left: expr,
                         token_type: token.token_type.clone(),
-                        right: Box::new(right),
+                        right,
                     };
+                    expr = self.push_expr_from(binary, start);
                 }
                 _ => break,
             };
@@ -372,7 +771,8 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    fn addition(&mut self) -> Result<Expr> {
+    fn addition(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let mut expr = self.multiplication()?;
 
         while let Some(&token) = self.token_iter.peek() {
@@ -380,11 +780,12 @@ impl<'a> Parser<'a> {
                 TokenType::Minus | TokenType::Plus => {
                     self.token_iter.next();
                     let right = self.multiplication()?;
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
+                    let binary = Expr::Binary {
+                        left: expr,
                         token_type: token.token_type.clone(),
-                        right: Box::new(right),
+                        right,
                     };
+                    expr = self.push_expr_from(binary, start);
                 }
                 _ => break,
             }
@@ -393,7 +794,8 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    fn multiplication(&mut self) -> Result<Expr> {
+    fn multiplication(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let mut expr = self.unary()?;
 
         while let Some(&token) = self.token_iter.peek() {
@@ -401,11 +803,12 @@ impl<'a> Parser<'a> {
                 TokenType::Slash | TokenType::Star => {
                     self.token_iter.next();
                     let right = self.unary()?;
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
+                    let binary = Expr::Binary {
+                        left: expr,
                         token_type: token.token_type.clone(),
-                        right: Box::new(right),
+                        right,
                     };
+                    expr = self.push_expr_from(binary, start);
                 }
                 _ => break,
             }
@@ -414,16 +817,17 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr> {
+    fn unary(&mut self) -> Result<ExprRef> {
         if let Some(&token) = self.token_iter.peek() {
             match &token.token_type {
-                TokenType::Bang | TokenType::Minus => {
+                TokenType::Bang | TokenType::Minus | TokenType::Typeof => {
                     self.token_iter.next();
-                    let right = self.unary()?;
-                    Ok(Expr::Unary {
+                    let right = self.nested(Self::unary)?;
+                    let unary = Expr::Unary {
                         token_type: token.token_type.clone(),
-                        right: Box::new(right),
-                    })
+                        right,
+                    };
+                    Ok(self.push_expr_from(unary, token.span.start))
                 }
                 _ => self.call(),
             }
@@ -432,13 +836,14 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn call(&mut self) -> Result<Expr> {
+    fn call(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
         let mut expr = self.primary()?;
 
         loop {
             if self.matches(&[TokenType::LeftParen]) {
                 self.token_iter.next();
-                expr = self.finish_call(expr)?;
+                expr = self.finish_call(expr, start)?;
             } else if self.matches(&[TokenType::Dot]) {
                 self.token_iter.next();
 
@@ -446,10 +851,12 @@ impl<'a> Parser<'a> {
                 if let Some(token) = token {
                     match &token.token_type {
                         TokenType::Identifier => {
-                            expr = Expr::Get {
-                                object: Box::new(expr),
-                                name: token.lexeme.to_string(),
+                            let get = Expr::Get {
+                                id: self.ast.new_id(),
+                                object: expr,
+                                name: self.interner.intern(token.lexeme),
                             };
+                            expr = self.push_expr_from(get, start);
                         }
                         _ => {
                             return Err(LoxError::ParserError(
@@ -472,38 +879,44 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr> {
+    fn finish_call(&mut self, callee: ExprRef, start: usize) -> Result<ExprRef> {
         let mut arguments = vec![];
         if !self.matches(&[TokenType::RightParen]) {
             arguments.push(self.expression()?);
             while self.matches(&[TokenType::Comma]) {
                 self.token_iter.next();
+                // a trailing comma
+                if self.matches(&[TokenType::RightParen]) {
+                    break;
+                }
                 arguments.push(self.expression()?);
             }
         }
         self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
 
-        Ok(Expr::Call {
-            callee: Box::new(callee),
-            arguments: Box::new(arguments),
-        })
+        let call = Expr::Call { callee, arguments };
+        Ok(self.push_expr_from(call, start))
     }
 
-    fn primary(&mut self) -> Result<Expr> {
+    fn primary(&mut self) -> Result<ExprRef> {
+        let start = self.next_start();
+        let expr = self.primary_expr()?;
+        Ok(self.push_expr_from(expr, start))
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr> {
         if let Some(token) = self.token_iter.next() {
             match token.token_type {
                 TokenType::False => Ok(Expr::Boolean(false)),
                 TokenType::True => Ok(Expr::Boolean(true)),
                 TokenType::Nil => Ok(Expr::Nil),
                 TokenType::Number(num) => Ok(Expr::Number(num)),
-                TokenType::String(ref string) => Ok(Expr::String(string.to_string())),
+                TokenType::String(ref string) => Ok(Expr::String(Rc::clone(string))),
                 TokenType::LeftParen => {
-                    let expr = self.expression()?;
+                    let expression = self.expression()?;
                     if let Some(token) = self.token_iter.next() {
-                        if &token.token_type == &TokenType::RightParen {
-                            Ok(Expr::Grouping {
-                                expression: Box::new(expr),
-                            })
+                        if token.token_type == TokenType::RightParen {
+                            Ok(Expr::Grouping { expression })
                         } else {
                             Err(LoxError::ParserError(
                                 Some(token.line),
@@ -515,8 +928,8 @@ impl<'a> Parser<'a> {
                     }
                 }
                 TokenType::Identifier => Ok(Expr::Variable {
-                    id: next_id(),
-                    name: token.lexeme.to_string(),
+                    id: self.ast.new_id(),
+                    name: self.interner.intern(token.lexeme),
                 }),
                 TokenType::Super => {
                     self.consume(TokenType::Dot, "Expect '.' after super.")?;
@@ -537,15 +950,42 @@ impl<'a> Parser<'a> {
                         ));
                     };
                     Ok(Expr::Super {
-                        id: next_id(),
+                        id: self.ast.new_id(),
                         keyword: "super",
-                        method: method.to_string(),
+                        method: self.interner.intern(method),
                     })
                 }
                 TokenType::This => Ok(Expr::This {
-                    id: next_id(),
+                    id: self.ast.new_id(),
                     keyword: "this",
                 }),
+                TokenType::Match => self.match_expression(),
+                TokenType::LeftBracket => {
+                    let mut elements = vec![];
+                    while !self.matches(&[TokenType::RightBracket]) {
+                        elements.push(self.expression()?);
+                        if !self.matches(&[TokenType::Comma]) {
+                            break;
+                        }
+                        self.token_iter.next();
+                    }
+                    self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+                    Ok(Expr::List { elements })
+                }
+                TokenType::LeftBrace => {
+                    let mut entries = vec![];
+                    while !self.matches(&[TokenType::RightBrace]) {
+                        let key = self.expression()?;
+                        self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                        entries.push((key, self.expression()?));
+                        if !self.matches(&[TokenType::Comma]) {
+                            break;
+                        }
+                        self.token_iter.next();
+                    }
+                    self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+                    Ok(Expr::Map { entries })
+                }
                 _ => Parser::expected_expression(None),
             }
         } else {
@@ -553,25 +993,105 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// The rest of a `match` expression, after the keyword. Arms are separated by commas, the
+    /// last one may be followed by one.
+    fn match_expression(&mut self) -> Result<Expr> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'match'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after match value.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before match arms.")?;
+
+        let mut arms = vec![];
+        while !self.matches(&[TokenType::RightBrace]) {
+            let pattern = self.pattern()?;
+            self.consume(TokenType::FatArrow, "Expect '=>' after pattern.")?;
+            let body = self.expression()?;
+            arms.push(MatchArm { pattern, body });
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+            self.token_iter.next();
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.")?;
+
+        Ok(Expr::Match { subject, arms })
+    }
+
+    /// `_`, `Class(binding)` or else a value to compare the subject with.
+    fn pattern(&mut self) -> Result<Pattern> {
+        let name = match self.token_iter.peek() {
+            Some(token) if token.token_type == TokenType::Identifier => token.lexeme,
+            _ => return Ok(Pattern::Value(self.expression()?)),
+        };
+        match self.peek_second() {
+            Some(TokenType::FatArrow) if name == "_" => {
+                self.token_iter.next();
+                Ok(Pattern::Wildcard)
+            }
+            Some(TokenType::LeftParen) => {
+                let class = self.primary()?;
+                self.token_iter.next();
+                let binding = self.identifier_name("binding")?;
+                self.consume(TokenType::RightParen, "Expect ')' after binding name.")?;
+                Ok(Pattern::Class {
+                    id: self.ast.new_id(),
+                    class,
+                    binding: self.interner.intern(binding),
+                })
+            }
+            _ => Ok(Pattern::Value(self.expression()?)),
+        }
+    }
+
     fn identifier_name(&mut self, kind: &'static str) -> Result<&'a str> {
         if let Some(token) = self.token_iter.next() {
             match token.token_type {
                 TokenType::Identifier => Ok(token.lexeme),
-                _ => {
-                    return Err(LoxError::ParserError(
-                        Some(token.line),
-                        format!("Expect {} name", kind).into(),
-                    ));
-                }
+                _ => Err(LoxError::ParserError(
+                    Some(token.line),
+                    format!("Expect {} name", kind).into(),
+                )),
             }
         } else {
-            return Err(LoxError::ParserError(
+            Err(LoxError::ParserError(
                 None,
                 format!("Expect {} name.", kind).into(),
-            ));
+            ))
         }
     }
 
+    /// Parses an optional annotation introduced by `prefix`, i.e. `: Type` or `-> Type`.
+    fn type_annotation(&mut self, prefix: TokenType) -> Result<Option<TypeAnnotation>> {
+        if !self.matches(&[prefix]) {
+            return Ok(None);
+        }
+        self.token_iter.next();
+
+        match self.token_iter.next() {
+            Some(token) if token.token_type == TokenType::Identifier => {
+                if self.options.ignore_type_annotations {
+                    Ok(None)
+                } else {
+                    Ok(Some(TypeAnnotation {
+                        name: token.lexeme.to_string(),
+                        line: token.line,
+                    }))
+                }
+            }
+            Some(token) => Err(LoxError::ParserError(
+                Some(token.line),
+                "Expect type name.".into(),
+            )),
+            None => Err(LoxError::ParserError(None, "Expect type name.".into())),
+        }
+    }
+
+    /// The type of the token after the next one.
+    fn peek_second(&self) -> Option<&TokenType> {
+        let consumed = self.tokens.len() - self.token_iter.len();
+        self.tokens.get(consumed + 1).map(|token| &token.token_type)
+    }
+
     fn matches(&mut self, token_types: &[TokenType]) -> bool {
         self.token_iter
             .peek()
@@ -594,6 +1114,53 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Consumes the `;` that ends a statement, unless `optional_semicolons` lets the statement
+    /// end without one.
+    fn end_statement(&mut self, error_message: &'static str) -> Result<()> {
+        if self.at_implicit_end() {
+            return Ok(());
+        }
+        self.consume(TokenType::Semicolon, error_message)
+    }
+
+    /// Whether the statement parsed so far may end here without a `;`, because the next token
+    /// is on a later line, closes a block or ends the source.
+    fn at_implicit_end(&mut self) -> bool {
+        if !self.options.optional_semicolons {
+            return false;
+        }
+        let consumed = self.tokens.len() - self.token_iter.len();
+        let previous = consumed.checked_sub(1).map(|last| &self.tokens[last]);
+        match self.token_iter.peek() {
+            None
+            | Some(Token {
+                token_type: TokenType::RightBrace | TokenType::Eof,
+                ..
+            }) => true,
+            // a string may span lines, the statement ends on its last one
+            Some(next) => previous.is_some_and(|previous| {
+                next.line > previous.line + previous.lexeme.matches('\n').count() as u32
+            }),
+        }
+    }
+
+    /// The token an error in `line` was raised at: the last one consumed or else the next one,
+    /// whichever is in that line. Errors without a line are at the end of the source.
+    fn error_span(&mut self, line: Option<u32>) -> Option<Span> {
+        let line = match line {
+            Some(line) => line,
+            None => return self.tokens.last().map(|token| token.span),
+        };
+        let consumed = self.tokens.len() - self.token_iter.len();
+        let previous = consumed.checked_sub(1).map(|last| &self.tokens[last]);
+        let next = self.token_iter.peek().copied();
+        previous
+            .into_iter()
+            .chain(next)
+            .find(|token| token.line == line)
+            .map(|token| token.span)
+    }
+
     fn expected_expression(line: Option<u32>) -> Result<Expr> {
         Err(LoxError::ParserError(
             line,
@@ -603,55 +1170,124 @@ impl<'a> Parser<'a> {
 }
 
 impl<'a> Iterator for Parser<'a> {
-    type Item = Result<Stmt>;
+    type Item = Result<StmtRef>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(statement) = self.queued.pop_front() {
+            return Some(Ok(statement));
+        }
         match self.token_iter.peek() {
             None
             | Some(Token {
                 token_type: TokenType::Eof,
                 ..
             }) => None,
-            _ => Some(self.statement()),
+            _ => match self.declarations() {
+                Ok(statements) => {
+                    self.queued.extend(statements);
+                    self.queued.pop_front().map(Ok)
+                }
+                Err(error) => Some(Err(error)),
+            },
         }
     }
 }
 
-pub fn parse<'a>(tokens: &'a Vec<Token<'a>>) -> (Vec<Stmt>, Vec<LoxError>) {
-    let parser = Parser::new(tokens);
-    let (expressions, errors): (Vec<_>, Vec<_>) = parser.partition(Result::is_ok);
-    let expressions = expressions.into_iter().map(Result::unwrap).collect();
-    let errors = errors.into_iter().map(Result::unwrap_err).collect();
+/// Parses the tokens of a whole program. A statement that fails to parse is reported and
+/// skipped, so the program holds every statement that parsed.
+pub fn parse<'a>(tokens: &'a [Token<'a>]) -> (Program, Vec<LoxError>) {
+    parse_with_options(tokens, ParserOptions::default())
+}
 
-    (expressions, errors)
+pub fn parse_with_options<'a>(
+    tokens: &'a [Token<'a>],
+    options: ParserOptions,
+) -> (Program, Vec<LoxError>) {
+    let (program, errors) = parse_into(tokens, Ast::with_first_id(options.first_id), options);
+    (
+        program,
+        errors.into_iter().map(|(error, _)| error).collect(),
+    )
+}
+
+/// Parses like `parse_with_options`, but adds the nodes to `ast`, whose ids the new ones follow.
+/// Errors come with the token they were raised at, if known.
+pub(crate) fn parse_into<'a>(
+    tokens: &'a [Token<'a>],
+    ast: Ast,
+    options: ParserOptions,
+) -> (Program, Vec<(LoxError, Option<Span>)>) {
+    let mut parser = Parser::new(tokens, options);
+    parser.ast = ast;
+    let mut statements = vec![];
+    let mut errors = vec![];
+    while let Some(result) = parser.next() {
+        match result {
+            Ok(statement) => statements.push(statement),
+            Err(error) => {
+                let span = parser.error_span(error.line());
+                errors.push((error, span));
+            }
+        }
+    }
+
+    (
+        Program {
+            ast: parser.ast,
+            statements,
+        },
+        errors,
+    )
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::parse;
-    use super::{Expr, Stmt};
+    use super::{parse, parse_with_options, ParserOptions};
+    use super::{Expr, Pattern, Stmt, TypeAnnotation};
+    use crate::error::LoxError;
     use crate::lexer;
-    use crate::token::TokenType;
+    use crate::token::{Span, TokenType};
+
+    use std::ops::Range;
+    use std::rc::Rc;
 
     #[test]
     fn simple_mathematical_expression() {
         let source = "(3 + 4) * 6;";
         let (tokens, _) = lexer::lex(source);
-        let (statements, errors) = parse(&tokens);
+        let (program, errors) = parse(&tokens);
         assert_eq!(errors.len(), 0);
-        assert_eq!(statements.len(), 1);
+        assert_eq!(program.statements.len(), 1);
 
-        let expected_expression = "Binary { left: \
-            Grouping { expression: Binary { left: Number(3.0), token_type: Plus, right: Number(4.0) } }, \
-            token_type: Star, \
-            right: Number(6.0) }";
-
-        match &statements[0] {
-            Stmt::Expression { expression } => {
-                assert_eq!(format!("{:?}", expression), expected_expression)
-            }
+        let ast = &program.ast;
+        let expression = match &ast[program.statements[0]] {
+            Stmt::Expression { expression } => &ast[*expression],
             something_else => panic!("Expected expression statement, got '{:?}'", something_else),
+        };
+        match expression {
+            Expr::Binary {
+                left,
+                token_type: TokenType::Star,
+                right,
+            } => {
+                assert_eq!(ast[*right], Expr::Number(6.0));
+                match &ast[*left] {
+                    Expr::Grouping { expression } => match &ast[*expression] {
+                        Expr::Binary {
+                            left,
+                            token_type: TokenType::Plus,
+                            right,
+                        } => {
+                            assert_eq!(ast[*left], Expr::Number(3.0));
+                            assert_eq!(ast[*right], Expr::Number(4.0));
+                        }
+                        something_else => panic!("Expected addition, got '{:?}'", something_else),
+                    },
+                    something_else => panic!("Expected grouping, got '{:?}'", something_else),
+                }
+            }
+            something_else => panic!("Expected multiplication, got '{:?}'", something_else),
         }
     }
 
@@ -659,38 +1295,80 @@ mod tests {
     fn var_declaration() {
         let source = "var answer = 42;";
         let (tokens, _) = lexer::lex(source);
-        let (mut statements, errors) = parse(&tokens);
+        let (program, errors) = parse(&tokens);
         assert_eq!(errors.len(), 0);
-        assert_eq!(statements.len(), 1);
-
-        match statements.remove(0) {
-            Stmt::Var { name, initializer } => {
-                assert_eq!(name, "answer");
-                assert_eq!(initializer.unwrap(), Expr::Number(42.0));
+        assert_eq!(program.statements.len(), 1);
+
+        match &program.ast[program.statements[0]] {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                assert_eq!(&**name, "answer");
+                assert_eq!(program.ast[initializer.unwrap()], Expr::Number(42.0));
             }
             _ => panic!("Expected to be of type Stmt::Var"),
         }
     }
 
+    #[test]
+    fn several_variables() {
+        let source = "var a = 1, b: Number, c = a;\n{ var d, e; }";
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+
+        // declared like three declarations of their own
+        let ast = &program.ast;
+        let names: Vec<_> = program.statements[..3]
+            .iter()
+            .map(|stmt| match &ast[*stmt] {
+                Stmt::Var { name, .. } => name.to_string(),
+                _ => panic!("Expected to be of type Stmt::Var"),
+            })
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(ast.stmt_span(program.statements[2]), Some(Span::new(0, 28)));
+        match &ast[program.statements[3]] {
+            Stmt::Block { statements } => assert_eq!(statements.len(), 2),
+            _ => panic!("Expected to be of type Stmt::Block"),
+        }
+
+        let (tokens, _) = lexer::lex("const a = 1, b = 2;");
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+        assert!(program
+            .statements
+            .iter()
+            .all(|stmt| matches!(program.ast[*stmt], Stmt::Var { constant: true, .. })));
+
+        for source in &["var a, ;", "var a = 1 b = 2;", "var a,", "const a = 1, b;"] {
+            let (tokens, _) = lexer::lex(source);
+            let (_, errors) = parse(&tokens);
+            assert!(!errors.is_empty(), "{}", source);
+        }
+    }
+
     #[test]
     fn or_operator() {
         let source = "true or false;";
         let (tokens, _) = lexer::lex(source);
-        let (statements, errors) = parse(&tokens);
+        let (program, errors) = parse(&tokens);
         assert_eq!(errors.len(), 0);
-        assert_eq!(statements.len(), 1);
-
-        match &statements[0] {
-            &Stmt::Expression { ref expression } => {
-                assert_eq!(
-                    expression,
-                    &Expr::Logical {
-                        left: Box::new(Expr::Boolean(true)),
-                        operator: TokenType::Or,
-                        right: Box::new(Expr::Boolean(false))
-                    }
-                );
-            }
+        assert_eq!(program.statements.len(), 1);
+
+        let ast = &program.ast;
+        match &ast[program.statements[0]] {
+            Stmt::Expression { expression } => match &ast[*expression] {
+                Expr::Logical {
+                    left,
+                    operator: TokenType::Or,
+                    right,
+                } => {
+                    assert_eq!(ast[*left], Expr::Boolean(true));
+                    assert_eq!(ast[*right], Expr::Boolean(false));
+                }
+                _ => panic!("Expected to be of type Expr::Logical"),
+            },
             _ => panic!("Expected to be of type Stmt::Expression"),
         }
     }
@@ -703,8 +1381,427 @@ mod tests {
             }
         "#;
         let (tokens, _) = lexer::lex(source);
-        let (statements, errors) = parse(&tokens);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+        assert_eq!(program.statements.len(), 1);
+    }
+
+    #[test]
+    fn type_annotations() {
+        let source = r#"
+            var answer: Number = 42;
+            fun greet(name: String, times) -> Nil {
+                print name;
+            }
+        "#;
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+
+        match &program.ast[program.statements[0]] {
+            Stmt::Var {
+                type_annotation, ..
+            } => assert_eq!(
+                type_annotation,
+                &Some(TypeAnnotation {
+                    name: "Number".to_string(),
+                    line: 2
+                })
+            ),
+            _ => panic!("Expected to be of type Stmt::Var"),
+        }
+        match &program.ast[program.statements[1]] {
+            Stmt::Function {
+                parameter_types,
+                return_type,
+                ..
+            } => {
+                let parameter_types: Vec<_> = parameter_types
+                    .iter()
+                    .map(|annotation| annotation.as_ref().map(|a| a.name.as_str()))
+                    .collect();
+                assert_eq!(parameter_types, vec![Some("String"), None]);
+                assert_eq!(return_type.as_ref().unwrap().name, "Nil");
+            }
+            _ => panic!("Expected to be of type Stmt::Function"),
+        }
+    }
+
+    #[test]
+    fn expression_ids() {
+        let (tokens, _) = lexer::lex("var a = 1; a = a + 1;");
+        let (first, _) = parse(&tokens);
+        let (second, _) = parse(&tokens);
+        assert_eq!(format!("{:?}", first), format!("{:?}", second));
+        assert_eq!(first.ast.next_id(), 3);
+
+        let options = ParserOptions {
+            first_id: 10,
+            ..ParserOptions::default()
+        };
+        let (mut program, _) = parse_with_options(&tokens, options);
+        match &program.ast[program.statements[0]] {
+            Stmt::Var { id, .. } => assert_eq!(*id, 10),
+            _ => panic!("Expected to be of type Stmt::Var"),
+        }
+        assert_eq!(program.ast.next_id(), 13);
+        program.ast.renumber_ids(20);
+        assert_eq!(program.ast.next_id(), 23);
+
+        let (mut shifted, _) = parse_with_options(&tokens, options);
+        shifted.ast.shift_ids(5);
+        let options = ParserOptions {
+            first_id: 15,
+            ..ParserOptions::default()
+        };
+        let (expected, _) = parse_with_options(&tokens, options);
+        assert_eq!(format!("{:?}", shifted), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let nested = |depth| format!("{}1{};", "(".repeat(depth), ")".repeat(depth));
+        let source = nested(5_000);
+        let (tokens, _) = lexer::lex(&source);
+        let (_, errors) = parse(&tokens);
+        assert_eq!(errors, []);
+
+        let source = format!("{} print 2;", nested(100_000));
+        let (tokens, _) = lexer::lex(&source);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(
+            errors,
+            [LoxError::ParserError(Some(1), "Too much nesting.".into())]
+        );
+        assert!(program.statements.is_empty());
+    }
+
+    #[test]
+    fn spans() {
+        let source = "x = -f(1).g;\nfor (;;) print x;";
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+
+        let ast = &program.ast;
+        let text = |span: Option<Span>| &source[Range::from(span.unwrap())];
+        assert_eq!(text(ast.stmt_span(program.statements[0])), "x = -f(1).g;");
+        let assignment = match &ast[program.statements[0]] {
+            Stmt::Expression { expression } => *expression,
+            _ => panic!("Expected to be of type Stmt::Expression"),
+        };
+        assert_eq!(text(ast.expr_span(assignment)), "x = -f(1).g");
+        let get = match &ast[assignment] {
+            Expr::Assign { value, .. } => match &ast[*value] {
+                Expr::Unary { right, .. } => *right,
+                _ => panic!("Expected to be of type Expr::Unary"),
+            },
+            _ => panic!("Expected to be of type Expr::Assign"),
+        };
+        assert_eq!(text(ast.expr_span(get)), "f(1).g");
+        match &ast[get] {
+            Expr::Get { object, .. } => assert_eq!(text(ast.expr_span(*object)), "f(1)"),
+            _ => panic!("Expected to be of type Expr::Get"),
+        }
+
+        // the loop is made of nodes the parser made up
+        assert_eq!(
+            text(ast.stmt_span(program.statements[1])),
+            "for (;;) print x;"
+        );
+        match &ast[program.statements[1]] {
+            Stmt::While { condition, body } => {
+                assert_eq!(ast.expr_span(*condition), None);
+                assert_eq!(text(ast.stmt_span(*body)), "print x;");
+            }
+            _ => panic!("Expected to be of type Stmt::While"),
+        }
+    }
+
+    #[test]
+    fn trailing_commas() {
+        let (tokens, _) = lexer::lex("fun f(a, b,) {}\nf(1, 2,);");
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+        match &program.ast[program.statements[0]] {
+            Stmt::Function { parameters, .. } => assert_eq!(parameters.len(), 2),
+            _ => panic!("Expected to be of type Stmt::Function"),
+        }
+        match &program.ast[program.statements[1]] {
+            Stmt::Expression { expression } => match &program.ast[*expression] {
+                Expr::Call { arguments, .. } => assert_eq!(arguments.len(), 2),
+                _ => panic!("Expected to be of type Expr::Call"),
+            },
+            _ => panic!("Expected to be of type Stmt::Expression"),
+        }
+
+        for source in &["f(,);", "f(1,,);", "fun f(,) {}", "fun f(a b) {}"] {
+            let (tokens, _) = lexer::lex(source);
+            let (_, errors) = parse(&tokens);
+            assert!(!errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn switch_statement() {
+        let source = "switch (x) { case 1: print 1; print 2; case \"a\": default: }";
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+        let ast = &program.ast;
+        match &ast[program.statements[0]] {
+            Stmt::Switch { cases, default, .. } => {
+                assert_eq!(cases.len(), 2);
+                assert_eq!(ast[cases[1].0], Expr::String("a".into()));
+                let lengths: Vec<_> = cases
+                    .iter()
+                    .map(|(_, body)| *body)
+                    .chain(*default)
+                    .map(|body| match &ast[body] {
+                        Stmt::Block { statements } => statements.len(),
+                        _ => panic!("Expected to be of type Stmt::Block"),
+                    })
+                    .collect();
+                assert_eq!(lengths, [2, 0, 0]);
+            }
+            _ => panic!("Expected to be of type Stmt::Switch"),
+        }
+
+        for source in &[
+            "switch (x) { default: case 1: }",
+            "switch (x) { print 1; }",
+            "switch (x) { case 1 print 1; }",
+        ] {
+            let (tokens, _) = lexer::lex(source);
+            let (_, errors) = parse(&tokens);
+            assert!(!errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn match_expression() {
+        let source = "print match (x) { Circle(c) => c, 1 => 2, f(y) => 3, _ => nil, };";
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 0);
+        let ast = &program.ast;
+        let expression = match &ast[program.statements[0]] {
+            Stmt::Print { expression } => *expression,
+            _ => panic!("Expected to be of type Stmt::Print"),
+        };
+        match &ast[expression] {
+            Expr::Match { arms, .. } => {
+                assert_eq!(arms.len(), 4);
+                assert!(
+                    matches!(&arms[0].pattern, Pattern::Class { binding, .. } if &**binding == "c")
+                );
+                assert!(
+                    matches!(arms[1].pattern, Pattern::Value(value) if ast[value] == Expr::Number(1.0))
+                );
+                assert!(matches!(arms[2].pattern, Pattern::Class { .. }));
+                assert_eq!(arms[3].pattern, Pattern::Wildcard);
+            }
+            _ => panic!("Expected to be of type Expr::Match"),
+        }
+
+        for source in &[
+            "match (x) { 1 2 };",
+            "match (x) { 1 => 2 3 => 4 };",
+            "match (x) { A() => 1 };",
+            "match x { _ => 1 };",
+        ] {
+            let (tokens, _) = lexer::lex(source);
+            let (_, errors) = parse(&tokens);
+            assert!(!errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn destructuring() {
+        let names = |source: &str| -> Vec<String> {
+            let (tokens, _) = lexer::lex(source);
+            let (program, errors) = parse(&tokens);
+            assert_eq!(errors.len(), 0);
+            let ast = &program.ast;
+            program
+                .statements
+                .iter()
+                .map(|stmt| match &ast[*stmt] {
+                    Stmt::Var {
+                        name,
+                        initializer: Some(initializer),
+                        ..
+                    } => match &ast[*initializer] {
+                        Expr::Get {
+                            object,
+                            name: field,
+                            ..
+                        } => match &ast[*object] {
+                            Expr::Variable { name: object, .. } => {
+                                format!("{} = {}.{}", name, object, field)
+                            }
+                            _ => panic!("Expected to be of type Expr::Variable"),
+                        },
+                        _ => name.to_string(),
+                    },
+                    _ => panic!("Expected to be of type Stmt::Var"),
+                })
+                .collect()
+        };
+        assert_eq!(names("var {x, y} = p;"), ["x = p.x", "y = p.y"]);
+        assert_eq!(
+            names("var {x} = f();"),
+            ["<destructured>", "x = <destructured>.x"]
+        );
+
+        for source in &["var {} = p;", "var {x, y};", "var {x y} = p;"] {
+            let (tokens, _) = lexer::lex(source);
+            let (_, errors) = parse(&tokens);
+            assert!(!errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn loops_and_labels() {
+        let source = "outer: loop { break outer; }\nwhile (true) break\n";
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 1);
+        let ast = &program.ast;
+        match &ast[program.statements[0]] {
+            Stmt::Labeled { label, body } => {
+                assert_eq!(&**label, "outer");
+                match &ast[*body] {
+                    Stmt::While { condition, .. } => {
+                        assert_eq!(ast[*condition], Expr::Boolean(true))
+                    }
+                    _ => panic!("Expected to be of type Stmt::While"),
+                }
+            }
+            _ => panic!("Expected to be of type Stmt::Labeled"),
+        }
+
+        let options = ParserOptions {
+            optional_semicolons: true,
+            ..ParserOptions::default()
+        };
+        let (tokens, _) = lexer::lex("while (true) break\nprint 1\n");
+        let (program, errors) = parse_with_options(&tokens, options);
+        assert_eq!((program.statements.len(), errors.len()), (2, 0));
+    }
+
+    #[test]
+    fn optional_semicolons() {
+        let source = "var a = 1\nprint a\n  + 2\nfun f() { return }\nprint \"two\nlines\" print 3";
+        let (tokens, _) = lexer::lex(source);
+        let (_, errors) = parse(&tokens);
+        assert!(!errors.is_empty());
+
+        let options = ParserOptions {
+            optional_semicolons: true,
+            ..ParserOptions::default()
+        };
+        let (program, errors) = parse_with_options(&tokens, options);
+        // the last two statements share a line, so the first of them needs its ';'
+        assert_eq!(errors.len(), 1);
+        assert_eq!(program.statements.len(), 4);
+        match &program.ast[program.statements[1]] {
+            Stmt::Print { expression } => match &program.ast[*expression] {
+                Expr::Binary { .. } => {}
+                _ => panic!("Expected to be of type Expr::Binary"),
+            },
+            _ => panic!("Expected to be of type Stmt::Print"),
+        }
+
+        let (tokens, _) = lexer::lex("print 1; print 2");
+        let (program, errors) = parse_with_options(&tokens, options);
+        assert_eq!((program.statements.len(), errors.len()), (2, 0));
+    }
+
+    #[test]
+    fn ignore_type_annotations() {
+        let source = "var answer: Number = 42;";
+        let (tokens, _) = lexer::lex(source);
+        let options = ParserOptions {
+            ignore_type_annotations: true,
+            ..ParserOptions::default()
+        };
+        let (program, errors) = parse_with_options(&tokens, options);
+        assert_eq!(errors.len(), 0);
+
+        match &program.ast[program.statements[0]] {
+            Stmt::Var {
+                type_annotation, ..
+            } => assert_eq!(type_annotation, &None),
+            _ => panic!("Expected to be of type Stmt::Var"),
+        }
+    }
+
+    #[test]
+    fn missing_type_name() {
+        let source = "var answer: = 42;";
+        let (tokens, _) = lexer::lex(source);
+        let (_, errors) = parse(&tokens);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn interned_names() {
+        let source = r#"a + a + "a";"#;
+        let (tokens, _) = lexer::lex(source);
+        let (program, errors) = parse(&tokens);
         assert_eq!(errors.len(), 0);
-        assert_eq!(statements.len(), 1);
+
+        let ast = &program.ast;
+        let sum = match &ast[program.statements[0]] {
+            Stmt::Expression { expression } => &ast[*expression],
+            _ => panic!("Expected to be of type Stmt::Expression"),
+        };
+        match sum {
+            Expr::Binary { left, .. } => match &ast[*left] {
+                Expr::Binary { left, right, .. } => match (&ast[*left], &ast[*right]) {
+                    (Expr::Variable { name: first, .. }, Expr::Variable { name: second, .. }) => {
+                        assert!(Rc::ptr_eq(first, second))
+                    }
+                    _ => panic!("Expected two variables"),
+                },
+                _ => panic!("Expected to be of type Expr::Binary"),
+            },
+            _ => panic!("Expected to be of type Expr::Binary"),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        use crate::statement::Program;
+        use crate::token::Token;
+        use crate::{resolver, Interpreter};
+
+        let source = r#"
+            class Counter {
+                init() { this.count = 0; }
+                increment() { this.count = this.count + 1; return this.count; }
+            }
+            var counter = Counter();
+            counter.increment();
+            counter.increment();
+        "#;
+        let (tokens, _) = lexer::lex(source);
+        let json = serde_json::to_string(&tokens).unwrap();
+        let tokens: Vec<Token> = serde_json::from_str(&json).unwrap();
+        let (program, _) = parse(&tokens);
+
+        let json = serde_json::to_string(&program).unwrap();
+        let mut program: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(program.statements.len(), 4);
+        program.ast.renumber_ids(0);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        assert_eq!(
+            interpreter.interpret(program),
+            Ok(crate::object::Object::Number(2.0))
+        );
     }
 }
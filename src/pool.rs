@@ -0,0 +1,142 @@
+use crate::error::Result;
+use crate::interpreter::InterpreterConfig;
+use crate::lox::Lox;
+
+/// Interpreters that already ran a prelude, for hosts running many short scripts, such as one
+/// per request of a server. Every script checked out gets the globals the prelude defined, but
+/// none of those defined by the scripts before it. Interpreters cannot leave their thread, so
+/// each thread needs a pool of its own.
+///
+/// ```
+/// use rlox::{InterpreterConfig, InterpreterPool};
+///
+/// let prelude = "fun greet(name) { return \"hello \" + name; }";
+/// let mut pool = InterpreterPool::new(prelude, InterpreterConfig::default(), 2)?;
+///
+/// let mut lox = pool.checkout()?;
+/// lox.eval("var greeting = greet(\"world\");")?;
+/// pool.checkin(lox);
+///
+/// let mut lox = pool.checkout()?;
+/// assert!(lox.eval("greeting;").is_err());
+/// # Ok::<(), rlox::LoxError>(())
+/// ```
+pub struct InterpreterPool {
+    prelude: String,
+    config: InterpreterConfig,
+    idle: Vec<Lox>,
+}
+
+impl InterpreterPool {
+    /// Prepares `size` interpreters, failing if the prelude does. More are prepared when all of
+    /// them are checked out.
+    pub fn new(prelude: &str, config: InterpreterConfig, size: usize) -> Result<Self> {
+        let mut pool = Self {
+            prelude: prelude.to_string(),
+            config,
+            idle: Vec::with_capacity(size),
+        };
+        for _ in 0..size {
+            let lox = pool.prepare()?;
+            pool.idle.push(lox);
+        }
+        Ok(pool)
+    }
+
+    fn prepare(&self) -> Result<Lox> {
+        let mut lox = Lox::with_config(self.config);
+        lox.eval(&self.prelude)?;
+        lox.interpreter().checkpoint()?;
+        Ok(lox)
+    }
+
+    /// An interpreter with just the prelude's globals defined.
+    pub fn checkout(&mut self) -> Result<Lox> {
+        match self.idle.pop() {
+            Some(lox) => Ok(lox),
+            None => self.prepare(),
+        }
+    }
+
+    /// Takes back an interpreter once its script is done, forgetting everything the script
+    /// defined or changed in the prelude's values, see `Interpreter::reset`. Settings like its
+    /// output stay as they are. An interpreter that fails to reset is dropped instead.
+    pub fn checkin(&mut self, mut lox: Lox) {
+        if lox.interpreter().reset().is_ok() {
+            self.idle.push(lox);
+        }
+    }
+
+    /// How many interpreters are ready to be checked out without running the prelude.
+    pub fn idle(&self) -> usize {
+        self.idle.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::InterpreterPool;
+    use crate::error::LoxError;
+    use crate::interpreter::InterpreterConfig;
+    use crate::object::Object;
+
+    fn pool(prelude: &str) -> InterpreterPool {
+        InterpreterPool::new(prelude, InterpreterConfig::default(), 1).unwrap()
+    }
+
+    #[test]
+    fn reset_keeps_the_prelude() {
+        let mut pool = pool("var base = 10; fun add(x) { return base + x; }");
+        let mut lox = pool.checkout().unwrap();
+        lox.eval("base = 20; fun sub(x) { return base - x; }")
+            .unwrap();
+        assert_eq!(lox.eval("add(1) + sub(1);"), Ok(Object::Number(40.0)));
+        pool.checkin(lox);
+
+        let mut lox = pool.checkout().unwrap();
+        assert_eq!(lox.eval("add(1);"), Ok(Object::Number(11.0)));
+        assert_eq!(
+            lox.eval("sub;"),
+            Err(LoxError::EnvironmentError(
+                "Undefined variable 'sub'.".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn reset_copies_the_prelude_values() {
+        let mut pool = pool(
+            "var registry = []; var byName = {}; class Box {} var box = Box(); box.items = registry;",
+        );
+        let mut lox = pool.checkout().unwrap();
+        lox.eval("registry.push(1); byName.set(\"a\", 1); box.extra = true;")
+            .unwrap();
+        assert_eq!(lox.eval("box.items.len();"), Ok(Object::Number(1.0)));
+        pool.checkin(lox);
+
+        let mut lox = pool.checkout().unwrap();
+        assert_eq!(lox.eval("registry.len();"), Ok(Object::Number(0.0)));
+        assert_eq!(lox.eval("byName.size();"), Ok(Object::Number(0.0)));
+        assert!(lox.eval("box.extra;").is_err());
+        // the copies share what the originals shared
+        lox.eval("registry.push(2);").unwrap();
+        assert_eq!(lox.eval("box.items.len();"), Ok(Object::Number(1.0)));
+    }
+
+    #[test]
+    fn prepares_more_when_empty() {
+        let mut pool = pool("var x = 1;");
+        let first = pool.checkout().unwrap();
+        let mut second = pool.checkout().unwrap();
+        assert_eq!(second.eval("x;"), Ok(Object::Number(1.0)));
+        pool.checkin(first);
+        pool.checkin(second);
+        assert_eq!(pool.idle(), 2);
+    }
+
+    #[test]
+    fn prelude_errors() {
+        assert!(InterpreterPool::new("nope;", InterpreterConfig::default(), 1).is_err());
+    }
+}
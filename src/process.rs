@@ -0,0 +1,239 @@
+use crate::error::{LoxError, Result};
+use crate::functions::Function;
+use crate::interpreter::Interpreter;
+use crate::natives::{native, signature, NativeSignature};
+use crate::object::Object;
+use crate::userdata::Userdata;
+
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+
+/// A running child process, as returned by `spawn()`. Its stderr goes where the interpreter's
+/// does.
+pub struct Process {
+    child: RefCell<Child>,
+    stdin: RefCell<Option<ChildStdin>>,
+    stdout: RefCell<Option<BufReader<ChildStdout>>>,
+}
+
+impl Process {
+    fn spawn(program: &str, arguments: &[String]) -> Result<Object> {
+        let mut child = Command::new(program)
+            .args(arguments)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|error| failed("spawn", error))?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().map(BufReader::new);
+        Ok(Object::Userdata(Userdata::new(Process {
+            child: RefCell::new(child),
+            stdin: RefCell::new(stdin),
+            stdout: RefCell::new(stdout),
+        })))
+    }
+
+    fn write_stdin(&self, arguments: &[Object]) -> Result<Object> {
+        let text = match &arguments[0] {
+            Object::String(text) => text,
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "writeStdin() expects a string, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let mut stdin = self.stdin.borrow_mut();
+        let stdin = stdin
+            .as_mut()
+            .ok_or_else(|| LoxError::InterpreterError("The process's stdin is closed.".into()))?;
+        stdin
+            .write_all(text.as_bytes())
+            .and_then(|_| stdin.flush())
+            .map_err(|error| failed("writeStdin", error))?;
+        Ok(Object::Nil)
+    }
+
+    /// Lets the process see the end of its input, as many tools wait for it before they answer.
+    /// Closing it twice does nothing.
+    fn close_stdin(&self) -> Result<Object> {
+        self.stdin.borrow_mut().take();
+        Ok(Object::Nil)
+    }
+
+    /// The next line the process printed, without its line break, or nil once it closed its
+    /// stdout. Blocks until there is a whole line.
+    fn read_stdout_line(&self) -> Result<Object> {
+        let mut stdout = self.stdout.borrow_mut();
+        let stdout = match stdout.as_mut() {
+            Some(stdout) => stdout,
+            None => return Ok(Object::Nil),
+        };
+        let mut line = Vec::new();
+        let read = stdout
+            .read_until(b'\n', &mut line)
+            .map_err(|error| failed("readStdoutLine", error))?;
+        if read == 0 {
+            return Ok(Object::Nil);
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Ok(Object::String(String::from_utf8_lossy(&line).into()))
+    }
+
+    /// Closes stdin and waits for the process to exit. Returns its exit code, or nil if a signal
+    /// ended it.
+    fn wait(&self) -> Result<Object> {
+        self.stdin.borrow_mut().take();
+        let status = self
+            .child
+            .borrow_mut()
+            .wait()
+            .map_err(|error| failed("wait", error))?;
+        Ok(status
+            .code()
+            .map_or(Object::Nil, |code| Object::Number(code as f64)))
+    }
+
+    /// Killing a process that already exited does nothing.
+    fn kill(&self) -> Result<Object> {
+        let mut child = self.child.borrow_mut();
+        let exited = child
+            .try_wait()
+            .map_err(|error| failed("kill", error))?
+            .is_some();
+        if !exited {
+            child.kill().map_err(|error| failed("kill", error))?;
+            child.wait().map_err(|error| failed("kill", error))?;
+        }
+        Ok(Object::Nil)
+    }
+}
+
+/// `spawn(program, arguments)` with its signature. The processes it returns get their methods
+/// from `define_methods`.
+pub(crate) fn functions() -> Vec<(Rc<dyn Function>, NativeSignature)> {
+    let spawn = signature(&["String", "Any"], "Any");
+    let function = native("spawn", 2, |arguments| {
+        let program = match &arguments[0] {
+            Object::String(program) => program,
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "spawn() expects a program name, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let list = match &arguments[1] {
+            Object::List(list) => list.borrow(),
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "spawn() expects a list of arguments, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let process_arguments = list
+            .iter()
+            .map(|argument| match argument {
+                Object::String(argument) => Ok(argument.to_string()),
+                _ => Err(LoxError::InterpreterError(
+                    format!(
+                        "spawn() expects string arguments, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Process::spawn(program, &process_arguments)
+    });
+    vec![(function, spawn)]
+}
+
+/// Defines the methods of processes: `writeStdin(text)`, `closeStdin()`, `readStdoutLine()`,
+/// `wait()` and `kill()`.
+pub(crate) fn define_methods(interpreter: &mut Interpreter) {
+    interpreter.define_method("writeStdin", 1, |process: &Process, arguments| {
+        process.write_stdin(arguments)
+    });
+    interpreter.define_method("closeStdin", 0, |process: &Process, _| {
+        process.close_stdin()
+    });
+    interpreter.define_method("readStdoutLine", 0, |process: &Process, _| {
+        process.read_stdout_line()
+    });
+    interpreter.define_method("wait", 0, |process: &Process, _| process.wait());
+    interpreter.define_method("kill", 0, |process: &Process, _| process.kill());
+}
+
+fn failed(operation: &str, error: std::io::Error) -> LoxError {
+    LoxError::InterpreterError(format!("{}() failed: {}.", operation, error).into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::object::Object;
+    use crate::test_support::eval;
+
+    #[test]
+    fn pipes_through_a_child() {
+        let source = r#"
+            var process = spawn("cat", []);
+            // Lox strings have no escapes, the line breaks are part of the string
+            process.writeStdin("first
+second
+");
+            var first = process.readStdoutLine();
+            process.closeStdin();
+            var second = process.readStdoutLine();
+            var end = process.readStdoutLine();
+            [first, second, end, process.wait()];
+        "#;
+        assert_eq!(eval(source).unwrap().to_string(), "[first, second, nil, 0]");
+    }
+
+    #[test]
+    fn kill() {
+        let source = r#"
+            var process = spawn("sleep", ["10"]);
+            process.kill();
+            process.kill();
+            process.wait();
+        "#;
+        assert_eq!(eval(source), Ok(Object::Nil));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            eval("spawn(\"true\", [1]);"),
+            Err(LoxError::InterpreterError(
+                "spawn() expects string arguments, but got 'number'.".into()
+            ))
+        );
+        assert_eq!(
+            eval("var process = spawn(\"true\", []); process.wait(); process.writeStdin(\"x\");"),
+            Err(LoxError::InterpreterError(
+                "The process's stdin is closed.".into()
+            ))
+        );
+    }
+}
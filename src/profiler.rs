@@ -0,0 +1,165 @@
+//! Call counts and timing per function, behind `--profile`. See `Interpreter::enable_profiler`.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// What the profiler measured for the functions of one name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    /// Natives are counted apart from Lox functions of the same name.
+    pub native: bool,
+    pub calls: u64,
+    /// The time spent in the calls, including the functions they called. Recursive calls are
+    /// counted once, by the outermost call.
+    pub inclusive: Duration,
+    /// The time spent in the calls themselves.
+    pub exclusive: Duration,
+    /// Calls in progress.
+    active: usize,
+}
+
+/// A call in progress.
+struct Frame {
+    entry: usize,
+    start: Instant,
+    /// The time spent in the calls it made.
+    children: Duration,
+}
+
+/// Measures the calls the interpreter reports with `enter` and `exit`.
+#[derive(Default)]
+pub struct Profiler {
+    entries: Vec<Entry>,
+    /// The index of each entry, by name and whether it is native.
+    indices: HashMap<(String, bool), usize>,
+    stack: Vec<Frame>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A call of the function `name` starts.
+    pub fn enter(&mut self, name: &str, native: bool) {
+        let entries = &mut self.entries;
+        let entry = *self
+            .indices
+            .entry((name.to_string(), native))
+            .or_insert_with(|| {
+                entries.push(Entry {
+                    name: name.to_string(),
+                    native,
+                    calls: 0,
+                    inclusive: Duration::ZERO,
+                    exclusive: Duration::ZERO,
+                    active: 0,
+                });
+                entries.len() - 1
+            });
+        self.entries[entry].active += 1;
+        self.stack.push(Frame {
+            entry,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    /// The innermost call in progress returns.
+    pub fn exit(&mut self) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let entry = &mut self.entries[frame.entry];
+        entry.calls += 1;
+        entry.active -= 1;
+        if entry.active == 0 {
+            entry.inclusive += elapsed;
+        }
+        entry.exclusive += elapsed.saturating_sub(frame.children);
+        if let Some(caller) = self.stack.last_mut() {
+            caller.children += elapsed;
+        }
+    }
+
+    /// The functions called so far, the ones that took the most time themselves first.
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut entries = self.entries.clone();
+        entries.sort_by(|a, b| {
+            b.exclusive
+                .cmp(&a.exclusive)
+                .then_with(|| b.calls.cmp(&a.calls))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        entries
+    }
+}
+
+/// A table of the entries.
+impl Display for Profiler {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:>10} {:>14} {:>14}  function",
+            "calls", "inclusive ms", "exclusive ms"
+        )?;
+        for entry in self.entries() {
+            write!(
+                f,
+                "\n{:>10} {:>14.3} {:>14.3}  {}{}",
+                entry.calls,
+                entry.inclusive.as_secs_f64() * 1000.0,
+                entry.exclusive.as_secs_f64() * 1000.0,
+                entry.name,
+                if entry.native { " (native)" } else { "" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Profiler;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn timing() {
+        let mut profiler = Profiler::new();
+        profiler.enter("outer", false);
+        sleep(Duration::from_millis(5));
+        for _ in 0..2 {
+            profiler.enter("inner", false);
+            profiler.enter("inner", false);
+            sleep(Duration::from_millis(10));
+            profiler.exit();
+            profiler.exit();
+        }
+        profiler.enter("inner", true);
+        profiler.exit();
+        profiler.exit();
+
+        let entries = profiler.entries();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.native, entry.calls))
+            .collect();
+        assert_eq!(
+            names,
+            [("inner", false, 4), ("outer", false, 1), ("inner", true, 1)]
+        );
+        let (inner, outer) = (&entries[0], &entries[1]);
+        assert!(inner.inclusive >= Duration::from_millis(20));
+        assert!(inner.inclusive < outer.inclusive);
+        assert!(outer.exclusive >= Duration::from_millis(5));
+        assert!(outer.exclusive < Duration::from_millis(20));
+        assert!(profiler.to_string().contains(" outer\n"));
+        assert!(profiler.to_string().ends_with(" inner (native)"));
+    }
+}
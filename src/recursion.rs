@@ -0,0 +1,17 @@
+/// How deeply statements and expressions may nest in the source. The parser and the resolver
+/// fail beyond it, so the passes after them need not check.
+pub(crate) const MAX_SYNTAX_DEPTH: usize = 10_000;
+
+/// How much stack has to be left for another level of recursion, more than a level of the
+/// parser, resolver, optimizer or interpreter takes up in a debug build.
+const RED_ZONE: usize = 256 * 1024;
+
+/// How much stack is added at a time once it runs low.
+const STACK_SEGMENT: usize = 8 * 1024 * 1024;
+
+/// Runs `f` one level of recursion deeper, on a new stack segment if the current one is about
+/// to run out. Syntax trees and Lox calls nest as deeply as a program likes, the thread running
+/// them (a host's, a test's) may have a small stack.
+pub(crate) fn grow_stack<T>(f: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, f)
+}
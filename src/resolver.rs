@@ -1,7 +1,10 @@
 use crate::error::{LoxError, Result};
-use crate::statement::{Expr, ExprId, Stmt};
+use crate::recursion::{self, MAX_SYNTAX_DEPTH};
+use crate::statement::{Ast, Expr, ExprId, ExprRef, Pattern, Program, Stmt, StmtRef};
+use crate::token::Span;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 enum FunctionType {
@@ -18,80 +21,226 @@ enum ClassType {
     SubClass,
 }
 
-pub type Depth = u64;
+pub type Slot = usize;
+
+/// The locals of a function in scope at some point, with their slots.
+pub type ScopeLocals = Rc<[(Rc<str>, Slot)]>;
+
+/// Where a local variable lives, seen from the function that is running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Local {
+    /// Declared by the running function, at index `slot` of its frame.
+    Slot(Slot),
+    /// Declared by an enclosing function, the running one captured it as its `n`th upvalue.
+    Upvalue(usize),
+}
+
+/// Where a variable is declared.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Definition {
+    /// By a `var`, `const`, `fun`, `class` or `enum` statement.
+    Statement(StmtRef),
+    /// As the `n`th parameter of the function statement.
+    Parameter(StmtRef, usize),
+    /// As the binding of the `match` pattern whose class is the expression.
+    Binding(ExprRef),
+}
+
+/// What the interpreter needs to know about the variables of a program.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    /// Local variable accesses and declarations, everything else is a global.
+    pub locals: HashMap<ExprId, Local>,
+    /// The variables each function captures when it is created, keyed by the function's
+    /// declaration and seen from the function declaring it.
+    pub captures: HashMap<ExprId, Rc<[Local]>>,
+    /// Where `super` expressions find `this`.
+    pub super_receivers: HashMap<ExprId, Local>,
+    /// The locals of the running function in scope at the first statement of each line, in the
+    /// order of their slots. Only `resolve_for_debugging` records them.
+    pub line_locals: HashMap<u32, ScopeLocals>,
+    /// The locals of the running function in scope where the global `locals` is used, as in
+    /// `line_locals`, for the `locals()` native. Keyed by the variable expression.
+    pub call_site_locals: HashMap<ExprId, ScopeLocals>,
+    /// The variable and assignment expressions, in the order they were resolved, with the
+    /// declaration of the variable each one uses. Only `resolve_for_editing` records them, and
+    /// only for variables whose declaration is known.
+    pub references: Vec<(ExprRef, Definition)>,
+}
+
+impl Resolution {
+    /// Follows the nodes of the program to their new ids after `Ast::shift_ids`.
+    pub fn shift_ids(&mut self, offset: ExprId) {
+        fn shift<T>(map: &mut HashMap<ExprId, T>, offset: ExprId) {
+            *map = map
+                .drain()
+                .map(|(id, value)| (id + offset, value))
+                .collect();
+        }
+        shift(&mut self.locals, offset);
+        shift(&mut self.captures, offset);
+        shift(&mut self.super_receivers, offset);
+        shift(&mut self.call_site_locals, offset);
+    }
+}
+
+struct Variable {
+    defined: bool,
+    slot: Slot,
+    constant: bool,
+    definition: Option<Definition>,
+}
+
+/// The scopes of the function being resolved, the top-level code counts as a function too.
+/// Every local of a function gets its own slot in the function's frame, no matter how deeply
+/// nested the block declaring it is.
+#[derive(Default)]
+struct FunctionScopes<'a> {
+    scopes: Vec<HashMap<&'a str, Variable>>,
+    slots: usize,
+    upvalues: Vec<Local>,
+    /// The loops around the statement being resolved, with their labels.
+    loops: Vec<Option<&'a str>>,
+}
 
 struct Resolver<'a> {
-    scopes: Vec<HashMap<&'a str, bool>>,
-    expr_id_to_depth: HashMap<ExprId, Depth>,
+    ast: &'a Ast,
+    functions: Vec<FunctionScopes<'a>>,
+    resolution: Resolution,
     current_function: FunctionType,
     current_class: ClassType,
+    record_line_locals: bool,
+    record_references: bool,
+    /// Where the globals are declared, for `Resolution::references`. A global declared more
+    /// than once is found at the declaration before the code using it, or else at the first.
+    global_definitions: HashMap<&'a str, Definition>,
+    /// The innermost statement or expression with a span that an error came from.
+    error_span: Option<Span>,
+    /// The globals declared with `const` so far. Globals of earlier programs run by the same
+    /// interpreter are unknown here, the interpreter checks assignments to those.
+    global_constants: HashSet<&'a str>,
+    /// How many statements and expressions the one being resolved is nested in.
+    depth: usize,
 }
 
 impl<'a> Resolver<'a> {
-    fn new() -> Self {
+    fn new(ast: &'a Ast) -> Self {
         Self {
-            scopes: Vec::new(),
-            expr_id_to_depth: HashMap::new(),
+            ast,
+            functions: vec![FunctionScopes::default()],
+            resolution: Resolution::default(),
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            record_line_locals: false,
+            record_references: false,
+            global_definitions: HashMap::new(),
+            error_span: None,
+            global_constants: HashSet::new(),
+            depth: 0,
         }
     }
 
-    fn resolve(&mut self, statements: &'a [Stmt]) -> Result<HashMap<ExprId, Depth>> {
+    /// Resolves a statement or an expression inside the one being resolved. Trees that were not
+    /// parsed from source, e.g. deserialized ones, may nest deeper than the parser allows.
+    /// Records `span`, the source of the node, as where an error of it came from.
+    fn nested(
+        &mut self,
+        span: Option<Span>,
+        resolve: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let result = if self.depth >= MAX_SYNTAX_DEPTH {
+            Err(LoxError::ResolverError("Too much nesting."))
+        } else {
+            self.depth += 1;
+            let result = recursion::grow_stack(|| resolve(self));
+            self.depth -= 1;
+            result
+        };
+        if result.is_err() && self.error_span.is_none() {
+            self.error_span = span;
+        }
+        result
+    }
+
+    fn resolve(&mut self, statements: &[StmtRef]) -> Result<Resolution> {
         self.resolve_statements(statements)?;
-        Ok(std::mem::take(&mut self.expr_id_to_depth))
+        Ok(std::mem::take(&mut self.resolution))
     }
 
-    fn resolve_statements(&mut self, stmts: &'a [Stmt]) -> Result<()> {
-        for stmt in stmts {
+    fn resolve_statements(&mut self, stmts: &[StmtRef]) -> Result<()> {
+        for &stmt in stmts {
             self.resolve_statement(stmt)?;
         }
         Ok(())
     }
 
-    fn resolve_statement(&mut self, stmt: &'a Stmt) -> Result<()> {
-        match stmt {
+    fn resolve_statement(&mut self, stmt: StmtRef) -> Result<()> {
+        let span = self.ast.stmt_span(stmt);
+        self.nested(span, |resolver| resolver.visit_statement(stmt))
+    }
+
+    fn visit_statement(&mut self, stmt: StmtRef) -> Result<()> {
+        let ast = self.ast;
+        if self.record_line_locals && !matches!(ast[stmt], Stmt::Block { .. }) {
+            if let Some(line) = ast.line(stmt) {
+                self.record_locals(line);
+            }
+        }
+        match &ast[stmt] {
             Stmt::Block { statements } => {
                 self.begin_scope();
-                self.resolve_statements(statements.as_ref())?;
+                self.resolve_statements(statements)?;
                 self.end_scope();
             }
-            Stmt::Var { name, initializer } => {
-                self.declare(name);
+            Stmt::Var {
+                id,
+                name,
+                initializer,
+                constant,
+                ..
+            } => {
+                self.declare(*id, name, Definition::Statement(stmt));
                 self.define(name);
-                if let Some(initializer) = initializer {
+                if *constant {
+                    self.make_constant(name);
+                }
+                if let Some(initializer) = *initializer {
                     self.resolve_expression(initializer)?;
                 }
             }
             Stmt::Function {
+                id,
                 name,
                 parameters,
                 body,
+                ..
             } => {
-                self.resolve_function(name, parameters, body, FunctionType::Function)?;
+                self.declare(*id, name, Definition::Statement(stmt));
+                self.define(name);
+                self.resolve_function(stmt, parameters, body, FunctionType::Function)?;
             }
             Stmt::Expression { expression } => {
-                self.resolve_expression(expression)?;
+                self.resolve_expression(*expression)?;
             }
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
             } => {
-                self.resolve_expression(condition)?;
-                self.resolve_statement(then_branch.as_ref())?;
-                if let Some(stmt) = else_branch {
+                self.resolve_expression(*condition)?;
+                self.resolve_statement(*then_branch)?;
+                if let Some(stmt) = *else_branch {
                     self.resolve_statement(stmt)?;
                 }
             }
-            Stmt::Print { expression } => self.resolve_expression(expression)?,
+            Stmt::Print { expression } => self.resolve_expression(*expression)?,
             Stmt::Return { value } => {
                 if self.current_function == FunctionType::None {
                     return Err(LoxError::ResolverError(
                         "Cannot return from top-level code.",
                     ));
                 }
-                if let Some(value) = value {
+                if let Some(value) = *value {
                     if self.current_function == FunctionType::Initializer {
                         return Err(LoxError::ResolverError(
                             "Cannot return a value from an initializer.",
@@ -101,24 +250,62 @@ impl<'a> Resolver<'a> {
                 }
             }
             Stmt::While { condition, body } => {
-                self.resolve_expression(condition)?;
-                self.resolve_statement(body)?;
+                self.resolve_expression(*condition)?;
+                self.resolve_loop(None, *body)?;
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.resolve_loop(None, *body)?;
+                self.resolve_expression(*condition)?;
+            }
+            Stmt::Labeled { label, body } => self.resolve_loop(Some(label), *body)?,
+            Stmt::Break { label } => {
+                let loops = &self.functions.last().unwrap().loops;
+                match label {
+                    _ if loops.is_empty() => {
+                        return Err(LoxError::ResolverError("Cannot break outside of a loop."))
+                    }
+                    Some(label) if !loops.contains(&Some(&**label)) => {
+                        return Err(LoxError::ResolverError(
+                            "Cannot break to a label that does not name an enclosing loop.",
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                self.resolve_expression(*subject)?;
+                for (value, body) in cases {
+                    self.resolve_expression(*value)?;
+                    self.resolve_statement(*body)?;
+                }
+                if let Some(body) = *default {
+                    self.resolve_statement(body)?;
+                }
+            }
+            Stmt::Enum { id, name, .. } => {
+                self.declare(*id, name, Definition::Statement(stmt));
+                self.define(name);
             }
             Stmt::Class {
+                id,
                 name,
                 superclass,
                 methods,
             } => {
                 let enclosing_class = self.current_class;
                 self.current_class = ClassType::Class;
-                self.declare(name);
+                self.declare(*id, name, Definition::Statement(stmt));
                 self.define(name);
 
-                if let Some(superclass) = superclass {
+                if let Some(superclass) = *superclass {
                     if let Expr::Variable {
                         id: _,
                         name: superclass_name,
-                    } = superclass.as_ref()
+                    } = &ast[superclass]
                     {
                         if name == superclass_name {
                             return Err(LoxError::ResolverError(
@@ -129,41 +316,27 @@ impl<'a> Resolver<'a> {
 
                     self.current_class = ClassType::SubClass;
                     self.resolve_expression(superclass)?;
-
-                    self.begin_scope();
-                    self.scopes
-                        .last_mut()
-                        .map(|scope| scope.insert("super", true));
                 }
-                self.begin_scope();
-                self.scopes
-                    .last_mut()
-                    .map(|scope| scope.insert("this", true));
 
-                for method in methods.as_ref() {
+                for &method in methods {
                     if let Stmt::Function {
                         name,
                         parameters,
                         body,
-                    } = method
+                        ..
+                    } = &ast[method]
                     {
-                        let function_type = if name == "init" {
+                        let function_type = if &**name == "init" {
                             FunctionType::Initializer
                         } else {
                             FunctionType::Method
                         };
-                        self.resolve_function(name, parameters, body, function_type)?;
+                        self.resolve_function(method, parameters, body, function_type)?;
                     } else {
                         unreachable!()
                     }
                 }
 
-                self.end_scope();
-
-                if superclass.is_some() {
-                    self.end_scope();
-                }
-
                 self.current_class = enclosing_class;
             }
         };
@@ -172,37 +345,63 @@ impl<'a> Resolver<'a> {
 
     fn resolve_function(
         &mut self,
-        name: &'a str,
-        parameters: &'a Vec<String>,
-        body: &'a [Stmt],
+        declaration: StmtRef,
+        parameters: &'a [Rc<str>],
+        body: &[StmtRef],
         function_type: FunctionType,
     ) -> Result<()> {
-        self.declare(name);
-        self.define(name);
         let enclosing_function = self.current_function;
         self.current_function = function_type;
+        self.functions.push(FunctionScopes::default());
         self.begin_scope();
-        for param in parameters {
-            self.declare(&param);
-            self.define(&param);
+        // methods find `this` and, in subclasses, `super` in front of their parameters
+        if matches!(
+            function_type,
+            FunctionType::Method | FunctionType::Initializer
+        ) {
+            self.define_keyword("this");
+            if self.current_class == ClassType::SubClass {
+                self.define_keyword("super");
+            }
         }
-        self.resolve_statements(body)?;
-        self.end_scope();
+        for (index, param) in parameters.iter().enumerate() {
+            self.declare_parameter(param, Definition::Parameter(declaration, index));
+        }
+        let result = self.resolve_statements(body);
+        let function = self.functions.pop().unwrap();
+        let id = match &self.ast[declaration] {
+            Stmt::Function { id, .. } => *id,
+            _ => unreachable!(),
+        };
+        self.resolution
+            .captures
+            .insert(id, function.upvalues.into());
         self.current_function = enclosing_function;
-        Ok(())
+        result
     }
 
-    fn resolve_expression(&mut self, expr: &'a Expr) -> Result<()> {
-        match expr {
+    fn resolve_expression(&mut self, expr: ExprRef) -> Result<()> {
+        let span = self.ast.expr_span(expr);
+        self.nested(span, |resolver| resolver.visit_expression(expr))
+    }
+
+    fn visit_expression(&mut self, expr: ExprRef) -> Result<()> {
+        let ast = self.ast;
+        match &ast[expr] {
             Expr::Variable { id, name } => {
-                if let Some(scope) = self.scopes.last() {
-                    if scope.get::<str>(name) == Some(&false) {
+                if let Some(scope) = self.current_scopes().last() {
+                    if scope.get::<str>(name).map(|variable| variable.defined) == Some(false) {
                         return Err(LoxError::ResolverError(
                             "Cannot read local variable in ints own initializer",
                         ));
                     }
                     self.resolve_local(*id, name);
                 }
+                self.record_reference(expr, name);
+                if name.as_ref() == "locals" && !self.resolution.locals.contains_key(id) {
+                    let locals = self.visible_locals();
+                    self.resolution.call_site_locals.insert(*id, locals);
+                }
             }
             Expr::This { id, keyword } => {
                 if self.current_class == ClassType::None {
@@ -228,118 +427,572 @@ impl<'a> Resolver<'a> {
                     ));
                 }
                 self.resolve_local(*id, keyword);
+                if let Some(receiver) = self.lookup(self.functions.len() - 1, "this") {
+                    self.resolution.super_receivers.insert(*id, receiver);
+                }
             }
             Expr::Assign { id, value, name } => {
-                self.resolve_expression(value)?;
+                if self.is_constant(name) {
+                    return Err(LoxError::ResolverError("Cannot assign to a constant."));
+                }
+                self.resolve_expression(*value)?;
                 self.resolve_local(*id, name);
+                self.record_reference(expr, name);
             }
             Expr::Binary {
                 left,
                 token_type: _,
                 right,
             } => {
-                self.resolve_expression(left)?;
-                self.resolve_expression(right)?;
+                self.resolve_expression(*left)?;
+                self.resolve_expression(*right)?;
             }
             Expr::Call { callee, arguments } => {
-                self.resolve_expression(callee)?;
-                for arg in arguments.as_ref() {
+                self.resolve_expression(*callee)?;
+                for &arg in arguments {
                     self.resolve_expression(arg)?;
                 }
             }
-            Expr::Get { object, name: _ } => {
-                self.resolve_expression(object)?;
+            Expr::Get { object, .. } => {
+                self.resolve_expression(*object)?;
             }
             Expr::Set {
                 object,
                 name: _,
                 value,
             } => {
-                self.resolve_expression(object)?;
-                self.resolve_expression(value)?;
+                self.resolve_expression(*object)?;
+                self.resolve_expression(*value)?;
             }
             Expr::Grouping { expression } => {
-                self.resolve_expression(expression)?;
+                self.resolve_expression(*expression)?;
             }
             Expr::Logical {
                 left,
                 operator: _,
                 right,
             } => {
-                self.resolve_expression(left)?;
-                self.resolve_expression(right)?;
+                self.resolve_expression(*left)?;
+                self.resolve_expression(*right)?;
             }
             Expr::Unary {
                 token_type: _,
                 right,
             } => {
-                self.resolve_expression(right)?;
+                self.resolve_expression(*right)?;
+            }
+            Expr::List { elements } => {
+                for &element in elements {
+                    self.resolve_expression(element)?;
+                }
+            }
+            Expr::Map { entries } => {
+                for &(key, value) in entries {
+                    self.resolve_expression(key)?;
+                    self.resolve_expression(value)?;
+                }
+            }
+            Expr::Match { subject, arms } => {
+                self.resolve_expression(*subject)?;
+                for arm in arms {
+                    match &arm.pattern {
+                        Pattern::Value(value) => self.resolve_expression(*value)?,
+                        Pattern::Class { id, class, binding } => {
+                            self.resolve_expression(*class)?;
+                            // the binding is only visible in the body of its arm
+                            self.begin_scope();
+                            self.declare(*id, binding, Definition::Binding(*class));
+                            self.define(binding);
+                            self.resolve_expression(arm.body)?;
+                            self.end_scope();
+                            continue;
+                        }
+                        Pattern::Wildcard => {}
+                    }
+                    self.resolve_expression(arm.body)?;
+                }
             }
             Expr::Nil | Expr::Boolean(_) | Expr::Number(_) | Expr::String(_) => {}
         };
         Ok(())
     }
 
+    /// Resolves the body of a loop, which `break` can end, by `label` if it has one.
+    fn resolve_loop(&mut self, label: Option<&'a str>, body: StmtRef) -> Result<()> {
+        self.functions.last_mut().unwrap().loops.push(label);
+        let result = self.resolve_statement(body);
+        self.functions.last_mut().unwrap().loops.pop();
+        result
+    }
+
+    fn current_scopes(&mut self) -> &mut Vec<HashMap<&'a str, Variable>> {
+        &mut self.functions.last_mut().unwrap().scopes
+    }
+
+    fn record_locals(&mut self, line: u32) {
+        if !self.resolution.line_locals.contains_key(&line) {
+            let locals = self.visible_locals();
+            self.resolution.line_locals.insert(line, locals);
+        }
+    }
+
+    fn visible_locals(&mut self) -> ScopeLocals {
+        // innermost first, as a variable shadows the ones of the same name in outer blocks
+        let mut locals: Vec<(Rc<str>, Slot)> = vec![];
+        for scope in self.current_scopes().iter().rev() {
+            for (name, variable) in scope {
+                if variable.defined && !locals.iter().any(|(known, _)| &**known == *name) {
+                    locals.push((Rc::from(*name), variable.slot));
+                }
+            }
+        }
+        locals.sort_by_key(|(_, slot)| *slot);
+        locals.into()
+    }
+
     fn begin_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.current_scopes().push(HashMap::new());
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        self.current_scopes().pop();
     }
 
-    fn declare(&mut self, name: &'a str) {
-        self.scopes
-            .last_mut()
-            .map(|scope| scope.insert(name, false));
+    /// Declares `name` in the innermost scope and records the slot it occupies under the
+    /// declaration's id. Redeclaring a name within the same scope reuses its slot.
+    fn declare(&mut self, declaration_id: ExprId, name: &'a str, definition: Definition) {
+        if let Some(slot) = self.declare_slot(name, Some(definition)) {
+            self.resolution
+                .locals
+                .insert(declaration_id, Local::Slot(slot));
+        } else {
+            self.global_constants.remove(name);
+            if self.record_references {
+                self.global_definitions.insert(name, definition);
+            }
+        }
+    }
+
+    /// Makes the variable `name` just declared a constant.
+    fn make_constant(&mut self, name: &'a str) {
+        match self.current_scopes().last_mut() {
+            Some(scope) => {
+                if let Some(variable) = scope.get_mut(name) {
+                    variable.constant = true;
+                }
+            }
+            None => {
+                self.global_constants.insert(name);
+            }
+        }
+    }
+
+    /// Whether `name`, as seen from the code being resolved, is a constant.
+    fn is_constant(&self, name: &str) -> bool {
+        let local = self
+            .functions
+            .iter()
+            .rev()
+            .flat_map(|function| function.scopes.iter().rev())
+            .find_map(|scope| scope.get(name));
+        match local {
+            Some(variable) => variable.constant,
+            None => self.global_constants.contains(name),
+        }
+    }
+
+    /// Parameters are bound in order when a function is called, so they never need an id.
+    fn declare_parameter(&mut self, name: &'a str, definition: Definition) {
+        self.declare_slot(name, Some(definition));
+        self.define(name);
+    }
+
+    fn define_keyword(&mut self, keyword: &'static str) {
+        self.declare_slot(keyword, None);
+        self.define(keyword);
+    }
+
+    fn declare_slot(&mut self, name: &'a str, definition: Option<Definition>) -> Option<Slot> {
+        let function = self.functions.last_mut().unwrap();
+        let scope = function.scopes.last_mut()?;
+        let slot = match scope.get(name) {
+            Some(variable) => variable.slot,
+            None => {
+                function.slots += 1;
+                function.slots - 1
+            }
+        };
+        scope.insert(
+            name,
+            Variable {
+                defined: false,
+                slot,
+                constant: false,
+                definition,
+            },
+        );
+        Some(slot)
     }
 
     fn define(&mut self, name: &'a str) {
-        self.scopes.last_mut().map(|scope| scope.insert(name, true));
+        if let Some(variable) = self
+            .current_scopes()
+            .last_mut()
+            .and_then(|scope| scope.get_mut(name))
+        {
+            variable.defined = true;
+        }
+    }
+
+    /// Records the declaration of the variable `name` that `expr` uses, for
+    /// `resolve_for_editing`.
+    fn record_reference(&mut self, expr: ExprRef, name: &str) {
+        if !self.record_references {
+            return;
+        }
+        let local = self
+            .functions
+            .iter()
+            .rev()
+            .flat_map(|function| function.scopes.iter().rev())
+            .find_map(|scope| scope.get(name));
+        let definition = match local {
+            Some(variable) => variable.definition,
+            None => self.global_definitions.get(name).copied(),
+        };
+        if let Some(definition) = definition {
+            self.resolution.references.push((expr, definition));
+        }
     }
 
     fn resolve_local(&mut self, expr_id: ExprId, name: &'a str) {
-        self.scopes
-            .iter_mut()
+        if let Some(local) = self.lookup(self.functions.len() - 1, name) {
+            self.resolution.locals.insert(expr_id, local);
+        }
+    }
+
+    /// Finds `name` as seen from the `function`th function. A variable of an enclosing function
+    /// becomes an upvalue of every function in between.
+    fn lookup(&mut self, function: usize, name: &str) -> Option<Local> {
+        let slot = self.functions[function]
+            .scopes
+            .iter()
             .rev()
-            .enumerate()
-            .find(|(_, scope)| scope.contains_key(name))
-            .map(|(depth, _)| (expr_id, depth as u64))
-            .map(|(expr_id, depth)| self.expr_id_to_depth.insert(expr_id, depth));
+            .find_map(|scope| scope.get(name).map(|variable| Local::Slot(variable.slot)));
+        if slot.is_some() || function == 0 {
+            return slot;
+        }
+
+        let captured = self.lookup(function - 1, name)?;
+        let upvalues = &mut self.functions[function].upvalues;
+        let index = match upvalues.iter().position(|&upvalue| upvalue == captured) {
+            Some(index) => index,
+            None => {
+                upvalues.push(captured);
+                upvalues.len() - 1
+            }
+        };
+        Some(Local::Upvalue(index))
     }
 }
 
-pub fn resolve(statements: &[Stmt]) -> Result<HashMap<ExprId, Depth>> {
-    let mut resolver = Resolver::new();
-    resolver.resolve(statements)
+pub fn resolve(program: &Program) -> Result<Resolution> {
+    let mut resolver = Resolver::new(&program.ast);
+    resolver.resolve(&program.statements)
+}
+
+/// Like `resolve`, but also records `Resolution::line_locals`.
+pub fn resolve_for_debugging(program: &Program) -> Result<Resolution> {
+    let mut resolver = Resolver::new(&program.ast);
+    resolver.record_line_locals = true;
+    resolver.resolve(&program.statements)
+}
+
+/// Like `resolve`, but also records `Resolution::references`, for editors. An error comes with
+/// the source of the statement or expression it is about, if known.
+#[cfg(any(test, feature = "lsp"))]
+pub fn resolve_for_editing(
+    program: &Program,
+) -> std::result::Result<Resolution, (LoxError, Option<Span>)> {
+    let mut resolver = Resolver::new(&program.ast);
+    resolver.record_references = true;
+    // functions may use globals declared after them
+    for &stmt in &program.statements {
+        if let Stmt::Var { name, .. }
+        | Stmt::Function { name, .. }
+        | Stmt::Class { name, .. }
+        | Stmt::Enum { name, .. } = &program.ast[stmt]
+        {
+            resolver
+                .global_definitions
+                .entry(name)
+                .or_insert(Definition::Statement(stmt));
+        }
+    }
+    resolver
+        .resolve(&program.statements)
+        .map_err(|error| (error, resolver.error_span))
+}
+
+/// Resolves `program` as if it ran inside a function whose frame holds `locals`, the names of
+/// its slots as in `Resolution::line_locals`. Names other than these refer to globals. A local
+/// `this` makes `this` available.
+pub fn resolve_in<'a>(program: &'a Program, locals: &'a [(Rc<str>, Slot)]) -> Result<Resolution> {
+    let mut resolver = Resolver::new(&program.ast);
+    let scope = locals
+        .iter()
+        .map(|(name, slot)| {
+            let variable = Variable {
+                defined: true,
+                slot: *slot,
+                constant: false,
+                definition: None,
+            };
+            (&**name, variable)
+        })
+        .collect();
+    let function = &mut resolver.functions[0];
+    function.slots = locals.iter().map(|(_, slot)| slot + 1).max().unwrap_or(0);
+    function.scopes.push(scope);
+    if locals.iter().any(|(name, _)| &**name == "this") {
+        resolver.current_class = ClassType::Class;
+    }
+    resolver.resolve(&program.statements)
+}
+
+/// Lists where every variable of `program` lives according to `resolution`, in source order, for
+/// `--dump-scopes`. Each line shows the line of the statement, the variable as it appears there
+/// (declarations with their keyword, assignments with `=`) and its slot, upvalue or `global`.
+/// Functions are followed by the variables they capture.
+pub fn dump(program: &Program, resolution: &Resolution) -> String {
+    let mut dump = Dump {
+        ast: &program.ast,
+        resolution,
+        line: 0,
+        out: String::new(),
+    };
+    dump.statements(&program.statements);
+    dump.out
+}
+
+struct Dump<'a> {
+    ast: &'a Ast,
+    resolution: &'a Resolution,
+    /// The line of the statement being dumped.
+    line: u32,
+    out: String,
+}
+
+impl Dump<'_> {
+    fn statements(&mut self, stmts: &[StmtRef]) {
+        stmts.iter().for_each(|stmt| self.statement(*stmt));
+    }
+
+    fn statement(&mut self, stmt: StmtRef) {
+        if let Some(line) = self.ast.line(stmt) {
+            self.line = line;
+        }
+        match &self.ast[stmt] {
+            Stmt::Expression { expression } | Stmt::Print { expression } => {
+                self.expression(*expression)
+            }
+            Stmt::Var {
+                id,
+                name,
+                initializer,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.expression(*initializer);
+                }
+                self.variable(&format!("var {}", name), id);
+            }
+            Stmt::Block { statements } => self.statements(statements),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(*condition);
+                self.statement(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(*else_branch);
+                }
+            }
+            Stmt::While { condition, body } => {
+                self.expression(*condition);
+                self.statement(*body);
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.statement(*body);
+                self.expression(*condition);
+            }
+            Stmt::Labeled { body, .. } => self.statement(*body),
+            Stmt::Break { .. } => {}
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                self.expression(*subject);
+                for (value, body) in cases {
+                    self.expression(*value);
+                    self.statement(*body);
+                }
+                if let Some(body) = default {
+                    self.statement(*body);
+                }
+            }
+            Stmt::Function { id, name, body, .. } => {
+                self.variable(&format!("fun {}", name), id);
+                self.function(id, body);
+            }
+            Stmt::Return { value } => {
+                if let Some(value) = value {
+                    self.expression(*value);
+                }
+            }
+            Stmt::Class {
+                id,
+                name,
+                superclass,
+                methods,
+            } => {
+                if let Some(superclass) = superclass {
+                    self.expression(*superclass);
+                }
+                self.variable(&format!("class {}", name), id);
+                for &method in methods {
+                    if let Some(line) = self.ast.line(method) {
+                        self.line = line;
+                    }
+                    if let Stmt::Function { id, name, body, .. } = &self.ast[method] {
+                        self.entry(&format!("method {}", name), "-".to_string());
+                        self.function(id, body);
+                    }
+                }
+            }
+            Stmt::Enum { id, name, .. } => self.variable(&format!("enum {}", name), id),
+        }
+    }
+
+    fn function(&mut self, id: &ExprId, body: &[StmtRef]) {
+        let captures = self.resolution.captures.get(id);
+        if let Some(captures) = captures.filter(|captures| !captures.is_empty()) {
+            let captures: Vec<_> = captures.iter().map(|local| location(Some(local))).collect();
+            self.entry("  captures", captures.join(", "));
+        }
+        self.statements(body);
+    }
+
+    fn expression(&mut self, expr: ExprRef) {
+        match &self.ast[expr] {
+            Expr::Number(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Nil => {}
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expression(*left);
+                self.expression(*right);
+            }
+            Expr::Call { callee, arguments } => {
+                self.expression(*callee);
+                arguments
+                    .iter()
+                    .for_each(|argument| self.expression(*argument));
+            }
+            Expr::Get { object, .. } => self.expression(*object),
+            Expr::Set { object, value, .. } => {
+                self.expression(*object);
+                self.expression(*value);
+            }
+            Expr::Super { id, .. } => {
+                let receiver = self.resolution.super_receivers.get(id);
+                self.entry("super", format!("this in {}", location(receiver)));
+            }
+            Expr::This { id, keyword } => self.variable(keyword, id),
+            Expr::Grouping { expression } => self.expression(*expression),
+            Expr::Unary { right, .. } => self.expression(*right),
+            Expr::Variable { id, name } => self.variable(name, id),
+            Expr::Assign { id, name, value } => {
+                self.expression(*value);
+                self.variable(&format!("{} =", name), id);
+            }
+            Expr::List { elements } => elements
+                .iter()
+                .for_each(|element| self.expression(*element)),
+            Expr::Map { entries } => entries.iter().for_each(|&(key, value)| {
+                self.expression(key);
+                self.expression(value);
+            }),
+            Expr::Match { subject, arms } => {
+                self.expression(*subject);
+                for arm in arms {
+                    match &arm.pattern {
+                        Pattern::Value(value) => self.expression(*value),
+                        Pattern::Class { id, class, binding } => {
+                            self.expression(*class);
+                            self.variable(&format!("match {}", binding), id);
+                        }
+                        Pattern::Wildcard => {}
+                    }
+                    self.expression(arm.body);
+                }
+            }
+        }
+    }
+
+    fn variable(&mut self, label: &str, id: &ExprId) {
+        self.entry(label, location(self.resolution.locals.get(id)));
+    }
+
+    fn entry(&mut self, label: &str, location: String) {
+        let line = format!("{:>4}  {:<20} {}\n", self.line, label, location);
+        self.out.push_str(&line);
+    }
+}
+
+fn location(local: Option<&Local>) -> String {
+    match local {
+        Some(Local::Slot(slot)) => format!("slot {}", slot),
+        Some(Local::Upvalue(index)) => format!("upvalue {}", index),
+        None => "global".to_string(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::{resolve, Depth};
+    use super::{dump, resolve, resolve_for_editing, Definition, Local, Resolution};
 
     use crate::error::{LoxError, Result};
     use crate::lexer;
     use crate::parser;
-    use crate::statement::ExprId;
-
-    use std::collections::HashMap;
 
-    fn scopes(source: &'static str) -> Result<HashMap<ExprId, Depth>> {
+    fn scopes(source: &'static str) -> Result<Resolution> {
         let (tokens, lexer_errors) = lexer::lex(source);
         assert_eq!(lexer_errors.len(), 0);
-        let (statements, parser_errors) = parser::parse(&tokens);
+        let (program, parser_errors) = parser::parse(&tokens);
         assert_eq!(parser_errors.len(), 0);
 
-        resolve(&statements)
+        resolve(&program)
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // the parser loops over a chain of additions, the tree it builds nests
+        let source = format!("1{};", " + 1".repeat(20_000));
+        let (tokens, _) = lexer::lex(&source);
+        let (program, parser_errors) = parser::parse(&tokens);
+        assert_eq!(parser_errors, []);
+        assert_eq!(
+            resolve(&program).unwrap_err(),
+            LoxError::ResolverError("Too much nesting.")
+        );
     }
 
     #[test]
     fn invalid_return_statement() {
         let source = "return 42;";
         let scopes = scopes(source);
-        assert_eq!(scopes.is_err(), true);
+        assert!(scopes.is_err());
         assert_eq!(
             scopes.unwrap_err(),
             LoxError::ResolverError("Cannot return from top-level code.")
@@ -354,14 +1007,38 @@ mod tests {
             }
         "#;
         let scopes = scopes(source);
-        assert_eq!(scopes.is_ok(), true);
+        assert!(scopes.is_ok());
+    }
+
+    #[test]
+    fn assign_to_constant() {
+        for source in &[
+            "const a = 1; a = 2;",
+            "{ const a = 1; fun f() { a = 2; } }",
+            "const a = 1; fun f() { a = 2; }",
+            "for (const i = 0; i < 3; i = i + 1) {}",
+        ] {
+            assert_eq!(
+                scopes(source).unwrap_err(),
+                LoxError::ResolverError("Cannot assign to a constant."),
+                "{}",
+                source
+            );
+        }
+        for source in &[
+            "const a = 1; { var a = 2; a = 3; }",
+            "const a = 1; var a = 2; a = 3;",
+            "const a = 1; fun f(a) { a = 2; }",
+        ] {
+            assert!(scopes(source).is_ok(), "{}", source);
+        }
     }
 
     #[test]
     fn invalid_this() {
         let source = "var a = this;";
         let scopes = scopes(source);
-        assert_eq!(scopes.is_err(), true);
+        assert!(scopes.is_err());
         assert_eq!(
             scopes.unwrap_err(),
             LoxError::ResolverError("Cannot use 'this' outside of a class.")
@@ -378,7 +1055,7 @@ mod tests {
             }
         "#;
         let scopes = scopes(source);
-        assert_eq!(scopes.is_err(), true);
+        assert!(scopes.is_err());
         assert_eq!(
             scopes.unwrap_err(),
             LoxError::ResolverError("Cannot return a value from an initializer.")
@@ -389,7 +1066,7 @@ mod tests {
     fn cannot_use_super_outside_of_class() {
         let source = "super.foo();";
         let scopes = scopes(source);
-        assert_eq!(scopes.is_err(), true);
+        assert!(scopes.is_err());
         assert_eq!(
             scopes.unwrap_err(),
             LoxError::ResolverError("Cannot use 'super' outside of a class.")
@@ -406,10 +1083,136 @@ mod tests {
             }
         "#;
         let scopes = scopes(source);
-        assert_eq!(scopes.is_err(), true);
+        assert!(scopes.is_err());
         assert_eq!(
             scopes.unwrap_err(),
             LoxError::ResolverError("Cannot use 'super' in a class with no superclass.")
         );
     }
+
+    #[test]
+    fn slots() {
+        let source = r#"
+            fun add(a, b) {
+                var sum = a + b;
+                {
+                    var a;
+                }
+                return sum;
+            }
+        "#;
+        let scopes = scopes(source).unwrap();
+        let mut locals: Vec<_> = scopes.locals.values().copied().collect();
+        locals.sort_by_key(|local| match local {
+            Local::Slot(slot) => *slot,
+            Local::Upvalue(_) => unreachable!(),
+        });
+        // a, b, the declaration of sum and its use in the return statement, then the nested
+        // declaration of a
+        assert_eq!(
+            locals,
+            vec![
+                Local::Slot(0),
+                Local::Slot(1),
+                Local::Slot(2),
+                Local::Slot(2),
+                Local::Slot(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn upvalues() {
+        let source = r#"
+            fun outer() {
+                var unused = 0;
+                var a = 1;
+                fun middle() {
+                    fun inner() {
+                        return a;
+                    }
+                    return a;
+                }
+            }
+        "#;
+        let captures: Vec<_> = scopes(source)
+            .unwrap()
+            .captures
+            .into_values()
+            .map(|captures| captures.to_vec())
+            .collect();
+        // outer captures nothing, middle captures `a`, inner captures middle's upvalue
+        assert_eq!(captures.len(), 3);
+        assert!(captures.contains(&vec![]));
+        assert!(captures.contains(&vec![Local::Slot(1)]));
+        assert!(captures.contains(&vec![Local::Upvalue(0)]));
+    }
+
+    #[test]
+    fn dumped() {
+        let source = "var a = 1;\nfun f(x) {\n  var y = x;\n  fun g() {\n    y = a;\n  }\n}\nclass A {\n  m() {\n    return this;\n  }\n}\n";
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        let resolution = resolve(&program).unwrap();
+        assert_eq!(
+            dump(&program, &resolution),
+            "   1  var a                global
+   2  fun f                global
+   3  x                    slot 0
+   3  var y                slot 1
+   4  fun g                slot 2
+   4    captures           slot 1
+   5  a                    global
+   5  y =                  upvalue 0
+   8  class A              global
+   9  method m             -
+  10  this                 slot 0
+"
+        );
+    }
+
+    #[test]
+    fn references() {
+        let source = "fun f(a) { var b = a; b = g; }\nvar a = 1;\nfun g() { return a; }\n";
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        let resolution = resolve_for_editing(&program).unwrap();
+        let (f, g) = (program.statements[0], program.statements[2]);
+        let b = match &program.ast[f] {
+            crate::statement::Stmt::Function { body, .. } => body[0],
+            _ => unreachable!(),
+        };
+        let references: Vec<_> = resolution
+            .references
+            .iter()
+            .map(|&(expr, definition)| {
+                let span = program.ast.expr_span(expr).unwrap();
+                (&source[span.start..span.end], definition)
+            })
+            .collect();
+        assert_eq!(
+            references,
+            [
+                ("a", Definition::Parameter(f, 0)),
+                // the global `g` is declared after `f`
+                ("g", Definition::Statement(g)),
+                ("b = g", Definition::Statement(b)),
+                ("a", Definition::Statement(program.statements[1])),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_spans() {
+        let source = "fun f() {\n  const c = 1;\n  print c = 2;\n}\n";
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        let (error, span) = resolve_for_editing(&program).unwrap_err();
+        assert_eq!(
+            error,
+            LoxError::ResolverError("Cannot assign to a constant.")
+        );
+        let span = span.unwrap();
+        assert_eq!(&source[span.start..span.end], "c = 2");
+    }
 }
@@ -0,0 +1,165 @@
+use crate::error::{LoxError, Result};
+use crate::functions::{check_arity, Function};
+use crate::gc::Marker;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::{flag, low_level, SigId};
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The signals `onInterrupt()` handles.
+const SIGNALS: [i32; 2] = [SIGINT, SIGTERM];
+
+/// The handler a script registered with `onInterrupt()`, and the signal that came in last. The
+/// handlers installed for the process are removed again when this is dropped.
+pub(crate) struct Interrupts {
+    handler: Object,
+    /// The signal waiting to be handled, 0 if there is none.
+    received: Arc<AtomicUsize>,
+    /// Whether a signal is being handled; a second one kills the process as usual, so a
+    /// handler that hangs cannot keep it alive.
+    handling: Arc<AtomicBool>,
+    ids: Vec<SigId>,
+}
+
+impl Interrupts {
+    pub(crate) fn register(handler: Object) -> Result<Self> {
+        let mut interrupts = Self {
+            handler,
+            received: Arc::new(AtomicUsize::new(0)),
+            handling: Arc::new(AtomicBool::new(false)),
+            ids: Vec::new(),
+        };
+        for signal in SIGNALS {
+            // the shutdown has to come first, it only looks at the flag set by the last signal
+            let ids = [
+                flag::register_conditional_shutdown(signal, 1, Arc::clone(&interrupts.handling)),
+                flag::register(signal, Arc::clone(&interrupts.handling)),
+                flag::register_usize(signal, Arc::clone(&interrupts.received), signal as usize),
+            ];
+            for id in ids {
+                let id = id.map_err(|error| {
+                    LoxError::InterpreterError(format!("onInterrupt() failed: {}.", error).into())
+                })?;
+                interrupts.ids.push(id);
+            }
+        }
+        Ok(interrupts)
+    }
+
+    pub(crate) fn set_handler(&mut self, handler: Object) {
+        self.handler = handler;
+    }
+
+    /// The handler and the name of the signal it has to handle, if one came in.
+    pub(crate) fn take(&self) -> Option<(Object, &'static str)> {
+        let signal = self.received.swap(0, Ordering::SeqCst);
+        if signal == 0 {
+            return None;
+        }
+        let name = low_level::signal_name(signal as i32).unwrap_or("a signal");
+        Some((self.handler.clone(), name))
+    }
+
+    /// Lets signals be handled again once the program stopped.
+    pub(crate) fn handled(&self) {
+        self.handling.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn trace(&self, marker: &mut Marker) {
+        marker.mark_object(&self.handler);
+    }
+}
+
+impl Drop for Interrupts {
+    fn drop(&mut self) {
+        for id in self.ids.drain(..) {
+            low_level::unregister(id);
+        }
+    }
+}
+
+/// `onInterrupt(handler)` calls `handler` with the signal's name, `"SIGINT"` or `"SIGTERM"`,
+/// when the process receives one, and stops the program afterwards. Signals are only looked at
+/// between statements, so the statement running when one comes in still finishes.
+pub struct OnInterrupt;
+
+impl Function for OnInterrupt {
+    fn name(&self) -> &str {
+        "onInterrupt"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(1, arguments)?;
+        if !matches!(arguments[0], Object::Function(_)) {
+            return Err(LoxError::InterpreterError(
+                format!(
+                    "onInterrupt() expects a function, but got '{}'.",
+                    arguments[0].type_name()
+                )
+                .into(),
+            ));
+        }
+        interpreter.on_interrupt(arguments[0].clone())?;
+        Ok(Object::Nil)
+    }
+}
+
+impl std::fmt::Debug for OnInterrupt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::lox::Lox;
+    use crate::object::Object;
+
+    use signal_hook::consts::SIGTERM;
+    use signal_hook::low_level;
+
+    #[test]
+    fn handler_runs_between_statements() {
+        let mut lox = Lox::new();
+        lox.interpreter().define_native("raise", 0, |_| {
+            low_level::raise(SIGTERM).unwrap();
+            Ok(Object::Nil)
+        });
+        let source = r#"
+            var log = [];
+            fun flush(signal) { log.push("flushed on " + signal); }
+            onInterrupt(flush);
+            log.push("before");
+            raise();
+            log.push("not reached");
+        "#;
+        assert_eq!(
+            lox.eval(source),
+            Err(LoxError::InterpreterError("Interrupted by SIGTERM.".into()))
+        );
+        assert_eq!(
+            lox.eval("log;").unwrap().to_string(),
+            "[before, flushed on SIGTERM]"
+        );
+    }
+
+    #[test]
+    fn expects_a_function() {
+        assert_eq!(
+            Lox::new().eval("onInterrupt(1);"),
+            Err(LoxError::InterpreterError(
+                "onInterrupt() expects a function, but got 'number'.".into()
+            ))
+        );
+    }
+}
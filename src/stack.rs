@@ -0,0 +1,197 @@
+use crate::environment::undefined_variable;
+use crate::error::Result;
+use crate::gc::Marker;
+use crate::object::Object;
+use crate::resolver::Slot;
+
+use std::cell::RefCell;
+use std::mem::size_of;
+use std::rc::Rc;
+
+/// A variable captured by a closure, shared between the closure and the frame declaring it.
+pub type Upvalue = Rc<RefCell<Object>>;
+
+#[derive(Debug)]
+enum Binding {
+    Value(Object),
+    /// Moved into an upvalue the first time a closure captured it.
+    Captured(Upvalue),
+}
+
+impl Binding {
+    fn get(&self) -> Object {
+        match self {
+            Binding::Value(value) => value.clone(),
+            Binding::Captured(upvalue) => upvalue.borrow().clone(),
+        }
+    }
+
+    fn set(&mut self, value: Object) {
+        match self {
+            Binding::Value(current) => *current = value,
+            Binding::Captured(upvalue) => *upvalue.borrow_mut() = value,
+        }
+    }
+}
+
+/// The locals of a running function start at `base`.
+#[derive(Debug)]
+struct Frame {
+    base: usize,
+    upvalues: Rc<[Upvalue]>,
+}
+
+/// The locals of all running functions, one frame per call. The top-level code has a frame
+/// too, for the locals of its blocks.
+#[derive(Debug)]
+pub struct Stack {
+    slots: Vec<Binding>,
+    frames: Vec<Frame>,
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            frames: vec![Frame {
+                base: 0,
+                upvalues: Rc::new([]),
+            }],
+        }
+    }
+
+    /// An estimate of the bytes the locals take up.
+    pub fn size(&self) -> usize {
+        self.slots.len() * size_of::<Binding>() + self.frames.len() * size_of::<Frame>()
+    }
+
+    pub fn push_frame(&mut self, upvalues: Rc<[Upvalue]>) {
+        self.frames.push(Frame {
+            base: self.slots.len(),
+            upvalues,
+        });
+    }
+
+    pub fn pop_frame(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        self.slots.truncate(frame.base);
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    /// Stores `value` in `slot`, as a new variable that closures created earlier do not see.
+    /// Slots can be skipped when a declaration was never executed (e.g. `if (false) var a;`),
+    /// those hold nil.
+    pub fn define(&mut self, slot: Slot, value: Object) {
+        let index = self.frame().base + slot;
+        if index < self.slots.len() {
+            self.slots[index] = Binding::Value(value);
+        } else {
+            self.slots
+                .resize_with(index, || Binding::Value(Object::Nil));
+            self.slots.push(Binding::Value(value));
+        }
+    }
+
+    /// `name` is only used for the error message when the slot was never defined.
+    pub fn get(&self, slot: Slot, name: &str) -> Result<Object> {
+        self.slots
+            .get(self.frame().base + slot)
+            .map(Binding::get)
+            .ok_or_else(|| undefined_variable(name))
+    }
+
+    pub fn assign(&mut self, slot: Slot, name: &str, value: Object) -> Result<()> {
+        let index = self.frame().base + slot;
+        if let Some(current) = self.slots.get_mut(index) {
+            current.set(value);
+            Ok(())
+        } else {
+            Err(undefined_variable(name))
+        }
+    }
+
+    /// Shares the variable in `slot` with a closure. Capturing a slot that was not defined yet
+    /// (a local function referring to itself) defines it as nil.
+    pub fn capture(&mut self, slot: Slot) -> Upvalue {
+        let index = self.frame().base + slot;
+        if index >= self.slots.len() {
+            self.define(slot, Object::Nil);
+        }
+        let binding = &mut self.slots[index];
+        if let Binding::Value(value) = binding {
+            let value = std::mem::replace(value, Object::Nil);
+            *binding = Binding::Captured(Rc::new(RefCell::new(value)));
+        }
+        match binding {
+            Binding::Captured(upvalue) => Rc::clone(upvalue),
+            Binding::Value(_) => unreachable!(),
+        }
+    }
+
+    /// The `index`th variable the running function captured.
+    pub fn upvalue(&self, index: usize) -> &Upvalue {
+        &self.frame().upvalues[index]
+    }
+
+    pub fn trace(&self, marker: &mut Marker) {
+        for binding in &self.slots {
+            match binding {
+                Binding::Value(value) => marker.mark_object(value),
+                Binding::Captured(upvalue) => marker.mark_upvalue(upvalue),
+            }
+        }
+        for frame in &self.frames {
+            frame
+                .upvalues
+                .iter()
+                .for_each(|upvalue| marker.mark_upvalue(upvalue));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Stack;
+    use crate::object::Object;
+
+    use std::rc::Rc;
+
+    #[test]
+    fn frames() {
+        let mut stack = Stack::new();
+        stack.define(0, Object::Number(42.0));
+
+        stack.push_frame(Rc::new([]));
+        stack.define(1, Object::Boolean(true));
+        assert_eq!(stack.get(0, "skipped").unwrap(), Object::Nil);
+        assert_eq!(stack.get(1, "truth").unwrap(), Object::Boolean(true));
+        assert!(stack.get(2, "missing").is_err());
+        stack.pop_frame();
+
+        stack.assign(0, "answer", Object::Number(21.0)).unwrap();
+        assert_eq!(stack.get(0, "answer").unwrap(), Object::Number(21.0));
+        assert!(stack.get(1, "truth").is_err());
+    }
+
+    #[test]
+    fn upvalues() {
+        let mut stack = Stack::new();
+        stack.define(0, Object::Number(1.0));
+
+        let upvalue = stack.capture(0);
+        stack.assign(0, "captured", Object::Number(2.0)).unwrap();
+        assert_eq!(*upvalue.borrow(), Object::Number(2.0));
+
+        // a new declaration does not change what the closure sees
+        stack.define(0, Object::Number(3.0));
+        assert_eq!(*upvalue.borrow(), Object::Number(2.0));
+        assert_eq!(stack.get(0, "redeclared").unwrap(), Object::Number(3.0));
+
+        stack.push_frame(Rc::new([upvalue]));
+        assert_eq!(*stack.upvalue(0).borrow(), Object::Number(2.0));
+    }
+}
@@ -1,103 +1,487 @@
-use crate::token::TokenType;
+use crate::token::{Span, TokenType};
 
+use std::ops::{Index, IndexMut};
 use std::rc::Rc;
 
+/// Identifies the nodes the resolver binds. The parser numbers them in order, from
+/// `ParserOptions::first_id` on. Programs run by the same interpreter need distinct ranges,
+/// `Interpreter::interpret` shifts the ids of a program that clash with those of the programs
+/// before (see `Interpreter::next_expression_id`). Ids are not serialized:
+/// deserialized nodes all get 0 until `Ast::renumber_ids` numbers them.
 pub type ExprId = u64;
 
+/// Handle of an expression stored in an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExprRef(u32);
+
+/// Handle of a statement stored in an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StmtRef(u32);
+
+/// Owns all nodes of a parsed program. Nodes refer to their children by handle rather than
+/// through `Box`es, so a whole tree lives in two contiguous allocations.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ast {
+    exprs: Vec<Expr>,
+    stmts: Vec<Stmt>,
+    /// The line each statement starts in, 0 if unknown.
+    #[cfg_attr(feature = "serde", serde(default))]
+    stmt_lines: Vec<u32>,
+    /// The `///` comments of documented statements.
+    #[cfg_attr(feature = "serde", serde(default))]
+    stmt_docs: Vec<Option<String>>,
+    /// The bytes each expression was parsed from, empty if unknown.
+    #[cfg_attr(feature = "serde", serde(default))]
+    expr_spans: Vec<Span>,
+    /// The bytes each statement was parsed from, empty if unknown.
+    #[cfg_attr(feature = "serde", serde(default))]
+    stmt_spans: Vec<Span>,
+    /// The id the first node got.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    first_id: ExprId,
+    /// The id the next node gets.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    next_id: ExprId,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty tree whose nodes get ids from `first_id` on.
+    pub fn with_first_id(first_id: ExprId) -> Self {
+        Self {
+            first_id,
+            next_id: first_id,
+            ..Self::default()
+        }
+    }
+
+    /// How many expressions and statements the tree holds.
+    pub fn node_count(&self) -> usize {
+        self.exprs.len() + self.stmts.len()
+    }
+
+    /// A fresh id for a node about to be pushed.
+    pub fn new_id(&mut self) -> ExprId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// The id the first node got.
+    pub fn first_id(&self) -> ExprId {
+        self.first_id
+    }
+
+    /// The id the next node would get, one past the ids in use.
+    pub fn next_id(&self) -> ExprId {
+        self.next_id
+    }
+
+    /// Gives every node with an id a fresh one, from `first_id` on, e.g. after deserializing.
+    /// Resolutions of the tree made before are no longer valid.
+    pub fn renumber_ids(&mut self, first_id: ExprId) {
+        let mut next_id = first_id;
+        self.for_each_id(|id| {
+            *id = next_id;
+            next_id += 1;
+        });
+        self.first_id = first_id;
+        self.next_id = next_id;
+    }
+
+    /// Adds `offset` to the id of every node, see `Resolution::shift_ids`.
+    pub fn shift_ids(&mut self, offset: ExprId) {
+        self.for_each_id(|id| *id += offset);
+        self.first_id += offset;
+        self.next_id += offset;
+    }
+
+    fn for_each_id(&mut self, mut f: impl FnMut(&mut ExprId)) {
+        for expr in &mut self.exprs {
+            match expr {
+                Expr::Get { id, .. }
+                | Expr::Super { id, .. }
+                | Expr::This { id, .. }
+                | Expr::Variable { id, .. }
+                | Expr::Assign { id, .. } => f(id),
+                Expr::Match { arms, .. } => {
+                    for arm in arms {
+                        if let Pattern::Class { id, .. } = &mut arm.pattern {
+                            f(id);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for stmt in &mut self.stmts {
+            match stmt {
+                Stmt::Var { id, .. }
+                | Stmt::Function { id, .. }
+                | Stmt::Class { id, .. }
+                | Stmt::Enum { id, .. } => f(id),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn push_expr(&mut self, expr: Expr) -> ExprRef {
+        self.exprs.push(expr);
+        ExprRef(self.exprs.len() as u32 - 1)
+    }
+
+    pub fn push_stmt(&mut self, stmt: Stmt) -> StmtRef {
+        self.stmts.push(stmt);
+        StmtRef(self.stmts.len() as u32 - 1)
+    }
+
+    pub fn set_line(&mut self, stmt: StmtRef, line: u32) {
+        let index = stmt.0 as usize;
+        if self.stmt_lines.len() <= index {
+            self.stmt_lines.resize(index + 1, 0);
+        }
+        self.stmt_lines[index] = line;
+    }
+
+    /// The line `stmt` starts in, if the parser recorded it.
+    pub fn line(&self, stmt: StmtRef) -> Option<u32> {
+        self.stmt_lines
+            .get(stmt.0 as usize)
+            .copied()
+            .filter(|line| *line != 0)
+    }
+
+    pub fn set_expr_span(&mut self, expr: ExprRef, span: Span) {
+        set_span(&mut self.expr_spans, expr.0, span);
+    }
+
+    /// The source `expr` was parsed from, if the parser recorded it. Nodes the parser made up,
+    /// like the `true` of `for (;;)`, have none.
+    pub fn expr_span(&self, expr: ExprRef) -> Option<Span> {
+        span_at(&self.expr_spans, expr.0)
+    }
+
+    pub fn set_stmt_span(&mut self, stmt: StmtRef, span: Span) {
+        set_span(&mut self.stmt_spans, stmt.0, span);
+    }
+
+    /// The source `stmt` was parsed from, if the parser recorded it.
+    pub fn stmt_span(&self, stmt: StmtRef) -> Option<Span> {
+        span_at(&self.stmt_spans, stmt.0)
+    }
+
+    /// Moves the nodes parsed from byte `from` on by `bytes` and `lines`, after the source before
+    /// them was edited.
+    pub fn shift(&mut self, from: usize, bytes: isize, lines: i64) {
+        let moved = |line: &mut u32| {
+            if *line != 0 {
+                *line = (*line as i64 + lines) as u32;
+            }
+        };
+        for span in &mut self.expr_spans {
+            if span.start != span.end && span.start >= from {
+                *span = span.shift(bytes);
+            }
+        }
+        for (index, span) in self.stmt_spans.iter_mut().enumerate() {
+            if span.start == span.end || span.start < from {
+                continue;
+            }
+            *span = span.shift(bytes);
+            if let Some(line) = self.stmt_lines.get_mut(index) {
+                moved(line);
+            }
+            match &mut self.stmts[index] {
+                Stmt::Var {
+                    type_annotation, ..
+                } => type_annotation
+                    .iter_mut()
+                    .for_each(|annotation| moved(&mut annotation.line)),
+                Stmt::Function {
+                    parameter_types,
+                    return_type,
+                    ..
+                } => parameter_types
+                    .iter_mut()
+                    .flatten()
+                    .chain(return_type)
+                    .for_each(|annotation| moved(&mut annotation.line)),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn set_doc(&mut self, stmt: StmtRef, doc: String) {
+        let index = stmt.0 as usize;
+        if self.stmt_docs.len() <= index {
+            self.stmt_docs.resize(index + 1, None);
+        }
+        self.stmt_docs[index] = Some(doc);
+    }
+
+    /// The doc comment of `stmt`, without the slashes, see `doc::attach`.
+    pub fn doc(&self, stmt: StmtRef) -> Option<&str> {
+        self.stmt_docs.get(stmt.0 as usize)?.as_deref()
+    }
+}
+
+fn set_span(spans: &mut Vec<Span>, index: u32, span: Span) {
+    let index = index as usize;
+    if spans.len() <= index {
+        spans.resize(index + 1, Span::default());
+    }
+    spans[index] = span;
+}
+
+fn span_at(spans: &[Span], index: u32) -> Option<Span> {
+    spans
+        .get(index as usize)
+        .copied()
+        .filter(|span| span.start != span.end)
+}
+
+impl Index<ExprRef> for Ast {
+    type Output = Expr;
+
+    fn index(&self, expr: ExprRef) -> &Expr {
+        &self.exprs[expr.0 as usize]
+    }
+}
+
+impl IndexMut<ExprRef> for Ast {
+    fn index_mut(&mut self, expr: ExprRef) -> &mut Expr {
+        &mut self.exprs[expr.0 as usize]
+    }
+}
+
+impl Index<StmtRef> for Ast {
+    type Output = Stmt;
+
+    fn index(&self, stmt: StmtRef) -> &Stmt {
+        &self.stmts[stmt.0 as usize]
+    }
+}
+
+impl IndexMut<StmtRef> for Ast {
+    fn index_mut(&mut self, stmt: StmtRef) -> &mut Stmt {
+        &mut self.stmts[stmt.0 as usize]
+    }
+}
+
+#[cfg(feature = "serde")]
+fn this_keyword() -> &'static str {
+    "this"
+}
+
+#[cfg(feature = "serde")]
+fn super_keyword() -> &'static str {
+    "super"
+}
+
+/// The result of parsing: the nodes and, in order, the top-level statements among them.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub ast: Ast,
+    pub statements: Vec<StmtRef>,
+}
+
+/// A type named in a declaration, e.g. the `Number` in `var x: Number = 1;`.
+/// Annotations are only recorded, the interpreter never looks at them.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeAnnotation {
+    pub name: String,
+    pub line: u32,
+}
+
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     // literal values
     Number(f64),
-    String(String),
+    String(Rc<str>),
     Boolean(bool),
     Nil,
     // compound expressions
     Binary {
-        left: Box<Expr>,
+        left: ExprRef,
         token_type: TokenType,
-        right: Box<Expr>,
+        right: ExprRef,
     },
     Call {
-        callee: Box<Expr>,
-        arguments: Box<Vec<Expr>>,
+        callee: ExprRef,
+        arguments: Vec<ExprRef>,
     },
     Get {
-        object: Box<Expr>,
-        name: String,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        id: ExprId,
+        object: ExprRef,
+        name: Rc<str>,
     },
     Set {
-        object: Box<Expr>,
-        name: String,
-        value: Rc<Expr>,
+        object: ExprRef,
+        name: Rc<str>,
+        value: ExprRef,
     },
     Super {
+        #[cfg_attr(feature = "serde", serde(skip))]
         id: ExprId,
+        #[cfg_attr(feature = "serde", serde(skip, default = "super_keyword"))]
         keyword: &'static str,
-        method: String,
+        method: Rc<str>,
     },
     This {
+        #[cfg_attr(feature = "serde", serde(skip))]
         id: ExprId,
+        #[cfg_attr(feature = "serde", serde(skip, default = "this_keyword"))]
         keyword: &'static str,
     },
     Grouping {
-        expression: Box<Expr>,
+        expression: ExprRef,
     },
     Unary {
         token_type: TokenType,
-        right: Box<Expr>,
+        right: ExprRef,
     },
     Logical {
-        left: Box<Expr>,
+        left: ExprRef,
         operator: TokenType,
-        right: Box<Expr>,
+        right: ExprRef,
     },
     // assignments
     Variable {
+        #[cfg_attr(feature = "serde", serde(skip))]
         id: ExprId,
-        name: String,
+        name: Rc<str>,
     },
     Assign {
+        #[cfg_attr(feature = "serde", serde(skip))]
+        id: ExprId,
+        name: Rc<str>,
+        value: ExprRef,
+    },
+    /// `[first, second]`, a new list of the elements.
+    List {
+        elements: Vec<ExprRef>,
+    },
+    /// `{key: value}`, a new map of the entries.
+    Map {
+        entries: Vec<(ExprRef, ExprRef)>,
+    },
+    /// The value of the first arm whose pattern matches the subject.
+    Match {
+        subject: ExprRef,
+        arms: Vec<MatchArm>,
+    },
+}
+
+/// `pattern => body`, one arm of a `match`.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: ExprRef,
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    /// Matches a subject equal to the value.
+    Value(ExprRef),
+    /// `Circle(c)`, matches instances of the class or of its subclasses and binds them to
+    /// `binding` in the body of the arm.
+    Class {
+        #[cfg_attr(feature = "serde", serde(skip))]
         id: ExprId,
-        name: String,
-        value: Box<Expr>,
+        class: ExprRef,
+        binding: Rc<str>,
     },
+    /// `_`, matches anything.
+    Wildcard,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Stmt {
     Expression {
-        expression: Expr,
+        expression: ExprRef,
     },
     Print {
-        expression: Expr,
+        expression: ExprRef,
     },
     Var {
-        name: String,
-        initializer: Option<Expr>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        id: ExprId,
+        name: Rc<str>,
+        type_annotation: Option<TypeAnnotation>,
+        initializer: Option<ExprRef>,
+        /// Declared with `const`, so it cannot be assigned to.
+        constant: bool,
     },
     Block {
-        statements: Box<Vec<Stmt>>,
+        statements: Vec<StmtRef>,
     },
     If {
-        condition: Expr,
-        then_branch: Box<Stmt>,
-        else_branch: Option<Box<Stmt>>,
+        condition: ExprRef,
+        then_branch: StmtRef,
+        else_branch: Option<StmtRef>,
     },
     While {
-        condition: Expr,
-        body: Box<Stmt>,
+        condition: ExprRef,
+        body: StmtRef,
+    },
+    /// A loop `break` can end by naming `label`.
+    Labeled {
+        label: Rc<str>,
+        body: StmtRef,
+    },
+    /// Ends the innermost loop, or the one labeled `label`. Switch cases do not count as loops.
+    Break {
+        label: Option<Rc<str>>,
+    },
+    /// Like `While`, but checks the condition after running the body.
+    DoWhile {
+        body: StmtRef,
+        condition: ExprRef,
+    },
+    /// Runs the body of the first case whose value equals the subject, or else the default.
+    /// Every body is a block of its own, there is no fallthrough.
+    Switch {
+        subject: ExprRef,
+        cases: Vec<(ExprRef, StmtRef)>,
+        default: Option<StmtRef>,
     },
     Function {
-        name: String,
-        parameters: Rc<Vec<String>>,
-        body: Rc<Vec<Stmt>>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        id: ExprId,
+        name: Rc<str>,
+        parameters: Rc<Vec<Rc<str>>>,
+        parameter_types: Vec<Option<TypeAnnotation>>,
+        return_type: Option<TypeAnnotation>,
+        body: Rc<[StmtRef]>,
     },
     Return {
-        value: Option<Expr>,
+        value: Option<ExprRef>,
     },
     Class {
-        name: String,
-        superclass: Option<Box<Expr>>,
-        methods: Box<Vec<Stmt>>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        id: ExprId,
+        name: Rc<str>,
+        superclass: Option<ExprRef>,
+        methods: Vec<StmtRef>,
+    },
+    /// `enum Name { First, Second }`, the members in order.
+    Enum {
+        #[cfg_attr(feature = "serde", serde(skip))]
+        id: ExprId,
+        name: Rc<str>,
+        members: Vec<Rc<str>>,
     },
 }
@@ -0,0 +1,34 @@
+//! Counters the interpreter keeps while it runs programs, to see where a script spends its
+//! allocations and calls. Printed by `--stats` and by the `stats()` native.
+
+use std::fmt::{Display, Formatter};
+
+/// What the programs run by an interpreter did so far, see `Interpreter::stats`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RuntimeStats {
+    /// Strings built at runtime, by concatenation or by natives.
+    pub strings: u64,
+    pub instances: u64,
+    /// Closures and bound methods.
+    pub functions: u64,
+    /// Variables captured by closures, once for every closure capturing them.
+    pub upvalues: u64,
+    /// The frames holding the locals of Lox function calls.
+    pub frames: u64,
+    /// Calls of functions, natives and classes.
+    pub calls: u64,
+    /// The most calls in progress at once.
+    pub peak_call_depth: usize,
+}
+
+impl Display for RuntimeStats {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "strings allocated:    {}", self.strings)?;
+        writeln!(f, "instances allocated:  {}", self.instances)?;
+        writeln!(f, "functions allocated:  {}", self.functions)?;
+        writeln!(f, "variables captured:   {}", self.upvalues)?;
+        writeln!(f, "frames created:       {}", self.frames)?;
+        writeln!(f, "calls:                {}", self.calls)?;
+        write!(f, "peak call depth:      {}", self.peak_call_depth)
+    }
+}
@@ -0,0 +1,166 @@
+use crate::error::{LoxError, Result};
+use crate::functions::Function;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    Split,
+    Join,
+    Trim,
+    Replace,
+    StartsWith,
+    EndsWith,
+}
+
+/// The methods of strings, with their names and arities.
+const METHODS: [(Method, &str, usize); 6] = [
+    (Method::Split, "split", 1),
+    (Method::Join, "join", 1),
+    (Method::Trim, "trim", 0),
+    (Method::Replace, "replace", 2),
+    (Method::StartsWith, "startsWith", 1),
+    (Method::EndsWith, "endsWith", 1),
+];
+
+/// A method of a string, bound to the string it was looked up on, as in `line.split(",")`.
+pub struct StringMethod {
+    string: Rc<str>,
+    method: Method,
+    name: &'static str,
+    arity: usize,
+}
+
+impl StringMethod {
+    /// The method called `name` of `string`, if strings have one.
+    pub fn new(string: &Rc<str>, name: &str) -> Option<Self> {
+        let &(method, name, arity) = METHODS.iter().find(|(_, method, _)| *method == name)?;
+        Some(Self {
+            string: Rc::clone(string),
+            method,
+            name,
+            arity,
+        })
+    }
+
+    fn error(&self, message: String) -> LoxError {
+        LoxError::InterpreterError(format!("String method '{}' {}", self.name, message).into())
+    }
+
+    fn string<'a>(&self, argument: &'a Object) -> Result<&'a str> {
+        match argument {
+            Object::String(string) => Ok(string),
+            _ => Err(self.error(format!(
+                "expects a string, but got '{}'.",
+                argument.type_name()
+            ))),
+        }
+    }
+}
+
+impl Function for StringMethod {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        if arguments.len() != self.arity {
+            let plural = if self.arity == 1 { "" } else { "s" };
+            return Err(self.error(format!(
+                "expects {} argument{} but got {}.",
+                self.arity,
+                plural,
+                arguments.len()
+            )));
+        }
+        let string = &*self.string;
+        match self.method {
+            Method::Split => {
+                let separator = self.string(&arguments[0])?;
+                // an empty separator splits the string into its characters
+                let parts: Vec<String> = if separator.is_empty() {
+                    string.chars().map(String::from).collect()
+                } else {
+                    string.split(separator).map(String::from).collect()
+                };
+                let parts = parts
+                    .into_iter()
+                    .map(|part| interpreter.new_string(part))
+                    .collect::<Result<_>>()?;
+                interpreter.new_list(parts)
+            }
+            Method::Join => {
+                let list = match &arguments[0] {
+                    Object::List(list) => Rc::clone(list),
+                    argument => {
+                        return Err(self.error(format!(
+                            "expects a list, but got '{}'.",
+                            argument.type_name()
+                        )))
+                    }
+                };
+                let parts: Vec<String> = list.borrow().iter().map(Object::to_string).collect();
+                Ok(Object::String(parts.join(string).into()))
+            }
+            Method::Trim => Ok(Object::String(string.trim().into())),
+            Method::Replace => {
+                let from = self.string(&arguments[0])?;
+                let to = self.string(&arguments[1])?;
+                Ok(Object::String(string.replace(from, to).into()))
+            }
+            Method::StartsWith => Ok(Object::Boolean(
+                string.starts_with(self.string(&arguments[0])?),
+            )),
+            Method::EndsWith => Ok(Object::Boolean(
+                string.ends_with(self.string(&arguments[0])?),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::object::Object;
+    use crate::test_support::{eval, print};
+
+    #[test]
+    fn methods() {
+        assert_eq!(print("\"a,b,,c\".split(\",\");"), "[a, b, , c]");
+        assert_eq!(print("\"abc\".split(\"\");"), "[a, b, c]");
+        assert_eq!(print("\"-\".join([1, \"two\", nil]);"), "1-two-nil");
+        assert_eq!(print("\", \".join(\"a b\".split(\" \"));"), "a, b");
+        assert_eq!(print("\"  padded \n\".trim();"), "padded");
+        assert_eq!(print("\"a-b-c\".replace(\"-\", \"+\");"), "a+b+c");
+        assert_eq!(
+            eval("\"prefix\".startsWith(\"pre\") and \"suffix\".endsWith(\"fix\");"),
+            Ok(Object::Boolean(true))
+        );
+        assert_eq!(eval("\"abc\".endsWith(\"b\");"), Ok(Object::Boolean(false)));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |message: &str| Err(LoxError::InterpreterError(message.to_string().into()));
+        assert_eq!(
+            eval("\"a\".replace(\"a\");"),
+            error("String method 'replace' expects 2 arguments but got 1.")
+        );
+        assert_eq!(
+            eval("\"a\".split(1);"),
+            error("String method 'split' expects a string, but got 'number'.")
+        );
+        assert_eq!(
+            eval("\"a\".join(\"b\");"),
+            error("String method 'join' expects a list, but got 'string'.")
+        );
+        assert_eq!(eval("\"a\".length;"), error("Undefined property length."));
+    }
+}
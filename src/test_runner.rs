@@ -0,0 +1,201 @@
+//! The runner behind `rlox test`. A test script states what it should print in comments:
+//!
+//! ```text
+//! print 1 + 2; // expect: 3
+//! print "a" - 1; // expect runtime error: Expected both operands to be numbers, but got 'a' and '1'
+//! ```
+//!
+//! The script passes if it prints the `// expect:` lines in order and nothing else, and fails
+//! with the message of the `// expect runtime error:` comment if it has one. As in the test
+//! suite of Crafting Interpreters, `// Error at ...` and `// [line N] Error ...` comments
+//! expect it not to compile, because of an error in their own line or in line `N`. Only the
+//! line of the error is checked, not its message.
+
+use crate::error::LoxError;
+use crate::interpreter::Output;
+use crate::lox::Lox;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const ERROR: &str = "// Error";
+const ERROR_IN_LINE: &str = "// [line ";
+
+/// What a test script states about itself.
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    /// The lines it prints, in order.
+    pub output: Vec<String>,
+    /// The message of the error it stops with.
+    pub runtime_error: Option<String>,
+    /// The lines of the errors that keep it from compiling.
+    pub compile_errors: Vec<u32>,
+}
+
+/// Reads the expectation comments of `source`.
+pub fn expectations(source: &str) -> Expectations {
+    let mut expectations = Expectations::default();
+    for (index, line) in source.lines().enumerate() {
+        if line.contains(ERROR) {
+            expectations.compile_errors.push(index as u32 + 1);
+        } else if let Some(error_line) = line
+            .find(ERROR_IN_LINE)
+            .and_then(|start| line[start + ERROR_IN_LINE.len()..].split(']').next())
+            .and_then(|number| number.parse().ok())
+        {
+            expectations.compile_errors.push(error_line);
+        } else if let Some(index) = line.find(EXPECT) {
+            let expected = &line[index + EXPECT.len()..];
+            expectations.output.push(expected.to_string());
+        } else if let Some(index) = line.find(EXPECT_RUNTIME_ERROR) {
+            let expected = &line[index + EXPECT_RUNTIME_ERROR.len()..];
+            expectations.runtime_error = Some(expected.to_string());
+        }
+    }
+    expectations
+}
+
+/// Runs the test script `source` in a fresh interpreter. Returns how it went against its
+/// expectations, as one line per difference; the script passed if there are none.
+pub fn check(source: &str) -> Vec<String> {
+    let expectations = expectations(source);
+    let output = Rc::new(RefCell::new(vec![]));
+    let mut lox = Lox::new();
+    lox.interpreter().set_output(Output(Rc::clone(&output)));
+    let result = lox.eval(source);
+
+    let mut failures = vec![];
+    let output = output.borrow();
+    let printed: Vec<_> = String::from_utf8_lossy(&output)
+        .lines()
+        .map(String::from)
+        .collect();
+    for (index, expected) in expectations.output.iter().enumerate() {
+        match printed.get(index) {
+            Some(line) if line == expected => {}
+            Some(line) => failures.push(format!("Expected '{}', got '{}'.", expected, line)),
+            None => failures.push(format!("Missing expected output '{}'.", expected)),
+        }
+    }
+    for line in printed.iter().skip(expectations.output.len()) {
+        failures.push(format!("Got unexpected output '{}'.", line));
+    }
+
+    let compile_error = matches!(
+        result,
+        Err(LoxError::LexerError(..) | LoxError::ParserError(..) | LoxError::ResolverError(_))
+    );
+    if compile_error {
+        let error = result.unwrap_err();
+        let lines = &expectations.compile_errors;
+        if lines.is_empty() {
+            failures.push(format!("Unexpected error '{}'.", error));
+        } else if error.line().is_some_and(|line| !lines.contains(&line)) {
+            failures.push(format!(
+                "Expected a compile error in line {}, got '{}'.",
+                lines[0], error
+            ));
+        }
+        return failures;
+    }
+    if let Some(line) = expectations.compile_errors.first() {
+        failures.push(format!("Expected a compile error in line {}.", line));
+    }
+
+    match (result, expectations.runtime_error) {
+        (Ok(_), None) => {}
+        (Ok(_), Some(expected)) => {
+            failures.push(format!("Expected runtime error '{}'.", expected));
+        }
+        (Err(error), None) => failures.push(format!("Unexpected error '{}'.", error)),
+        (Err(error), Some(expected)) => {
+            if error.to_string() != expected {
+                failures.push(format!(
+                    "Expected runtime error '{}', got '{}'.",
+                    expected, error
+                ));
+            }
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{check, expectations, Expectations};
+
+    #[test]
+    fn read() {
+        let source = "print 1; // expect: 1\n// expect: a b\nprint nil + 1; // expect runtime error: Nope.\n";
+        assert_eq!(
+            expectations(source),
+            Expectations {
+                output: vec!["1".to_string(), "a b".to_string()],
+                runtime_error: Some("Nope.".to_string()),
+                compile_errors: vec![],
+            }
+        );
+
+        let source = "var = 1; // Error at '=': Expect variable name.\n// [line 7] Error at end: Expect ';'.\n";
+        assert_eq!(expectations(source).compile_errors, [1, 7]);
+    }
+
+    #[test]
+    fn compile_errors() {
+        assert!(check("print 1;\nvar = 1; // Error at '=': Expect variable name.").is_empty());
+        assert!(check("// [line 2] Error at '='\nvar = 1;").is_empty());
+        assert_eq!(
+            check("var = 1;\nvar = 2; // Error at '='"),
+            vec!["Expected a compile error in line 2, got 'Parser error in line 1: Expect variable name after 'var'.'."]
+        );
+        assert_eq!(
+            check("print 1; // Error at '1'"),
+            vec![
+                "Got unexpected output '1'.",
+                "Expected a compile error in line 1."
+            ]
+        );
+    }
+
+    #[test]
+    fn passing() {
+        assert!(
+            check("print 1 + 2; // expect: 3\nprint \"a\" + \"b\"; // expect: ab\n").is_empty()
+        );
+        assert!(check("var a = 1;").is_empty());
+    }
+
+    #[test]
+    fn failing() {
+        assert_eq!(
+            check("print 1; // expect: 2\n// expect: 3\n"),
+            vec!["Expected '2', got '1'.", "Missing expected output '3'."]
+        );
+        assert_eq!(
+            check("print 1;\nprint 2; // expect: 1\n"),
+            vec!["Got unexpected output '2'."]
+        );
+        assert_eq!(
+            check("print 1; // expect runtime error: Boom."),
+            vec![
+                "Got unexpected output '1'.",
+                "Expected runtime error 'Boom.'."
+            ]
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let source = "print 1; // expect: 1\nundefined;";
+        let failures = check(source);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].starts_with("Unexpected error '"));
+
+        let error = failures[0]["Unexpected error '".len()..failures[0].len() - 2].to_string();
+        let source = format!("{} // expect runtime error: {}", source, error);
+        assert!(check(&source).is_empty());
+    }
+}
@@ -0,0 +1,23 @@
+use crate::lox::Lox;
+use crate::object::Object;
+use crate::statement::Program;
+use crate::{lexer, parser};
+
+/// Runs `source` in a fresh interpreter, with all natives.
+pub(crate) fn eval(source: &str) -> crate::Result<Object> {
+    Lox::new().eval(source)
+}
+
+/// The program `source` parses to, failing the test if it has errors.
+pub(crate) fn parse(source: &str) -> Program {
+    let (tokens, lexer_errors) = lexer::lex(source);
+    assert_eq!(lexer_errors.len(), 0);
+    let (program, parser_errors) = parser::parse(&tokens);
+    assert_eq!(parser_errors.len(), 0);
+    program
+}
+
+/// How `source` prints the value it evaluates to, failing the test if it fails.
+pub(crate) fn print(source: &str) -> String {
+    eval(source).unwrap().to_string()
+}
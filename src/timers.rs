@@ -0,0 +1,330 @@
+#[cfg(feature = "time")]
+use crate::error::{LoxError, Result};
+#[cfg(feature = "time")]
+use crate::functions::{check_arity, Function};
+use crate::gc::Marker;
+#[cfg(feature = "time")]
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+use std::collections::BTreeMap;
+#[cfg(feature = "watch")]
+use std::rc::Rc;
+use std::thread;
+#[cfg(feature = "watch")]
+use std::time::SystemTime;
+use std::time::{Duration, Instant};
+
+/// How often a watched file is checked for changes.
+#[cfg(feature = "watch")]
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A callback waiting for its time to run.
+struct Timer {
+    callback: Object,
+    /// How long to wait before running it again, if it repeats.
+    interval: Option<Duration>,
+    /// The file whose changes the callback is waiting for, if any.
+    #[cfg(feature = "watch")]
+    file: Option<WatchedFile>,
+}
+
+impl Timer {
+    /// The arguments to call the callback with, nothing if it does not have to run yet.
+    fn arguments(&mut self) -> Option<Vec<Object>> {
+        #[cfg(feature = "watch")]
+        if let Some(file) = &mut self.file {
+            return if file.changed() {
+                Some(vec![Object::String(Rc::clone(&file.path))])
+            } else {
+                None
+            };
+        }
+        Some(Vec::new())
+    }
+}
+
+/// A file as a watch saw it last: when it was modified and how long it was, nothing if it did
+/// not exist.
+#[cfg(feature = "watch")]
+struct WatchedFile {
+    path: Rc<str>,
+    stamp: Option<(SystemTime, u64)>,
+}
+
+#[cfg(feature = "watch")]
+impl WatchedFile {
+    fn new(path: Rc<str>) -> Self {
+        let stamp = Self::stamp(&path);
+        Self { path, stamp }
+    }
+
+    fn stamp(path: &str) -> Option<(SystemTime, u64)> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// Whether the file changed, was created or was removed since the last time.
+    fn changed(&mut self) -> bool {
+        let stamp = Self::stamp(&self.path);
+        std::mem::replace(&mut self.stamp, stamp) != self.stamp
+    }
+}
+
+/// The timers scripts scheduled with `setTimeout()`, `setInterval()` and `watch()`. Once a
+/// program is done, the interpreter runs their callbacks in the order they come due, until none
+/// are left. Nothing interrupts running Lox code, so a callback runs late when the one before
+/// takes long.
+#[derive(Default)]
+pub(crate) struct Timers {
+    /// By due time, timers due at the same time in the order they were scheduled.
+    pending: BTreeMap<(Instant, u64), Timer>,
+    #[cfg(any(feature = "time", feature = "watch"))]
+    last_id: u64,
+}
+
+impl Timers {
+    /// Schedules `callback` to run after `delay`, and every `delay` after that if it repeats.
+    /// Returns the id that cancels it.
+    #[cfg(feature = "time")]
+    pub(crate) fn schedule(&mut self, callback: Object, delay: Duration, repeat: bool) -> u64 {
+        let timer = Timer {
+            callback,
+            interval: if repeat { Some(delay) } else { None },
+            #[cfg(feature = "watch")]
+            file: None,
+        };
+        self.add(timer, delay)
+    }
+
+    /// Schedules `callback` to run with the path whenever the file at `path` changes, until it
+    /// is cancelled. Returns the id that cancels it.
+    #[cfg(feature = "watch")]
+    pub(crate) fn watch(&mut self, path: Rc<str>, callback: Object) -> u64 {
+        let timer = Timer {
+            callback,
+            interval: Some(POLL_INTERVAL),
+            file: Some(WatchedFile::new(path)),
+        };
+        self.add(timer, POLL_INTERVAL)
+    }
+
+    #[cfg(any(feature = "time", feature = "watch"))]
+    fn add(&mut self, timer: Timer, delay: Duration) -> u64 {
+        self.last_id += 1;
+        self.pending
+            .insert((Instant::now() + delay, self.last_id), timer);
+        self.last_id
+    }
+
+    /// Cancels the timer with `id`. Cancelling a timer that already ran, or never existed, does
+    /// nothing.
+    #[cfg(any(feature = "time", feature = "watch"))]
+    pub(crate) fn cancel(&mut self, id: u64) {
+        self.pending.retain(|&(_, timer), _| timer != id);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Waits for the next callback that has to run, and returns it with its arguments. A
+    /// repeating timer is scheduled again right away, so its callback can still cancel it.
+    pub(crate) fn next(&mut self) -> Option<(Object, Vec<Object>)> {
+        loop {
+            let (&(due, id), _) = self.pending.iter().next()?;
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+            let mut timer = self.pending.remove(&(due, id))?;
+            let callback = timer.callback.clone();
+            let arguments = timer.arguments();
+            if let Some(interval) = timer.interval {
+                self.pending.insert((due + interval, id), timer);
+            }
+            if let Some(arguments) = arguments {
+                return Some((callback, arguments));
+            }
+        }
+    }
+
+    pub(crate) fn trace(&self, marker: &mut Marker) {
+        for timer in self.pending.values() {
+            marker.mark_object(&timer.callback);
+        }
+    }
+}
+
+/// `setTimeout(callback, milliseconds)` runs `callback` once, `setInterval(callback,
+/// milliseconds)` runs it every so often. Both return the timer's id for `clearTimer()`.
+#[cfg(feature = "time")]
+pub struct SetTimer {
+    name: &'static str,
+    repeat: bool,
+}
+
+#[cfg(feature = "time")]
+impl SetTimer {
+    pub const ALL: [SetTimer; 2] = [
+        SetTimer {
+            name: "setTimeout",
+            repeat: false,
+        },
+        SetTimer {
+            name: "setInterval",
+            repeat: true,
+        },
+    ];
+}
+
+#[cfg(feature = "time")]
+impl Function for SetTimer {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(2, arguments)?;
+        if !matches!(arguments[0], Object::Function(_)) {
+            return Err(LoxError::InterpreterError(
+                format!(
+                    "{}() expects a function, but got '{}'.",
+                    self.name,
+                    arguments[0].type_name()
+                )
+                .into(),
+            ));
+        }
+        let delay = match arguments[1] {
+            Object::Number(milliseconds) if milliseconds >= 0.0 && milliseconds.is_finite() => {
+                Duration::from_secs_f64(milliseconds / 1000.0)
+            }
+            _ => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "{}() expects a delay in milliseconds, but got '{}'.",
+                        self.name, arguments[1]
+                    )
+                    .into(),
+                ))
+            }
+        };
+        let id = interpreter.schedule_timer(arguments[0].clone(), delay, self.repeat);
+        Ok(Object::Number(id as f64))
+    }
+}
+
+#[cfg(feature = "time")]
+impl std::fmt::Debug for SetTimer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// `clearTimer(id)` cancels a timer before its callback runs (again).
+#[cfg(feature = "time")]
+pub struct ClearTimer;
+
+#[cfg(feature = "time")]
+impl Function for ClearTimer {
+    fn name(&self) -> &str {
+        "clearTimer"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(1, arguments)?;
+        match arguments[0] {
+            Object::Number(id) if id.fract() == 0.0 && id >= 0.0 => {
+                interpreter.cancel_timer(id as u64);
+                Ok(Object::Nil)
+            }
+            _ => Err(LoxError::InterpreterError(
+                format!(
+                    "clearTimer() expects a timer id, but got '{}'.",
+                    arguments[0]
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "time")]
+impl std::fmt::Debug for ClearTimer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(all(test, feature = "time"))]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::lox::Lox;
+    use crate::object::Object;
+    use crate::test_support::eval;
+
+    #[test]
+    fn run_in_due_order() {
+        let source = r#"
+            var events = [];
+            fun late() { events.push("late"); }
+            fun early() { events.push("early"); }
+            fun alsoEarly() { events.push("also early"); }
+            setTimeout(late, 20);
+            setTimeout(early, 0);
+            setTimeout(alsoEarly, 0);
+            events.push("program");
+            events;
+        "#;
+        assert_eq!(
+            eval(source).unwrap().to_string(),
+            "[program, early, also early, late]"
+        );
+    }
+
+    #[test]
+    fn intervals_repeat_until_cleared() {
+        let source = r#"
+            var ticks = 0;
+            fun tick() {
+                ticks = ticks + 1;
+                if (ticks == 3) clearTimer(timer);
+            }
+            fun never() { ticks = 100; }
+            var timer = setInterval(tick, 1);
+            clearTimer(setTimeout(never, 0));
+        "#;
+        let mut lox = Lox::new();
+        lox.eval(source).unwrap();
+        assert_eq!(lox.eval("ticks;"), Ok(Object::Number(3.0)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            eval("setTimeout(1, 0);"),
+            Err(LoxError::InterpreterError(
+                "setTimeout() expects a function, but got 'number'.".into()
+            ))
+        );
+        assert_eq!(
+            eval("setInterval(clock, -1);"),
+            Err(LoxError::InterpreterError(
+                "setInterval() expects a delay in milliseconds, but got '-1'.".into()
+            ))
+        );
+        assert_eq!(
+            eval("clearTimer(\"a\");"),
+            Err(LoxError::InterpreterError(
+                "clearTimer() expects a timer id, but got 'a'.".into()
+            ))
+        );
+    }
+}
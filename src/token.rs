@@ -1,10 +1,64 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+/// The bytes of the source something was lexed or parsed from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    /// One past the last byte.
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Self {
+        Self::new(self.start, other.end)
+    }
+
+    /// The line and the column `self` starts at in `source`, both counted from 1, the column in
+    /// characters as the lexer counts them.
+    pub fn position(self, source: &str) -> (u32, u32) {
+        let before = &source[..self.start.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let line = before.matches('\n').count() as u32 + 1;
+        (line, before[line_start..].chars().count() as u32 + 1)
+    }
+
+    /// The span moved by `bytes`.
+    pub fn shift(self, bytes: isize) -> Self {
+        let moved = |offset: usize| (offset as isize + bytes) as usize;
+        Self::new(moved(self.start), moved(self.end))
+    }
+}
+
+impl From<Range<usize>> for Span {
+    fn from(range: Range<usize>) -> Self {
+        Self::new(range.start, range.end)
+    }
+}
+
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Self {
+        span.start..span.end
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenType {
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -20,34 +74,88 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,
+    FatArrow,
 
     Identifier,
-    String(String),
+    String(Rc<str>),
     Number(f64),
 
     And,
+    Break,
+    Case,
     Class,
+    Const,
+    Default,
+    Do,
     Else,
+    Enum,
     False,
     Fun,
     For,
     If,
+    Loop,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
+    Switch,
     This,
     True,
+    Typeof,
     Var,
     While,
 
+    /// A `//` comment, without the line break ending it. Only lexed on request, see
+    /// `lexer::LexerOptions`.
+    Comment,
+    /// A run of spaces, tabs and line breaks. Only lexed on request.
+    Whitespace,
+
     Eof,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token<'a> {
     pub token_type: TokenType,
     pub lexeme: &'a str,
     pub line: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+}
+
+/// A token owning its lexeme, for lexers that do not keep the whole source around, see
+/// `lexer::StreamLexer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedToken {
+    pub token_type: TokenType,
+    pub lexeme: String,
+    pub line: u32,
+    pub span: Span,
+}
+
+impl OwnedToken {
+    /// The token borrowing its lexeme from this one, as the parser takes them.
+    pub fn as_token(&self) -> Token<'_> {
+        Token {
+            token_type: self.token_type.clone(),
+            lexeme: &self.lexeme,
+            line: self.line,
+            span: self.span,
+        }
+    }
+}
+
+impl From<Token<'_>> for OwnedToken {
+    fn from(token: Token<'_>) -> Self {
+        Self {
+            token_type: token.token_type,
+            lexeme: token.lexeme.to_string(),
+            line: token.line,
+            span: token.span,
+        }
+    }
 }
@@ -0,0 +1,113 @@
+//! The execution trace behind `--trace`: every statement is printed with its line before it
+//! runs, indented by the calls in progress, and optionally every value stored in a variable.
+//!
+//! ```text
+//! [line 5] print add(1, 2);
+//!   [line 2] var sum = a + b;
+//!   sum = 3
+//!   [line 3] return sum;
+//! ```
+
+use crate::debug::Debugger;
+use crate::error::Result;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+use std::io::Write;
+
+pub struct Tracer {
+    /// The lines of the traced source.
+    lines: Vec<String>,
+    assignments: bool,
+    output: Box<dyn Write>,
+}
+
+impl Tracer {
+    /// Traces a program parsed from `source` to `output`, and with `assignments` also the
+    /// values stored in variables.
+    pub fn new(source: &str, assignments: bool, output: impl Write + 'static) -> Self {
+        Self {
+            lines: source.lines().map(|line| line.trim().to_string()).collect(),
+            assignments,
+            output: Box::new(output),
+        }
+    }
+}
+
+impl Debugger for Tracer {
+    fn statement(&mut self, interpreter: &mut Interpreter, line: u32) -> Result<()> {
+        let text = self.lines.get(line as usize - 1).map_or("", String::as_str);
+        let indent = 2 * interpreter.call_depth();
+        // the trace is best effort, a closed output must not stop the program
+        writeln!(
+            self.output,
+            "{:indent$}[line {}] {}",
+            "",
+            line,
+            text,
+            indent = indent
+        )
+        .ok();
+        Ok(())
+    }
+
+    fn assignment(&mut self, interpreter: &Interpreter, name: &str, value: &Object) {
+        if self.assignments {
+            let indent = 2 * interpreter.call_depth();
+            writeln!(
+                self.output,
+                "{:indent$}{} = {}",
+                "",
+                name,
+                value,
+                indent = indent
+            )
+            .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Tracer;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::Output;
+    use crate::{lexer, parser, resolver};
+
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    fn trace(source: &str, assignments: bool) -> String {
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(io::sink());
+        let tracer = Tracer::new(source, assignments, Output(Rc::clone(&output)));
+        interpreter.set_debugger(tracer);
+        let (tokens, _) = lexer::lex(source);
+        let (program, _) = parser::parse(&tokens);
+        interpreter.add_scopes(resolver::resolve(&program).unwrap());
+        interpreter.interpret(program).unwrap();
+        let output = output.borrow();
+        String::from_utf8(output.clone()).unwrap()
+    }
+
+    const SOURCE: &str = "fun add(a, b) {\n    var sum = a + b;\n    return sum;\n}\nvar total;\ntotal = add(1, 2);\n";
+
+    #[test]
+    fn statements() {
+        assert_eq!(
+            trace(SOURCE, false),
+            "[line 1] fun add(a, b) {\n[line 5] var total;\n[line 6] total = add(1, 2);\n  [line 2] var sum = a + b;\n  [line 3] return sum;\n"
+        );
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(
+            trace(SOURCE, true),
+            "[line 1] fun add(a, b) {\n[line 5] var total;\ntotal = nil\n[line 6] total = add(1, 2);\n  [line 2] var sum = a + b;\n  sum = 3\n  [line 3] return sum;\ntotal = 3\n"
+        );
+    }
+}
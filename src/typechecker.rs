@@ -0,0 +1,798 @@
+use crate::error::LoxError;
+use crate::natives::{self, Natives};
+use crate::recursion;
+use crate::statement::{Ast, Expr, ExprRef, Pattern, Program, Stmt, StmtRef, TypeAnnotation};
+use crate::token::{Span, TokenType};
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    /// Anything without an annotation. Always compatible, this is what makes the checker gradual.
+    Any,
+    Nil,
+    Boolean,
+    Number,
+    String,
+    Function(Rc<Signature>),
+    Class(String),
+    Instance(String),
+}
+
+#[derive(Debug, PartialEq)]
+struct Signature {
+    parameters: Vec<Type>,
+    return_type: Type,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Nil => write!(f, "Nil"),
+            Type::Boolean => write!(f, "Bool"),
+            Type::Number => write!(f, "Number"),
+            Type::String => write!(f, "String"),
+            Type::Function(_) => write!(f, "Function"),
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+struct TypeChecker<'a> {
+    ast: &'a Ast,
+    /// The source the program was parsed from, to turn the spans of its nodes into lines.
+    source: &'a str,
+    /// The natives and the globals defined before the program, visible behind all its scopes.
+    builtins: HashMap<Rc<str>, Type>,
+    scopes: Vec<HashMap<&'a str, Type>>,
+    superclasses: HashMap<&'a str, Option<&'a str>>,
+    current_return_type: Option<Type>,
+    current_class: Option<&'a str>,
+    errors: Vec<LoxError>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn new(ast: &'a Ast, source: &'a str, natives: Natives, globals: &[&str]) -> Self {
+        let mut builtins: HashMap<Rc<str>, Type> = globals
+            .iter()
+            .map(|&name| (name.into(), Type::Any))
+            .collect();
+        for (name, signature) in natives::signatures(natives) {
+            let parameters = signature
+                .parameters
+                .iter()
+                .map(|name| builtin_type(name).unwrap_or(Type::Any))
+                .collect();
+            let return_type = builtin_type(signature.returns).unwrap_or(Type::Any);
+            let signature = Signature {
+                parameters,
+                return_type,
+            };
+            builtins.insert(name, Type::Function(Rc::new(signature)));
+        }
+        Self {
+            ast,
+            source,
+            builtins,
+            scopes: vec![HashMap::new()],
+            superclasses: HashMap::new(),
+            current_return_type: None,
+            current_class: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn check(&mut self, statements: &[StmtRef]) -> Vec<LoxError> {
+        self.declare_globals(statements);
+        self.declare_classes(statements);
+        self.check_statements(statements);
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Functions may use globals declared after them, so those are known up front, if not yet
+    /// their types.
+    fn declare_globals(&mut self, statements: &[StmtRef]) {
+        let ast = self.ast;
+        for &statement in statements {
+            match &ast[statement] {
+                Stmt::Var { name, .. }
+                | Stmt::Function { name, .. }
+                | Stmt::Class { name, .. }
+                | Stmt::Enum { name, .. } => self.define(name, Type::Any),
+                _ => {}
+            }
+        }
+    }
+
+    /// Classes may be used in annotations before their declaration, so collect them up front.
+    fn declare_classes(&mut self, statements: &[StmtRef]) {
+        recursion::grow_stack(|| {
+            let ast = self.ast;
+            for &statement in statements {
+                match &ast[statement] {
+                    Stmt::Class {
+                        name, superclass, ..
+                    } => {
+                        let superclass = superclass.and_then(|superclass| {
+                            if let Expr::Variable { name, .. } = &ast[superclass] {
+                                Some(&**name)
+                            } else {
+                                None
+                            }
+                        });
+                        self.superclasses.insert(name, superclass);
+                    }
+                    Stmt::Block { statements } => self.declare_classes(statements),
+                    Stmt::Function { body, .. } => self.declare_classes(body),
+                    _ => {}
+                }
+            }
+        })
+    }
+
+    fn check_statements(&mut self, statements: &[StmtRef]) {
+        for &statement in statements {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, stmt: StmtRef) {
+        recursion::grow_stack(|| {
+            let ast = self.ast;
+            match &ast[stmt] {
+                Stmt::Expression { expression } | Stmt::Print { expression } => {
+                    self.check_expression(*expression);
+                }
+                Stmt::Var {
+                    name,
+                    type_annotation,
+                    initializer,
+                    ..
+                } => {
+                    let declared = self.annotated_type(type_annotation.as_ref());
+                    if let Some(initializer) = *initializer {
+                        let actual = self.check_expression(initializer);
+                        let line = type_annotation.as_ref().map(|annotation| annotation.line);
+                        self.expect(
+                            &declared,
+                            &actual,
+                            line,
+                            format!("Cannot initialize '{}'", name),
+                        );
+                    }
+                    self.define(name, declared);
+                }
+                Stmt::Block { statements } => {
+                    self.begin_scope();
+                    self.check_statements(statements);
+                    self.end_scope();
+                }
+                Stmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                } => {
+                    self.check_expression(*condition);
+                    self.check_statement(*then_branch);
+                    if let Some(else_branch) = *else_branch {
+                        self.check_statement(else_branch);
+                    }
+                }
+                Stmt::While { condition, body } => {
+                    self.check_expression(*condition);
+                    self.check_statement(*body);
+                }
+                Stmt::DoWhile { body, condition } => {
+                    self.check_statement(*body);
+                    self.check_expression(*condition);
+                }
+                Stmt::Labeled { body, .. } => self.check_statement(*body),
+                Stmt::Break { .. } => {}
+                Stmt::Switch {
+                    subject,
+                    cases,
+                    default,
+                } => {
+                    self.check_expression(*subject);
+                    for (value, body) in cases {
+                        self.check_expression(*value);
+                        self.check_statement(*body);
+                    }
+                    if let Some(default) = *default {
+                        self.check_statement(default);
+                    }
+                }
+                Stmt::Function {
+                    name,
+                    parameters,
+                    parameter_types,
+                    return_type,
+                    body,
+                    ..
+                } => {
+                    let signature = self.signature(parameter_types, return_type.as_ref());
+                    self.define(name, Type::Function(Rc::clone(&signature)));
+                    self.check_function(parameters, &signature, body);
+                }
+                Stmt::Return { value } => {
+                    let actual = if let Some(value) = *value {
+                        self.check_expression(value)
+                    } else {
+                        Type::Nil
+                    };
+                    if let Some(expected) = self.current_return_type.clone() {
+                        let line = self.line(ast.stmt_span(stmt));
+                        self.expect(&expected, &actual, line, "Cannot return value".to_string());
+                    }
+                }
+                Stmt::Enum { name, .. } => self.define(name, Type::Any),
+                Stmt::Class { name, methods, .. } => {
+                    self.define(name, Type::Class(name.to_string()));
+                    let enclosing_class = self.current_class.replace(name);
+                    for &method in methods {
+                        if let Stmt::Function {
+                            parameters,
+                            parameter_types,
+                            return_type,
+                            body,
+                            ..
+                        } = &ast[method]
+                        {
+                            let signature = self.signature(parameter_types, return_type.as_ref());
+                            self.check_function(parameters, &signature, body);
+                        }
+                    }
+                    self.current_class = enclosing_class;
+                }
+            }
+        })
+    }
+
+    fn check_function(
+        &mut self,
+        parameters: &'a [Rc<str>],
+        signature: &Signature,
+        body: &[StmtRef],
+    ) {
+        let enclosing_return_type = self
+            .current_return_type
+            .replace(signature.return_type.clone());
+        self.begin_scope();
+        for (parameter, parameter_type) in parameters.iter().zip(signature.parameters.iter()) {
+            self.define(parameter, parameter_type.clone());
+        }
+        self.check_statements(body);
+        self.end_scope();
+        self.current_return_type = enclosing_return_type;
+    }
+
+    fn check_expression(&mut self, expr: ExprRef) -> Type {
+        recursion::grow_stack(|| {
+            let ast = self.ast;
+            let line = self.line(ast.expr_span(expr));
+            match &ast[expr] {
+                Expr::Nil => Type::Nil,
+                Expr::Boolean(_) => Type::Boolean,
+                Expr::Number(_) => Type::Number,
+                Expr::String(_) => Type::String,
+                Expr::Grouping { expression } => self.check_expression(*expression),
+                Expr::Variable { name, .. } => self.lookup(name, line),
+                Expr::Assign { name, value, .. } => {
+                    let actual = self.check_expression(*value);
+                    let declared = self.lookup(name, line);
+                    self.expect(
+                        &declared,
+                        &actual,
+                        line,
+                        format!("Cannot assign to '{}'", name),
+                    );
+                    actual
+                }
+                Expr::Unary { token_type, right } => {
+                    let right = self.check_expression(*right);
+                    match token_type {
+                        TokenType::Minus => {
+                            self.expect_operand(token_type, &right, line);
+                            Type::Number
+                        }
+                        TokenType::Typeof => Type::String,
+                        _ => Type::Boolean,
+                    }
+                }
+                Expr::Binary {
+                    left,
+                    token_type,
+                    right,
+                } => {
+                    let left = self.check_expression(*left);
+                    let right = self.check_expression(*right);
+                    self.binary(token_type, left, right, line)
+                }
+                Expr::Logical { left, right, .. } => {
+                    let left = self.check_expression(*left);
+                    let right = self.check_expression(*right);
+                    if left == right {
+                        left
+                    } else {
+                        Type::Any
+                    }
+                }
+                Expr::Call { callee, arguments } => {
+                    let callee = self.check_expression(*callee);
+                    self.call(callee, arguments, line)
+                }
+                Expr::Get { object, .. } => {
+                    self.check_expression(*object);
+                    Type::Any
+                }
+                Expr::Set { object, value, .. } => {
+                    self.check_expression(*object);
+                    self.check_expression(*value);
+                    Type::Nil
+                }
+                Expr::This { .. } => self
+                    .current_class
+                    .map(|class| Type::Instance(class.to_string()))
+                    .unwrap_or(Type::Any),
+                Expr::Super { .. } => Type::Any,
+                Expr::List { elements } => {
+                    for &element in elements {
+                        self.check_expression(element);
+                    }
+                    Type::Any
+                }
+                Expr::Map { entries } => {
+                    for &(key, value) in entries {
+                        self.check_expression(key);
+                        self.check_expression(value);
+                    }
+                    Type::Any
+                }
+                Expr::Match { subject, arms } => {
+                    self.check_expression(*subject);
+                    let mut arm_types = arms.iter().map(|arm| {
+                        self.begin_scope();
+                        match &arm.pattern {
+                            Pattern::Value(value) => {
+                                self.check_expression(*value);
+                            }
+                            Pattern::Class { class, binding, .. } => {
+                                let binding_type = match self.check_expression(*class) {
+                                    Type::Class(name) => Type::Instance(name),
+                                    _ => Type::Any,
+                                };
+                                self.define(binding, binding_type);
+                            }
+                            Pattern::Wildcard => {}
+                        }
+                        let arm_type = self.check_expression(arm.body);
+                        self.end_scope();
+                        arm_type
+                    });
+                    let first = arm_types.next().unwrap_or(Type::Any);
+                    arm_types.fold(
+                        first,
+                        |all, arm_type| {
+                            if all == arm_type {
+                                all
+                            } else {
+                                Type::Any
+                            }
+                        },
+                    )
+                }
+            }
+        })
+    }
+
+    fn binary(
+        &mut self,
+        token_type: &TokenType,
+        left: Type,
+        right: Type,
+        line: Option<u32>,
+    ) -> Type {
+        match token_type {
+            TokenType::Plus => match (&left, &right) {
+                (Type::Number, Type::Number) => Type::Number,
+                (Type::String, Type::String) => Type::String,
+                (Type::Any, _) | (_, Type::Any) => Type::Any,
+                _ => {
+                    self.error(
+                        line,
+                        format!(
+                            "The '+' operator requires either 2 numbers or 2 strings, but got {} and {}.",
+                            left, right
+                        ),
+                    );
+                    Type::Any
+                }
+            },
+            TokenType::Minus | TokenType::Star | TokenType::Slash => {
+                self.expect_operand(token_type, &left, line);
+                self.expect_operand(token_type, &right, line);
+                Type::Number
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => {
+                self.expect_operand(token_type, &left, line);
+                self.expect_operand(token_type, &right, line);
+                Type::Boolean
+            }
+            _ => Type::Boolean,
+        }
+    }
+
+    /// Checks the `arguments` of a call in `line`, each reported in its own line.
+    fn call(&mut self, callee: Type, arguments: &[ExprRef], line: Option<u32>) -> Type {
+        let ast = self.ast;
+        let arguments: Vec<_> = arguments
+            .iter()
+            .map(|&argument| {
+                let argument_type = self.check_expression(argument);
+                (argument_type, self.line(ast.expr_span(argument)))
+            })
+            .collect();
+        match callee {
+            Type::Function(signature) => {
+                if signature.parameters.len() != arguments.len() {
+                    self.error(
+                        line,
+                        format!(
+                            "Expected {} arguments but got {}.",
+                            signature.parameters.len(),
+                            arguments.len()
+                        ),
+                    );
+                } else {
+                    for (index, (expected, (actual, argument_line))) in signature
+                        .parameters
+                        .iter()
+                        .zip(arguments.iter())
+                        .enumerate()
+                    {
+                        self.expect(
+                            expected,
+                            actual,
+                            *argument_line,
+                            format!("Cannot pass argument {}", index + 1),
+                        );
+                    }
+                }
+                signature.return_type.clone()
+            }
+            Type::Class(name) => Type::Instance(name),
+            Type::Any => Type::Any,
+            _ => {
+                self.error(
+                    line,
+                    format!("Can only call functions and classes, but got {}.", callee),
+                );
+                Type::Any
+            }
+        }
+    }
+
+    fn expect_operand(&mut self, operator: &TokenType, operand: &Type, line: Option<u32>) {
+        if !self.is_assignable(&Type::Number, operand) {
+            self.error(
+                line,
+                format!(
+                    "Operands of '{}' must be numbers, but got {}.",
+                    operator_lexeme(operator),
+                    operand
+                ),
+            );
+        }
+    }
+
+    fn expect(&mut self, expected: &Type, actual: &Type, line: Option<u32>, context: String) {
+        if !self.is_assignable(expected, actual) {
+            self.error(
+                line,
+                format!("{}: expected {} but got {}.", context, expected, actual),
+            );
+        }
+    }
+
+    fn is_assignable(&self, expected: &Type, actual: &Type) -> bool {
+        match (expected, actual) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Function(_), Type::Function(_)) => true,
+            (Type::Instance(expected), Type::Instance(actual)) => {
+                self.is_subclass(actual, expected)
+            }
+            _ => expected == actual,
+        }
+    }
+
+    fn is_subclass(&self, class: &str, ancestor: &str) -> bool {
+        let mut current = Some(class);
+        while let Some(class) = current {
+            if class == ancestor {
+                return true;
+            }
+            current = self.superclasses.get(class).copied().flatten();
+        }
+        false
+    }
+
+    fn signature(
+        &mut self,
+        parameter_types: &[Option<TypeAnnotation>],
+        return_type: Option<&TypeAnnotation>,
+    ) -> Rc<Signature> {
+        let parameters = parameter_types
+            .iter()
+            .map(|annotation| self.annotated_type(annotation.as_ref()))
+            .collect();
+        let return_type = self.annotated_type(return_type);
+        Rc::new(Signature {
+            parameters,
+            return_type,
+        })
+    }
+
+    fn annotated_type(&mut self, annotation: Option<&TypeAnnotation>) -> Type {
+        let annotation = if let Some(annotation) = annotation {
+            annotation
+        } else {
+            return Type::Any;
+        };
+        if let Some(builtin) = builtin_type(&annotation.name) {
+            return builtin;
+        }
+        match annotation.name.as_str() {
+            name if self.superclasses.contains_key(name) => Type::Instance(name.to_string()),
+            name => {
+                self.error(Some(annotation.line), format!("Unknown type '{}'.", name));
+                Type::Any
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn define(&mut self, name: &'a str, value_type: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, value_type);
+        }
+    }
+
+    fn lookup(&mut self, name: &str, line: Option<u32>) -> Type {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.builtins.get(name))
+            .cloned();
+        found.unwrap_or_else(|| {
+            self.error(line, format!("Undefined variable '{}'.", name));
+            Type::Any
+        })
+    }
+
+    /// The line `span` starts in, if the parser recorded it.
+    fn line(&self, span: Option<Span>) -> Option<u32> {
+        let before = self.source.get(..span?.start)?;
+        Some(before.matches('\n').count() as u32 + 1)
+    }
+
+    fn error(&mut self, line: Option<u32>, reason: String) {
+        self.errors.push(LoxError::TypeError(line, reason.into()));
+    }
+}
+
+/// The types every program can name in annotations.
+fn builtin_type(name: &str) -> Option<Type> {
+    match name {
+        "Any" => Some(Type::Any),
+        "Nil" => Some(Type::Nil),
+        "Bool" | "Boolean" => Some(Type::Boolean),
+        "Number" => Some(Type::Number),
+        "String" => Some(Type::String),
+        "Function" => Some(Type::Function(Rc::new(Signature {
+            parameters: vec![],
+            return_type: Type::Any,
+        }))),
+        _ => None,
+    }
+}
+
+fn operator_lexeme(operator: &TokenType) -> &'static str {
+    match operator {
+        TokenType::Minus => "-",
+        TokenType::Star => "*",
+        TokenType::Slash => "/",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        _ => "?",
+    }
+}
+
+/// Checks annotated declarations and call sites. Unannotated code is never reported, apart
+/// from names that are not defined anywhere, so the returned errors can be treated as warnings
+/// or as hard errors by the caller. The program runs with the given natives, in an interpreter
+/// that already defined `globals`, e.g. through a prelude or `define_native`; their types are
+/// not known. Errors are reported in the lines of `source`, the code `program` was parsed from.
+pub fn check(program: &Program, source: &str, natives: Natives, globals: &[&str]) -> Vec<LoxError> {
+    let mut checker = TypeChecker::new(&program.ast, source, natives, globals);
+    checker.check(&program.statements)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::check;
+
+    use crate::error::LoxError;
+    use crate::natives::Natives;
+    use crate::test_support::parse;
+
+    fn type_errors(source: &str) -> Vec<LoxError> {
+        check(&parse(source), source, Natives::ALL, &[])
+    }
+
+    #[test]
+    fn unannotated_code_is_accepted() {
+        let source = r#"
+            var a = 1;
+            a = "now a string";
+            fun add(a, b) {
+                return a + b;
+            }
+            add(1, "two");
+        "#;
+        assert_eq!(type_errors(source), vec![]);
+    }
+
+    #[test]
+    fn annotated_variable() {
+        let source = r#"
+            var answer: Number = "42";
+        "#;
+        assert_eq!(
+            type_errors(source),
+            vec![LoxError::TypeError(
+                Some(2),
+                "Cannot initialize 'answer': expected Number but got String.".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn call_site() {
+        let source = r#"
+            fun greet(name: String) -> String {
+                return "Hi " + name;
+            }
+            greet(42);
+            var greeting: Number = greet("Bob");
+        "#;
+        let errors = type_errors(source);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0],
+            LoxError::TypeError(
+                Some(5),
+                "Cannot pass argument 1: expected String but got Number.".into()
+            )
+        );
+    }
+
+    #[test]
+    fn return_type() {
+        let source = r#"
+            fun answer() -> Number {
+                return "42";
+            }
+        "#;
+        let errors = type_errors(source);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line(), Some(3));
+    }
+
+    #[test]
+    fn operators() {
+        let source = r#"
+            var a: Number = 1;
+            var b: String = "b";
+            var c = a - b;
+        "#;
+        assert_eq!(
+            type_errors(source),
+            vec![LoxError::TypeError(
+                Some(4),
+                "Operands of '-' must be numbers, but got String.".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn undefined_names() {
+        let source = r#"
+            fun early() { return late + 1; }
+            var late = 1;
+            print missing;
+        "#;
+        assert_eq!(
+            type_errors(source),
+            vec![LoxError::TypeError(
+                Some(4),
+                "Undefined variable 'missing'.".into()
+            )]
+        );
+        let source = "print prelude;";
+        assert_eq!(
+            check(&parse(source), source, Natives::NONE, &["prelude"]),
+            vec![]
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "strings", feature = "time"))]
+    fn natives() {
+        let source = r#"
+            var code: String = charCodeAt("a", 0);
+            fromCharCode(1, 2);
+            setTimeout(clock, "soon");
+        "#;
+        assert_eq!(
+            type_errors(source),
+            vec![
+                LoxError::TypeError(
+                    Some(2),
+                    "Cannot initialize 'code': expected String but got Number.".into()
+                ),
+                LoxError::TypeError(Some(3), "Expected 1 arguments but got 2.".into()),
+                LoxError::TypeError(
+                    Some(4),
+                    "Cannot pass argument 2: expected Number but got String.".into()
+                ),
+            ]
+        );
+        let source = "clock();";
+        assert_eq!(
+            check(&parse(source), source, Natives::STRINGS, &[]),
+            vec![LoxError::TypeError(
+                Some(1),
+                "Undefined variable 'clock'.".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let source = format!("print {}1{};", "(".repeat(9_000), ")".repeat(9_000));
+        assert_eq!(type_errors(&source), vec![]);
+        let source = format!("{}print 1;{}", "{".repeat(9_000), "}".repeat(9_000));
+        assert_eq!(type_errors(&source), vec![]);
+    }
+
+    #[test]
+    fn subclass_instances() {
+        let source = r#"
+            class Animal {}
+            class Dog < Animal {}
+            class Car {}
+            var animal: Animal = Dog();
+            var dog: Dog = Car();
+        "#;
+        assert_eq!(type_errors(source).len(), 1);
+    }
+}
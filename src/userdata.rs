@@ -0,0 +1,57 @@
+use crate::error::Result;
+use crate::object::Object;
+
+use std::any::{Any, TypeId};
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+/// A Rust value handed to Lox code, e.g. a file or a game entity. Scripts can only pass it
+/// around and call the methods the host registered for its type with
+/// `Interpreter::define_method`. Values needing mutation should use interior mutability.
+///
+/// Lox values stored inside userdata are invisible to the cycle collector, so cycles through
+/// them are never freed.
+#[derive(Clone)]
+pub struct Userdata {
+    type_name: &'static str,
+    value: Rc<dyn Any>,
+}
+
+impl Userdata {
+    pub fn new<T: Any>(value: T) -> Self {
+        Self {
+            type_name: std::any::type_name::<T>(),
+            value: Rc::new(value),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn value_type_id(&self) -> TypeId {
+        (*self.value).type_id()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+
+    pub fn ptr_eq(&self, other: &Userdata) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl Debug for Userdata {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "<userdata {}>", self.type_name)
+    }
+}
+
+pub type UserdataFn = dyn Fn(&Userdata, &[Object]) -> Result<Object>;
+
+/// A method registered for a type of userdata, called with the userdata it was looked up on.
+pub struct UserdataMethod {
+    pub arity: usize,
+    pub function: Box<UserdataFn>,
+}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use std::io;
+
+/// Where scripts are read from. Hosts without a real filesystem (WASM, plugins) replace the
+/// default `OsFileSystem`, e.g. with a `MemoryFileSystem`.
+pub trait FileSystem {
+    fn read_to_string(&self, path: &str) -> io::Result<String>;
+}
+
+/// The filesystem of the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Files kept in memory, looked up by their exact path.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFileSystem {
+    files: HashMap<String, String>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, replacing one with the same path.
+    pub fn insert(&mut self, path: &str, contents: &str) {
+        self.files.insert(path.to_string(), contents.to_string());
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such file in memory"))
+    }
+}
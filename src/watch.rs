@@ -0,0 +1,142 @@
+use crate::error::{LoxError, Result};
+use crate::functions::{check_arity, Function};
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+/// `watch(path, callback)` calls `callback` with the path whenever the file there is modified,
+/// created or removed, and returns the id `unwatch()` takes. Files are checked for changes every
+/// so often once the program is done, like timers; the program keeps running until every watch
+/// is cancelled.
+pub struct Watch;
+
+/// `unwatch(id)` stops a watch. Stopping one twice does nothing.
+pub struct Unwatch;
+
+impl Function for Watch {
+    fn name(&self) -> &str {
+        "watch"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(2, arguments)?;
+        let path = match &arguments[0] {
+            Object::String(path) => path.clone(),
+            argument => {
+                return Err(LoxError::InterpreterError(
+                    format!(
+                        "watch() expects a path, but got '{}'.",
+                        argument.type_name()
+                    )
+                    .into(),
+                ))
+            }
+        };
+        if !matches!(arguments[1], Object::Function(_)) {
+            return Err(LoxError::InterpreterError(
+                format!(
+                    "watch() expects a function, but got '{}'.",
+                    arguments[1].type_name()
+                )
+                .into(),
+            ));
+        }
+        let id = interpreter.watch_file(path, arguments[1].clone());
+        Ok(Object::Number(id as f64))
+    }
+}
+
+impl Function for Unwatch {
+    fn name(&self) -> &str {
+        "unwatch"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Object]) -> Result<Object> {
+        check_arity(1, arguments)?;
+        match arguments[0] {
+            Object::Number(id) if id.fract() == 0.0 && id >= 0.0 => {
+                interpreter.cancel_timer(id as u64);
+                Ok(Object::Nil)
+            }
+            _ => Err(LoxError::InterpreterError(
+                format!("unwatch() expects a watch id, but got '{}'.", arguments[0]).into(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Debug for Watch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl std::fmt::Debug for Unwatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::LoxError;
+    use crate::lox::Lox;
+    use crate::object::Object;
+
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn calls_back_on_changes() {
+        let path = std::env::temp_dir().join(format!("rlox-watch-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(250));
+                fs::write(&path, "changed").unwrap();
+            })
+        };
+        let source = format!(
+            r#"
+            var changes = 0;
+            fun changed(path) {{
+                changes = changes + 1;
+                unwatch(id);
+            }}
+            var id = watch("{}", changed);
+        "#,
+            path.display()
+        );
+        let mut lox = Lox::new();
+        lox.eval(&source).unwrap();
+        writer.join().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lox.eval("changes;"), Ok(Object::Number(1.0)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Lox::new().eval("watch(1, clock);"),
+            Err(LoxError::InterpreterError(
+                "watch() expects a path, but got 'number'.".into()
+            ))
+        );
+        assert_eq!(
+            Lox::new().eval("watch(\"a\", nil);"),
+            Err(LoxError::InterpreterError(
+                "watch() expects a function, but got 'nil'.".into()
+            ))
+        );
+    }
+}
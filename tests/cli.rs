@@ -0,0 +1,67 @@
+//! Runs the `rlox` binary on scripts, for what only shows at the command line: the messages on
+//! stderr and the exit codes.
+
+use std::process::{Command, Output};
+
+/// Runs `source`, saved as `name`.lox, with the options in `arguments`.
+fn output(name: &str, source: &str, arguments: &[&str]) -> Output {
+    let path = std::env::temp_dir().join(format!("rlox_cli_{}.lox", name));
+    std::fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(arguments)
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+/// The exit code and stderr of running `source`, saved as `name`.lox.
+fn run(name: &str, source: &str) -> (Option<i32>, String) {
+    let output = output(name, source, &[]);
+    (
+        output.status.code(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn resolver_errors() {
+    assert_eq!(
+        run("break_outside_loop", "print 1;\nbreak;\n"),
+        (Some(64), "Cannot break outside of a loop.\n".to_string())
+    );
+    assert_eq!(
+        run(
+            "break_in_switch",
+            "switch (1) {\n  case 1:\n    break;\n}\n"
+        ),
+        (Some(64), "Cannot break outside of a loop.\n".to_string())
+    );
+}
+
+#[test]
+fn folding_before_resolution() {
+    let source = "fun f() {\n  var a = 1;\n  print true or a;\n}\n";
+    let scopes = |arguments: &[&str]| {
+        let output = output("folding", source, arguments);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        scopes(&["--dump-scopes"]),
+        "   1  fun f                global\n   2  var a                slot 0\n   3  a                    slot 0\n"
+    );
+    // the folded away `a` is never resolved
+    assert_eq!(
+        scopes(&["-O1", "--dump-scopes"]),
+        "   1  fun f                global\n   2  var a                slot 0\n"
+    );
+}
+
+#[test]
+fn assigning_a_constant() {
+    assert_eq!(
+        run("assign_constant", "const limit = 1;\nlimit = 2;\n"),
+        (Some(64), "Cannot assign to a constant.\n".to_string())
+    );
+}
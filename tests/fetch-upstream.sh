@@ -0,0 +1,10 @@
+#!/bin/sh
+# Fetches the test suite of Crafting Interpreters into tests/lox/upstream, where the suite
+# test picks it up.
+set -e
+cd "$(dirname "$0")"
+rm -rf lox/upstream craftinginterpreters
+git clone --depth 1 --filter=blob:none --sparse https://github.com/munificent/craftinginterpreters
+git -C craftinginterpreters sparse-checkout set test
+mv craftinginterpreters/test lox/upstream
+rm -rf craftinginterpreters
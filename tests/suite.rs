@@ -0,0 +1,107 @@
+//! Runs the test scripts in `tests/lox` with `rlox test`, in the format of the test suite
+//! of Crafting Interpreters. `tests/fetch-upstream.sh` adds that suite in `tests/lox/upstream`.
+//! Tests listed in `tests/lox/known_failures.txt` may fail; the report groups failures by
+//! directory.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn lox_files(directory: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<_> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            lox_files(&entry, files);
+        } else if entry
+            .extension()
+            .is_some_and(|extension| extension == "lox")
+        {
+            files.push(entry);
+        }
+    }
+}
+
+fn known_failures(root: &Path) -> Vec<String> {
+    fs::read_to_string(root.join("known_failures.txt"))
+        .unwrap()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// The differences `rlox test` found between what `file` did and what it expected.
+fn check(file: &Path) -> Vec<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("test")
+        .arg(file)
+        .output()
+        .unwrap();
+    let mut failures: Vec<_> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("     "))
+        .map(String::from)
+        .collect();
+    if !output.status.success() && failures.is_empty() {
+        failures.push(String::from_utf8(output.stderr).unwrap());
+    }
+    failures
+}
+
+#[test]
+fn suite() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let known_failures = known_failures(&root);
+    let mut files = vec![];
+    lox_files(&root, &mut files);
+
+    let mut unexpected = vec![];
+    let mut by_directory = BTreeMap::new();
+    for file in files {
+        let name = file
+            .strip_prefix(&root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        let known = known_failures.iter().any(|known| {
+            *known == name || (known.ends_with('/') && name.starts_with(known.as_str()))
+        });
+        // the directories of known failures are not run at all
+        if known && !known_failures.contains(&name) {
+            continue;
+        }
+
+        let failures = check(&file);
+        let directory = name.rsplit_once('/').map_or("", |(directory, _)| directory);
+        let (passed, failed) = by_directory.entry(directory.to_string()).or_insert((0, 0));
+        if failures.is_empty() {
+            *passed += 1;
+            if known {
+                unexpected.push(format!(
+                    "{} passes, remove it from known_failures.txt",
+                    name
+                ));
+            }
+        } else {
+            *failed += 1;
+            if !known {
+                unexpected.push(format!("{} failed:\n    {}", name, failures.join("\n    ")));
+            }
+        }
+    }
+
+    for (directory, (passed, failed)) in by_directory {
+        println!(
+            "{:<40} {:>4} passed {:>4} failed",
+            directory, passed, failed
+        );
+    }
+    assert!(unexpected.is_empty(), "\n{}", unexpected.join("\n"));
+}